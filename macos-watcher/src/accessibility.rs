@@ -1,21 +1,73 @@
+// The attribute and role names below are plain strings so the label and
+// replay logic can be exercised off macOS; everything else needs the framework.
+#[cfg(target_os = "macos")]
 use objc::runtime::Object;
-// Remove unused import
-// use core_foundation::string::{CFString, CFStringRef};
-use core_foundation::string::CFStringRef; // Keep used import
+#[cfg(target_os = "macos")]
+use core_foundation::array::{CFArrayGetCount, CFArrayGetTypeID, CFArrayGetValueAtIndex, CFArrayRef};
+#[cfg(target_os = "macos")]
+use core_foundation::base::{CFGetTypeID, CFRelease, CFRetain};
+#[cfg(target_os = "macos")]
+use core_foundation::runloop::CFRunLoopSourceRef;
+#[cfg(target_os = "macos")]
+use core_foundation::string::{CFStringGetTypeID, CFStringRef};
+#[cfg(target_os = "macos")]
 use std::os::raw::{c_int, c_void};
+#[cfg(target_os = "macos")]
+use std::ptr;
+
+#[cfg(target_os = "macos")]
+use crate::cf_utils::{cf_string_ref, cfstring_to_string};
+#[cfg(target_os = "macos")]
+use crate::geometry::{CGPoint, CGRect, CGSize};
 
 // Type Aliases
 // Make the type alias public so it can be used/re-exported by event_tap.rs
+#[cfg(target_os = "macos")]
 pub type AXUIElementRef = *mut Object; // AXUIElementRef is often treated like an NSObject
+#[cfg(target_os = "macos")]
+pub type AXObserverRef = *mut c_void; // Opaque pointer (actually __AXObserver*)
+#[cfg(target_os = "macos")]
+pub type AXValueRef = *mut c_void; // Opaque pointer (actually __AXValue*)
+pub type AXValueType = u32;
+#[cfg(target_os = "macos")]
+pub type CGWindowID = u32;
+
+// Type for the AXObserver notification callback
+#[cfg(target_os = "macos")]
+pub type AXObserverCallback = unsafe extern "C" fn(
+    observer: AXObserverRef,
+    element: AXUIElementRef,
+    notification: CFStringRef,
+    refcon: *mut c_void,
+);
 
 // Constants
 // Accessibility Attributes (as Rust strings, convert to CFStringRef later)
 pub const K_AX_ROLE_ATTRIBUTE: &str = "AXRole";
 pub const K_AX_IDENTIFIER_ATTRIBUTE: &str = "AXIdentifier";
 pub const K_AX_DESCRIPTION_ATTRIBUTE: &str = "AXDescription";
+pub const K_AX_TITLE_ATTRIBUTE: &str = "AXTitle";
+pub const K_AX_VALUE_ATTRIBUTE: &str = "AXValue";
+pub const K_AX_HELP_ATTRIBUTE: &str = "AXHelp";
+pub const K_AX_CHILDREN_ATTRIBUTE: &str = "AXChildren";
+pub const K_AX_POSITION_ATTRIBUTE: &str = "AXPosition";
+pub const K_AX_SIZE_ATTRIBUTE: &str = "AXSize";
+pub const K_AX_WINDOW_ATTRIBUTE: &str = "AXWindow";
+// AXValueType
+pub const K_AX_VALUE_CG_POINT_TYPE: AXValueType = 1;
+pub const K_AX_VALUE_CG_SIZE_TYPE: AXValueType = 2;
 // Accessibility Roles (as Rust strings)
 pub const K_AX_BUTTON_ROLE: &str = "AXButton";
+pub const K_AX_STATIC_TEXT_ROLE: &str = "AXStaticText";
+// Accessibility Notifications (as Rust strings)
+pub const K_AX_FOCUSED_UI_ELEMENT_CHANGED_NOTIFICATION: &str = "AXFocusedUIElementChanged";
+pub const K_AX_FOCUSED_WINDOW_CHANGED_NOTIFICATION: &str = "AXFocusedWindowChanged";
+pub const K_AX_WINDOW_CREATED_NOTIFICATION: &str = "AXWindowCreated";
+pub const K_AX_SHEET_CREATED_NOTIFICATION: &str = "AXSheetCreated";
+pub const K_AX_VALUE_CHANGED_NOTIFICATION: &str = "AXValueChanged";
+pub const K_AX_TITLE_CHANGED_NOTIFICATION: &str = "AXTitleChanged";
 
+#[cfg(target_os = "macos")]
 #[link(name = "ApplicationServices", kind = "framework")]
 #[allow(non_snake_case)] // To allow function names like AXUIElementCopyAttributeValue
 // Remove pub from the extern block
@@ -48,10 +100,190 @@ unsafe extern "C" {
     #[link_name = "AXUIElementGetPid"]
     pub fn ax_ui_element_get_pid(element: AXUIElementRef, pid: *mut i32) -> c_int; // Returns AXError
 
-    // AXUIElementCreateApplication is not directly used in the current callback, 
-    // but keeping it here if needed later.
-    // Allow dead code for this unused function
-    #[allow(dead_code)]
+    // Used by the AXObserver subsystem to subscribe to notifications per app
     #[link_name = "AXUIElementCreateApplication"]
     pub fn ax_ui_element_create_application(pid: i32) -> AXUIElementRef;
-} 
\ No newline at end of file
+
+    #[link_name = "AXObserverCreate"]
+    pub fn ax_observer_create(
+        application: i32,
+        callback: AXObserverCallback,
+        out_observer: *mut AXObserverRef
+    ) -> c_int; // Returns AXError
+
+    #[link_name = "AXObserverAddNotification"]
+    pub fn ax_observer_add_notification(
+        observer: AXObserverRef,
+        element: AXUIElementRef,
+        notification: CFStringRef,
+        refcon: *mut c_void
+    ) -> c_int; // Returns AXError
+
+    #[link_name = "AXObserverGetRunLoopSource"]
+    pub fn ax_observer_get_run_loop_source(observer: AXObserverRef) -> CFRunLoopSourceRef;
+
+    #[link_name = "AXValueGetTypeID"]
+    pub fn ax_value_get_type_id() -> usize; // Returns CFTypeID
+
+    #[link_name = "AXValueGetValue"]
+    pub fn ax_value_get_value(
+        value: AXValueRef,
+        the_type: AXValueType,
+        value_ptr: *mut c_void // Receives CGPoint, CGSize, ... depending on the_type
+    ) -> bool;
+
+    // Private, but the only way to map an AX window to its CGWindowID
+    #[link_name = "_AXUIElementGetWindow"]
+    pub fn ax_ui_element_get_window(element: AXUIElementRef, window_id: *mut CGWindowID) -> c_int; // Returns AXError
+}
+
+// Helper function to read a string attribute from an element.
+// Returns None if the attribute is missing or holds a non-string value
+// (AXValue, for example, is a CFNumber on sliders and checkboxes).
+#[cfg(target_os = "macos")]
+pub unsafe fn copy_string_attribute(element: AXUIElementRef, attribute: &str) -> Option<String> {
+    let mut value_ref: *mut c_void = ptr::null_mut();
+    let attr = unsafe { cf_string_ref(attribute) };
+    let result = unsafe { ax_ui_element_copy_attribute_value(element, attr, &mut value_ref) };
+    unsafe { CFRelease(attr as *const c_void); }
+
+    if result != 0 || value_ref.is_null() { // kAXErrorSuccess is 0
+        return None;
+    }
+    let value = if unsafe { CFGetTypeID(value_ref) == CFStringGetTypeID() } {
+        unsafe { cfstring_to_string(value_ref as CFStringRef) }
+    } else {
+        None
+    };
+    unsafe { CFRelease(value_ref); }
+    value
+} 
+
+// Owned AXUIElementRef that is released when dropped.
+// Saves repeating the copy/convert/CFRelease dance for every attribute we read.
+#[cfg(target_os = "macos")]
+pub struct AxElement(AXUIElementRef);
+
+// AX elements are CF objects: they can be retained, released and queried from any thread
+#[cfg(target_os = "macos")]
+unsafe impl Send for AxElement {}
+
+#[cfg(target_os = "macos")]
+impl AxElement {
+    // Take ownership of a ref we got from a Create/Copy function
+    pub unsafe fn from_create_rule(element: AXUIElementRef) -> Option<Self> {
+        if element.is_null() { None } else { Some(AxElement(element)) }
+    }
+
+    // Retain a ref we do not own (e.g. one borrowed from a CFArray)
+    pub unsafe fn from_get_rule(element: AXUIElementRef) -> Option<Self> {
+        if element.is_null() {
+            return None;
+        }
+        unsafe { CFRetain(element as *const c_void) };
+        Some(AxElement(element))
+    }
+
+    // Borrow the underlying ref, e.g. to pass it to AXObserverAddNotification
+    pub fn as_ptr(&self) -> AXUIElementRef {
+        self.0
+    }
+
+    pub fn pid(&self) -> Option<i32> {
+        let mut pid: i32 = -1;
+        let result = unsafe { ax_ui_element_get_pid(self.0, &mut pid) };
+        if result == 0 { Some(pid) } else { None }
+    }
+
+    pub fn string_attribute(&self, attribute: &str) -> Option<String> {
+        unsafe { copy_string_attribute(self.0, attribute) }
+    }
+
+    // Read an element-valued attribute such as AXWindow
+    pub fn element_attribute(&self, attribute: &str) -> Option<AxElement> {
+        let mut value_ref: *mut c_void = ptr::null_mut();
+        let attr = unsafe { cf_string_ref(attribute) };
+        let result = unsafe { ax_ui_element_copy_attribute_value(self.0, attr, &mut value_ref) };
+        unsafe { CFRelease(attr as *const c_void); }
+
+        if result != 0 {
+            return None;
+        }
+        unsafe { AxElement::from_create_rule(value_ref as AXUIElementRef) }
+    }
+
+    pub fn position(&self) -> Option<CGPoint> {
+        let mut point = CGPoint { x: 0.0, y: 0.0 };
+        let found = unsafe {
+            self.ax_value_attribute(K_AX_POSITION_ATTRIBUTE, K_AX_VALUE_CG_POINT_TYPE, &mut point as *mut CGPoint as *mut c_void)
+        };
+        if found { Some(point) } else { None }
+    }
+
+    pub fn size(&self) -> Option<CGSize> {
+        let mut size = CGSize { width: 0.0, height: 0.0 };
+        let found = unsafe {
+            self.ax_value_attribute(K_AX_SIZE_ATTRIBUTE, K_AX_VALUE_CG_SIZE_TYPE, &mut size as *mut CGSize as *mut c_void)
+        };
+        if found { Some(size) } else { None }
+    }
+
+    // AXPosition + AXSize, in global display points
+    pub fn frame(&self) -> Option<CGRect> {
+        Some(CGRect { origin: self.position()?, size: self.size()? })
+    }
+
+    pub fn window_id(&self) -> Option<CGWindowID> {
+        let mut window_id: CGWindowID = 0;
+        let result = unsafe { ax_ui_element_get_window(self.0, &mut window_id) };
+        if result == 0 && window_id != 0 { Some(window_id) } else { None }
+    }
+
+    // Unwrap an AXValue-wrapped struct (CGPoint, CGSize, ...) into `out`
+    unsafe fn ax_value_attribute(&self, attribute: &str, value_type: AXValueType, out: *mut c_void) -> bool {
+        let mut value_ref: *mut c_void = ptr::null_mut();
+        let attr = unsafe { cf_string_ref(attribute) };
+        let result = unsafe { ax_ui_element_copy_attribute_value(self.0, attr, &mut value_ref) };
+        unsafe { CFRelease(attr as *const c_void); }
+
+        if result != 0 || value_ref.is_null() {
+            return false;
+        }
+        let found = unsafe {
+            CFGetTypeID(value_ref) == ax_value_get_type_id() && ax_value_get_value(value_ref, value_type, out)
+        };
+        unsafe { CFRelease(value_ref); }
+        found
+    }
+
+    pub fn children(&self) -> Vec<AxElement> {
+        let mut children = Vec::new();
+        let mut value_ref: *mut c_void = ptr::null_mut();
+        let attr = unsafe { cf_string_ref(K_AX_CHILDREN_ATTRIBUTE) };
+        let result = unsafe { ax_ui_element_copy_attribute_value(self.0, attr, &mut value_ref) };
+        unsafe { CFRelease(attr as *const c_void); }
+
+        if result != 0 || value_ref.is_null() {
+            return children;
+        }
+        if unsafe { CFGetTypeID(value_ref) == CFArrayGetTypeID() } {
+            let array = value_ref as CFArrayRef;
+            let count = unsafe { CFArrayGetCount(array) };
+            for index in 0..count {
+                let child = unsafe { CFArrayGetValueAtIndex(array, index) } as AXUIElementRef;
+                if let Some(child) = unsafe { AxElement::from_get_rule(child) } {
+                    children.push(child);
+                }
+            }
+        }
+        unsafe { CFRelease(value_ref); }
+        children
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for AxElement {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0 as *const c_void); }
+    }
+}
@@ -0,0 +1,202 @@
+use core_foundation::base::CFRelease;
+use core_foundation::runloop::{CFRunLoopAddSource, CFRunLoopGetCurrent, CFRunLoopRemoveSource, CFRunLoopSourceRef};
+use core_foundation::string::CFStringRef;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+// Import necessary items from other modules
+use crate::accessibility::*;
+use crate::cf_utils::core_foundation_private::kCFRunLoopCommonModes;
+use crate::cf_utils::{cf_string_ref, cfstring_to_string};
+use crate::utils::get_app_name_from_pid;
+
+// Notifications we subscribe to for every tracked app. These cover the UI
+// changes that happen without direct input, e.g. a sheet appearing after a
+// network response comes back.
+pub const OBSERVED_NOTIFICATIONS: &[&str] = &[
+    K_AX_FOCUSED_UI_ELEMENT_CHANGED_NOTIFICATION,
+    K_AX_FOCUSED_WINDOW_CHANGED_NOTIFICATION,
+    K_AX_WINDOW_CREATED_NOTIFICATION,
+    K_AX_SHEET_CREATED_NOTIFICATION,
+    K_AX_VALUE_CHANGED_NOTIFICATION,
+    K_AX_TITLE_CHANGED_NOTIFICATION,
+];
+
+// Start time of a process (seconds, microseconds). Tells a reused PID apart
+// from the process we subscribed to.
+type ProcessStart = (u64, u64);
+
+// An observer and its run loop source; both go away when this is dropped
+struct AppObserver {
+    observer: AXObserverRef,
+    source: CFRunLoopSourceRef,
+}
+
+impl Drop for AppObserver {
+    fn drop(&mut self) {
+        unsafe {
+            CFRunLoopRemoveSource(CFRunLoopGetCurrent(), self.source, kCFRunLoopCommonModes);
+            CFRelease(self.observer as *const c_void);
+        }
+    }
+}
+
+struct TrackedApp {
+    started: ProcessStart,
+    // None means creating the observer failed and we should not retry on every click.
+    // Only held so that removing the entry unsubscribes.
+    #[allow(dead_code)]
+    observer: Option<AppObserver>,
+}
+
+// Observers must be added to the run loop of the thread that creates them, and
+// the event tap callback only ever runs on the main run loop, so per-thread
+// storage is enough here.
+thread_local! {
+    static OBSERVERS: RefCell<HashMap<i32, TrackedApp>> = RefCell::new(HashMap::new());
+}
+
+// Start observing UI notifications for the app with the given PID.
+// Safe to call repeatedly; apps are only subscribed once. Observers of apps
+// that have quit since (or whose PID was reused) are removed on the way.
+pub unsafe fn track_pid(pid: i32) {
+    if pid <= 0 {
+        return;
+    }
+    let Some(started) = process_start(pid) else { return };
+
+    OBSERVERS.with(|observers| {
+        observers.borrow_mut().retain(|tracked_pid, tracked| {
+            let running = process_start(*tracked_pid) == Some(tracked.started);
+            if !running {
+                log::debug!("Stopped observing UI notifications for PID {}, the process is gone", tracked_pid);
+            }
+            running
+        })
+    });
+    if OBSERVERS.with(|observers| observers.borrow().contains_key(&pid)) {
+        return;
+    }
+
+    let observer = unsafe { create_observer(pid) };
+    OBSERVERS.with(|observers| observers.borrow_mut().insert(pid, TrackedApp { started, observer }));
+}
+
+// None once the process has exited. Falls back to a start time of zero for
+// processes we may signal-check but not inspect.
+fn process_start(pid: i32) -> Option<ProcessStart> {
+    let mut info: libc::proc_bsdinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_bsdinfo>() as c_int;
+    let written = unsafe {
+        libc::proc_pidinfo(pid, libc::PROC_PIDTBSDINFO, 0, &mut info as *mut libc::proc_bsdinfo as *mut c_void, size)
+    };
+    if written == size {
+        return Some((info.pbi_start_tvsec, info.pbi_start_tvusec));
+    }
+    let alive = unsafe { libc::kill(pid, 0) } == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    if alive { Some((0, 0)) } else { None }
+}
+
+unsafe fn create_observer(pid: i32) -> Option<AppObserver> {
+    let mut observer: AXObserverRef = ptr::null_mut();
+    let result = unsafe { ax_observer_create(pid, observer_callback, &mut observer) };
+    if result != 0 || observer.is_null() { // kAXErrorSuccess is 0
+        log::debug!("Failed to create AXObserver for PID {}. AXError={}", pid, result);
+        return None;
+    }
+
+    let Some(app_element) = (unsafe { AxElement::from_create_rule(ax_ui_element_create_application(pid)) }) else {
+        log::debug!("Failed to create application element for PID {}", pid);
+        unsafe { CFRelease(observer as *const c_void); }
+        return None;
+    };
+
+    // The observer keeps its own reference to the element; ours is released
+    // when `app_element` goes out of scope
+    let mut subscribed = 0;
+    for notification in OBSERVED_NOTIFICATIONS {
+        let notification_ref = unsafe { cf_string_ref(notification) };
+        let add_result = unsafe {
+            ax_observer_add_notification(observer, app_element.as_ptr(), notification_ref, ptr::null_mut())
+        };
+        unsafe { CFRelease(notification_ref as *const c_void); }
+
+        if add_result == 0 {
+            subscribed += 1;
+        } else {
+            log::debug!("Could not subscribe to {} for PID {}. AXError={}", notification, pid, add_result);
+        }
+    }
+
+    if subscribed == 0 {
+        unsafe { CFRelease(observer as *const c_void); }
+        return None;
+    }
+
+    let source = unsafe { ax_observer_get_run_loop_source(observer) };
+    unsafe { CFRunLoopAddSource(CFRunLoopGetCurrent(), source, kCFRunLoopCommonModes); }
+
+    log::debug!("Observing {} UI notifications for PID {}", subscribed, pid);
+    Some(AppObserver { observer, source })
+}
+
+// A notification waiting to be looked at by the worker thread
+struct PendingNotification {
+    element: AxElement,
+    notification: String,
+}
+
+// The attribute reads and the `ps` lookup happen on a worker thread: the
+// callback runs on the main run loop, which the event tap needs to stay responsive
+thread_local! {
+    static NOTIFICATIONS: Sender<PendingNotification> = {
+        let (sender, receiver) = mpsc::channel::<PendingNotification>();
+        thread::spawn(move || {
+            for pending in receiver {
+                log_notification(&pending);
+            }
+        });
+        sender
+    };
+}
+
+// Called on the main run loop for every notification we subscribed to
+unsafe extern "C" fn observer_callback(
+    _observer: AXObserverRef,
+    element: AXUIElementRef,
+    notification: CFStringRef,
+    _refcon: *mut c_void,
+) {
+    // Both element and notification follow the get rule; the element is
+    // retained so it outlives the callback
+    let Some(element) = (unsafe { AxElement::from_get_rule(element) }) else { return };
+    let notification = unsafe { cfstring_to_string(notification) }.unwrap_or_default();
+    NOTIFICATIONS.with(|sender| {
+        let _ = sender.send(PendingNotification { element, notification });
+    });
+}
+
+fn log_notification(pending: &PendingNotification) {
+    let element = &pending.element;
+    let pid = element.pid().unwrap_or(-1);
+    let app_name = get_app_name_from_pid(pid);
+
+    let role = element.string_attribute(K_AX_ROLE_ATTRIBUTE);
+    let identifier = element.string_attribute(K_AX_IDENTIFIER_ATTRIBUTE);
+    let title = element.string_attribute(K_AX_TITLE_ATTRIBUTE);
+
+    log::info!(
+        "UI Notification: App='{}' (PID={}), Notification='{}', Role='{}', ID='{}', Title='{}'",
+        app_name,
+        pid,
+        pending.notification,
+        role.unwrap_or_else(|| "<No Role>".to_string()),
+        identifier.unwrap_or_else(|| "<No ID>".to_string()),
+        title.unwrap_or_else(|| "<No Title>".to_string())
+    );
+}
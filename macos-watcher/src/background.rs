@@ -0,0 +1,331 @@
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dashboard::format_rate;
+use crate::events::{self, EventSink, WatcherEvent};
+use crate::metrics;
+use crate::network::sample_nettop_rows;
+use crate::shutdown;
+use crate::summary::ByteTotals;
+use crate::traffic::TrafficHistory;
+use crate::utils::process_tree;
+
+// How often the per-app rate gauges are updated
+const RATE_INTERVAL: Duration = Duration::from_secs(10);
+
+// How often the live 🌙 line sums up background traffic
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// What caused a burst of traffic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Origin {
+    // A click or key press in the process (or the app that spawned it)
+    // happened within the correlation window before or during the burst
+    User,
+    Background,
+}
+
+// Traffic of one app over a session, split by origin
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppTraffic {
+    pub user: ByteTotals,
+    pub background: ByteTotals,
+    pub user_bursts: u64,
+    pub background_bursts: u64,
+    // Background bytes per second over the whole session
+    pub background_rate_in: f64,
+    pub background_rate_out: f64,
+}
+
+impl AppTraffic {
+    fn add(&mut self, origin: Origin, delta_in: u64, delta_out: u64) {
+        let (totals, bursts) = match origin {
+            Origin::User => (&mut self.user, &mut self.user_bursts),
+            Origin::Background => (&mut self.background, &mut self.background_bursts),
+        };
+        totals.bytes_in += delta_in;
+        totals.bytes_out += delta_out;
+        *bursts += 1;
+    }
+}
+
+// Splits nettop deltas into user-initiated and background traffic. Time
+// comes from the caller, so the same inputs always classify the same way.
+pub struct TrafficClassifier {
+    window: ChronoDuration,
+    // Last click or key press per PID
+    inputs: HashMap<i32, DateTime<Local>>,
+    // Processes spawned by an app that received input, mapped to its PID
+    owners: HashMap<i32, i32>,
+    // Keyed by process name, so restarts of an app add up
+    apps: BTreeMap<String, AppTraffic>,
+    // Bytes per app since the rates were last taken
+    recent: BTreeMap<String, AppTraffic>,
+}
+
+impl TrafficClassifier {
+    pub fn new(window: Duration) -> Self {
+        TrafficClassifier {
+            window: ChronoDuration::from_std(window).unwrap_or_else(|_| ChronoDuration::zero()),
+            inputs: HashMap::new(),
+            owners: HashMap::new(),
+            apps: BTreeMap::new(),
+            recent: BTreeMap::new(),
+        }
+    }
+
+    pub fn note_input(&mut self, pid: i32, at: DateTime<Local>) {
+        self.inputs.insert(pid, at);
+    }
+
+    // PIDs whose window is still open at `since`; traffic of their child
+    // processes counts as theirs once passed to note_children
+    pub fn recent_inputs(&self, since: DateTime<Local>) -> Vec<i32> {
+        self.inputs.iter().filter(|(_, at)| **at + self.window >= since).map(|(pid, _)| *pid).collect()
+    }
+
+    pub fn note_children(&mut self, owner: i32, pids: &HashSet<i32>) {
+        for pid in pids.iter().filter(|pid| **pid != owner) {
+            self.owners.insert(*pid, owner);
+        }
+    }
+
+    // Classify what process `name` (`pid`) moved between two readings
+    pub fn classify(&mut self, since: DateTime<Local>, until: DateTime<Local>, name: &str, pid: i32, delta_in: u64, delta_out: u64) -> Origin {
+        let owner = self.owners.get(&pid).copied().unwrap_or(pid);
+        let initiated = [pid, owner]
+            .iter()
+            .filter_map(|pid| self.inputs.get(pid))
+            .any(|input| *input <= until && *input + self.window >= since);
+
+        let origin = if initiated { Origin::User } else { Origin::Background };
+        self.apps.entry(name.to_string()).or_default().add(origin, delta_in, delta_out);
+        self.recent.entry(name.to_string()).or_default().add(origin, delta_in, delta_out);
+        origin
+    }
+
+    // Bytes per app since the last call. Apps that went quiet come back with
+    // zero totals, so their rates drop to zero too.
+    pub fn take_recent(&mut self) -> BTreeMap<String, AppTraffic> {
+        let mut recent = std::mem::take(&mut self.recent);
+        self.apps.keys().map(|name| (name.clone(), recent.remove(name).unwrap_or_default())).collect()
+    }
+
+    // Totals per app with rates over `duration_secs`
+    pub fn report(&self, duration_secs: f64) -> BTreeMap<String, AppTraffic> {
+        let mut apps = self.apps.clone();
+        if duration_secs > 0.0 {
+            for app in apps.values_mut() {
+                app.background_rate_in = app.background.bytes_in as f64 / duration_secs;
+                app.background_rate_out = app.background.bytes_out as f64 / duration_secs;
+            }
+        }
+        apps
+    }
+}
+
+// Reads every process's nettop counters in the background and classifies
+// what moved. Clone it before handing it to events::add_sink; the clones
+// share one classifier.
+#[derive(Clone)]
+pub struct BackgroundMonitor {
+    classifier: Arc<Mutex<TrafficClassifier>>,
+}
+
+impl BackgroundMonitor {
+    pub fn start(window: Duration, interval: Duration) -> Self {
+        let classifier = Arc::new(Mutex::new(TrafficClassifier::new(window)));
+        {
+            let classifier = classifier.clone();
+            thread::spawn(move || run(classifier, interval));
+        }
+        BackgroundMonitor { classifier }
+    }
+
+    pub fn report(&self, duration_secs: f64) -> BTreeMap<String, AppTraffic> {
+        self.classifier.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).report(duration_secs)
+    }
+}
+
+impl EventSink for BackgroundMonitor {
+    fn handle(&mut self, event: &WatcherEvent) {
+        let (pid, at) = match event {
+            WatcherEvent::Click(click) => (click.pid, click.timestamp),
+            WatcherEvent::Key(key) => (key.pid, key.timestamp),
+            _ => return,
+        };
+        self.classifier.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).note_input(pid, at);
+    }
+}
+
+fn run(classifier: Arc<Mutex<TrafficClassifier>>, interval: Duration) {
+    let lock = || classifier.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut history = TrafficHistory::default();
+    let mut previous = Local::now();
+    let mut last_rates = Instant::now();
+    let mut last_report = Instant::now();
+    // Background bytes per app since the last 🌙 line
+    let mut unreported: BTreeMap<String, ByteTotals> = BTreeMap::new();
+
+    while !shutdown::is_stopping() {
+        thread::sleep(interval);
+        let rows = sample_nettop_rows();
+        let now = Local::now();
+
+        // `ps` runs without the lock held, so clicks are not held up
+        let owners = lock().recent_inputs(previous);
+        let trees: Vec<(i32, HashSet<i32>)> = owners.into_iter().map(|owner| (owner, process_tree(owner))).collect();
+        let mut state = lock();
+        for (owner, tree) in &trees {
+            state.note_children(*owner, tree);
+        }
+
+        for row in &rows {
+            // The first reading of a process is only its baseline (delta 0)
+            let recorded = history.record(&row.process, Instant::now(), row.bytes_in, row.bytes_out);
+            if events::is_paused() || (recorded.delta_in == 0 && recorded.delta_out == 0) {
+                continue;
+            }
+            let origin = state.classify(previous, now, &row.name, row.pid, recorded.delta_in, recorded.delta_out);
+            if origin == Origin::Background {
+                metrics::add_counter(metrics::BACKGROUND_BYTES_TOTAL, &[("app", &row.name), ("direction", "in")], recorded.delta_in);
+                metrics::add_counter(metrics::BACKGROUND_BYTES_TOTAL, &[("app", &row.name), ("direction", "out")], recorded.delta_out);
+                log::debug!("Background traffic from {}: ↑ {} B ↓ {} B", row.process, recorded.delta_out, recorded.delta_in);
+            }
+        }
+        previous = now;
+
+        if last_rates.elapsed() >= RATE_INTERVAL {
+            let recent = state.take_recent();
+            publish_rates(&recent, last_rates.elapsed().as_secs_f64());
+            for (name, traffic) in recent {
+                let totals = unreported.entry(name).or_default();
+                totals.bytes_in += traffic.background.bytes_in;
+                totals.bytes_out += traffic.background.bytes_out;
+            }
+            last_rates = Instant::now();
+        }
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            log_recent(&std::mem::take(&mut unreported), last_report.elapsed().as_secs_f64());
+            last_report = Instant::now();
+        }
+    }
+}
+
+// Set the rate gauges of every app from its bytes over the last `elapsed_secs`
+fn publish_rates(recent: &BTreeMap<String, AppTraffic>, elapsed_secs: f64) {
+    for (name, traffic) in recent {
+        for (origin, totals) in [("user", &traffic.user), ("background", &traffic.background)] {
+            for (direction, bytes) in [("in", totals.bytes_in), ("out", totals.bytes_out)] {
+                let labels = [("app", name.as_str()), ("origin", origin), ("direction", direction)];
+                metrics::set_gauge(metrics::APP_BYTES_PER_SECOND, &labels, bytes as f64 / elapsed_secs);
+            }
+        }
+    }
+}
+
+// e.g. "🌙 Background traffic over the last 60s: Dropbox ↑ 1.2 KB/s ↓ 300 B/s, ..."
+fn log_recent(recent: &BTreeMap<String, ByteTotals>, elapsed_secs: f64) {
+    let mut apps: Vec<(&String, &ByteTotals)> = recent.iter().filter(|(_, totals)| totals.bytes_in + totals.bytes_out > 0).collect();
+    apps.sort_by_key(|(name, totals)| (std::cmp::Reverse(totals.bytes_in + totals.bytes_out), *name));
+    let parts: Vec<String> = apps
+        .iter()
+        .map(|(name, totals)| {
+            format!(
+                "{} ↑ {} ↓ {}",
+                name,
+                format_rate(totals.bytes_out as f64 / elapsed_secs),
+                format_rate(totals.bytes_in as f64 / elapsed_secs)
+            )
+        })
+        .collect();
+    if !parts.is_empty() {
+        log::info!("🌙 Background traffic over the last {:.0}s: {}", elapsed_secs, parts.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::at;
+
+    fn pids(pids: &[i32]) -> HashSet<i32> {
+        pids.iter().copied().collect()
+    }
+
+    #[test]
+    fn bursts_after_input_are_user_initiated() {
+        let mut classifier = TrafficClassifier::new(Duration::from_secs(3));
+        classifier.note_input(42, at(1000));
+        // Before the input, during its window and after the window closed
+        assert_eq!(classifier.classify(at(0), at(500), "Mail", 42, 10, 1), Origin::Background);
+        assert_eq!(classifier.classify(at(500), at(2500), "Mail", 42, 20, 2), Origin::User);
+        assert_eq!(classifier.classify(at(3900), at(5900), "Mail", 42, 40, 4), Origin::User);
+        assert_eq!(classifier.classify(at(4100), at(6100), "Mail", 42, 80, 8), Origin::Background);
+        assert_eq!(classifier.classify(at(500), at(2500), "Dropbox", 50, 1, 1), Origin::Background);
+
+        let report = classifier.report(10.0);
+        let mail = &report["Mail"];
+        assert_eq!((mail.user.bytes_in, mail.user_bursts, mail.background.bytes_in, mail.background_bursts), (60, 2, 90, 2));
+        assert_eq!((mail.background_rate_in, mail.background_rate_out), (9.0, 0.9));
+        assert_eq!(report["Dropbox"].background_bursts, 1);
+    }
+
+    #[test]
+    fn children_of_an_app_with_input_count_as_user_traffic() {
+        let mut classifier = TrafficClassifier::new(Duration::from_secs(3));
+        classifier.note_input(42, at(1000));
+        classifier.note_input(7, at(-10_000));
+        assert_eq!(classifier.recent_inputs(at(2000)), [42]);
+
+        classifier.note_children(42, &pids(&[42, 43, 44]));
+        assert_eq!(classifier.classify(at(1000), at(3000), "MailHelper", 43, 5, 5), Origin::User);
+        assert_eq!(classifier.classify(at(1000), at(3000), "Unrelated", 45, 5, 5), Origin::Background);
+        assert!(!classifier.owners.contains_key(&42));
+    }
+
+    #[test]
+    fn recent_totals_cover_every_app_until_taken() {
+        let mut classifier = TrafficClassifier::new(Duration::from_secs(3));
+        classifier.note_input(42, at(0));
+        classifier.classify(at(0), at(100), "Mail", 42, 100, 10);
+        classifier.classify(at(0), at(100), "Dropbox", 50, 300, 30);
+
+        let recent = classifier.take_recent();
+        assert_eq!((recent["Mail"].user.bytes_in, recent["Mail"].background.bytes_in), (100, 0));
+        assert_eq!(recent["Dropbox"].background.bytes_out, 30);
+
+        classifier.classify(at(5000), at(6000), "Mail", 42, 1, 0);
+        let recent = classifier.take_recent();
+        // Dropbox went quiet and is reported with nothing, so its rate drops to zero
+        assert_eq!(recent["Dropbox"], AppTraffic::default());
+        assert_eq!((recent["Mail"].user.bytes_in, recent["Mail"].background.bytes_in), (0, 1));
+        // The session totals are unaffected
+        assert_eq!(classifier.report(0.0)["Mail"].user.bytes_in, 100);
+    }
+
+    #[test]
+    fn rates_are_published_as_gauges() {
+        let mut classifier = TrafficClassifier::new(Duration::from_secs(3));
+        classifier.note_input(42, at(0));
+        classifier.classify(at(0), at(100), "RateTestApp", 42, 1000, 500);
+        publish_rates(&classifier.take_recent(), 10.0);
+
+        let rendered = metrics::render();
+        let gauge = |origin: &str, direction: &str| {
+            let prefix = format!("{}{{app=\"RateTestApp\",origin=\"{}\",direction=\"{}\"}} ", metrics::APP_BYTES_PER_SECOND, origin, direction);
+            rendered.lines().find_map(|line| line.strip_prefix(prefix.as_str())).map(str::to_string)
+        };
+        assert_eq!(gauge("user", "in").as_deref(), Some("100"));
+        assert_eq!(gauge("user", "out").as_deref(), Some("50"));
+        assert_eq!(gauge("background", "in").as_deref(), Some("0"));
+
+        publish_rates(&classifier.take_recent(), 10.0);
+        let rendered = metrics::render();
+        assert!(rendered.contains(&format!("{}{{app=\"RateTestApp\",origin=\"user\",direction=\"in\"}} 0\n", metrics::APP_BYTES_PER_SECOND)));
+    }
+}
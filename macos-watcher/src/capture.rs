@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::events::{EventSink, WatcherEvent};
+use crate::log_file::{RotatingFile, RotationPolicy};
+use crate::session;
+
+// One capture line: the event plus the watcher session that recorded it.
+// Capture files are appended to, so a file can hold several sessions.
+#[derive(Serialize, Deserialize)]
+struct CaptureEntry {
+    // Missing in captures written before files were appended to
+    #[serde(default)]
+    session_id: Option<String>,
+    #[serde(flatten)]
+    event: WatcherEvent,
+}
+
+// Records clicks and key presses as JSON lines, one WatcherEvent per line.
+// This is the input format for `macos-watcher replay`.
+pub struct CaptureRecorder {
+    writer: RotatingFile,
+}
+
+impl CaptureRecorder {
+    pub fn create(path: &Path, policy: RotationPolicy) -> io::Result<Self> {
+        Ok(CaptureRecorder {
+            writer: RotatingFile::open(path, policy)?,
+        })
+    }
+}
+
+impl EventSink for CaptureRecorder {
+    fn handle(&mut self, event: &WatcherEvent) {
+        if !is_replayable(event) {
+            return;
+        }
+        let entry = CaptureEntry {
+            session_id: Some(session::session_id().to_string()),
+            event: event.clone(),
+        };
+        // Serialize first so the line reaches the file in one piece, and
+        // flush per line so a killed watcher still leaves a usable capture
+        let result = serde_json::to_vec(&entry)
+            .map_err(io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                self.writer.write_all(&line)
+            })
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            log::error!("Failed to write capture entry: {}", e);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            log::error!("Failed to flush capture file: {}", e);
+        }
+    }
+}
+
+pub fn is_replayable(event: &WatcherEvent) -> bool {
+    matches!(event, WatcherEvent::Click(_) | WatcherEvent::Key(_))
+}
+
+// Load one session from a capture file: `session_id` if given, otherwise the
+// last one recorded. Blank lines are skipped; anything else that does not
+// parse is an error so a truncated capture is noticed before driving an app.
+pub fn read_capture(path: &Path, session_id: Option<&str>) -> Result<Vec<WatcherEvent>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: CaptureEntry = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: invalid capture entry: {}", path.display(), index + 1, e))?;
+        if is_replayable(&entry.event) {
+            entries.push(entry);
+        }
+    }
+
+    let wanted = match session_id {
+        Some(id) => Some(id.to_string()),
+        None => entries.last().and_then(|entry| entry.session_id.clone()),
+    };
+    if let Some(id) = &session_id {
+        if !entries.iter().any(|entry| entry.session_id.as_deref() == Some(id)) {
+            return Err(format!("{} has no session '{}'", path.display(), id));
+        }
+    }
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.session_id == wanted)
+        .map(|entry| entry.event)
+        .collect())
+}
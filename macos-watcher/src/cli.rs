@@ -0,0 +1,575 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::control::{self, CtlArgs, DEFAULT_CONTROL_SOCKET};
+use crate::diff::{DiffArgs, DEFAULT_MIN_BYTES, DEFAULT_THRESHOLD_PERCENT};
+use crate::export::{self, ExportArgs, ExportFormat};
+use crate::flows::FlowsArgs;
+use crate::label::LabelChain;
+use crate::log_file::{self, RotationPolicy};
+use crate::query::{QueryArgs, Report};
+use crate::replay::{ReplayArgs, ReplayOptions};
+use crate::report::ReportArgs;
+use crate::utils::home_file;
+use crate::verify::VerifyArgs;
+use crate::webhook::{self, PayloadFormat, WebhookOptions, DEFAULT_SPOOL_FILE};
+
+// What the process was asked to do
+pub enum Command {
+    Watch(Options),
+    Replay(ReplayArgs),
+    // Verify runs the normal watcher alongside the scenario checks
+    Verify(VerifyArgs, Options),
+    Query(QueryArgs),
+    Ctl(CtlArgs),
+    Flows(FlowsArgs),
+    Export(ExportArgs),
+    Report(ReportArgs),
+    Diff(DiffArgs),
+}
+
+// Default location of the SQLite event store
+pub const DEFAULT_DB_FILE: &str = "macos_watcher.db";
+
+// Session summaries are appended here on shutdown, one JSON object per line
+pub const DEFAULT_SUMMARY_FILE: &str = "macos_watcher_sessions.jsonl";
+
+// Default name of the text log in the home directory
+pub const DEFAULT_LOG_FILE: &str = "macos_watcher.log";
+
+// service.name reported on exported spans
+const DEFAULT_OTLP_SERVICE_NAME: &str = "macos-watcher";
+
+// Clicks are followed by this many seconds of per-process traffic sampling
+pub const DEFAULT_CORRELATION_WINDOW_SECS: f64 = 3.0;
+
+// Command line options for the watcher
+pub struct Options {
+    pub label_chain: LabelChain,
+    // Write clicks and key presses as a replayable capture file
+    pub record_path: Option<PathBuf>,
+    // Store every event in this SQLite database
+    pub db_path: Option<PathBuf>,
+    // Zero turns click/traffic correlation off
+    pub correlation_window: Duration,
+    // Text log location; defaults to ~/macos_watcher.log
+    pub log_path: PathBuf,
+    // Rotation for the text log and the capture file
+    pub rotation: RotationPolicy,
+    // Export clicks as OpenTelemetry spans to this OTLP/HTTP collector
+    pub otlp_endpoint: Option<String>,
+    pub otlp_service_name: String,
+    // Serve Prometheus metrics on this address, e.g. 127.0.0.1:9464
+    pub metrics_addr: Option<String>,
+    // Unix socket for `macos-watcher ctl`; off unless asked for
+    pub control_socket: Option<PathBuf>,
+    pub summary_path: PathBuf,
+    // Full screen dashboard instead of scrolling log lines
+    pub tui: bool,
+    // Look up hostnames of remote addresses; off unless asked for, since
+    // every lookup tells a DNS server which hosts the apps talk to
+    pub resolve_hosts: bool,
+    // Send reverse lookups to this server instead of the system resolver
+    pub dns_server: Option<SocketAddr>,
+    // Learn hostnames from DNS responses on this interface or in this pcap file
+    pub dns_snoop: Option<String>,
+    // Capture packets on this interface (or read this pcap file) for per-flow timing
+    pub capture: Option<String>,
+    // YAML file of alert rules
+    pub rules_path: Option<PathBuf>,
+    // How often all processes are sampled to find background traffic; zero (the default) turns it off
+    pub background_interval: Duration,
+    // POST selected events to an HTTP webhook
+    pub webhook: WebhookOptions,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            label_chain: LabelChain::default(),
+            record_path: None,
+            db_path: None,
+            correlation_window: Duration::from_secs_f64(DEFAULT_CORRELATION_WINDOW_SECS),
+            log_path: home_file(DEFAULT_LOG_FILE),
+            rotation: RotationPolicy::default(),
+            otlp_endpoint: None,
+            otlp_service_name: DEFAULT_OTLP_SERVICE_NAME.to_string(),
+            metrics_addr: None,
+            control_socket: None,
+            summary_path: home_file(DEFAULT_SUMMARY_FILE),
+            tui: false,
+            resolve_hosts: false,
+            dns_server: None,
+            dns_snoop: None,
+            capture: None,
+            rules_path: None,
+            background_interval: Duration::ZERO,
+            webhook: WebhookOptions::default(),
+        }
+    }
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            if !options.parse_flag(&arg, &mut args)? {
+                return Err(format!("Unknown argument: '{}'", arg));
+            }
+        }
+        Ok(options)
+    }
+
+    // Apply one watcher flag, pulling its value from `args` if it takes one.
+    // Returns false if `arg` is not a watcher flag, so subcommands can share these.
+    pub fn parse_flag<I: Iterator<Item = String>>(&mut self, arg: &str, args: &mut I) -> Result<bool, String> {
+        match arg {
+            "--label-chain" => self.label_chain = LabelChain::parse(&required_value(args, arg)?)?,
+            "--record" => self.record_path = Some(PathBuf::from(required_value(args, arg)?)),
+            "--db" => self.db_path = Some(PathBuf::from(required_value(args, arg)?)),
+            "--correlation-window" => {
+                let value = required_value(args, arg)?;
+                self.correlation_window = match value.parse::<f64>() {
+                    Ok(seconds) if seconds >= 0.0 => Duration::from_secs_f64(seconds),
+                    _ => return Err(format!("{} expects a number of seconds, got '{}'", arg, value)),
+                };
+            }
+            "--log-file" => self.log_path = PathBuf::from(required_value(args, arg)?),
+            "--log-max-size" => {
+                let value = required_value(args, arg)?;
+                self.rotation.max_bytes = match log_file::parse_size(&value)? {
+                    0 => None,
+                    bytes => Some(bytes),
+                };
+            }
+            "--log-rotate-every" => {
+                let value = required_value(args, arg)?;
+                let interval = log_file::parse_duration(&value)?;
+                self.rotation.max_age = if interval.is_zero() { None } else { Some(interval) };
+            }
+            "--log-retain" => {
+                let value = required_value(args, arg)?;
+                self.rotation.retain = value.parse().map_err(|_| format!("{} expects a number, got '{}'", arg, value))?;
+            }
+            "--no-log-compress" => self.rotation.compress = false,
+            "--otlp-endpoint" => self.otlp_endpoint = Some(required_value(args, arg)?),
+            "--otlp-service-name" => self.otlp_service_name = required_value(args, arg)?,
+            "--metrics-addr" => self.metrics_addr = Some(required_value(args, arg)?),
+            "--control-socket" => self.control_socket = Some(home_file(DEFAULT_CONTROL_SOCKET)),
+            "--control-socket-path" => self.control_socket = Some(PathBuf::from(required_value(args, arg)?)),
+            "--summary-file" => self.summary_path = PathBuf::from(required_value(args, arg)?),
+            "--tui" => self.tui = true,
+            "--resolve" => self.resolve_hosts = true,
+            // Both only make sense with resolution on
+            "--dns-server" => {
+                self.dns_server = Some(parse_dns_server(&required_value(args, arg)?)?);
+                self.resolve_hosts = true;
+            }
+            "--dns-snoop" => {
+                self.dns_snoop = Some(required_value(args, arg)?);
+                self.resolve_hosts = true;
+            }
+            "--capture" => self.capture = Some(required_value(args, arg)?),
+            "--rules" => self.rules_path = Some(PathBuf::from(required_value(args, arg)?)),
+            "--background-interval" => {
+                let value = required_value(args, arg)?;
+                self.background_interval = match value.parse::<f64>() {
+                    Ok(seconds) if seconds >= 0.0 => Duration::from_secs_f64(seconds),
+                    _ => return Err(format!("{} expects a number of seconds, got '{}'", arg, value)),
+                };
+            }
+            "--webhook" => self.webhook.url = Some(required_value(args, arg)?),
+            "--webhook-format" => self.webhook.format = PayloadFormat::parse(&required_value(args, arg)?)?,
+            "--webhook-events" => self.webhook.event_types = webhook::parse_event_types(&required_value(args, arg)?)?,
+            "--webhook-templates" => self.webhook.templates_path = Some(PathBuf::from(required_value(args, arg)?)),
+            "--webhook-spool" => self.webhook.spool_path = PathBuf::from(required_value(args, arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+pub fn parse_args() -> Result<Command, String> {
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("replay") => {
+            args.next();
+            parse_replay_args(args).map(Command::Replay)
+        }
+        Some("verify") => {
+            args.next();
+            parse_verify_args(args)
+        }
+        Some("query") => {
+            args.next();
+            parse_query_args(args).map(Command::Query)
+        }
+        Some("ctl") => {
+            args.next();
+            parse_ctl_args(args).map(Command::Ctl)
+        }
+        Some("flows") => {
+            args.next();
+            parse_flows_args(args).map(Command::Flows)
+        }
+        Some("export") => {
+            args.next();
+            parse_export_args(args).map(Command::Export)
+        }
+        Some("report") => {
+            args.next();
+            parse_report_args(args).map(Command::Report)
+        }
+        Some("diff") => {
+            args.next();
+            parse_diff_args(args).map(Command::Diff)
+        }
+        Some("watch") => {
+            args.next();
+            Options::parse(args).map(Command::Watch)
+        }
+        _ => Options::parse(args).map(Command::Watch),
+    }
+}
+
+fn parse_replay_args<I: Iterator<Item = String>>(mut args: I) -> Result<ReplayArgs, String> {
+    let mut capture_path = None;
+    let mut session_id = None;
+    let mut drive = false;
+    let mut options = ReplayOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--drive" => drive = true,
+            "--speed" => options.speed = parse_number(&required_value(&mut args, &arg)?, &arg)?,
+            "--element-timeout" => {
+                options.element_timeout = Duration::from_secs_f64(parse_number(&required_value(&mut args, &arg)?, &arg)?);
+            }
+            "--label-chain" => options.label_chain = LabelChain::parse(&required_value(&mut args, &arg)?)?,
+            "--session" => session_id = Some(required_value(&mut args, &arg)?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if capture_path.is_none() => capture_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: '{}'", arg)),
+        }
+    }
+
+    Ok(ReplayArgs {
+        capture_path: capture_path.ok_or("replay requires a capture file")?,
+        session_id,
+        drive,
+        options,
+    })
+}
+
+fn parse_verify_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut scenario_path = None;
+    let mut junit_path = PathBuf::from("macos-watcher-verify.xml");
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--junit" => junit_path = PathBuf::from(required_value(&mut args, &arg)?),
+            // Everything else is a regular watcher option
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if scenario_path.is_none() => scenario_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: '{}'", arg)),
+        }
+    }
+
+    let verify_args = VerifyArgs {
+        scenario_path: scenario_path.ok_or("verify requires a scenario file")?,
+        junit_path,
+    };
+    Ok(Command::Verify(verify_args, options))
+}
+
+fn parse_query_args<I: Iterator<Item = String>>(mut args: I) -> Result<QueryArgs, String> {
+    let mut report = None;
+    let mut db_path = home_file(DEFAULT_DB_FILE);
+    let mut session_id = None;
+    let mut limit = 20;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => db_path = PathBuf::from(required_value(&mut args, &arg)?),
+            "--session" => session_id = Some(required_value(&mut args, &arg)?),
+            "--limit" => {
+                let value = required_value(&mut args, &arg)?;
+                limit = value.parse().map_err(|_| format!("{} expects a number, got '{}'", arg, value))?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if report.is_none() => report = Some(Report::parse(&arg)?),
+            _ => return Err(format!("Unexpected argument: '{}'", arg)),
+        }
+    }
+
+    Ok(QueryArgs {
+        report: report.ok_or("query requires a report name")?,
+        db_path,
+        session_id,
+        limit,
+    })
+}
+
+fn parse_ctl_args<I: Iterator<Item = String>>(mut args: I) -> Result<CtlArgs, String> {
+    let mut socket_path = home_file(DEFAULT_CONTROL_SOCKET);
+    let mut method = None;
+    let mut pairs = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => socket_path = PathBuf::from(required_value(&mut args, &arg)?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if method.is_none() => method = Some(arg),
+            _ => pairs.push(arg),
+        }
+    }
+
+    Ok(CtlArgs {
+        socket_path,
+        method: method.ok_or("ctl requires a method")?,
+        params: control::parse_params(&pairs)?,
+    })
+}
+
+fn parse_flows_args<I: Iterator<Item = String>>(args: I) -> Result<FlowsArgs, String> {
+    let mut capture_path = None;
+    for arg in args {
+        match arg.as_str() {
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if capture_path.is_none() => capture_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: '{}'", arg)),
+        }
+    }
+    Ok(FlowsArgs {
+        capture_path: capture_path.ok_or("flows requires a .pcap file")?,
+    })
+}
+
+fn parse_export_args<I: Iterator<Item = String>>(mut args: I) -> Result<ExportArgs, String> {
+    let mut format = None;
+    let mut db_path = home_file(DEFAULT_DB_FILE);
+    let mut session_id = None;
+    let mut from = None;
+    let mut to = None;
+    let mut window = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => db_path = PathBuf::from(required_value(&mut args, &arg)?),
+            "--session" => session_id = Some(required_value(&mut args, &arg)?),
+            "--from" => from = Some(export::parse_time(&required_value(&mut args, &arg)?)?),
+            "--to" => to = Some(export::parse_time(&required_value(&mut args, &arg)?)?),
+            "--window" => window = Some(Duration::from_secs_f64(parse_number(&required_value(&mut args, &arg)?, &arg)?)),
+            "-o" | "--output" => output = Some(PathBuf::from(required_value(&mut args, &arg)?)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if format.is_none() => format = Some(ExportFormat::parse(&arg)?),
+            _ => return Err(format!("Unexpected argument: '{}'", arg)),
+        }
+    }
+
+    Ok(ExportArgs {
+        format: format.ok_or("export requires a format")?,
+        db_path,
+        session_id,
+        from,
+        to,
+        window,
+        output,
+    })
+}
+
+fn parse_report_args<I: Iterator<Item = String>>(mut args: I) -> Result<ReportArgs, String> {
+    let mut log_path = None;
+    let mut session_id = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--session" => session_id = Some(required_value(&mut args, &arg)?),
+            "-o" | "--output" => output = Some(PathBuf::from(required_value(&mut args, &arg)?)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if log_path.is_none() => log_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: '{}'", arg)),
+        }
+    }
+
+    Ok(ReportArgs {
+        log_path: log_path.unwrap_or_else(|| home_file(DEFAULT_LOG_FILE)),
+        session_id,
+        output,
+    })
+}
+
+fn parse_diff_args<I: Iterator<Item = String>>(mut args: I) -> Result<DiffArgs, String> {
+    let mut logs = Vec::new();
+    let mut session_a = None;
+    let mut session_b = None;
+    let mut threshold_percent = DEFAULT_THRESHOLD_PERCENT;
+    let mut min_bytes = DEFAULT_MIN_BYTES;
+    let mut json = false;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--session-a" => session_a = Some(required_value(&mut args, &arg)?),
+            "--session-b" => session_b = Some(required_value(&mut args, &arg)?),
+            "--threshold" => {
+                let value = required_value(&mut args, &arg)?;
+                threshold_percent = match value.trim_end_matches('%').parse::<f64>() {
+                    Ok(percent) if percent >= 0.0 => percent,
+                    _ => return Err(format!("{} expects a percentage, got '{}'", arg, value)),
+                };
+            }
+            "--min-bytes" => min_bytes = log_file::parse_size(&required_value(&mut args, &arg)?)?,
+            "--json" => json = true,
+            "-o" | "--output" => output = Some(PathBuf::from(required_value(&mut args, &arg)?)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: '{}'", arg)),
+            _ if logs.len() < 2 => logs.push(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: '{}'", arg)),
+        }
+    }
+
+    let mut logs = logs.into_iter();
+    let (Some(log_a), Some(log_b)) = (logs.next(), logs.next()) else {
+        return Err("diff requires two log files".to_string());
+    };
+    Ok(DiffArgs {
+        log_a,
+        log_b,
+        session_a,
+        session_b,
+        threshold_percent,
+        min_bytes,
+        json,
+        output,
+    })
+}
+
+fn required_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} requires a value", flag))
+}
+
+// "1.1.1.1", "127.0.0.1:5353" or "[::1]:53"
+fn parse_dns_server(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    value.parse().map_err(|_| format!("--dns-server expects an IP address with an optional port, got '{}'", value))
+}
+
+fn parse_number(value: &str, flag: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number > 0.0 => Ok(number),
+        _ => Err(format!("{} expects a positive number, got '{}'", flag, value)),
+    }
+}
+
+pub fn print_usage() {
+    eprintln!("Usage: macos-watcher [watch] [OPTIONS]");
+    eprintln!("       macos-watcher replay <CAPTURE> [--drive] [REPLAY OPTIONS]");
+    eprintln!("       macos-watcher verify <SCENARIO.yaml> [--junit <FILE>] [OPTIONS]");
+    eprintln!("       macos-watcher query <REPORT> [--db <FILE>] [--session <ID>] [--limit <N>]");
+    eprintln!("       macos-watcher ctl <METHOD> [KEY=VALUE ...] [--socket <FILE>]");
+    eprintln!("       macos-watcher flows <FILE.pcap>");
+    eprintln!("       macos-watcher export <har|trace> [--db <FILE>] [--session <ID>] [--from <TIME>] [--to <TIME>]");
+    eprintln!("                                        [--window <SECS>] [-o <FILE>]");
+    eprintln!("       macos-watcher report [LOG] [--session <ID>] [-o <FILE>]");
+    eprintln!("       macos-watcher diff <A.log> <B.log> [--threshold <PERCENT>] [--min-bytes <SIZE>] [--json] [-o <FILE>]");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --label-chain <STEPS>  Comma separated label sources for clicked elements,");
+    eprintln!("                         tried in order (default: AXTitle,AXDescription,AXValue,AXHelp,children)");
+    eprintln!("                         'children[:depth]' searches descendant AXStaticText");
+    eprintln!("  --record <FILE>        Write clicks and key presses to a capture file for replay");
+    eprintln!("  --db <FILE>            Store sessions, UI events and traffic in a SQLite database");
+    eprintln!("  --correlation-window <SECS>");
+    eprintln!("                         Sample the clicked app's traffic for this long after each click");
+    eprintln!("                         (default: 3, 0 disables)");
+    eprintln!("  --log-file <FILE>      Text log location (default: ~/{})", DEFAULT_LOG_FILE);
+    eprintln!("  --log-max-size <SIZE>  Rotate the log and capture files at this size, e.g. 512K, 10M");
+    eprintln!("                         (default: 10M, 0 disables)");
+    eprintln!("  --log-rotate-every <DURATION>");
+    eprintln!("                         Rotate files older than this, e.g. 30m, 12h, 7d (default: 1d, 0 disables)");
+    eprintln!("  --log-retain <N>       Rotated files to keep per log (default: 5)");
+    eprintln!("  --no-log-compress      Keep rotated files uncompressed instead of gzipping them");
+    eprintln!("  --otlp-endpoint <URL>  Export clicks as OpenTelemetry spans over OTLP/HTTP,");
+    eprintln!("                         e.g. http://localhost:4318 (/v1/traces is appended)");
+    eprintln!("  --otlp-service-name <NAME>");
+    eprintln!("                         service.name of exported spans (default: {})", DEFAULT_OTLP_SERVICE_NAME);
+    eprintln!("  --metrics-addr <ADDR>  Serve Prometheus metrics at http://ADDR/metrics, e.g. 127.0.0.1:9464");
+    eprintln!("  --control-socket       Listen for `ctl` commands on ~/{}", DEFAULT_CONTROL_SOCKET);
+    eprintln!("  --control-socket-path <FILE>");
+    eprintln!("                         Listen for `ctl` commands on this Unix socket instead");
+    eprintln!("  --summary-file <FILE>  Append the session summary written on shutdown here");
+    eprintln!("                         (default: ~/{})", DEFAULT_SUMMARY_FILE);
+    eprintln!("  --tui                  Live terminal dashboard instead of log lines (q quits, / filters by app)");
+    eprintln!("  --resolve              Look up hostnames of remote addresses (reverse DNS)");
+    eprintln!("  --dns-server <ADDR>    Send reverse lookups to this DNS server, e.g. 1.1.1.1 or 127.0.0.1:5353");
+    eprintln!("                         (default: the system resolver; implies --resolve)");
+    eprintln!("  --dns-snoop <INTERFACE|FILE>");
+    eprintln!("                         Learn hostnames from DNS responses captured on INTERFACE");
+    eprintln!("                         (runs tcpdump, needs root) or read from a .pcap FILE (implies --resolve)");
+    eprintln!("  --capture <INTERFACE|FILE>");
+    eprintln!("                         Capture packets with libpcap (needs root) or read a .pcap FILE,");
+    eprintln!("                         and log first/last byte times of each flow after a click");
+    eprintln!("  --rules <FILE>         Raise alerts for the rules in this YAML file (see README)");
+    eprintln!("  --background-interval <SECS>");
+    eprintln!("                         Sample every process this often and report traffic no UI action");
+    eprintln!("                         explains, e.g. 2 (default: 0, off)");
+    eprintln!("  --webhook <URL>        POST events as JSON to this URL, in batches, retrying with backoff");
+    eprintln!("  --webhook-format <json|slack>");
+    eprintln!("                         Request body: the events as JSON, or a Slack-style {{\"text\": ...}} (default: json)");
+    eprintln!("  --webhook-events <KINDS>");
+    eprintln!("                         Comma separated event types to post (default: {})", webhook::DEFAULT_EVENT_TYPES.join(","));
+    eprintln!("  --webhook-templates <FILE>");
+    eprintln!("                         YAML map of event type to text template, e.g. click: \"{{{{app_name}}}} clicked\"");
+    eprintln!("  --webhook-spool <FILE> Keep batches here while the webhook is down (default: ~/{})", DEFAULT_SPOOL_FILE);
+    eprintln!();
+    eprintln!("Replay options:");
+    eprintln!("  --drive                  Post the captured input instead of only printing the schedule");
+    eprintln!("  --speed <FACTOR>         Speed multiplier for the recorded timing (default: 1.0)");
+    eprintln!("  --element-timeout <SECS> How long to wait for each target element (default: 5)");
+    eprintln!("  --label-chain <STEPS>    Label chain used to match elements (should match the recording)");
+    eprintln!("  --session <ID>           Session to replay from an appended capture (default: the last one)");
+    eprintln!();
+    eprintln!("Verify options:");
+    eprintln!("  --junit <FILE>           Where to write the JUnit XML report (default: macos-watcher-verify.xml)");
+    eprintln!();
+    eprintln!("Query reports: sessions, top-talkers, silent-clicks, bytes-per-button, alerts");
+    eprintln!("  --db <FILE>              Event store to read (default: ~/{})", DEFAULT_DB_FILE);
+    eprintln!("  --session <ID>           Only report on one session");
+    eprintln!("  --limit <N>              Maximum number of rows (default: 20)");
+    eprintln!();
+    eprintln!("Export formats: har (one page per click, one entry per flow of the clicked app),");
+    eprintln!("  trace (Chrome Trace Event JSON for chrome://tracing or ui.perfetto.dev)");
+    eprintln!("  --db <FILE>              Event store to read (default: ~/{})", DEFAULT_DB_FILE);
+    eprintln!("  --session <ID>           Session to export (default: the latest, unless --from/--to is given)");
+    eprintln!("  --from <TIME>, --to <TIME>");
+    eprintln!("                           Only actions in this range, e.g. 2024-05-01 10:00 or RFC 3339");
+    eprintln!("  --window <SECS>          Only flows starting this long after a click (default: the recorded window)");
+    eprintln!("  -o, --output <FILE>      Where to write the export (default: stdout)");
+    eprintln!();
+    eprintln!("Report: a self-contained HTML page with a timeline, per-app totals, UI actions,");
+    eprintln!("  top remote hosts and click heatmaps, read from a text log (default: ~/{}, .gz works too)", DEFAULT_LOG_FILE);
+    eprintln!("  --session <ID>           Session to report on (default: the last one in the log)");
+    eprintln!("  -o, --output <FILE>      Where to write the HTML (default: stdout)");
+    eprintln!();
+    eprintln!("Diff: align the clicks of two text logs by app, AX selector and order, and show how the");
+    eprintln!("  traffic after each click changed from A to B. Exits 0 if nothing changed, 1 otherwise.");
+    eprintln!("  --session-a <ID>, --session-b <ID>");
+    eprintln!("                           Sessions to compare (default: the last one in each log)");
+    eprintln!("  --threshold <PERCENT>    Report byte changes of at least this much (default: {})", DEFAULT_THRESHOLD_PERCENT);
+    eprintln!("  --min-bytes <SIZE>       ...and at least this many bytes, e.g. 512, 4K (default: {})", DEFAULT_MIN_BYTES);
+    eprintln!("  --json                   Write the differences as JSON instead of text");
+    eprintln!("  -o, --output <FILE>      Where to write the diff (default: stdout)");
+    eprintln!();
+    eprintln!("Ctl methods: pause, resume, get_filter, stats, shutdown,");
+    eprintln!("  set_filter [include_apps=A,B] [exclude_apps=C] [event_types=click,key]");
+    eprintln!("  start_recording name=<NAME> (writes ~/macos_watcher-<NAME>.jsonl), stop_recording name=<NAME>");
+    eprintln!("  checkpoint label=<TEXT>");
+    eprintln!("  --socket <FILE>          Control socket of the watcher (default: ~/{})", DEFAULT_CONTROL_SOCKET);
+}
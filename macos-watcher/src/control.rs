@@ -0,0 +1,539 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::capture::CaptureRecorder;
+use crate::events::{self, Checkpoint, EventFilter, EventSink, SinkId, WatcherEvent};
+use crate::log_file::RotationPolicy;
+use crate::session;
+use crate::shutdown;
+use crate::utils::home_file;
+
+// Default location of the control socket
+pub const DEFAULT_CONTROL_SOCKET: &str = "macos_watcher.sock";
+
+// A client that stops talking is dropped after this long
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+// --- Protocol ---
+//
+// One JSON-RPC 2.0 request per line, answered by one response per line.
+// Methods: pause, resume, get_filter, set_filter, start_recording,
+// stop_recording, checkpoint, stats, shutdown.
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    fn success(id: Value, result: Value) -> Self {
+        Response { jsonrpc: "2.0".to_string(), id, result: Some(result), error: None }
+    }
+
+    fn failure(id: Value, code: i64, message: impl Into<String>) -> Self {
+        Response {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError { code, message: message.into() }),
+        }
+    }
+}
+
+// What `stats` reports about the running watcher
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub session_id: String,
+    pub uptime_secs: u64,
+    pub paused: bool,
+    // Events that reached the sinks, by kind
+    pub events: BTreeMap<String, u64>,
+    pub recordings: BTreeMap<String, PathBuf>,
+    pub filter: EventFilter,
+}
+
+// The operations the control API exposes. The socket server only talks to
+// this trait, so the protocol can be exercised without an event tap.
+pub trait Controller {
+    fn pause(&mut self);
+    fn resume(&mut self);
+    fn filter(&self) -> EventFilter;
+    fn set_filter(&mut self, filter: EventFilter);
+    // Returns the file the recording is written to. `name` is a recording_name.
+    fn start_recording(&mut self, name: &str) -> Result<PathBuf, String>;
+    fn stop_recording(&mut self, name: &str) -> Result<PathBuf, String>;
+    fn checkpoint(&mut self, label: &str);
+    fn stats(&self) -> Stats;
+    fn shutdown(&mut self);
+}
+
+// Answer one request line. Never fails: errors become JSON-RPC error responses.
+pub fn handle_line(controller: &mut dyn Controller, line: &str) -> Response {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return Response::failure(Value::Null, PARSE_ERROR, format!("Parse error: {}", e)),
+    };
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    match serde_json::from_value::<Request>(value) {
+        Ok(request) if request.jsonrpc == "2.0" => dispatch(controller, request),
+        Ok(_) => Response::failure(id, INVALID_REQUEST, "jsonrpc must be \"2.0\""),
+        Err(e) => Response::failure(id, INVALID_REQUEST, format!("Invalid request: {}", e)),
+    }
+}
+
+pub fn dispatch(controller: &mut dyn Controller, request: Request) -> Response {
+    let id = request.id.clone();
+    let params = &request.params;
+    let result = match request.method.as_str() {
+        "pause" => {
+            controller.pause();
+            Ok(json!({ "paused": true }))
+        }
+        "resume" => {
+            controller.resume();
+            Ok(json!({ "paused": false }))
+        }
+        "get_filter" => Ok(json!(controller.filter())),
+        "set_filter" => parse_filter(params).map(|filter| {
+            controller.set_filter(filter.clone());
+            json!(filter)
+        }),
+        "start_recording" => string_param(params, "name").and_then(recording_name).and_then(|name| {
+            controller
+                .start_recording(&name)
+                .map(|path| json!({ "name": name, "path": path }))
+                .map_err(|message| (SERVER_ERROR, message))
+        }),
+        "stop_recording" => string_param(params, "name").and_then(|name| {
+            controller
+                .stop_recording(&name)
+                .map(|path| json!({ "name": name, "path": path }))
+                .map_err(|message| (SERVER_ERROR, message))
+        }),
+        "checkpoint" => string_param(params, "label").map(|label| {
+            controller.checkpoint(&label);
+            json!({ "label": label })
+        }),
+        "stats" => Ok(json!(controller.stats())),
+        "shutdown" => {
+            controller.shutdown();
+            Ok(json!({ "shutting_down": true }))
+        }
+        method => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+    };
+
+    match result {
+        Ok(result) => Response::success(id, result),
+        Err((code, message)) => Response::failure(id, code, message),
+    }
+}
+
+fn string_param(params: &Value, name: &str) -> Result<String, (i64, String)> {
+    match params.get(name) {
+        Some(Value::String(value)) if !value.is_empty() => Ok(value.clone()),
+        _ => Err((INVALID_PARAMS, format!("'{}' must be a non-empty string", name))),
+    }
+}
+
+// Recordings are written next to the log as "macos_watcher-<name>.jsonl", so
+// clients pick a name, never a path
+fn recording_name(name: String) -> Result<String, (i64, String)> {
+    if name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+        Ok(name)
+    } else {
+        Err((INVALID_PARAMS, format!("'name' may only contain letters, digits, '-' and '_', got '{}'", name)))
+    }
+}
+
+// Lists may be JSON arrays or comma separated strings (handy from the shell)
+fn string_list(params: &Value, name: &str) -> Result<Vec<String>, (i64, String)> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(value)) => Ok(value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(String::from).collect()),
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| value.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .ok_or((INVALID_PARAMS, format!("'{}' must only contain strings", name))),
+        Some(_) => Err((INVALID_PARAMS, format!("'{}' must be a list of strings", name))),
+    }
+}
+
+fn parse_filter(params: &Value) -> Result<EventFilter, (i64, String)> {
+    Ok(EventFilter {
+        include_apps: string_list(params, "include_apps")?,
+        exclude_apps: string_list(params, "exclude_apps")?,
+        event_types: string_list(params, "event_types")?,
+    })
+}
+
+// --- Server ---
+
+// Serve the control API on a Unix socket from a background thread. Every
+// client gets a thread of its own; requests take turns on the controller.
+pub fn serve<C: Controller + Send + 'static>(path: &Path, controller: C) -> io::Result<()> {
+    if path.exists() {
+        // A socket nobody answers on is left over from a crash
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "another watcher is listening on this socket"));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Whoever can connect can pause the watcher or start recordings
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+    let controller = Arc::new(Mutex::new(controller));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let controller = controller.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_client(&*controller, stream) {
+                            log::debug!("Control client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => log::warn!("Control socket error: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle_client<C: Controller>(controller: &Mutex<C>, stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_line(&mut *controller.lock().unwrap_or_else(|poisoned| poisoned.into_inner()), &line);
+        let mut encoded = serde_json::to_vec(&response).map_err(io::Error::from)?;
+        encoded.push(b'\n');
+        writer.write_all(&encoded)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+// --- The real watcher ---
+
+// Counts what reaches the sinks for `stats`
+struct StatsSink {
+    counts: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl EventSink for StatsSink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        let mut counts = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *counts.entry(event.kind().to_string()).or_default() += 1;
+    }
+}
+
+// Controls the running watcher through the event bus
+pub struct WatcherController {
+    rotation: RotationPolicy,
+    recordings: HashMap<String, (SinkId, PathBuf)>,
+    counts: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl WatcherController {
+    // Registers the sink that feeds `stats`
+    pub fn new(rotation: RotationPolicy) -> Self {
+        let counts = Arc::new(Mutex::new(BTreeMap::new()));
+        events::add_sink(Box::new(StatsSink { counts: counts.clone() }));
+        WatcherController { rotation, recordings: HashMap::new(), counts }
+    }
+}
+
+impl Controller for WatcherController {
+    fn pause(&mut self) {
+        events::set_paused(true);
+        log::info!("⏸ Capture paused via control socket");
+    }
+
+    fn resume(&mut self) {
+        events::set_paused(false);
+        log::info!("▶ Capture resumed via control socket");
+    }
+
+    fn filter(&self) -> EventFilter {
+        events::filter()
+    }
+
+    fn set_filter(&mut self, filter: EventFilter) {
+        log::info!("Event filter changed via control socket: {:?}", filter);
+        events::set_filter(filter);
+    }
+
+    fn start_recording(&mut self, name: &str) -> Result<PathBuf, String> {
+        if self.recordings.contains_key(name) {
+            return Err(format!("Recording '{}' is already running", name));
+        }
+        let path = home_file(&format!("macos_watcher-{}.jsonl", name));
+        let recorder = CaptureRecorder::create(&path, self.rotation.clone())
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let sink_id = events::add_sink(Box::new(recorder));
+        self.recordings.insert(name.to_string(), (sink_id, path.clone()));
+        log::info!("⏺ Recording '{}' started: {:?}", name, path);
+        Ok(path)
+    }
+
+    fn stop_recording(&mut self, name: &str) -> Result<PathBuf, String> {
+        let (sink_id, path) = self.recordings.remove(name).ok_or_else(|| format!("No recording named '{}'", name))?;
+        events::remove_sink(sink_id);
+        log::info!("⏹ Recording '{}' stopped: {:?}", name, path);
+        Ok(path)
+    }
+
+    fn checkpoint(&mut self, label: &str) {
+        log::info!("📍 Checkpoint: {}", label);
+        events::emit(&WatcherEvent::Checkpoint(Checkpoint {
+            timestamp: chrono::Local::now(),
+            label: label.to_string(),
+        }));
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            session_id: session::session_id().to_string(),
+            uptime_secs: (chrono::Local::now() - session::started_at()).num_seconds().max(0) as u64,
+            paused: events::is_paused(),
+            events: self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+            recordings: self.recordings.iter().map(|(name, (_, path))| (name.clone(), path.clone())).collect(),
+            filter: events::filter(),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        log::info!("Shutdown requested via control socket");
+        shutdown::request_stop();
+    }
+}
+
+// --- Client (`macos-watcher ctl`) ---
+
+// Arguments for `macos-watcher ctl`
+pub struct CtlArgs {
+    pub socket_path: PathBuf,
+    pub method: String,
+    pub params: Value,
+}
+
+// Send one request and wait for its response
+pub fn call(socket_path: &Path, method: &str, params: Value) -> Result<Response, String> {
+    let stream = UnixStream::connect(socket_path)
+        .map_err(|e| format!("Failed to connect to {}: {} (is the watcher running with --control-socket?)", socket_path.display(), e))?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT)).map_err(|e| e.to_string())?;
+
+    let request = Request { jsonrpc: "2.0".to_string(), id: json!(1), method: method.to_string(), params };
+    let mut encoded = serde_json::to_vec(&request).map_err(|e| e.to_string())?;
+    encoded.push(b'\n');
+    (&stream).write_all(&encoded).map_err(|e| format!("Failed to send request: {}", e))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).map_err(|e| format!("Failed to read response: {}", e))?;
+    serde_json::from_str(&line).map_err(|e| format!("Invalid response '{}': {}", line.trim(), e))
+}
+
+// Turn `key=value` arguments into a params object. Values that parse as JSON
+// (numbers, arrays, ...) are used as such, anything else is a string.
+pub fn parse_params(pairs: &[String]) -> Result<Value, String> {
+    let mut params = serde_json::Map::new();
+    for pair in pairs {
+        let (key, value) = pair.split_once('=').ok_or_else(|| format!("Expected key=value, got '{}'", pair))?;
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        params.insert(key.to_string(), value);
+    }
+    Ok(Value::Object(params))
+}
+
+// Entry point for the ctl subcommand. Returns the process exit code.
+pub fn run_cli(args: CtlArgs) -> i32 {
+    match call(&args.socket_path, &args.method, args.params) {
+        Ok(Response { result: Some(result), .. }) => {
+            println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+            0
+        }
+        Ok(Response { error: Some(error), .. }) => {
+            eprintln!("Error: {} ({})", error.message, error.code);
+            1
+        }
+        Ok(_) => {
+            eprintln!("Error: Empty response");
+            1
+        }
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records what the protocol asked of it
+    #[derive(Default)]
+    struct FakeController {
+        paused: bool,
+        filter: EventFilter,
+        recordings: BTreeMap<String, PathBuf>,
+        checkpoints: Vec<String>,
+        shutdown: bool,
+    }
+
+    impl Controller for FakeController {
+        fn pause(&mut self) {
+            self.paused = true;
+        }
+
+        fn resume(&mut self) {
+            self.paused = false;
+        }
+
+        fn filter(&self) -> EventFilter {
+            self.filter.clone()
+        }
+
+        fn set_filter(&mut self, filter: EventFilter) {
+            self.filter = filter;
+        }
+
+        fn start_recording(&mut self, name: &str) -> Result<PathBuf, String> {
+            let path = PathBuf::from(format!("/recordings/{}.jsonl", name));
+            self.recordings.insert(name.to_string(), path.clone());
+            Ok(path)
+        }
+
+        fn stop_recording(&mut self, name: &str) -> Result<PathBuf, String> {
+            self.recordings.remove(name).ok_or_else(|| format!("No recording named '{}'", name))
+        }
+
+        fn checkpoint(&mut self, label: &str) {
+            self.checkpoints.push(label.to_string());
+        }
+
+        fn stats(&self) -> Stats {
+            Stats { session_id: "test".to_string(), paused: self.paused, recordings: self.recordings.clone(), ..Stats::default() }
+        }
+
+        fn shutdown(&mut self) {
+            self.shutdown = true;
+        }
+    }
+
+    fn error_code(response: &Response) -> Option<i64> {
+        response.error.as_ref().map(|error| error.code)
+    }
+
+    #[test]
+    fn malformed_requests_get_json_rpc_errors() {
+        let mut controller = FakeController::default();
+        assert_eq!(error_code(&handle_line(&mut controller, "{not json")), Some(PARSE_ERROR));
+        let response = handle_line(&mut controller, r#"{"jsonrpc":"1.0","id":7,"method":"pause"}"#);
+        assert_eq!((error_code(&response), response.id), (Some(INVALID_REQUEST), json!(7)));
+        assert_eq!(error_code(&handle_line(&mut controller, r#"{"jsonrpc":"2.0","id":1}"#)), Some(INVALID_REQUEST));
+        assert_eq!(error_code(&handle_line(&mut controller, r#"{"jsonrpc":"2.0","id":1,"method":"reboot"}"#)), Some(METHOD_NOT_FOUND));
+        assert_eq!(error_code(&handle_line(&mut controller, r#"{"jsonrpc":"2.0","id":1,"method":"checkpoint","params":{"label":""}}"#)), Some(INVALID_PARAMS));
+        assert!(!controller.paused);
+    }
+
+    #[test]
+    fn filters_accept_lists_and_comma_separated_strings() {
+        let mut controller = FakeController::default();
+        let response = handle_line(
+            &mut controller,
+            r#"{"jsonrpc":"2.0","id":1,"method":"set_filter","params":{"include_apps":["Chat","Mail"],"event_types":"click, key"}}"#,
+        );
+        assert!(response.error.is_none());
+        assert_eq!(controller.filter.include_apps, ["Chat", "Mail"]);
+        assert_eq!(controller.filter.event_types, ["click", "key"]);
+        let response = handle_line(&mut controller, r#"{"jsonrpc":"2.0","id":2,"method":"set_filter","params":{"exclude_apps":[1]}}"#);
+        assert_eq!(error_code(&response), Some(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn recordings_are_named_never_placed() {
+        let mut controller = FakeController::default();
+        let response = handle_line(
+            &mut controller,
+            r#"{"jsonrpc":"2.0","id":1,"method":"start_recording","params":{"name":"login-1","path":"/etc/cron.d/x"}}"#,
+        );
+        assert_eq!(response.result, Some(json!({ "name": "login-1", "path": "/recordings/login-1.jsonl" })));
+        for name in ["../../.ssh/authorized_keys", "a/b", ".hidden", "with space"] {
+            let request = json!({ "jsonrpc": "2.0", "id": 2, "method": "start_recording", "params": { "name": name } });
+            assert_eq!(error_code(&handle_line(&mut controller, &request.to_string())), Some(INVALID_PARAMS), "{}", name);
+        }
+        assert_eq!(controller.recordings.len(), 1);
+    }
+
+    #[test]
+    fn ctl_params_parse_json_values_and_strings() {
+        let params = parse_params(&["label=starting login".to_string(), "limit=5".to_string(), "apps=[\"Chat\"]".to_string()]).unwrap();
+        assert_eq!(params, json!({ "label": "starting login", "limit": 5, "apps": ["Chat"] }));
+        assert!(parse_params(&["label".to_string()]).is_err());
+    }
+
+    #[test]
+    fn serves_clients_concurrently_over_the_socket() {
+        let path = std::env::temp_dir().join(format!("mw-ctl-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        serve(&path, FakeController::default()).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // A client that connects and says nothing must not hold up the others
+        let idle = UnixStream::connect(&path).unwrap();
+        let response = call(&path, "checkpoint", json!({ "label": "one" })).unwrap();
+        assert_eq!(response.result, Some(json!({ "label": "one" })));
+        let response = call(&path, "pause", Value::Null).unwrap();
+        assert_eq!(response.id, json!(1));
+        let stats: Stats = serde_json::from_value(call(&path, "stats", Value::Null).unwrap().result.unwrap()).unwrap();
+        assert!(stats.paused);
+
+        // A second watcher refuses to take over a live socket
+        assert_eq!(serve(&path, FakeController::default()).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        drop(idle);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,447 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, ClickEvent, Correlation, EventSink, WatcherEvent};
+use crate::dissect::FlowDetails;
+use crate::flows;
+use crate::network::{sample_processes, ConnectionSample, ProcessTraffic};
+use crate::resolver;
+use crate::utils::process_tree;
+
+// How often nettop is sampled while a window is open
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+// Bytes moved by one connection during a window
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionDelta {
+    pub protocol: String,
+    pub remote_host: String,
+    pub remote_port: Option<u16>,
+    // Name of remote_host, when hostname resolution is on and it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    // The connection did not exist when the window opened
+    pub is_new: bool,
+}
+
+// Bytes moved between two consecutive samples during a window
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrafficPoint {
+    // Time of the sample, relative to the start of the window
+    pub offset_ms: u64,
+    pub delta_in: u64,
+    pub delta_out: u64,
+}
+
+// Network activity of one process in the window after a UI action
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WindowTraffic {
+    pub pid: i32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub connections: Vec<ConnectionDelta>,
+    // Per-sample deltas that moved any bytes, in order
+    #[serde(default)]
+    pub timeline: Vec<TrafficPoint>,
+    // Exact per-flow timing, when a packet capture is running
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flows: Vec<FlowTiming>,
+}
+
+// When one captured flow of the clicked process moved its bytes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FlowTiming {
+    pub protocol: String,
+    pub local: String,
+    pub remote: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub pid: Option<i32>,
+    // Milliseconds after the click; negative if the flow was already busy
+    pub first_byte_ms: i64,
+    // First byte from the server; None if it never answered
+    #[serde(default)]
+    pub first_response_ms: Option<i64>,
+    pub last_byte_ms: i64,
+    // Payload of the whole flow, including bytes outside the window
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    // SNI, HTTP requests and DNS queries seen in the flow
+    #[serde(default, skip_serializing_if = "FlowDetails::is_empty")]
+    pub details: FlowDetails,
+}
+
+impl WindowTraffic {
+    pub fn new_connections(&self) -> impl Iterator<Item = &ConnectionDelta> {
+        self.connections.iter().filter(|connection| connection.is_new)
+    }
+
+    // Distinct hostnames (or addresses, when unnamed) the window talked to
+    pub fn remote_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for connection in &self.connections {
+            let name = connection.display_host();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+}
+
+impl ConnectionDelta {
+    pub fn display_host(&self) -> &str {
+        self.hostname.as_deref().unwrap_or(&self.remote_host)
+    }
+}
+
+// Compare the samples taken when the window opened with the latest sample of
+// each process. `seen` holds the last sample of every connection observed
+// during the window, including ones that closed before the window ended.
+// Processes missing from `baseline` started (or opened sockets) after it,
+// so everything they report counts.
+pub fn diff_traffic(baseline: &[ProcessTraffic], latest: &[ProcessTraffic], seen: &[ConnectionSample]) -> WindowTraffic {
+    let before: HashMap<String, &ConnectionSample> =
+        baseline.iter().flat_map(|process| &process.connections).map(|c| (c.key(), c)).collect();
+
+    let connections = seen
+        .iter()
+        .filter_map(|connection| {
+            let previous = before.get(&connection.key());
+            let bytes_in = connection.bytes_in.saturating_sub(previous.map_or(0, |p| p.bytes_in));
+            let bytes_out = connection.bytes_out.saturating_sub(previous.map_or(0, |p| p.bytes_out));
+            let is_new = previous.is_none();
+            if !is_new && bytes_in == 0 && bytes_out == 0 {
+                return None; // Idle connection that was already open
+            }
+            Some(ConnectionDelta {
+                protocol: connection.protocol.clone(),
+                remote_host: connection.remote_host.clone(),
+                remote_port: connection.remote_port,
+                hostname: None,
+                bytes_in,
+                bytes_out,
+                is_new,
+            })
+        })
+        .collect();
+
+    let (bytes_in, bytes_out) = window_totals(baseline, latest);
+    WindowTraffic {
+        pid: latest.first().or(baseline.first()).map_or(0, |process| process.pid),
+        bytes_in,
+        bytes_out,
+        connections,
+        timeline: Vec::new(),
+        flows: Vec::new(),
+    }
+}
+
+fn window_totals(baseline: &[ProcessTraffic], latest: &[ProcessTraffic]) -> (u64, u64) {
+    latest.iter().fold((0, 0), |(total_in, total_out), after| {
+        let before = baseline.iter().find(|before| before.pid == after.pid);
+        (
+            total_in + after.bytes_in.saturating_sub(before.map_or(0, |before| before.bytes_in)),
+            total_out + after.bytes_out.saturating_sub(before.map_or(0, |before| before.bytes_out)),
+        )
+    })
+}
+
+// A window somebody asked the sampler to watch
+struct WindowRequest {
+    pid: i32,
+    // When the UI action happened; the window and the timeline count from here
+    started: Instant,
+    window: Duration,
+    // Sample of the process tree from before the action, if the caller has one
+    baseline: Vec<ProcessTraffic>,
+    done: Box<dyn FnOnce(WindowTraffic) + Send>,
+}
+
+struct OpenWindow {
+    request: WindowRequest,
+    pids: HashSet<i32>,
+    // Latest sample of every process in the tree nettop has listed so far
+    latest: HashMap<i32, ProcessTraffic>,
+    seen: HashMap<String, ConnectionSample>,
+    timeline: Vec<TrafficPoint>,
+}
+
+impl OpenWindow {
+    fn open(mut request: WindowRequest) -> Self {
+        let mut pids = process_tree(request.pid);
+        pids.extend(request.baseline.iter().map(|process| process.pid));
+        if request.baseline.is_empty() {
+            request.baseline = sample_processes(&pids);
+        }
+        let latest = request.baseline.iter().map(|process| (process.pid, process.clone())).collect();
+        OpenWindow { request, pids, latest, seen: HashMap::new(), timeline: Vec::new() }
+    }
+
+    fn latest(&self) -> Vec<ProcessTraffic> {
+        self.latest.values().cloned().collect()
+    }
+
+    fn update(&mut self, sample: &[ProcessTraffic]) {
+        let (previous_in, previous_out) = window_totals(&self.request.baseline, &self.latest());
+        for process in sample.iter().filter(|process| self.pids.contains(&process.pid)) {
+            for connection in &process.connections {
+                self.seen.insert(connection.key(), connection.clone());
+            }
+            self.latest.insert(process.pid, process.clone());
+        }
+        let (total_in, total_out) = window_totals(&self.request.baseline, &self.latest());
+        let point = TrafficPoint {
+            offset_ms: self.request.started.elapsed().as_millis() as u64,
+            delta_in: total_in.saturating_sub(previous_in),
+            delta_out: total_out.saturating_sub(previous_out),
+        };
+        if point.delta_in > 0 || point.delta_out > 0 {
+            self.timeline.push(point);
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        self.request.started.elapsed() >= self.request.window
+    }
+
+    fn close(self) {
+        let seen: Vec<ConnectionSample> = self.seen.into_values().collect();
+        let latest: Vec<ProcessTraffic> = self.latest.into_values().collect();
+        let mut traffic = diff_traffic(&self.request.baseline, &latest, &seen);
+        traffic.pid = self.request.pid;
+        traffic.timeline = self.timeline;
+        for connection in &mut traffic.connections {
+            connection.hostname = resolver::hostname(&connection.remote_host);
+        }
+        (self.request.done)(traffic);
+    }
+}
+
+// Samples nettop for every open window on one thread, so a burst of clicks
+// costs one nettop run per interval rather than one per click
+#[derive(Clone)]
+pub struct WindowSampler {
+    sender: Sender<WindowRequest>,
+}
+
+impl WindowSampler {
+    pub fn start(interval: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || run_sampler(receiver, interval));
+        WindowSampler { sender }
+    }
+
+    // Watch `pid` and its child processes from `started` until `window` has
+    // passed, then hand what changed to `done` (on the sampler thread).
+    // Without a `baseline` one is taken when the window opens.
+    pub fn watch<F>(&self, pid: i32, started: Instant, window: Duration, baseline: Vec<ProcessTraffic>, done: F)
+    where
+        F: FnOnce(WindowTraffic) + Send + 'static,
+    {
+        let _ = self.sender.send(WindowRequest { pid, started, window, baseline, done: Box::new(done) });
+    }
+}
+
+fn run_sampler(receiver: Receiver<WindowRequest>, interval: Duration) {
+    let mut open: Vec<OpenWindow> = Vec::new();
+    let mut disconnected = false;
+    loop {
+        // Idle until somebody opens a window
+        if open.is_empty() {
+            if disconnected {
+                return;
+            }
+            match receiver.recv() {
+                Ok(request) => open.push(OpenWindow::open(request)),
+                Err(_) => return,
+            }
+        }
+
+        let next_sample = Instant::now() + interval;
+        while !disconnected {
+            match receiver.recv_timeout(next_sample.saturating_duration_since(Instant::now())) {
+                Ok(request) => open.push(OpenWindow::open(request)),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => disconnected = true,
+            }
+        }
+
+        let pids: HashSet<i32> = open.iter().flat_map(|window| window.pids.iter().copied()).collect();
+        let sample = sample_processes(&pids);
+        for window in &mut open {
+            window.update(&sample);
+        }
+        let (over, still_open): (Vec<OpenWindow>, Vec<OpenWindow>) = open.into_iter().partition(OpenWindow::is_over);
+        open = still_open;
+        for window in over {
+            window.close();
+        }
+    }
+}
+
+// Watches the traffic of every clicked process for `window` and emits a
+// Correlation event with the result, so sinks can tie clicks to their requests.
+pub struct CorrelationSink {
+    window: Duration,
+    sampler: WindowSampler,
+}
+
+impl CorrelationSink {
+    pub fn new(window: Duration) -> Self {
+        CorrelationSink { window, sampler: WindowSampler::start(DEFAULT_SAMPLE_INTERVAL) }
+    }
+}
+
+impl EventSink for CorrelationSink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        let WatcherEvent::Click(click) = event else { return };
+        if click.pid <= 0 {
+            return;
+        }
+        let mut click = click.clone();
+        let baseline = std::mem::take(&mut click.traffic_before);
+        let window = self.window;
+        self.sampler.watch(click.pid, Instant::now(), window, baseline, move |traffic| report_window(click, window, traffic));
+    }
+}
+
+// Add flow timings and names to a finished window, log it and emit the Correlation
+fn report_window(click: Box<ClickEvent>, window: Duration, mut traffic: WindowTraffic) {
+    let clicked_at = Duration::from_millis(click.timestamp.timestamp_millis().max(0) as u64);
+    traffic.flows = flows::timings_for(&process_tree(click.pid), clicked_at, clicked_at + window);
+    // Names from SNI or Host headers also label nettop's connections
+    for connection in &mut traffic.connections {
+        let named = traffic.flows.iter().find(|flow| {
+            flow.details.server_name().is_some() && flow.remote.rsplit_once(':').is_some_and(|(host, _)| host.trim_matches(['[', ']']) == connection.remote_host)
+        });
+        if let Some(flow) = named {
+            connection.hostname = flow.hostname.clone();
+        }
+    }
+    let remotes = traffic.remote_names();
+    log::info!(
+        "🔗 {} in '{}' (PID={}) → ↑ {} B ↓ {} B, {} new connection(s) within {:.1}s{}",
+        click.selector.describe(),
+        click.app_name,
+        click.pid,
+        traffic.bytes_out,
+        traffic.bytes_in,
+        traffic.new_connections().count(),
+        window.as_secs_f64(),
+        if remotes.is_empty() { String::new() } else { format!(" [{}]", remotes.join(", ")) }
+    );
+    for connection in &traffic.connections {
+        log::debug!(
+            "   {} {} ({}:{}) ↑ {} B ↓ {} B{}",
+            connection.protocol,
+            connection.display_host(),
+            connection.remote_host,
+            connection.remote_port.map_or("*".to_string(), |port| port.to_string()),
+            connection.bytes_out,
+            connection.bytes_in,
+            if connection.is_new { " (new)" } else { "" }
+        );
+    }
+    for flow in &traffic.flows {
+        log::info!(
+            "   ⏱ {} {} → {} first byte {:+} ms, last byte {:+} ms, ↑ {} B ↓ {} B{}",
+            flow.protocol,
+            flow.local,
+            flow.hostname.as_deref().map_or(flow.remote.clone(), |hostname| format!("{} ({})", hostname, flow.remote)),
+            flow.first_byte_ms,
+            flow.last_byte_ms,
+            flow.bytes_out,
+            flow.bytes_in,
+            flow.details.summary().map_or(String::new(), |summary| format!(": {}", summary))
+        );
+    }
+    events::emit(&WatcherEvent::Correlation(Correlation {
+        timestamp: chrono::Local::now(),
+        click,
+        window_ms: window.as_millis() as u64,
+        traffic,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(remote: &str, bytes_in: u64, bytes_out: u64) -> ConnectionSample {
+        ConnectionSample {
+            protocol: "tcp4".to_string(),
+            local: "10.0.0.5:52345".to_string(),
+            remote_host: remote.to_string(),
+            remote_port: Some(443),
+            bytes_in,
+            bytes_out,
+        }
+    }
+
+    fn process(pid: i32, bytes_in: u64, bytes_out: u64, connections: Vec<ConnectionSample>) -> ProcessTraffic {
+        ProcessTraffic { name: "Chat".to_string(), pid, bytes_in, bytes_out, connections }
+    }
+
+    #[test]
+    fn counts_only_traffic_since_the_baseline() {
+        let baseline = [process(10, 1000, 500, vec![connection("93.184.216.34", 1000, 500)])];
+        let latest = [process(10, 1600, 700, vec![connection("93.184.216.34", 1600, 700)])];
+        let traffic = diff_traffic(&baseline, &latest, &latest[0].connections);
+
+        assert_eq!((traffic.bytes_in, traffic.bytes_out), (600, 200));
+        assert_eq!(traffic.connections.len(), 1);
+        assert_eq!((traffic.connections[0].bytes_in, traffic.connections[0].bytes_out), (600, 200));
+        assert!(!traffic.connections[0].is_new);
+    }
+
+    #[test]
+    fn helper_processes_that_appear_later_count_in_full() {
+        let baseline = [process(10, 1000, 500, Vec::new())];
+        let latest = [process(10, 1000, 500, Vec::new()), process(11, 300, 100, vec![connection("1.1.1.1", 300, 100)])];
+        let traffic = diff_traffic(&baseline, &latest, &latest[1].connections);
+
+        assert_eq!((traffic.bytes_in, traffic.bytes_out), (300, 100));
+        assert!(traffic.connections[0].is_new);
+        assert_eq!(traffic.new_connections().count(), 1);
+    }
+
+    #[test]
+    fn idle_connections_that_were_already_open_are_left_out() {
+        let open = vec![connection("93.184.216.34", 10, 10)];
+        let baseline = [process(10, 10, 10, open.clone())];
+        let traffic = diff_traffic(&baseline, &baseline, &open);
+        assert!(traffic.connections.is_empty());
+        assert_eq!((traffic.bytes_in, traffic.bytes_out), (0, 0));
+    }
+
+    #[test]
+    fn counters_going_backwards_do_not_underflow() {
+        let baseline = [process(10, 1000, 500, Vec::new())];
+        let latest = [process(10, 10, 5, Vec::new())];
+        assert_eq!(window_totals(&baseline, &latest), (0, 0));
+    }
+
+    #[test]
+    fn remote_names_prefer_hostnames_and_are_distinct() {
+        let delta = |remote: &str, hostname: Option<&str>| ConnectionDelta {
+            protocol: "tcp4".to_string(),
+            remote_host: remote.to_string(),
+            remote_port: Some(443),
+            hostname: hostname.map(str::to_string),
+            bytes_in: 1,
+            bytes_out: 1,
+            is_new: true,
+        };
+        let traffic = WindowTraffic {
+            connections: vec![delta("1.2.3.4", Some("api.example.com")), delta("1.2.3.5", Some("api.example.com")), delta("5.6.7.8", None)],
+            ..WindowTraffic::default()
+        };
+        assert_eq!(traffic.remote_names(), vec!["api.example.com", "5.6.7.8"]);
+    }
+}
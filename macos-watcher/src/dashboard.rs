@@ -0,0 +1,343 @@
+use chrono::{DateTime, Local};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::events::WatcherEvent;
+use crate::network::split_process_name;
+
+// How many rows the scrolling panes keep
+const MAX_UI_EVENTS: usize = 200;
+const MAX_CORRELATIONS: usize = 100;
+
+// One line in the UI events pane
+#[derive(Clone, Debug, PartialEq)]
+pub struct UiEventRow {
+    pub time: DateTime<Local>,
+    pub kind: &'static str,
+    pub app: String,
+    pub description: String,
+}
+
+// Live traffic of one nettop process row
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessRow {
+    // nettop's "<name>.<pid>"
+    pub process: String,
+    pub app: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    // Bytes per second over the last 10s
+    pub rate_in: f64,
+    pub rate_out: f64,
+    pub last_seen: DateTime<Local>,
+}
+
+// A click and the traffic that followed it
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorrelationRow {
+    pub time: DateTime<Local>,
+    pub app: String,
+    pub target: String,
+    // Hostnames (or addresses) the click's traffic went to
+    pub remotes: Vec<String>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub new_connections: usize,
+    pub window_ms: u64,
+}
+
+// Watcher health shown in the status pane
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Health {
+    pub uptime_secs: i64,
+    pub paused: bool,
+    pub tap_disabled: u64,
+    pub events_by_kind: BTreeMap<&'static str, u64>,
+}
+
+// Everything one frame shows, already filtered and sorted
+#[derive(Clone, Debug, PartialEq)]
+pub struct DashboardView {
+    pub ui_events: Vec<UiEventRow>,
+    pub processes: Vec<ProcessRow>,
+    pub correlations: Vec<CorrelationRow>,
+    pub health: Health,
+    pub app_filter: String,
+    pub editing_filter: bool,
+}
+
+// Keys the dashboard understands, independent of the terminal library
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DashboardKey {
+    Char(char),
+    Backspace,
+    Enter,
+    Escape,
+    Quit,
+}
+
+// State behind the TUI. It only sees watcher events and key presses, so it
+// can be driven and snapshot-tested without a terminal.
+#[derive(Clone, Debug, Default)]
+pub struct Dashboard {
+    ui_events: VecDeque<UiEventRow>,
+    processes: BTreeMap<String, ProcessRow>,
+    correlations: VecDeque<CorrelationRow>,
+    events_by_kind: BTreeMap<&'static str, u64>,
+    // Case-insensitive substring of the app name; empty shows everything
+    app_filter: String,
+    editing_filter: bool,
+}
+
+impl Dashboard {
+    pub fn handle_event(&mut self, event: &WatcherEvent) {
+        *self.events_by_kind.entry(event.kind()).or_default() += 1;
+        match event {
+            WatcherEvent::Click(click) => self.push_ui_event(UiEventRow {
+                time: click.timestamp,
+                kind: "click",
+                app: click.app_name.clone(),
+                description: click.selector.describe(),
+            }),
+            WatcherEvent::Key(key) => self.push_ui_event(UiEventRow {
+                time: key.timestamp,
+                kind: "key",
+                app: key.app_name.clone(),
+                description: format!("keycode {}", key.keycode),
+            }),
+            WatcherEvent::Checkpoint(checkpoint) => self.push_ui_event(UiEventRow {
+                time: checkpoint.timestamp,
+                kind: "checkpoint",
+                app: String::new(),
+                description: checkpoint.label.clone(),
+            }),
+            WatcherEvent::Alert(alert) => self.push_ui_event(UiEventRow {
+                time: alert.timestamp,
+                kind: "alert",
+                app: alert.app_name.clone().unwrap_or_default(),
+                description: format!("[{}] {}: {}", alert.severity, alert.rule, alert.message),
+            }),
+            WatcherEvent::TapDisabled(tap) => self.push_ui_event(UiEventRow {
+                time: tap.timestamp,
+                kind: "tap_disabled",
+                app: String::new(),
+                description: format!("Event tap disabled ({})", tap.reason),
+            }),
+            WatcherEvent::Startup(_) | WatcherEvent::Shutdown(_) => {}
+            WatcherEvent::NetworkSample(sample) => {
+                let app = split_process_name(&sample.process).map_or(sample.process.clone(), |(name, _)| name.to_string());
+                let row = self.processes.entry(sample.process.clone()).or_insert_with(|| ProcessRow {
+                    process: sample.process.clone(),
+                    app,
+                    bytes_in: 0,
+                    bytes_out: 0,
+                    rate_in: 0.0,
+                    rate_out: 0.0,
+                    last_seen: sample.timestamp,
+                });
+                // Prefer the watcher's 10s sliding window; fall back to the gap between samples
+                let elapsed = (sample.timestamp - row.last_seen).num_milliseconds() as f64 / 1000.0;
+                if let Some(rates) = &sample.rates {
+                    row.rate_in = rates.last_10s.bytes_in;
+                    row.rate_out = rates.last_10s.bytes_out;
+                } else if elapsed > 0.0 {
+                    row.rate_in = sample.delta_in as f64 / elapsed;
+                    row.rate_out = sample.delta_out as f64 / elapsed;
+                }
+                row.bytes_in = sample.bytes_in;
+                row.bytes_out = sample.bytes_out;
+                row.last_seen = sample.timestamp;
+            }
+            WatcherEvent::Correlation(correlation) => {
+                self.correlations.push_front(CorrelationRow {
+                    time: correlation.click.timestamp,
+                    app: correlation.click.app_name.clone(),
+                    target: correlation.click.selector.describe(),
+                    remotes: correlation.traffic.remote_names().into_iter().map(str::to_string).collect(),
+                    bytes_in: correlation.traffic.bytes_in,
+                    bytes_out: correlation.traffic.bytes_out,
+                    new_connections: correlation.traffic.new_connections().count(),
+                    window_ms: correlation.window_ms,
+                });
+                self.correlations.truncate(MAX_CORRELATIONS);
+            }
+        }
+    }
+
+    fn push_ui_event(&mut self, row: UiEventRow) {
+        self.ui_events.push_front(row);
+        self.ui_events.truncate(MAX_UI_EVENTS);
+    }
+
+    // Returns true when the dashboard should close
+    pub fn handle_key(&mut self, key: DashboardKey) -> bool {
+        if self.editing_filter {
+            match key {
+                DashboardKey::Char(c) => self.app_filter.push(c),
+                DashboardKey::Backspace => {
+                    self.app_filter.pop();
+                }
+                DashboardKey::Enter => self.editing_filter = false,
+                DashboardKey::Escape => {
+                    self.app_filter.clear();
+                    self.editing_filter = false;
+                }
+                DashboardKey::Quit => return true,
+            }
+            return false;
+        }
+        match key {
+            DashboardKey::Char('q') | DashboardKey::Quit => return true,
+            DashboardKey::Char('/') => self.editing_filter = true,
+            DashboardKey::Escape => self.app_filter.clear(),
+            _ => {}
+        }
+        false
+    }
+
+    fn matches_filter(&self, app: &str) -> bool {
+        self.app_filter.is_empty() || app.to_lowercase().contains(&self.app_filter.to_lowercase())
+    }
+
+    // Build what the next frame shows. `now`, `paused` and `tap_disabled`
+    // come from the caller so snapshots are deterministic.
+    pub fn view(&self, now: DateTime<Local>, started_at: DateTime<Local>, paused: bool, tap_disabled: u64) -> DashboardView {
+        let mut processes: Vec<ProcessRow> = self
+            .processes
+            .values()
+            .filter(|row| self.matches_filter(&row.app))
+            .cloned()
+            .collect();
+        // Busiest first, then by name so equal rows do not jump around
+        processes.sort_by(|a, b| {
+            (b.rate_in + b.rate_out)
+                .total_cmp(&(a.rate_in + a.rate_out))
+                .then((b.bytes_in + b.bytes_out).cmp(&(a.bytes_in + a.bytes_out)))
+                .then(a.process.cmp(&b.process))
+        });
+
+        DashboardView {
+            ui_events: self
+                .ui_events
+                .iter()
+                .filter(|row| row.kind == "checkpoint" || self.matches_filter(&row.app))
+                .cloned()
+                .collect(),
+            processes,
+            correlations: self.correlations.iter().filter(|row| self.matches_filter(&row.app)).cloned().collect(),
+            health: Health {
+                uptime_secs: (now - started_at).num_seconds().max(0),
+                paused,
+                tap_disabled,
+                events_by_kind: self.events_by_kind.clone(),
+            },
+            app_filter: self.app_filter.clone(),
+            editing_filter: self.editing_filter,
+        }
+    }
+}
+
+// "1.2 KB/s" style formatting shared by the TUI panes
+pub fn format_rate(bytes_per_sec: f64) -> String {
+    format!("{}/s", format_bytes(bytes_per_sec))
+}
+
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::correlation::WindowTraffic;
+    use crate::events::tests::{at, click, correlation, key, sample};
+    use crate::events::Checkpoint;
+
+    fn type_filter(dashboard: &mut Dashboard, text: &str) {
+        dashboard.handle_key(DashboardKey::Char('/'));
+        for c in text.chars() {
+            dashboard.handle_key(DashboardKey::Char(c));
+        }
+        dashboard.handle_key(DashboardKey::Enter);
+    }
+
+    #[test]
+    fn processes_sort_busiest_first_with_rates_from_sample_gaps() {
+        let mut dashboard = Dashboard::default();
+        dashboard.handle_event(&sample(0, "Chat.42", 42, 0, 0));
+        dashboard.handle_event(&sample(2000, "Chat.42", 42, 4000, 1000));
+        dashboard.handle_event(&sample(0, "Mail.50", 50, 0, 0));
+        dashboard.handle_event(&sample(1000, "Mail.50", 50, 100, 0));
+        dashboard.handle_event(&sample(0, "Idle.60", 60, 0, 0));
+
+        let view = dashboard.view(at(60_000), at(0), false, 0);
+        let order: Vec<&str> = view.processes.iter().map(|row| row.process.as_str()).collect();
+        assert_eq!(order, ["Chat.42", "Mail.50", "Idle.60"]);
+        assert_eq!((view.processes[0].app.as_str(), view.processes[0].rate_in, view.processes[0].rate_out), ("Chat", 2000.0, 500.0));
+        assert_eq!(view.health.uptime_secs, 60);
+        assert_eq!(view.health.events_by_kind["network_sample"], 5);
+    }
+
+    #[test]
+    fn app_filter_is_typed_and_cleared_with_keys() {
+        let mut dashboard = Dashboard::default();
+        dashboard.handle_event(&WatcherEvent::Click(Box::new(click(0, 42, "Chat", "Send"))));
+        dashboard.handle_event(&key(10, 50, "Mail", 36));
+        dashboard.handle_event(&WatcherEvent::Checkpoint(Checkpoint { timestamp: at(20), label: "login".to_string() }));
+        dashboard.handle_event(&correlation(click(0, 42, "Chat", "Send"), 3000, WindowTraffic { pid: 42, bytes_in: 10, ..WindowTraffic::default() }));
+
+        type_filter(&mut dashboard, "CHA");
+        let view = dashboard.view(at(0), at(0), false, 0);
+        assert_eq!((view.app_filter.as_str(), view.editing_filter), ("CHA", false));
+        // Newest first; checkpoints are never filtered out
+        let kinds: Vec<&str> = view.ui_events.iter().map(|row| row.kind).collect();
+        assert_eq!(kinds, ["checkpoint", "click"]);
+        assert_eq!(view.correlations.len(), 1);
+
+        dashboard.handle_key(DashboardKey::Char('/'));
+        dashboard.handle_key(DashboardKey::Backspace);
+        assert!(dashboard.view(at(0), at(0), false, 0).editing_filter);
+        dashboard.handle_key(DashboardKey::Escape);
+        let view = dashboard.view(at(0), at(0), false, 0);
+        assert_eq!((view.app_filter.as_str(), view.ui_events.len()), ("", 3));
+    }
+
+    #[test]
+    fn q_quits_unless_typed_into_the_filter() {
+        let mut dashboard = Dashboard::default();
+        assert!(!dashboard.handle_key(DashboardKey::Char('/')));
+        assert!(!dashboard.handle_key(DashboardKey::Char('q')));
+        assert!(!dashboard.handle_key(DashboardKey::Enter));
+        assert!(dashboard.handle_key(DashboardKey::Char('q')));
+        assert!(dashboard.handle_key(DashboardKey::Quit));
+    }
+
+    #[test]
+    fn panes_keep_only_the_newest_rows() {
+        let mut dashboard = Dashboard::default();
+        for index in 0..(MAX_UI_EVENTS as i64 + 5) {
+            dashboard.handle_event(&key(index, 42, "Chat", index));
+        }
+        let view = dashboard.view(at(0), at(0), false, 0);
+        assert_eq!(view.ui_events.len(), MAX_UI_EVENTS);
+        assert_eq!(view.ui_events[0].description, format!("keycode {}", MAX_UI_EVENTS + 4));
+    }
+
+    #[test]
+    fn formats_bytes_with_binary_units() {
+        assert_eq!(format_bytes(512.0), "512 B");
+        assert_eq!(format_bytes(1536.0), "1.5 KB");
+        assert_eq!(format_rate(3.0 * 1024.0 * 1024.0), "3.0 MB/s");
+        assert_eq!(format_bytes(5.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0), "5120.0 GB");
+    }
+}
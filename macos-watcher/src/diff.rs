@@ -0,0 +1,519 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::dashboard::format_bytes;
+use crate::log_parse::{self, LogSession, LoggedAction, LoggedInput, LoggedOutcome};
+
+// Byte changes smaller than either of these are noise between two runs
+pub const DEFAULT_THRESHOLD_PERCENT: f64 = 20.0;
+pub const DEFAULT_MIN_BYTES: u64 = 1024;
+
+// Arguments for `macos-watcher diff`
+pub struct DiffArgs {
+    pub log_a: PathBuf,
+    pub log_b: PathBuf,
+    // Sessions to compare (default: the last one in each log)
+    pub session_a: Option<String>,
+    pub session_b: Option<String>,
+    pub threshold_percent: f64,
+    pub min_bytes: u64,
+    pub json: bool,
+    // None writes to stdout
+    pub output: Option<PathBuf>,
+}
+
+// Entry point for the diff subcommand. Like diff(1), returns 0 when the
+// sessions match, 1 when they differ and 2 on errors.
+pub fn run_cli(args: DiffArgs) -> i32 {
+    let read = |path: &PathBuf, session_id: &Option<String>| {
+        log_parse::read_log_file(path).and_then(|text| log_parse::read_session(&text, session_id.as_deref()))
+    };
+    let sessions = read(&args.log_a, &args.session_a).and_then(|a| Ok((a, read(&args.log_b, &args.session_b)?)));
+    let (a, b) = match sessions {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 2;
+        }
+    };
+
+    let diff = diff_sessions(&a, &b, args.threshold_percent, args.min_bytes);
+    let text = if args.json {
+        match serde_json::to_string_pretty(&diff) {
+            Ok(json) => json + "\n",
+            Err(e) => {
+                eprintln!("Error: Failed to encode the diff: {}", e);
+                return 2;
+            }
+        }
+    } else {
+        diff.format_text()
+    };
+    let written = match &args.output {
+        Some(path) => std::fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    };
+    match written {
+        Ok(()) if diff.entries.is_empty() => 0,
+        Ok(()) => 1,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            2
+        }
+    }
+}
+
+// Result of comparing session `a` (the known good run) with session `b`
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionDiff {
+    pub session_a: Option<String>,
+    pub session_b: Option<String>,
+    pub clicks_a: usize,
+    pub clicks_b: usize,
+    // Clicks found in both sessions
+    pub aligned: usize,
+    pub threshold_percent: f64,
+    pub min_bytes: u64,
+    // Only clicks that differ, in the order of the aligned sessions
+    pub entries: Vec<DiffEntry>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffStatus {
+    Changed,
+    OnlyInA,
+    OnlyInB,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DiffEntry {
+    pub status: DiffStatus,
+    pub a: Option<ClickRef>,
+    pub b: Option<ClickRef>,
+    pub changes: Vec<Change>,
+}
+
+// One click of either session and the traffic that followed it
+#[derive(Clone, Debug, Serialize)]
+pub struct ClickRef {
+    // 1-based position among the session's clicks
+    pub index: usize,
+    pub time: String,
+    pub app_name: String,
+    pub pid: i32,
+    // AxSelector::describe() of the clicked element
+    pub target: String,
+    pub bytes_out: Option<u64>,
+    pub bytes_in: Option<u64>,
+    pub hosts: Vec<String>,
+}
+
+// How the traffic after an aligned click changed from `a` to `b`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    NewHost { host: String },
+    MissingHost { host: String },
+    Bytes { direction: String, a: u64, b: u64 },
+    NewConnections { a: usize, b: usize },
+    // Requests seen by --capture, e.g. "api.example.com POST /v1/items"
+    Requests { request: String, a: usize, b: usize },
+    // The click has a 🔗 line in one log only
+    NotCorrelated { log: String },
+}
+
+impl Change {
+    fn describe(&self) -> String {
+        match self {
+            Change::NewHost { host } => format!("new host {}", host),
+            Change::MissingHost { host } => format!("missing host {}", host),
+            Change::Bytes { direction, a, b } => {
+                let arrow = if direction == "out" { "↑" } else { "↓" };
+                let percent = if *a > 0 { format!(" ({:+.0}%)", (*b as f64 - *a as f64) / *a as f64 * 100.0) } else { String::new() };
+                format!("{} {} → {}{}", arrow, format_bytes(*a as f64), format_bytes(*b as f64), percent)
+            }
+            Change::NewConnections { a, b } => format!("new connections {} → {}", a, b),
+            Change::Requests { request, a, b: 0 } => format!("missing request {} (was {}x)", request, a),
+            Change::Requests { request, a: 0, b } => format!("new request {} ({}x)", request, b),
+            Change::Requests { request, a, b } => format!("request {} {}x → {}x", request, a, b),
+            Change::NotCorrelated { log } => format!("no traffic logged in {}", log),
+        }
+    }
+}
+
+// Clicks are aligned by app and AX selector, keeping their order (a longest
+// common subsequence), so an extra or missing click does not shift every
+// pair after it. Key presses carry no traffic and are left out.
+pub fn diff_sessions(a: &LogSession, b: &LogSession, threshold_percent: f64, min_bytes: u64) -> SessionDiff {
+    let clicks_a = clicks(a);
+    let clicks_b = clicks(b);
+    let keys_a: Vec<(&str, String)> = clicks_a.iter().map(|action| align_key(action)).collect();
+    let keys_b: Vec<(&str, String)> = clicks_b.iter().map(|action| align_key(action)).collect();
+
+    // common[i][j]: length of the common subsequence of keys_a[i..] and keys_b[j..]
+    let mut common = vec![vec![0usize; keys_b.len() + 1]; keys_a.len() + 1];
+    for i in (0..keys_a.len()).rev() {
+        for j in (0..keys_b.len()).rev() {
+            common[i][j] = if keys_a[i] == keys_b[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut aligned = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < clicks_a.len() || j < clicks_b.len() {
+        if i < clicks_a.len() && j < clicks_b.len() && keys_a[i] == keys_b[j] {
+            aligned += 1;
+            let changes = compare(clicks_a[i], clicks_b[j], threshold_percent, min_bytes);
+            if !changes.is_empty() {
+                entries.push(DiffEntry {
+                    status: DiffStatus::Changed,
+                    a: Some(click_ref(i, clicks_a[i])),
+                    b: Some(click_ref(j, clicks_b[j])),
+                    changes,
+                });
+            }
+            i += 1;
+            j += 1;
+        } else if j == clicks_b.len() || (i < clicks_a.len() && common[i + 1][j] >= common[i][j + 1]) {
+            entries.push(DiffEntry { status: DiffStatus::OnlyInA, a: Some(click_ref(i, clicks_a[i])), b: None, changes: Vec::new() });
+            i += 1;
+        } else {
+            entries.push(DiffEntry { status: DiffStatus::OnlyInB, a: None, b: Some(click_ref(j, clicks_b[j])), changes: Vec::new() });
+            j += 1;
+        }
+    }
+
+    SessionDiff {
+        session_a: a.id.clone(),
+        session_b: b.id.clone(),
+        clicks_a: clicks_a.len(),
+        clicks_b: clicks_b.len(),
+        aligned,
+        threshold_percent,
+        min_bytes,
+        entries,
+    }
+}
+
+fn clicks(session: &LogSession) -> Vec<&LoggedAction> {
+    session.actions.iter().filter(|action| matches!(action.input, LoggedInput::Click(_))).collect()
+}
+
+fn align_key(action: &LoggedAction) -> (&str, String) {
+    (action.app_name.as_str(), action.selector().map(|selector| selector.describe()).unwrap_or_default())
+}
+
+fn click_ref(index: usize, action: &LoggedAction) -> ClickRef {
+    let outcome = action.outcome.as_ref();
+    ClickRef {
+        index: index + 1,
+        time: action.time.to_rfc3339(),
+        app_name: action.app_name.clone(),
+        pid: action.pid,
+        target: align_key(action).1,
+        bytes_out: outcome.map(|outcome| outcome.correlation.bytes_out),
+        bytes_in: outcome.map(|outcome| outcome.correlation.bytes_in),
+        hosts: outcome.map(hosts).unwrap_or_default(),
+    }
+}
+
+// Remote hosts of the 🔗 line and of the flows below it, names preferred
+fn hosts(outcome: &LoggedOutcome) -> Vec<String> {
+    let mut hosts: Vec<String> = outcome.correlation.remotes.clone();
+    for flow in &outcome.flows {
+        let host = flow.hostname.clone().unwrap_or_else(|| strip_port(&flow.remote).to_string());
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    hosts
+}
+
+// "93.184.216.34:443" or "[2606:4700::1]:443"
+fn strip_port(remote: &str) -> &str {
+    remote.rsplit_once(':').map_or(remote, |(host, _)| host.trim_matches(['[', ']']))
+}
+
+// Flows keyed by what they asked for; the local port changes every run
+fn requests(outcome: &LoggedOutcome) -> BTreeMap<String, usize> {
+    let mut requests = BTreeMap::new();
+    for flow in &outcome.flows {
+        let request = flow.summary.clone().unwrap_or_else(|| {
+            format!("{} {}", flow.protocol, flow.hostname.as_deref().unwrap_or_else(|| strip_port(&flow.remote)))
+        });
+        *requests.entry(request).or_default() += 1;
+    }
+    requests
+}
+
+// A byte change counts once it exceeds both the absolute and the relative threshold
+fn bytes_changed(a: u64, b: u64, threshold_percent: f64, min_bytes: u64) -> bool {
+    let change = a.abs_diff(b);
+    change > 0 && change >= min_bytes && (a == 0 || change as f64 / a as f64 * 100.0 >= threshold_percent)
+}
+
+fn compare(a: &LoggedAction, b: &LoggedAction, threshold_percent: f64, min_bytes: u64) -> Vec<Change> {
+    let (a, b) = match (&a.outcome, &b.outcome) {
+        (Some(a), Some(b)) => (a, b),
+        (Some(_), None) => return vec![Change::NotCorrelated { log: "b".to_string() }],
+        (None, Some(_)) => return vec![Change::NotCorrelated { log: "a".to_string() }],
+        (None, None) => return Vec::new(),
+    };
+
+    let mut changes = Vec::new();
+    let (hosts_a, hosts_b) = (hosts(a), hosts(b));
+    for host in hosts_b.iter().filter(|host| !hosts_a.contains(host)) {
+        changes.push(Change::NewHost { host: host.clone() });
+    }
+    for host in hosts_a.iter().filter(|host| !hosts_b.contains(host)) {
+        changes.push(Change::MissingHost { host: host.clone() });
+    }
+
+    let (a_traffic, b_traffic) = (&a.correlation, &b.correlation);
+    for (direction, bytes_a, bytes_b) in [("out", a_traffic.bytes_out, b_traffic.bytes_out), ("in", a_traffic.bytes_in, b_traffic.bytes_in)] {
+        if bytes_changed(bytes_a, bytes_b, threshold_percent, min_bytes) {
+            changes.push(Change::Bytes { direction: direction.to_string(), a: bytes_a, b: bytes_b });
+        }
+    }
+    if a_traffic.new_connections != b_traffic.new_connections {
+        changes.push(Change::NewConnections { a: a_traffic.new_connections, b: b_traffic.new_connections });
+    }
+
+    let (requests_a, requests_b) = (requests(a), requests(b));
+    let mut names: Vec<&String> = requests_a.keys().chain(requests_b.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let (count_a, count_b) = (requests_a.get(name).copied().unwrap_or(0), requests_b.get(name).copied().unwrap_or(0));
+        if count_a != count_b {
+            changes.push(Change::Requests { request: name.clone(), a: count_a, b: count_b });
+        }
+    }
+    changes
+}
+
+impl SessionDiff {
+    // Human readable form: one block per differing click
+    pub fn format_text(&self) -> String {
+        let mut text = String::new();
+        let session = |id: &Option<String>| id.as_deref().map_or("the last session".to_string(), |id| format!("session {}", id));
+        let _ = writeln!(text, "a: {} ({} click(s))", session(&self.session_a), self.clicks_a);
+        let _ = writeln!(text, "b: {} ({} click(s))", session(&self.session_b), self.clicks_b);
+        let count = |status: fn(&DiffStatus) -> bool| self.entries.iter().filter(|entry| status(&entry.status)).count();
+        let _ = writeln!(
+            text,
+            "Aligned {} click(s): {} changed, {} only in a, {} only in b (bytes threshold {}% and {})",
+            self.aligned,
+            count(|status| matches!(status, DiffStatus::Changed)),
+            count(|status| matches!(status, DiffStatus::OnlyInA)),
+            count(|status| matches!(status, DiffStatus::OnlyInB)),
+            self.threshold_percent,
+            format_bytes(self.min_bytes as f64)
+        );
+        if self.entries.is_empty() {
+            let _ = writeln!(text, "No differences.");
+        }
+
+        for entry in &self.entries {
+            text.push('\n');
+            match (&entry.a, &entry.b) {
+                (Some(a), Some(b)) => {
+                    let _ = writeln!(text, "~ a#{} ↔ b#{} {} in '{}'", a.index, b.index, a.target, a.app_name);
+                    for change in &entry.changes {
+                        let _ = writeln!(text, "    {}", change.describe());
+                    }
+                }
+                (Some(click), None) => {
+                    let _ = writeln!(text, "- a#{} {} in '{}' (only in a){}", click.index, click.target, click.app_name, traffic(click));
+                }
+                (None, Some(click)) => {
+                    let _ = writeln!(text, "+ b#{} {} in '{}' (only in b){}", click.index, click.target, click.app_name, traffic(click));
+                }
+                (None, None) => {}
+            }
+        }
+        text
+    }
+}
+
+// ", ↑ 6.0 KB ↓ 1.4 KB [api.example.com]" or nothing for an uncorrelated click
+fn traffic(click: &ClickRef) -> String {
+    let (Some(bytes_out), Some(bytes_in)) = (click.bytes_out, click.bytes_in) else { return String::new() };
+    let hosts = if click.hosts.is_empty() { String::new() } else { format!(" [{}]", click.hosts.join(", ")) };
+    format!(", ↑ {} ↓ {}{}", format_bytes(bytes_out as f64), format_bytes(bytes_in as f64), hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(second: u32, id: &str) -> String {
+        format!("2026-10-18T09:15:{:02}Z [INFO] Button Clicked: App='Mail' (PID=812), ID='{}', Label='{}', LabelSource='title', Pos=(1.0, 2.0)\n", second, id, id)
+    }
+
+    fn correlated(second: u32, id: &str, bytes_out: u64, bytes_in: u64, new_connections: usize, remotes: &str) -> String {
+        format!(
+            "2026-10-18T09:15:{:02}Z [INFO] 🔗 #{} in 'Mail' (PID=812) → ↑ {} B ↓ {} B, {} new connection(s) within 2.0s{}\n",
+            second + 2,
+            id,
+            bytes_out,
+            bytes_in,
+            new_connections,
+            if remotes.is_empty() { String::new() } else { format!(" [{}]", remotes) }
+        )
+    }
+
+    fn flow(second: u32, port: u16, peer: &str, summary: &str) -> String {
+        let summary = if summary.is_empty() { String::new() } else { format!(": {}", summary) };
+        format!("2026-10-18T09:15:{:02}Z [INFO]    ⏱ tcp 10.0.0.5:{} → {} first byte +5 ms, last byte +80 ms, ↑ 900 B ↓ 300 B{}\n", second + 2, port, peer, summary)
+    }
+
+    // The known good run: send, archive, refresh
+    fn good() -> LogSession {
+        let log = [
+            click(0, "send"),
+            correlated(0, "send", 6000, 1400, 1, "smtp.example.com"),
+            flow(0, 52000, "smtp.example.com (192.0.2.1:465)", "smtp.example.com (TLS)"),
+            click(10, "archive"),
+            correlated(10, "archive", 100, 100, 0, ""),
+            click(20, "refresh"),
+            correlated(20, "refresh", 0, 0, 0, ""),
+        ];
+        log_parse::read_session(&log.concat(), None).unwrap()
+    }
+
+    // Sends more to one more host, composes instead of archiving and the
+    // refresh click's window never closed
+    fn regressed() -> LogSession {
+        let log = [
+            click(0, "send"),
+            correlated(0, "send", 9000, 1450, 2, "smtp.example.com, tracker.example.net"),
+            flow(0, 53000, "smtp.example.com (192.0.2.1:465)", "smtp.example.com (TLS)"),
+            flow(0, 53001, "smtp.example.com (192.0.2.1:465)", "smtp.example.com (TLS)"),
+            flow(0, 53002, "[2001:db8::9]:443", ""),
+            click(10, "compose"),
+            correlated(10, "compose", 10, 0, 0, ""),
+            click(20, "refresh"),
+        ];
+        log_parse::read_session(&log.concat(), None).unwrap()
+    }
+
+    #[test]
+    fn clicks_are_aligned_and_their_traffic_compared() {
+        let diff = diff_sessions(&good(), &regressed(), DEFAULT_THRESHOLD_PERCENT, DEFAULT_MIN_BYTES);
+        assert_eq!((diff.clicks_a, diff.clicks_b, diff.aligned), (3, 3, 2));
+
+        let entries: Vec<(String, Option<usize>, Option<usize>)> = diff
+            .entries
+            .iter()
+            .map(|entry| (format!("{:?}", entry.status), entry.a.as_ref().map(|a| a.index), entry.b.as_ref().map(|b| b.index)))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("Changed".to_string(), Some(1), Some(1)),
+                ("OnlyInA".to_string(), Some(2), None),
+                ("OnlyInB".to_string(), None, Some(2)),
+                ("Changed".to_string(), Some(3), Some(3)),
+            ]
+        );
+        assert_eq!(
+            diff.entries[0].changes,
+            [
+                Change::NewHost { host: "tracker.example.net".to_string() },
+                Change::NewHost { host: "2001:db8::9".to_string() },
+                Change::Bytes { direction: "out".to_string(), a: 6000, b: 9000 },
+                Change::NewConnections { a: 1, b: 2 },
+                Change::Requests { request: "smtp.example.com (TLS)".to_string(), a: 1, b: 2 },
+                Change::Requests { request: "tcp 2001:db8::9".to_string(), a: 0, b: 1 },
+            ]
+        );
+        assert_eq!(diff.entries[3].changes, [Change::NotCorrelated { log: "b".to_string() }]);
+    }
+
+    #[test]
+    fn text_output_has_a_block_per_difference() {
+        let text = diff_sessions(&good(), &regressed(), DEFAULT_THRESHOLD_PERCENT, DEFAULT_MIN_BYTES).format_text();
+        assert_eq!(
+            text,
+            "\
+a: the last session (3 click(s))
+b: the last session (3 click(s))
+Aligned 2 click(s): 2 changed, 1 only in a, 1 only in b (bytes threshold 20% and 1.0 KB)
+
+~ a#1 ↔ b#1 #send in 'Mail'
+    new host tracker.example.net
+    new host 2001:db8::9
+    ↑ 5.9 KB → 8.8 KB (+50%)
+    new connections 1 → 2
+    request smtp.example.com (TLS) 1x → 2x
+    new request tcp 2001:db8::9 (1x)
+
+- a#2 #archive in 'Mail' (only in a), ↑ 100 B ↓ 100 B
+
++ b#2 #compose in 'Mail' (only in b), ↑ 10 B ↓ 0 B
+
+~ a#3 ↔ b#3 #refresh in 'Mail'
+    no traffic logged in b
+"
+        );
+        let same = diff_sessions(&good(), &good(), DEFAULT_THRESHOLD_PERCENT, DEFAULT_MIN_BYTES);
+        assert!(same.entries.is_empty());
+        assert!(same.format_text().ends_with("No differences.\n"));
+    }
+
+    #[test]
+    fn byte_changes_must_pass_both_thresholds() {
+        assert!(!bytes_changed(10_000, 10_000, 20.0, 1024));
+        assert!(!bytes_changed(10_000, 11_000, 20.0, 1024));
+        assert!(!bytes_changed(100, 1000, 20.0, 1024));
+        assert!(bytes_changed(10_000, 12_000, 20.0, 1024));
+        assert!(bytes_changed(12_000, 10_000, 10.0, 1024));
+        assert!(bytes_changed(0, 2048, 20.0, 1024));
+        assert!(bytes_changed(0, 1, 20.0, 0));
+    }
+
+    #[test]
+    fn the_cli_exits_like_diff() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| dir.join(format!("macos-watcher-diff-{}-{}", std::process::id(), name));
+        let good_log = [click(0, "send"), correlated(0, "send", 6000, 1400, 1, "smtp.example.com")].concat();
+        let bad_log = [click(0, "send"), correlated(0, "send", 6000, 1400, 1, "tracker.example.net")].concat();
+        std::fs::write(path("a.log"), &good_log).unwrap();
+        std::fs::write(path("b.log"), &bad_log).unwrap();
+        let args = |log_b: &str, json: bool| DiffArgs {
+            log_a: path("a.log"),
+            log_b: path(log_b),
+            session_a: None,
+            session_b: None,
+            threshold_percent: DEFAULT_THRESHOLD_PERCENT,
+            min_bytes: DEFAULT_MIN_BYTES,
+            json,
+            output: Some(path("out")),
+        };
+
+        assert_eq!(run_cli(args("a.log", false)), 0);
+        assert_eq!(run_cli(args("b.log", true)), 1);
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path("out")).unwrap()).unwrap();
+        assert_eq!(run_cli(args("missing.log", false)), 2);
+        for name in ["a.log", "b.log", "out"] {
+            std::fs::remove_file(path(name)).unwrap();
+        }
+
+        assert_eq!(json["entries"][0]["status"], "changed");
+        assert_eq!(
+            json["entries"][0]["changes"],
+            serde_json::json!([
+                { "kind": "new_host", "host": "tracker.example.net" },
+                { "kind": "missing_host", "host": "smtp.example.com" },
+            ])
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+#[cfg(target_os = "macos")]
+use std::os::raw::c_void;
+
+use crate::geometry::{CGPoint, CGRect};
+
+// Type Aliases
+pub type CGDirectDisplayID = u32;
+#[cfg(target_os = "macos")]
+pub type CGDisplayModeRef = *mut c_void; // Opaque pointer
+#[cfg(target_os = "macos")]
+pub type CGError = i32;
+
+// Upper bound for CGGetActiveDisplayList; nobody has more screens than this
+#[cfg(target_os = "macos")]
+const MAX_DISPLAYS: usize = 32;
+
+#[cfg(target_os = "macos")]
+#[link(name = "CoreGraphics", kind = "framework")]
+#[allow(non_snake_case)] // To allow function names like CGGetActiveDisplayList
+unsafe extern "C" {
+    pub fn CGGetActiveDisplayList(
+        maxDisplays: u32,
+        activeDisplays: *mut CGDirectDisplayID,
+        displayCount: *mut u32,
+    ) -> CGError;
+
+    pub fn CGDisplayBounds(display: CGDirectDisplayID) -> CGRect;
+    pub fn CGDisplayCopyDisplayMode(display: CGDirectDisplayID) -> CGDisplayModeRef;
+    pub fn CGDisplayModeGetWidth(mode: CGDisplayModeRef) -> usize;
+    pub fn CGDisplayModeGetPixelWidth(mode: CGDisplayModeRef) -> usize;
+    pub fn CGDisplayModeRelease(mode: CGDisplayModeRef);
+}
+
+// One active display in global coordinates (points, origin at the top left of the main display)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Display {
+    pub id: CGDirectDisplayID,
+    pub bounds: CGRect,
+    // Backing pixels per point, 2.0 on Retina displays
+    pub scale: f64,
+}
+
+// A global point mapped onto the display it falls on
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DisplayPoint {
+    pub display_id: CGDirectDisplayID,
+    // Relative to the display's top left corner, in points
+    pub local: CGPoint,
+    // Same position in backing pixels
+    pub backing: CGPoint,
+    pub scale: f64,
+}
+
+// Arrangement of all active displays. The mapping math is plain Rust so it
+// works the same for the live layout and for layouts built by hand.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DisplayLayout {
+    pub displays: Vec<Display>,
+}
+
+impl DisplayLayout {
+    // Query the active displays from CoreGraphics
+    #[cfg(target_os = "macos")]
+    pub fn current() -> Self {
+        let mut ids = [0 as CGDirectDisplayID; MAX_DISPLAYS];
+        let mut count: u32 = 0;
+        let result = unsafe { CGGetActiveDisplayList(MAX_DISPLAYS as u32, ids.as_mut_ptr(), &mut count) };
+        if result != 0 { // kCGErrorSuccess is 0
+            log::debug!("CGGetActiveDisplayList failed. CGError={}", result);
+            return DisplayLayout::default();
+        }
+
+        let displays = ids[..count as usize]
+            .iter()
+            .map(|&id| Display {
+                id,
+                bounds: unsafe { CGDisplayBounds(id) },
+                scale: unsafe { display_scale(id) },
+            })
+            .collect();
+        DisplayLayout { displays }
+    }
+
+    // Map a global point (as returned by CGEventGetLocation) onto its display.
+    // Points in the gaps between displays are clamped onto the edge of the
+    // nearest one, which is where macOS would put the cursor anyway.
+    pub fn locate(&self, point: CGPoint) -> Option<DisplayPoint> {
+        let display = self
+            .displays
+            .iter()
+            .find(|display| display.bounds.contains(point))
+            .or_else(|| {
+                self.displays.iter().min_by(|a, b| {
+                    distance_to_rect(&a.bounds, point).total_cmp(&distance_to_rect(&b.bounds, point))
+                })
+            })?;
+
+        let local = display.bounds.relative_point(display.bounds.clamp(point));
+        Some(DisplayPoint {
+            display_id: display.id,
+            local,
+            backing: CGPoint { x: local.x * display.scale, y: local.y * display.scale },
+            scale: display.scale,
+        })
+    }
+}
+
+fn distance_to_rect(rect: &CGRect, point: CGPoint) -> f64 {
+    let dx = (rect.origin.x - point.x).max(point.x - (rect.origin.x + rect.size.width)).max(0.0);
+    let dy = (rect.origin.y - point.y).max(point.y - (rect.origin.y + rect.size.height)).max(0.0);
+    (dx * dx + dy * dy).sqrt()
+}
+
+// Pixel width of the current mode divided by its width in points
+#[cfg(target_os = "macos")]
+unsafe fn display_scale(display_id: CGDirectDisplayID) -> f64 {
+    let mode = unsafe { CGDisplayCopyDisplayMode(display_id) };
+    if mode.is_null() {
+        return 1.0;
+    }
+    let points = unsafe { CGDisplayModeGetWidth(mode) };
+    let pixels = unsafe { CGDisplayModeGetPixelWidth(mode) };
+    unsafe { CGDisplayModeRelease(mode) };
+
+    if points == 0 { 1.0 } else { pixels as f64 / points as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::CGSize;
+
+    fn display(id: CGDirectDisplayID, x: f64, y: f64, width: f64, height: f64, scale: f64) -> Display {
+        Display { id, bounds: CGRect { origin: CGPoint { x, y }, size: CGSize { width, height } }, scale }
+    }
+
+    // A Retina main display with a 1x display to its right, 200 points lower
+    fn layout() -> DisplayLayout {
+        DisplayLayout { displays: vec![display(1, 0.0, 0.0, 1440.0, 900.0, 2.0), display(2, 1440.0, 200.0, 1920.0, 1080.0, 1.0)] }
+    }
+
+    #[test]
+    fn maps_points_onto_the_display_they_fall_on() {
+        let main = layout().locate(CGPoint { x: 100.0, y: 50.0 }).unwrap();
+        assert_eq!(main.display_id, 1);
+        assert_eq!(main.local, CGPoint { x: 100.0, y: 50.0 });
+        assert_eq!(main.backing, CGPoint { x: 200.0, y: 100.0 });
+        assert_eq!(main.scale, 2.0);
+
+        let secondary = layout().locate(CGPoint { x: 1500.0, y: 300.0 }).unwrap();
+        assert_eq!(secondary.display_id, 2);
+        assert_eq!(secondary.local, CGPoint { x: 60.0, y: 100.0 });
+        assert_eq!(secondary.backing, CGPoint { x: 60.0, y: 100.0 });
+    }
+
+    #[test]
+    fn negative_global_coordinates_are_local_to_their_display() {
+        let layout = DisplayLayout { displays: vec![display(1, 0.0, 0.0, 1440.0, 900.0, 2.0), display(3, -1280.0, -100.0, 1280.0, 1024.0, 1.0)] };
+        let left = layout.locate(CGPoint { x: -1.0, y: -50.0 }).unwrap();
+        assert_eq!(left.display_id, 3);
+        assert_eq!(left.local, CGPoint { x: 1279.0, y: 50.0 });
+    }
+
+    #[test]
+    fn points_in_gaps_are_clamped_onto_the_nearest_display() {
+        // Just above the top of the secondary display, further from the main one
+        let clamped = layout().locate(CGPoint { x: 1600.0, y: 190.0 }).unwrap();
+        assert_eq!(clamped.display_id, 2);
+        assert_eq!(clamped.local, CGPoint { x: 160.0, y: 0.0 });
+
+        // Below the main display: onto its last row, which is still on it
+        let clamped = layout().locate(CGPoint { x: -20.0, y: 950.0 }).unwrap();
+        assert_eq!(clamped.display_id, 1);
+        assert_eq!(clamped.local, CGPoint { x: 0.0, y: 899.0 });
+        assert_eq!(clamped.backing, CGPoint { x: 0.0, y: 1798.0 });
+
+        // Right of the secondary display
+        let clamped = layout().locate(CGPoint { x: 3400.0, y: 1300.0 }).unwrap();
+        assert_eq!(clamped.display_id, 2);
+        assert_eq!(clamped.local, CGPoint { x: 1919.0, y: 1079.0 });
+        let display = &layout().displays[1];
+        assert!(display.bounds.contains(display.bounds.clamp(CGPoint { x: 3400.0, y: 1300.0 })));
+    }
+
+    #[test]
+    fn no_displays_means_no_location() {
+        assert_eq!(DisplayLayout::default().locate(CGPoint { x: 0.0, y: 0.0 }), None);
+    }
+}
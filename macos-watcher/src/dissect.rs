@@ -0,0 +1,579 @@
+use serde::{Deserialize, Serialize};
+
+use crate::dns;
+
+// Stop after this many requests per flow; long-lived keep-alive connections
+// would otherwise grow without bound
+const MAX_HTTP_REQUESTS: usize = 32;
+const MAX_DNS_QUERIES: usize = 32;
+
+const TLS_CONTENT_HANDSHAKE: u8 = 0x16;
+const TLS_HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const TLS_EXTENSION_SERVER_NAME: u16 = 0x0000;
+const TLS_EXTENSION_ALPN: u16 = 0x0010;
+
+const HTTP_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "CONNECT", "TRACE"];
+
+// What a TLS ClientHello gives away in the clear
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DnsQuery {
+    pub name: String,
+    pub record_type: String,
+}
+
+// Application level fields extracted from one flow
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FlowDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_server_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tls_alpn: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http_requests: Vec<HttpRequest>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_queries: Vec<DnsQuery>,
+}
+
+impl FlowDetails {
+    pub fn is_empty(&self) -> bool {
+        *self == FlowDetails::default()
+    }
+
+    // The name the app used for the server, if the traffic shows it
+    pub fn server_name(&self) -> Option<&str> {
+        self.tls_server_name
+            .as_deref()
+            .or_else(|| self.http_requests.iter().find_map(|request| request.host.as_deref()))
+    }
+
+    // Short description for log lines: "api.example.com POST /v1/items",
+    // "api.example.com (TLS, h2)" or "DNS A example.com"
+    pub fn summary(&self) -> Option<String> {
+        if let Some(request) = self.http_requests.first() {
+            let more = if self.http_requests.len() > 1 { format!(" (+{} more)", self.http_requests.len() - 1) } else { String::new() };
+            return Some(match &request.host {
+                Some(host) => format!("{} {} {}{}", host, request.method, request.path, more),
+                None => format!("{} {}{}", request.method, request.path, more),
+            });
+        }
+        if let Some(server_name) = &self.tls_server_name {
+            return Some(match self.tls_alpn.first() {
+                Some(protocol) => format!("{} (TLS, {})", server_name, protocol),
+                None => format!("{} (TLS)", server_name),
+            });
+        }
+        self.dns_queries.first().map(|query| format!("DNS {} {}", query.record_type, query.name))
+    }
+}
+
+// Run every stream dissector over the first bytes the client sent
+pub fn dissect_stream(client_data: &[u8]) -> FlowDetails {
+    let mut details = FlowDetails::default();
+    if let Some(hello) = parse_client_hello(client_data) {
+        details.tls_server_name = hello.server_name;
+        details.tls_alpn = hello.alpn;
+    } else {
+        details.http_requests = parse_http_requests(client_data);
+    }
+    details
+}
+
+// Bounds-checked big-endian reads; every dissector gets untrusted bytes
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let bytes = self.take(3)?;
+        Some(((bytes[0] as usize) << 16) | ((bytes[1] as usize) << 8) | bytes[2] as usize)
+    }
+
+    // A vector with a one or two byte length prefix
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+// The ClientHello at the start of a TLS stream. It may be split over several
+// records; those are joined before parsing. None if the stream is not TLS
+// or the hello is incomplete.
+pub fn parse_client_hello(data: &[u8]) -> Option<ClientHello> {
+    let mut records = Cursor { bytes: data };
+    let mut handshake = Vec::new();
+    loop {
+        let content_type = records.u8()?;
+        let version = records.u16()?;
+        if content_type != TLS_CONTENT_HANDSHAKE || version >> 8 != 0x03 {
+            return None;
+        }
+        handshake.extend_from_slice(records.vec16()?);
+        // Handshake header: type (1) and length (3)
+        if handshake.len() >= 4 {
+            if handshake[0] != TLS_HANDSHAKE_CLIENT_HELLO {
+                return None;
+            }
+            let len = Cursor { bytes: &handshake[1..4] }.u24()?;
+            if handshake.len() >= 4 + len {
+                return parse_client_hello_body(&handshake[4..4 + len]);
+            }
+        }
+    }
+}
+
+fn parse_client_hello_body(body: &[u8]) -> Option<ClientHello> {
+    let mut hello = Cursor { bytes: body };
+    hello.take(2)?; // legacy version
+    hello.take(32)?; // random
+    hello.vec8()?; // session id
+    hello.vec16()?; // cipher suites
+    hello.vec8()?; // compression methods
+
+    let mut result = ClientHello::default();
+    // Extensions are optional in TLS 1.0-1.2
+    let Some(extensions) = hello.vec16() else { return Some(result) };
+    let mut extensions = Cursor { bytes: extensions };
+    while let (Some(extension_type), Some(data)) = (extensions.u16(), extensions.vec16()) {
+        match extension_type {
+            TLS_EXTENSION_SERVER_NAME => result.server_name = parse_server_name(data),
+            TLS_EXTENSION_ALPN => result.alpn = parse_alpn(data).unwrap_or_default(),
+            _ => {}
+        }
+    }
+    Some(result)
+}
+
+fn parse_server_name(data: &[u8]) -> Option<String> {
+    let mut list = Cursor { bytes: Cursor { bytes: data }.vec16()? };
+    while let Some(name_type) = list.u8() {
+        let name = list.vec16()?;
+        // 0 is host_name, the only type ever defined
+        if name_type == 0 {
+            return std::str::from_utf8(name).ok().map(|name| name.trim_end_matches('.').to_ascii_lowercase());
+        }
+    }
+    None
+}
+
+fn parse_alpn(data: &[u8]) -> Option<Vec<String>> {
+    let mut list = Cursor { bytes: Cursor { bytes: data }.vec16()? };
+    let mut protocols = Vec::new();
+    while let Some(protocol) = list.vec8() {
+        protocols.push(String::from_utf8_lossy(protocol).into_owned());
+    }
+    Some(protocols)
+}
+
+// HTTP/1.x requests at the start of a plaintext stream, following
+// keep-alive requests as long as bodies have a Content-Length
+pub fn parse_http_requests(data: &[u8]) -> Vec<HttpRequest> {
+    let mut requests = Vec::new();
+    let mut rest = data;
+    while requests.len() < MAX_HTTP_REQUESTS {
+        let Some((request, body_len, header_end)) = parse_http_head(rest) else { break };
+        requests.push(request);
+        match body_len.and_then(|len| header_end.checked_add(len)) {
+            Some(next) if next < rest.len() => rest = &rest[next..],
+            _ => break,
+        }
+    }
+    requests
+}
+
+// One request head: the request, its Content-Length (None when the body
+// length is unknown, e.g. chunked) and where the head ends
+fn parse_http_head(data: &[u8]) -> Option<(HttpRequest, Option<usize>, usize)> {
+    let header_end = find(data, b"\r\n\r\n")? + 4;
+    let head = std::str::from_utf8(&data[..header_end]).ok()?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?;
+    let path = request_line.next()?;
+    let version = request_line.next()?;
+    if !HTTP_METHODS.contains(&method) || !version.starts_with("HTTP/1.") || request_line.next().is_some() {
+        return None;
+    }
+
+    let mut host = None;
+    let mut content_length = Some(0);
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("host") {
+            host = Some(value.to_ascii_lowercase());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().ok();
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            content_length = None;
+        }
+    }
+    let request = HttpRequest { method: method.to_string(), path: path.to_string(), host };
+    Some((request, content_length, header_end))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// Questions of a DNS query sent over UDP
+pub fn parse_dns_queries(payload: &[u8]) -> Vec<DnsQuery> {
+    match dns::parse_message(payload) {
+        Ok(message) if !message.is_response => message
+            .questions
+            .into_iter()
+            .map(|question| DnsQuery { name: question.name, record_type: record_type_name(question.record_type) })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// DNS over TCP frames every message with a two byte length
+pub fn parse_dns_stream(data: &[u8]) -> Vec<DnsQuery> {
+    let mut messages = Cursor { bytes: data };
+    let mut queries = Vec::new();
+    while let Some(message) = messages.vec16() {
+        queries.extend(parse_dns_queries(message));
+        if queries.len() >= MAX_DNS_QUERIES {
+            break;
+        }
+    }
+    queries.truncate(MAX_DNS_QUERIES);
+    queries
+}
+
+fn record_type_name(record_type: u16) -> String {
+    match record_type {
+        dns::TYPE_A => "A".to_string(),
+        dns::TYPE_CNAME => "CNAME".to_string(),
+        dns::TYPE_PTR => "PTR".to_string(),
+        dns::TYPE_AAAA => "AAAA".to_string(),
+        dns::TYPE_HTTPS => "HTTPS".to_string(),
+        other => format!("TYPE{}", other),
+    }
+}
+
+// Add the queries of one UDP datagram, up to MAX_DNS_QUERIES per flow
+pub fn add_dns_queries(details: &mut Vec<DnsQuery>, payload: &[u8]) {
+    if details.len() >= MAX_DNS_QUERIES {
+        return;
+    }
+    details.extend(parse_dns_queries(payload));
+    details.truncate(MAX_DNS_QUERIES);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flows::{self, OfflineCapture, TrafficSource};
+    use crate::pcap::LINKTYPE_ETHERNET;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::path::Path;
+
+    fn vec16(data: &[u8]) -> Vec<u8> {
+        let mut vector = (data.len() as u16).to_be_bytes().to_vec();
+        vector.extend_from_slice(data);
+        vector
+    }
+
+    fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut extension = extension_type.to_be_bytes().to_vec();
+        extension.extend(vec16(data));
+        extension
+    }
+
+    // A ClientHello handshake message (without the record layer)
+    fn client_hello(server_name: Option<&str>, alpn: &[&str]) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x42; 32]);
+        body.push(0); // session id
+        body.extend(vec16(&[0x13, 0x01]));
+        body.extend_from_slice(&[1, 0]);
+
+        let mut extensions = extension(0x002B, &[2, 0x03, 0x04]);
+        if let Some(name) = server_name {
+            let mut entry = vec![0];
+            entry.extend(vec16(name.as_bytes()));
+            extensions.extend(extension(TLS_EXTENSION_SERVER_NAME, &vec16(&entry)));
+        }
+        if !alpn.is_empty() {
+            let mut list = Vec::new();
+            for protocol in alpn {
+                list.push(protocol.len() as u8);
+                list.extend_from_slice(protocol.as_bytes());
+            }
+            extensions.extend(extension(TLS_EXTENSION_ALPN, &vec16(&list)));
+        }
+        body.extend(vec16(&extensions));
+
+        let mut handshake = vec![TLS_HANDSHAKE_CLIENT_HELLO];
+        handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend(body);
+        handshake
+    }
+
+    // `handshake` split into TLS records of at most `chunk` bytes
+    fn records(handshake: &[u8], chunk: usize) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for fragment in handshake.chunks(chunk) {
+            stream.extend_from_slice(&[TLS_CONTENT_HANDSHAKE, 0x03, 0x01]);
+            stream.extend(vec16(fragment));
+        }
+        stream
+    }
+
+    #[test]
+    fn client_hellos_give_the_server_name_and_alpn() {
+        let stream = records(&client_hello(Some("API.Example.com."), &["h2", "http/1.1"]), 1 << 14);
+        let expected = ClientHello { server_name: Some("api.example.com".to_string()), alpn: vec!["h2".to_string(), "http/1.1".to_string()] };
+        assert_eq!(parse_client_hello(&stream), Some(expected));
+
+        let details = dissect_stream(&stream);
+        assert_eq!(details.server_name(), Some("api.example.com"));
+        assert_eq!(details.summary().as_deref(), Some("api.example.com (TLS, h2)"));
+        assert!(details.http_requests.is_empty());
+    }
+
+    #[test]
+    fn client_hellos_split_over_records_are_joined() {
+        let handshake = client_hello(Some("example.com"), &[]);
+        let stream = records(&handshake, 7);
+        assert_eq!(parse_client_hello(&stream).and_then(|hello| hello.server_name).as_deref(), Some("example.com"));
+        assert_eq!(dissect_stream(&stream).summary().as_deref(), Some("example.com (TLS)"));
+    }
+
+    #[test]
+    fn hellos_without_extensions_are_still_tls() {
+        let mut handshake = client_hello(None, &[]);
+        // Drop the extensions block and fix up the handshake length
+        handshake.truncate(handshake.len() - 9);
+        let len = (handshake.len() - 4) as u32;
+        handshake[1..4].copy_from_slice(&len.to_be_bytes()[1..]);
+        assert_eq!(parse_client_hello(&records(&handshake, 1 << 14)), Some(ClientHello::default()));
+    }
+
+    #[test]
+    fn truncated_or_foreign_tls_is_rejected() {
+        let stream = records(&client_hello(Some("example.com"), &["h2"]), 1 << 14);
+        for len in [0, 1, 3, 5, 9, 40, stream.len() - 1] {
+            assert_eq!(parse_client_hello(&stream[..len]), None, "{} bytes", len);
+        }
+        // An application data record, an SSLv2 style version and a ServerHello
+        let mut other = stream.clone();
+        other[0] = 0x17;
+        assert_eq!(parse_client_hello(&other), None);
+        let mut other = stream.clone();
+        other[1] = 0x02;
+        assert_eq!(parse_client_hello(&other), None);
+        let mut other = stream.clone();
+        other[5] = 0x02;
+        assert_eq!(parse_client_hello(&other), None);
+        // Extensions that claim more bytes than there are end the list
+        let mut handshake = client_hello(Some("example.com"), &[]);
+        let last = handshake.len() - 1;
+        handshake[last - 17] = 0xFF;
+        assert_eq!(parse_client_hello(&records(&handshake, 1 << 14)).map(|hello| hello.server_name), Some(None));
+    }
+
+    #[test]
+    fn http_requests_follow_keep_alive_bodies() {
+        let stream = b"POST /v1/items HTTP/1.1\r\nHost: API.example.com\r\nContent-Length: 5\r\n\r\nhello\
+GET /v1/items?page=2 HTTP/1.1\r\nhost: api.example.com\r\n\r\n\
+GET /v1/more HTTP/1.0\r\n\r\n";
+        let requests = parse_http_requests(stream);
+        let lines: Vec<(&str, &str, Option<&str>)> =
+            requests.iter().map(|request| (request.method.as_str(), request.path.as_str(), request.host.as_deref())).collect();
+        assert_eq!(
+            lines,
+            [("POST", "/v1/items", Some("api.example.com")), ("GET", "/v1/items?page=2", Some("api.example.com")), ("GET", "/v1/more", None)]
+        );
+        assert_eq!(dissect_stream(stream).summary().as_deref(), Some("api.example.com POST /v1/items (+2 more)"));
+    }
+
+    #[test]
+    fn http_parsing_stops_where_the_body_length_is_unknown() {
+        let chunked = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        assert_eq!(parse_http_requests(chunked).len(), 1);
+        let bad_length = b"POST / HTTP/1.1\r\nContent-Length: lots\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        assert_eq!(parse_http_requests(bad_length).len(), 1);
+        let huge_length = b"POST / HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        assert_eq!(parse_http_requests(huge_length).len(), 1);
+    }
+
+    #[test]
+    fn things_that_are_not_http_requests_are_ignored() {
+        for stream in [
+            &b""[..],
+            b"GET / HTTP/1.1\r\nHost: example.com\r\n",
+            b"HTTP/1.1 200 OK\r\n\r\n",
+            b"BREW /pot HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/2\r\n\r\n",
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET\r\n\r\n",
+            b"GET /\xFF HTTP/1.1\r\n\r\n",
+            b"SSH-2.0-OpenSSH_9.6\r\n",
+        ] {
+            assert_eq!(parse_http_requests(stream), [], "{:?}", String::from_utf8_lossy(stream));
+            assert!(dissect_stream(stream).is_empty());
+        }
+    }
+
+    #[test]
+    fn dns_queries_over_udp_and_tcp() {
+        let query = dns::encode_query(1, "example.com", dns::TYPE_HTTPS);
+        assert_eq!(parse_dns_queries(&query), [DnsQuery { name: "example.com".to_string(), record_type: "HTTPS".to_string() }]);
+        // Responses are the server talking, not the app asking
+        assert_eq!(parse_dns_queries(&dns::tests::response(1, 0, "example.com", dns::TYPE_A, &[])), []);
+        assert_eq!(parse_dns_queries(&dns::encode_query(2, "example.com", 99))[0].record_type, "TYPE99");
+
+        let mut stream = vec16(&dns::encode_query(3, "a.example", dns::TYPE_A));
+        stream.extend(vec16(&dns::encode_query(4, "b.example", dns::TYPE_AAAA)));
+        // A frame cut off by the prefix limit
+        stream.extend_from_slice(&[0, 40, 0, 5]);
+        let names: Vec<String> = parse_dns_stream(&stream).into_iter().map(|query| format!("{} {}", query.record_type, query.name)).collect();
+        assert_eq!(names, ["A a.example", "AAAA b.example"]);
+    }
+
+    #[test]
+    fn dns_queries_are_capped_per_flow() {
+        let query = dns::encode_query(1, "example.com", dns::TYPE_A);
+        let mut queries = Vec::new();
+        for _ in 0..MAX_DNS_QUERIES + 5 {
+            add_dns_queries(&mut queries, &query);
+        }
+        assert_eq!(queries.len(), MAX_DNS_QUERIES);
+
+        let stream: Vec<u8> = (0..MAX_DNS_QUERIES + 5).flat_map(|_| vec16(&query)).collect();
+        assert_eq!(parse_dns_stream(&stream).len(), MAX_DNS_QUERIES);
+    }
+
+    #[test]
+    fn malformed_dns_adds_nothing() {
+        let mut queries = Vec::new();
+        add_dns_queries(&mut queries, &[0xFF; 3]);
+        add_dns_queries(&mut queries, &[0; 12]);
+        add_dns_queries(&mut queries, &dns::encode_query(1, "example.com", dns::TYPE_A)[..20]);
+        assert_eq!(queries, []);
+        assert_eq!(parse_dns_stream(&[0]), []);
+    }
+
+    // Real loopback traffic: curl fetching a page over HTTP, curl opening a
+    // TLS connection to secure.example.com (h2 offered) and a DNS query
+    #[test]
+    fn a_captured_pcap_is_dissected_into_flows() {
+        let mut capture = OfflineCapture::open(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/loopback.pcap")).unwrap();
+        assert_eq!(capture.linktype(), LINKTYPE_ETHERNET);
+        let table = flows::read_flows(&mut capture).unwrap();
+        let mut flows: Vec<&flows::Flow> = table.flows().collect();
+        flows.sort_by_key(|flow| flow.opened_at);
+        let summary: Vec<(u16, u64, Option<String>)> = flows.iter().map(|flow| (flow.remote.port(), flow.bytes_out(), flow.details().summary())).collect();
+        assert_eq!(
+            summary,
+            [
+                (8080, 94, Some("api.example.com GET /v1/items?page=2".to_string())),
+                (8443, 517, Some("secure.example.com (TLS, h2)".to_string())),
+                (5353, 29, Some("DNS A example.com".to_string())),
+            ]
+        );
+        assert_eq!(flows[0].bytes_in(), 59);
+        assert_eq!(flows[1].details().tls_alpn, ["h2", "http/1.1"]);
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_never_panic(data in vec(any::<u8>(), 0..2048)) {
+            parse_client_hello(&data);
+            prop_assert!(parse_http_requests(&data).len() <= MAX_HTTP_REQUESTS);
+            prop_assert!(parse_dns_stream(&data).len() <= MAX_DNS_QUERIES);
+            parse_dns_queries(&data);
+            dissect_stream(&data);
+        }
+
+        // Garbage behind valid record and handshake headers reaches the
+        // ClientHello body and extension parsers
+        #[test]
+        fn arbitrary_client_hello_bodies_never_panic(body in vec(any::<u8>(), 0..600), chunk in 1usize..64) {
+            let mut handshake = vec![TLS_HANDSHAKE_CLIENT_HELLO];
+            handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            handshake.extend(body);
+            parse_client_hello(&records(&handshake, chunk));
+        }
+
+        #[test]
+        fn client_hellos_round_trip_until_cut_short(
+            name in "[a-z0-9-]{1,20}(\\.[a-z0-9-]{1,20}){0,3}",
+            chunk in 1usize..300,
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let stream = records(&client_hello(Some(&name), &["h2"]), chunk);
+            prop_assert_eq!(parse_client_hello(&stream).and_then(|hello| hello.server_name), Some(name));
+            prop_assert_eq!(parse_client_hello(&stream[..cut.index(stream.len())]), None);
+        }
+
+        #[test]
+        fn keep_alive_requests_round_trip(
+            requests in vec(("GET|POST|PUT|DELETE", "/[!-~]{0,30}", "[a-z0-9.]{1,20}", vec(any::<u8>(), 0..50)), 1..6),
+        ) {
+            let mut stream = Vec::new();
+            for (method, path, host, body) in &requests {
+                stream.extend(format!("{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n", method, path, host, body.len()).into_bytes());
+                stream.extend(body);
+            }
+            let expected: Vec<HttpRequest> = requests
+                .iter()
+                .map(|(method, path, host, _)| HttpRequest { method: method.clone(), path: path.clone(), host: Some(host.clone()) })
+                .collect();
+            prop_assert_eq!(parse_http_requests(&stream), expected);
+        }
+
+        #[test]
+        fn dns_streams_round_trip(names in vec("[a-z0-9]{1,10}(\\.[a-z0-9]{1,10}){0,2}", 0..40), junk in vec(any::<u8>(), 0..30)) {
+            let mut stream: Vec<u8> = names.iter().enumerate().flat_map(|(id, name)| vec16(&dns::encode_query(id as u16, name, dns::TYPE_A))).collect();
+            stream.extend(junk);
+            let queries = parse_dns_stream(&stream);
+            let expected = names.len().min(MAX_DNS_QUERIES);
+            prop_assert!(queries.len() <= MAX_DNS_QUERIES && queries.len() >= expected);
+            let parsed: Vec<&str> = queries[..expected].iter().map(|query| query.name.as_str()).collect();
+            prop_assert_eq!(parsed, &names[..expected]);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// --- Record types the watcher cares about ---
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_HTTPS: u16 = 65;
+
+const CLASS_IN: u16 = 1;
+
+// Compression pointers may chain; a well-formed name never needs this many
+const MAX_POINTER_JUMPS: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Question {
+    pub name: String,
+    pub record_type: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ptr(String),
+    // Anything else, by record type
+    Other(u16),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+// The parts of a DNS message the resolver and the dissectors use.
+// Authority and additional sections are not decoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub id: u16,
+    pub is_response: bool,
+    pub rcode: u8,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(len).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| format!("DNS message truncated at offset {}", self.position))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // A possibly compressed name starting at the current position
+    fn name(&mut self) -> Result<String, String> {
+        let (name, end) = read_name(self.bytes, self.position)?;
+        self.position = end;
+        Ok(name)
+    }
+}
+
+// Decode the name at `offset`. Returns the dotted name (without the trailing
+// dot) and the offset right after it in the original message.
+fn read_name(bytes: &[u8], offset: usize) -> Result<(String, usize), String> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut position = offset;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *bytes.get(position).ok_or("DNS name runs past the end of the message")? as usize;
+        match len & 0xC0 {
+            0x00 if len == 0 => {
+                end.get_or_insert(position + 1);
+                break;
+            }
+            0x00 => {
+                let label = bytes
+                    .get(position + 1..position + 1 + len)
+                    .ok_or("DNS label runs past the end of the message")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                position += 1 + len;
+            }
+            0xC0 => {
+                let low = *bytes.get(position + 1).ok_or("DNS compression pointer truncated")? as usize;
+                end.get_or_insert(position + 2);
+                jumps += 1;
+                if jumps > MAX_POINTER_JUMPS {
+                    return Err("DNS compression pointers loop".to_string());
+                }
+                position = ((len & 0x3F) << 8) | low;
+            }
+            _ => return Err(format!("Unsupported DNS label type 0x{:02x}", len)),
+        }
+    }
+    Ok((labels.join("."), end.unwrap_or(position)))
+}
+
+pub fn parse_message(bytes: &[u8]) -> Result<Message, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    let question_count = reader.u16()?;
+    let answer_count = reader.u16()?;
+    reader.u16()?; // authority records
+    reader.u16()?; // additional records
+
+    let mut questions = Vec::new();
+    for _ in 0..question_count {
+        let name = reader.name()?;
+        let record_type = reader.u16()?;
+        reader.u16()?; // class
+        questions.push(Question { name, record_type });
+    }
+
+    let mut answers = Vec::new();
+    for _ in 0..answer_count {
+        let name = reader.name()?;
+        let record_type = reader.u16()?;
+        reader.u16()?; // class
+        let ttl = reader.u32()?;
+        let data_len = reader.u16()? as usize;
+        let data_start = reader.position;
+        let data = reader.take(data_len)?;
+        let data = match (record_type, data_len) {
+            (TYPE_A, 4) => RecordData::A(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            }
+            // Names in record data may point back into the message
+            (TYPE_CNAME, _) => RecordData::Cname(read_name(bytes, data_start)?.0),
+            (TYPE_PTR, _) => RecordData::Ptr(read_name(bytes, data_start)?.0),
+            (other, _) => RecordData::Other(other),
+        };
+        answers.push(Record { name, ttl, data });
+    }
+
+    Ok(Message {
+        id,
+        is_response: flags & 0x8000 != 0,
+        rcode: (flags & 0x000F) as u8,
+        questions,
+        answers,
+    })
+}
+
+// A standard recursive query for one name
+pub fn encode_query(id: u16, name: &str, record_type: u16) -> Vec<u8> {
+    let mut message = Vec::with_capacity(18 + name.len());
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&0x0100u16.to_be_bytes()); // recursion desired
+    message.extend_from_slice(&1u16.to_be_bytes());
+    message.extend_from_slice(&[0; 6]);
+    for label in name.trim_end_matches('.').split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        message.push(label.len() as u8);
+        message.extend_from_slice(label);
+    }
+    message.push(0);
+    message.extend_from_slice(&record_type.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    message
+}
+
+// Name of the PTR record for `ip`, e.g. "34.216.184.93.in-addr.arpa"
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+// Builders for DNS messages, shared with the resolver and dissector tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn push_name(message: &mut Vec<u8>, name: &str) {
+        for label in name.split('.').filter(|label| !label.is_empty()) {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+        message.push(0);
+    }
+
+    // An uncompressed response to a question for `name`
+    pub(crate) fn response(id: u16, rcode: u8, name: &str, record_type: u16, answers: &[Record]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&(0x8180u16 | rcode as u16).to_be_bytes());
+        message.extend_from_slice(&1u16.to_be_bytes());
+        message.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        message.extend_from_slice(&[0; 4]);
+        push_name(&mut message, name);
+        message.extend_from_slice(&record_type.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        for answer in answers {
+            push_name(&mut message, &answer.name);
+            let (record_type, data) = match &answer.data {
+                RecordData::A(ip) => (TYPE_A, ip.octets().to_vec()),
+                RecordData::Aaaa(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+                RecordData::Cname(target) | RecordData::Ptr(target) => {
+                    let mut data = Vec::new();
+                    push_name(&mut data, target);
+                    (if matches!(answer.data, RecordData::Cname(_)) { TYPE_CNAME } else { TYPE_PTR }, data)
+                }
+                RecordData::Other(record_type) => (*record_type, Vec::new()),
+            };
+            message.extend_from_slice(&record_type.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&answer.ttl.to_be_bytes());
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(&data);
+        }
+        message
+    }
+
+    pub(crate) fn record(name: &str, ttl: u32, data: RecordData) -> Record {
+        Record { name: name.to_string(), ttl, data }
+    }
+
+    #[test]
+    fn queries_parse_back() {
+        let message = parse_message(&encode_query(0x1234, "api.example.com.", TYPE_AAAA)).unwrap();
+        assert_eq!(message.id, 0x1234);
+        assert!(!message.is_response);
+        assert_eq!(message.questions, vec![Question { name: "api.example.com".to_string(), record_type: TYPE_AAAA }]);
+        assert!(message.answers.is_empty());
+    }
+
+    #[test]
+    fn responses_decode_every_record_type() {
+        let answers = [
+            record("api.example.com", 60, RecordData::Cname("edge.cdn.net".to_string())),
+            record("edge.cdn.net", 20, RecordData::A(Ipv4Addr::new(93, 184, 216, 34))),
+            record("edge.cdn.net", 20, RecordData::Aaaa("2606:2800:220:1::1".parse().unwrap())),
+            record("edge.cdn.net", 20, RecordData::Other(TYPE_HTTPS)),
+        ];
+        let message = parse_message(&response(7, 0, "api.example.com", TYPE_A, &answers)).unwrap();
+        assert!(message.is_response);
+        assert_eq!(message.rcode, 0);
+        assert_eq!(message.answers, answers);
+    }
+
+    #[test]
+    fn compressed_names_follow_pointers() {
+        // Question "example.com" at offset 12; the answer's name and PTR data point back at it
+        let mut message = encode_query(1, "example.com", TYPE_PTR);
+        message[2] = 0x81;
+        message[7] = 1;
+        message.extend_from_slice(&[0xC0, 12, 0, TYPE_PTR as u8, 0, 1, 0, 0, 0, 30, 0, 6, 3, b'w', b'w', b'w', 0xC0, 12]);
+        let message = parse_message(&message).unwrap();
+        assert_eq!(message.answers, vec![record("example.com", 30, RecordData::Ptr("www.example.com".to_string()))]);
+    }
+
+    #[test]
+    fn malformed_messages_are_errors_not_panics() {
+        let valid = response(7, 0, "api.example.com", TYPE_A, &[record("api.example.com", 60, RecordData::A(Ipv4Addr::LOCALHOST))]);
+        for len in 0..valid.len() {
+            assert!(parse_message(&valid[..len]).is_err(), "accepted {} of {} bytes", len, valid.len());
+        }
+
+        // A compression pointer to itself
+        let mut looping = encode_query(1, "", TYPE_A);
+        looping.truncate(12);
+        looping.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]);
+        assert_eq!(parse_message(&looping).unwrap_err(), "DNS compression pointers loop");
+
+        // Reserved label type 0x40
+        let mut reserved = encode_query(1, "", TYPE_A);
+        reserved.truncate(12);
+        reserved.extend_from_slice(&[0x41, b'x', 0, 0, 1, 0, 1]);
+        assert!(parse_message(&reserved).is_err());
+    }
+
+    #[test]
+    fn reverse_names_for_both_families() {
+        assert_eq!(reverse_name("93.184.216.34".parse().unwrap()), "34.216.184.93.in-addr.arpa");
+        assert_eq!(
+            reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+}
@@ -3,13 +3,20 @@ use core_foundation::runloop::CFRunLoopSourceRef;
 use core_foundation::string::CFStringRef;
 use std::os::raw::{c_longlong, c_void};
 use std::ptr;
-use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 // Import necessary items from other modules
 use crate::accessibility::*;
 use crate::cf_utils::{cf_string_ref, cfstring_to_string};
-use crate::utils::get_app_name_from_pid;
+use crate::utils::{console_output, get_app_name_from_pid};
 use crate::network::check_nettop_for_pid;
+use crate::traffic::TrafficHistory;
+use crate::ax_observer::track_pid;
+use crate::label::LabelChain;
+use crate::geometry::{CGPoint, ClickGeometry};
+use crate::display::DisplayLayout;
+use crate::events::{self, AxSelector, ClickEvent, KeyEvent, TapDisabled, WatcherEvent};
+use crate::metrics;
 
 // Re-export AXUIElementRef for use within this module if needed
 pub use crate::accessibility::AXUIElementRef;
@@ -24,13 +31,9 @@ pub type CGEventTapPlacement = u32;
 pub type CGEventTapOptions = u32;
 pub type CGEventMask = u64;
 pub type CGEventField = u32;
-
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-pub struct CGPoint {
-    pub x: f64,
-    pub y: f64,
-}
+pub type CGEventFlags = u64;
+pub type CGEventSourceRef = *mut c_void; // Opaque pointer
+pub type CGMouseButton = u32;
 
 // Type for the event tap callback
 pub type CGEventTapCallBack = unsafe extern "C" fn(
@@ -51,6 +54,7 @@ pub const K_CG_EVENT_TAP_DEFAULT: CGEventTapOptions = 0x00000000;
 #[allow(dead_code)]
 pub const K_CG_EVENT_NULL: CGEventType = 0; // Internal use
 pub const K_CG_EVENT_LEFT_MOUSE_DOWN: CGEventType = 1;
+pub const K_CG_EVENT_LEFT_MOUSE_UP: CGEventType = 2;
 pub const K_CG_EVENT_KEY_DOWN: CGEventType = 10;
 pub const K_CG_EVENT_TAP_DISABLED_BY_TIMEOUT: CGEventType = 0xFFFFFFFE;
 pub const K_CG_EVENT_TAP_DISABLED_BY_USER_INPUT: CGEventType = 0xFFFFFFFF;
@@ -59,6 +63,9 @@ pub const K_CG_EVENT_TAP_DISABLED_BY_USER_INPUT: CGEventType = 0xFFFFFFFF;
 pub const K_CG_EVENT_TARGET_UNIX_PROCESS_ID: CGEventField = 8; // kCGEventTargetUnixProcessID
 pub const K_CG_KEYBOARD_EVENT_KEYCODE: CGEventField = 9; // kCGKeyboardEventKeycode
 
+// CGMouseButton
+pub const K_CG_MOUSE_BUTTON_LEFT: CGMouseButton = 0;
+
 #[link(name = "CoreGraphics", kind = "framework")]
 #[allow(non_snake_case)] // To allow function names like CGEventTapCreate
 unsafe extern "C" {
@@ -75,6 +82,19 @@ unsafe extern "C" {
 
     pub fn CGEventGetLocation(event: CGEventRef) -> CGPoint;
     pub fn CGEventGetIntegerValueField(event: CGEventRef, field: CGEventField) -> c_longlong; // Note: Returns int64_t
+    pub fn CGEventGetFlags(event: CGEventRef) -> CGEventFlags;
+    pub fn CGEventSetFlags(event: CGEventRef, flags: CGEventFlags);
+
+    // Used by replay to post synthetic input
+    pub fn CGEventCreateMouseEvent(
+        source: CGEventSourceRef, // null for the default source
+        mouseType: CGEventType,
+        mouseCursorPosition: CGPoint,
+        mouseButton: CGMouseButton,
+    ) -> CGEventRef;
+    pub fn CGEventCreateKeyboardEvent(source: CGEventSourceRef, virtualKey: u16, keyDown: bool) -> CGEventRef;
+    pub fn CGEventPost(tap: CGEventTapLocation, event: CGEventRef);
+    pub fn CGEventPostToPid(pid: i32, event: CGEventRef);
 
     pub fn CFMachPortCreateRunLoopSource(
         allocator: CFAllocatorRef, // Usually kCFAllocatorDefault or null
@@ -83,8 +103,52 @@ unsafe extern "C" {
     ) -> CFRunLoopSourceRef;
 }
 
-// Global mutable cache for network stats (requires unsafe access)
-static mut EVENT_CALLBACK_CACHE: Option<HashMap<String, (u64, u64)>> = None;
+// State shared with the event tap callback through its userInfo pointer
+pub struct TapContext {
+    pub system_wide: AXUIElementRef,
+    pub label_chain: LabelChain,
+}
+
+// Run one accessibility query, adding how long it took to `total`
+fn timed<T>(total: &mut Duration, query: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = query();
+    *total += started.elapsed();
+    result
+}
+
+// Read the element frame and the frame/title/ID of its containing window.
+// Time spent in accessibility queries is added to `ax_time`.
+fn read_click_geometry(element: &AxElement, location: CGPoint, ax_time: &mut Duration) -> ClickGeometry {
+    let element_frame = timed(ax_time, || element.frame());
+    let relative_click_point = element_frame.map(|frame| frame.relative_point(location));
+    let on_edge = element_frame.is_some_and(|frame| frame.near_edge(location));
+    if on_edge {
+        log::debug!("Click at ({:.1}, {:.1}) landed on the edge of element frame {:?}", location.x, location.y, element_frame);
+    }
+
+    let (window_title, window_id, window_frame) = timed(ax_time, || {
+        let window = element.element_attribute(K_AX_WINDOW_ATTRIBUTE);
+        (
+            window.as_ref().and_then(|window| window.string_attribute(K_AX_TITLE_ATTRIBUTE)),
+            window.as_ref().and_then(|window| window.window_id()),
+            window.as_ref().and_then(|window| window.frame()),
+        )
+    });
+    ClickGeometry {
+        element_frame,
+        relative_click_point,
+        on_edge,
+        window_title,
+        window_id,
+        window_frame,
+        // Re-read on every click so plugging in a monitor needs no restart
+        display: DisplayLayout::current().locate(location),
+    }
+}
+
+// Global mutable traffic history for network stats (requires unsafe access)
+static mut EVENT_CALLBACK_CACHE: Option<TrafficHistory> = None;
 
 // The actual event callback function
 pub unsafe extern "C" fn event_callback(
@@ -93,23 +157,34 @@ pub unsafe extern "C" fn event_callback(
     event: CGEventRef,
     user_info: *mut c_void,
 ) -> CGEventRef {
-    
-    // Get userInfo (system_wide element)
+    let _timer = metrics::Timer::start(metrics::CALLBACK_DURATION, &[]);
+
+    // Get userInfo (TapContext holding the system_wide element)
     if user_info.is_null() {
-        log::error!("userInfo (system_wide element) is null in callback!");
+        log::error!("userInfo (TapContext) is null in callback!");
         return event; // Cannot proceed
     }
-    let system_wide_element = user_info as AXUIElementRef;
+    let context = unsafe { &*(user_info as *const TapContext) };
+    let system_wide_element = context.system_wide;
 
     // Handle tap disable events
     if type_ == K_CG_EVENT_TAP_DISABLED_BY_TIMEOUT || type_ == K_CG_EVENT_TAP_DISABLED_BY_USER_INPUT {
-         println!("DEBUG: Event tap disabled (type: {})", type_);
+         if console_output() { println!("DEBUG: Event tap disabled (type: {})", type_); }
          log::warn!("Event Tap disabled (type: {})! Input monitoring stopped.", type_);
+         let reason = if type_ == K_CG_EVENT_TAP_DISABLED_BY_TIMEOUT { "timeout" } else { "user_input" };
+         metrics::inc_counter(metrics::TAP_DISABLED_TOTAL, &[("reason", reason)]);
+         events::record_tap_disabled();
+         events::emit(&WatcherEvent::TapDisabled(TapDisabled { timestamp: chrono::Local::now(), reason: reason.to_string() }));
          // We might need to re-enable the tap if desired.
          // unsafe { CGEventTapEnable(proxy as CFMachPortRef, true) }; // Needs unsafe block if uncommented
          return event; // Return the event directly
     }
 
+    // Paused through the control socket; leave input alone entirely
+    if events::is_paused() {
+        return event;
+    }
+
     // We are interested in left mouse down OR key down events
     if type_ != K_CG_EVENT_LEFT_MOUSE_DOWN && type_ != K_CG_EVENT_KEY_DOWN {
         return event;
@@ -121,6 +196,8 @@ pub unsafe extern "C" fn event_callback(
 
     // --- Handle Left Mouse Down --- 
     if type_ == K_CG_EVENT_LEFT_MOUSE_DOWN {
+         // Only the accessibility queries themselves count, not ps or nettop
+         let mut ax_time = Duration::ZERO;
          let location = unsafe { CGEventGetLocation(event) };
          log::debug!(
              "LeftMouseDown detected. App='{}' (PID={}), Pos=({:.1}, {:.1}), SysWideRef={:p}",
@@ -129,23 +206,30 @@ pub unsafe extern "C" fn event_callback(
           
          // Get the AXUIElementRef for the element at the click location
          let mut element_ref: AXUIElementRef = ptr::null_mut();
-         let result = unsafe { ax_ui_element_copy_element_at_position(
+         let result = timed(&mut ax_time, || unsafe { ax_ui_element_copy_element_at_position(
              system_wide_element, // Use the system-wide ref here!
              location.x as f32, 
              location.y as f32, 
              &mut element_ref
-         )};
+         )});
          
+         if result != 0 { metrics::ax_error(result); }
          if result != 0 || element_ref.is_null() { // kAXErrorSuccess is 0
              log::debug!("Could not get element at position ({:.1}, {:.1}) for PID {}. AXError={}", location.x, location.y, pid, result);
              return event; // Can't proceed
          }
          log::debug!("Got element_ref ({:p}) at position ({:.1}, {:.1})", element_ref, location.x, location.y);
+         // Take ownership so the element is released however we leave this branch
+         let element = match unsafe { AxElement::from_create_rule(element_ref) } {
+             Some(element) => element,
+             None => return event,
+         };
 
          // --- Get Actual PID from Element ---
          let mut actual_pid: i32 = -1; // Default to -1 if error
-         let pid_result = unsafe { ax_ui_element_get_pid(element_ref, &mut actual_pid) };
+         let pid_result = timed(&mut ax_time, || unsafe { ax_ui_element_get_pid(element_ref, &mut actual_pid) });
          if pid_result != 0 { // kAXErrorSuccess is 0
+             metrics::ax_error(pid_result);
              log::debug!("Failed to get PID from element_ref ({:p}). AXError={}", element_ref, pid_result);
              actual_pid = pid; // Fallback to event PID if needed, though likely still 0
          } else {
@@ -154,16 +238,20 @@ pub unsafe extern "C" fn event_callback(
          let actual_app_name = get_app_name_from_pid(actual_pid); // Use imported function
          // --- End Get Actual PID ---
 
+         // Subscribe to UI notifications (windows, focus, values) for this app
+         unsafe { track_pid(actual_pid) };
+
          // --- Call nettop check ---
-         let pid_str = actual_pid.to_string();
          // Access and initialize the cache if needed (unsafe block required)
+         let mut traffic_before = Vec::new();
          unsafe {
              if EVENT_CALLBACK_CACHE.is_none() {
-                 EVENT_CALLBACK_CACHE = Some(HashMap::new());
+                 EVENT_CALLBACK_CACHE = Some(TrafficHistory::default());
              }
              if let Some(cache) = &mut EVENT_CALLBACK_CACHE {
                   log::debug!("Calling check_nettop_for_pid for PID {} (App: {})", actual_pid, actual_app_name);
-                  check_nettop_for_pid(&pid_str, cache);
+                  // The app has not seen the click yet, so this is the correlation baseline
+                  traffic_before = check_nettop_for_pid(actual_pid, cache);
              }
          }
          // --- End nettop check ---
@@ -171,11 +259,11 @@ pub unsafe extern "C" fn event_callback(
          // --- Get Identifier (Try this for all elements) ---
          let mut identifier_ref: *mut c_void = ptr::null_mut();
          let identifier_attr = unsafe { cf_string_ref(K_AX_IDENTIFIER_ATTRIBUTE) }; // Use imported cf_utils
-         let identifier_result = unsafe { ax_ui_element_copy_attribute_value(
+         let identifier_result = timed(&mut ax_time, || unsafe { ax_ui_element_copy_attribute_value(
              element_ref, 
              identifier_attr, 
              &mut identifier_ref
-         )};
+         )});
 
          let identifier_str = if identifier_result == 0 && !identifier_ref.is_null() {
              unsafe { cfstring_to_string(identifier_ref as CFStringRef) } // Use imported cf_utils
@@ -183,6 +271,7 @@ pub unsafe extern "C" fn event_callback(
              None
          };
          log::debug!("Element identifier is \"{:?}\" (AXError={})", identifier_str, identifier_result);
+         if identifier_result != 0 { metrics::ax_error(identifier_result); }
 
          // Clean up identifier attribute string and value ref
          unsafe { CFRelease(identifier_attr as *const c_void); }
@@ -192,11 +281,11 @@ pub unsafe extern "C" fn event_callback(
          // Get the role of the element
          let mut role_ref: *mut c_void = ptr::null_mut();
          let role_attr = unsafe { cf_string_ref(K_AX_ROLE_ATTRIBUTE) }; // Use imported cf_utils
-         let role_result = unsafe { ax_ui_element_copy_attribute_value(
+         let role_result = timed(&mut ax_time, || unsafe { ax_ui_element_copy_attribute_value(
              element_ref, 
              role_attr, 
              &mut role_ref
-         )};
+         )});
          
          let role_str = if role_result == 0 && !role_ref.is_null() {
              let converted_role = unsafe { cfstring_to_string(role_ref as CFStringRef) }; // Use imported cf_utils
@@ -204,6 +293,7 @@ pub unsafe extern "C" fn event_callback(
              converted_role
          } else {
              log::debug!("Failed to get element role (AXError={})", role_result);
+             metrics::ax_error(role_result);
              None
          };
 
@@ -211,69 +301,88 @@ pub unsafe extern "C" fn event_callback(
          unsafe { CFRelease(role_attr as *const c_void); }
          if !role_ref.is_null() { unsafe { CFRelease(role_ref); } }
 
+         // Element frame and window context, logged with every click below
+         let geometry = read_click_geometry(&element, location, &mut ax_time);
+         log::debug!("Click geometry: {:?}", geometry);
+
+         // --- Resolve Label via the configured chain ---
+         log::debug!("Attempting to resolve label for element {:p}", element_ref);
+         let resolved_label = timed(&mut ax_time, || context.label_chain.resolve(&element));
+         log::debug!("Resolved element label {:?}", resolved_label);
+         // --- End Resolve Label ---
+
+         // Hand the structured click to sinks (recordings, exporters)
+         events::emit(&WatcherEvent::Click(Box::new(ClickEvent {
+             timestamp: chrono::Local::now(),
+             pid: actual_pid,
+             app_name: actual_app_name.clone(),
+             selector: AxSelector {
+                 identifier: identifier_str.clone(),
+                 role: role_str.clone(),
+                 label: resolved_label.as_ref().map(|label| label.text.clone()),
+             },
+             label_source: resolved_label.as_ref().map(|label| label.source.to_string()),
+             position: location,
+             geometry: geometry.clone(),
+             ax_lookup_ms: Some(ax_time.as_secs_f64() * 1000.0),
+             traffic_before,
+         })));
+
          // Check if it's a button
          if let Some(role) = role_str {
              if role == K_AX_BUTTON_ROLE { // Use imported constant
-                 // --- Get Description Directly ---
-                 log::debug!("Attempting to get description for button element {:p}", element_ref);
-                 let mut desc_ref: *mut c_void = ptr::null_mut();
-                 let desc_attr = unsafe { cf_string_ref(K_AX_DESCRIPTION_ATTRIBUTE) }; // Use imported cf_utils
-                 let desc_result = unsafe { ax_ui_element_copy_attribute_value(
-                     element_ref, 
-                     desc_attr, 
-                     &mut desc_ref
-                 )};
-                 let description_str = if desc_result == 0 && !desc_ref.is_null() {
-                     let converted_desc = unsafe { cfstring_to_string(desc_ref as CFStringRef) }; // Use imported cf_utils
-                     log::debug!("Got element description \"{:?}\" (AXError={})", converted_desc, desc_result);
-                     converted_desc
-                 } else {
-                     log::debug!("Failed to get element description (AXError={})", desc_result);
-                     None
-                 };
-                 // Clean up description attribute and value refs
-                 unsafe { CFRelease(desc_attr as *const c_void); }
-                 if !desc_ref.is_null() { unsafe { CFRelease(desc_ref); } }
-                 // --- End Get Description ---
-
                  // Get the optional identifier string we fetched earlier
                  let id_str = identifier_str.clone().unwrap_or_else(|| "<No ID>".to_string());
                   
                  // Log the button click
-                 let button_label = description_str.unwrap_or_else(|| "<No Label>".to_string());
+                 let (button_label, label_source) = match resolved_label {
+                     Some(label) => (label.text, label.source.to_string()),
+                     None => ("<No Label>".to_string(), "<None>".to_string()),
+                 };
                  // Use actual_app_name and actual_pid
                  log::info!(
-                     "Button Clicked: App='{}' (PID={}), ID='{}', Label='{}', Pos=({:.1}, {:.1})",
+                     "Button Clicked: App='{}' (PID={}), ID='{}', Label='{}', LabelSource='{}', Pos=({:.1}, {:.1}), {}",
                      actual_app_name, 
                      actual_pid, 
                      id_str,
-                     button_label, // Use the final label (might be from Title or Description)
+                     button_label, // First non-empty entry of the label chain
+                     label_source,
                      location.x,
-                     location.y
+                     location.y,
+                     geometry.log_fields()
                  );
              } else {
                  // Log if it's not a button but has an identifier
                  if let Some(id) = identifier_str.clone() {
                      let role_name = role.clone(); // Already unwrapped Some(role)
                      log::info!(
-                         "Element Clicked: App='{}' (PID={}), ID='{}', Role='{}', Pos=({:.1}, {:.1})",
+                         "Element Clicked: App='{}' (PID={}), ID='{}', Role='{}', Pos=({:.1}, {:.1}), {}",
                          actual_app_name,
                          actual_pid,
                          id,
                          role_name,
                          location.x,
-                         location.y
+                         location.y,
+                         geometry.log_fields()
                      );
                  }
              }
          }
 
-         // Clean up element 
-         unsafe { CFRelease(element_ref as *const c_void); }
+         // element_ref is released when `element` goes out of scope
 
     // --- Handle Key Down ---    
     } else if type_ == K_CG_EVENT_KEY_DOWN {
         let keycode = unsafe { CGEventGetIntegerValueField(event, K_CG_KEYBOARD_EVENT_KEYCODE) };
+        let flags = unsafe { CGEventGetFlags(event) };
+        unsafe { track_pid(pid) };
+        events::emit(&WatcherEvent::Key(KeyEvent {
+            timestamp: chrono::Local::now(),
+            pid,
+            app_name: app_name.clone(),
+            keycode,
+            flags,
+        }));
         log::info!(
             "Key Down: App='{}' (PID={}), KeyCode={}",
             app_name, 
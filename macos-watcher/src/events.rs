@@ -0,0 +1,399 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::correlation::WindowTraffic;
+use crate::geometry::{CGPoint, ClickGeometry};
+use crate::network::ProcessTraffic;
+use crate::rules::{self, Severity};
+use crate::summary::SessionSummary;
+use crate::traffic::TrafficRates;
+
+// How an element is found again later: the AX identifier if the app sets
+// one, otherwise role + label. Raw coordinates are deliberately not part of it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AxSelector {
+    pub identifier: Option<String>,
+    pub role: Option<String>,
+    pub label: Option<String>,
+}
+
+impl AxSelector {
+    // A role alone matches far too many elements to be worth replaying
+    pub fn is_locatable(&self) -> bool {
+        self.identifier.is_some() || self.label.is_some()
+    }
+
+    // Short human readable form, e.g. "#ButtonA" or "AXButton 'Button A'"
+    pub fn describe(&self) -> String {
+        if let Some(identifier) = &self.identifier {
+            return format!("#{}", identifier);
+        }
+        match (&self.role, &self.label) {
+            (Some(role), Some(label)) => format!("{} '{}'", role, label),
+            (Some(role), None) => role.clone(),
+            (None, Some(label)) => format!("'{}'", label),
+            (None, None) => "<Any>".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClickEvent {
+    pub timestamp: DateTime<Local>,
+    pub pid: i32,
+    pub app_name: String,
+    pub selector: AxSelector,
+    // Which label chain step produced selector.label
+    pub label_source: Option<String>,
+    pub position: CGPoint,
+    pub geometry: ClickGeometry,
+    // Time spent inside the accessibility queries for the click (element,
+    // pid, identifier, role, frames and label), summed per query
+    #[serde(default)]
+    pub ax_lookup_ms: Option<f64>,
+    // nettop sample of the clicked process tree, taken before the app saw the
+    // click. The baseline of the correlation window; never written out.
+    #[serde(skip)]
+    pub traffic_before: Vec<ProcessTraffic>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyEvent {
+    pub timestamp: DateTime<Local>,
+    pub pid: i32,
+    pub app_name: String,
+    pub keycode: i64,
+    // CGEventFlags (modifier keys) at the time of the press
+    pub flags: u64,
+}
+
+// Byte counters nettop reported for one process, with the change since its last sample
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NetworkSample {
+    pub timestamp: DateTime<Local>,
+    pub pid: i32,
+    // nettop's "<name>.<pid>" row key
+    pub process: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub delta_in: u64,
+    pub delta_out: u64,
+    // Sliding-window rates of this process at the time of the sample
+    #[serde(default)]
+    pub rates: Option<TrafficRates>,
+}
+
+// Traffic of the clicked process in the window after a click
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Correlation {
+    pub timestamp: DateTime<Local>,
+    pub click: Box<ClickEvent>,
+    pub window_ms: u64,
+    pub traffic: WindowTraffic,
+}
+
+// A marker set through the control socket, e.g. "starting login flow"
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub timestamp: DateTime<Local>,
+    pub label: String,
+}
+
+// A rule from --rules matched (see rules.rs)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Alert {
+    pub timestamp: DateTime<Local>,
+    pub rule: String,
+    pub severity: Severity,
+    pub pid: Option<i32>,
+    pub app_name: Option<String>,
+    pub message: String,
+}
+
+// macOS switched the event tap off ("timeout" or "user_input")
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TapDisabled {
+    pub timestamp: DateTime<Local>,
+    pub reason: String,
+}
+
+// The watcher is set up and about to monitor input
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Startup {
+    pub timestamp: DateTime<Local>,
+    pub session_id: String,
+}
+
+// The watcher is shutting down; sinks have not been finished yet
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Shutdown {
+    pub timestamp: DateTime<Local>,
+    pub summary: SessionSummary,
+}
+
+// Everything the watcher observes, in a form sinks can serialize
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WatcherEvent {
+    Click(Box<ClickEvent>),
+    Key(KeyEvent),
+    NetworkSample(NetworkSample),
+    Correlation(Correlation),
+    Checkpoint(Checkpoint),
+    Alert(Alert),
+    TapDisabled(TapDisabled),
+    Startup(Startup),
+    Shutdown(Box<Shutdown>),
+}
+
+// Every kind() there is, for validating event type lists
+pub const EVENT_KINDS: &[&str] =
+    &["click", "key", "network_sample", "correlation", "checkpoint", "alert", "tap_disabled", "startup", "shutdown"];
+
+impl WatcherEvent {
+    pub fn timestamp(&self) -> DateTime<Local> {
+        match self {
+            WatcherEvent::Click(click) => click.timestamp,
+            WatcherEvent::Key(key) => key.timestamp,
+            WatcherEvent::NetworkSample(sample) => sample.timestamp,
+            WatcherEvent::Correlation(correlation) => correlation.timestamp,
+            WatcherEvent::Checkpoint(checkpoint) => checkpoint.timestamp,
+            WatcherEvent::Alert(alert) => alert.timestamp,
+            WatcherEvent::TapDisabled(tap) => tap.timestamp,
+            WatcherEvent::Startup(startup) => startup.timestamp,
+            WatcherEvent::Shutdown(shutdown) => shutdown.timestamp,
+        }
+    }
+
+    // The serde tag, e.g. "click" or "network_sample"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WatcherEvent::Click(_) => "click",
+            WatcherEvent::Key(_) => "key",
+            WatcherEvent::NetworkSample(_) => "network_sample",
+            WatcherEvent::Correlation(_) => "correlation",
+            WatcherEvent::Checkpoint(_) => "checkpoint",
+            WatcherEvent::Alert(_) => "alert",
+            WatcherEvent::TapDisabled(_) => "tap_disabled",
+            WatcherEvent::Startup(_) => "startup",
+            WatcherEvent::Shutdown(_) => "shutdown",
+        }
+    }
+
+    // Checkpoints and the state of the watcher itself are set on purpose or
+    // matter regardless of what is being watched: never paused or filtered out
+    pub fn is_marker(&self) -> bool {
+        matches!(
+            self,
+            WatcherEvent::Checkpoint(_) | WatcherEvent::TapDisabled(_) | WatcherEvent::Startup(_) | WatcherEvent::Shutdown(_)
+        )
+    }
+
+    // The app the event belongs to, if any
+    pub fn app_name(&self) -> Option<&str> {
+        match self {
+            WatcherEvent::Click(click) => Some(&click.app_name),
+            WatcherEvent::Key(key) => Some(&key.app_name),
+            WatcherEvent::NetworkSample(sample) => Some(&sample.process),
+            WatcherEvent::Correlation(correlation) => Some(&correlation.click.app_name),
+            WatcherEvent::Alert(alert) => alert.app_name.as_deref(),
+            WatcherEvent::Checkpoint(_) | WatcherEvent::TapDisabled(_) | WatcherEvent::Startup(_) | WatcherEvent::Shutdown(_) => None,
+        }
+    }
+}
+
+// Which events reach the sinks. Empty lists place no restriction.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventFilter {
+    // Only these apps (matched against the name or its basename)
+    #[serde(default)]
+    pub include_apps: Vec<String>,
+    #[serde(default)]
+    pub exclude_apps: Vec<String>,
+    // Only these event kinds, e.g. "click", "key"
+    #[serde(default)]
+    pub event_types: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn accepts(&self, event: &WatcherEvent) -> bool {
+        if event.is_marker() {
+            return true;
+        }
+        if !self.event_types.is_empty() && !self.event_types.iter().any(|kind| kind == event.kind()) {
+            return false;
+        }
+        let Some(app_name) = event.app_name() else { return true };
+        let matches = |wanted: &String| app_matches(app_name, wanted);
+        if self.exclude_apps.iter().any(matches) {
+            return false;
+        }
+        self.include_apps.is_empty() || self.include_apps.iter().any(matches)
+    }
+}
+
+// `ps` reports full executable paths and nettop "<name>.<pid>", so compare
+// the basename and allow the nettop suffix
+pub fn app_matches(app_name: &str, wanted: &str) -> bool {
+    let basename = app_name.rsplit('/').next().unwrap_or(app_name);
+    app_name == wanted
+        || basename == wanted
+        || basename.strip_prefix(wanted).is_some_and(|rest| rest.starts_with('.') && rest[1..].bytes().all(|b| b.is_ascii_digit()))
+}
+
+// Receives every event the watcher emits (recordings, exporters, ...)
+pub trait EventSink: Send {
+    fn handle(&mut self, event: &WatcherEvent);
+
+    // Called once when the watcher shuts down; write out anything still buffered
+    fn finish(&mut self) {}
+}
+
+// Handle for removing a sink again
+pub type SinkId = u64;
+
+static SINKS: Mutex<Vec<(SinkId, Box<dyn EventSink>)>> = Mutex::new(Vec::new());
+static NEXT_SINK_ID: AtomicU64 = AtomicU64::new(1);
+static FILTER: Mutex<Option<EventFilter>> = Mutex::new(None);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+// How often macOS disabled the tap during this run (reported in the session summary)
+static TAP_DISABLED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+pub fn add_sink(sink: Box<dyn EventSink>) -> SinkId {
+    let id = NEXT_SINK_ID.fetch_add(1, Ordering::Relaxed);
+    SINKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((id, sink));
+    id
+}
+
+// Returns false if no sink had that ID
+pub fn remove_sink(id: SinkId) -> bool {
+    let mut sinks = SINKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let before = sinks.len();
+    sinks.retain(|(sink_id, _)| *sink_id != id);
+    sinks.len() != before
+}
+
+pub fn set_filter(filter: EventFilter) {
+    *FILTER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(filter);
+}
+
+pub fn filter() -> EventFilter {
+    FILTER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone().unwrap_or_default()
+}
+
+// While paused, input events are not inspected and nothing reaches the sinks
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+pub fn record_tap_disabled() {
+    TAP_DISABLED_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn tap_disabled_count() -> u64 {
+    TAP_DISABLED_COUNT.load(Ordering::Relaxed)
+}
+
+// Let every sink finish up before the process exits
+pub fn finish_all() {
+    let mut sinks = SINKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (_, sink) in sinks.iter_mut() {
+        sink.finish();
+    }
+}
+
+// Hand an event to every registered sink, followed by any alerts it raised.
+// This runs on the event tap thread: sinks that do I/O (store, webhook)
+// queue the event for a thread of their own.
+pub fn emit(event: &WatcherEvent) {
+    if is_paused() && !event.is_marker() {
+        return;
+    }
+    // Rules see events the filter hides from the sinks, e.g. nettop samples
+    // of an excluded helper process
+    let alerts = rules::check(event);
+    if filter().accepts(event) {
+        let mut sinks = SINKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (_, sink) in sinks.iter_mut() {
+            sink.handle(event);
+        }
+    }
+    for alert in alerts {
+        emit(&WatcherEvent::Alert(alert));
+    }
+}
+
+// Event fixtures the tests of the sinks and exporters share
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::correlation::WindowTraffic;
+    use chrono::TimeZone;
+
+    // A fixed instant plus `millis`
+    pub fn at(millis: i64) -> DateTime<Local> {
+        Local.timestamp_millis_opt(1_700_000_000_000 + millis).unwrap()
+    }
+
+    pub fn click(millis: i64, pid: i32, app_name: &str, label: &str) -> ClickEvent {
+        ClickEvent {
+            timestamp: at(millis),
+            pid,
+            app_name: app_name.to_string(),
+            selector: AxSelector { identifier: None, role: Some("AXButton".to_string()), label: Some(label.to_string()) },
+            label_source: Some("title".to_string()),
+            position: CGPoint { x: 10.0, y: 20.0 },
+            geometry: ClickGeometry::default(),
+            ax_lookup_ms: Some(1.5),
+            traffic_before: Vec::new(),
+        }
+    }
+
+    pub fn key(millis: i64, pid: i32, app_name: &str, keycode: i64) -> WatcherEvent {
+        WatcherEvent::Key(KeyEvent { timestamp: at(millis), pid, app_name: app_name.to_string(), keycode, flags: 0 })
+    }
+
+    // A nettop sample whose counters equal its deltas
+    pub fn sample(millis: i64, process: &str, pid: i32, delta_in: u64, delta_out: u64) -> WatcherEvent {
+        WatcherEvent::NetworkSample(NetworkSample {
+            timestamp: at(millis),
+            pid,
+            process: process.to_string(),
+            bytes_in: delta_in,
+            bytes_out: delta_out,
+            delta_in,
+            delta_out,
+            rates: None,
+        })
+    }
+
+    pub fn correlation(click: ClickEvent, window_ms: u64, traffic: WindowTraffic) -> WatcherEvent {
+        WatcherEvent::Correlation(Correlation {
+            timestamp: click.timestamp + chrono::Duration::milliseconds(window_ms as i64),
+            click: Box::new(click),
+            window_ms,
+            traffic,
+        })
+    }
+
+    #[test]
+    fn filter_matches_app_basenames_and_nettop_names() {
+        let filter = EventFilter { include_apps: vec!["Chat".to_string()], ..EventFilter::default() };
+        assert!(filter.accepts(&WatcherEvent::Click(Box::new(click(0, 42, "/Applications/Chat.app/Contents/MacOS/Chat", "Send")))));
+        assert!(filter.accepts(&sample(0, "Chat.42", 42, 1, 1)));
+        assert!(!filter.accepts(&sample(0, "ChatHelper.43", 43, 1, 1)));
+        assert!(!filter.accepts(&key(0, 50, "Mail", 36)));
+        assert!(filter.accepts(&WatcherEvent::Checkpoint(Checkpoint { timestamp: at(0), label: "start".to_string() })));
+
+        let filter = EventFilter { exclude_apps: vec!["Chat".to_string()], event_types: vec!["key".to_string()], ..EventFilter::default() };
+        assert!(!filter.accepts(&key(0, 42, "Chat", 36)));
+        assert!(filter.accepts(&key(0, 50, "Mail", 36)));
+        assert!(!filter.accepts(&sample(0, "Mail.50", 50, 1, 1)));
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::har;
+use crate::store::EventStore;
+use crate::trace;
+
+// File formats `macos-watcher export` can write
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Har,
+    // Chrome Trace Event JSON, for chrome://tracing and ui.perfetto.dev
+    Trace,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "har" => Ok(ExportFormat::Har),
+            "trace" => Ok(ExportFormat::Trace),
+            _ => Err(format!("Unknown export format '{}' (expected har or trace)", name)),
+        }
+    }
+}
+
+// Arguments for `macos-watcher export`
+pub struct ExportArgs {
+    pub format: ExportFormat,
+    pub db_path: PathBuf,
+    // Without a session or a time range the latest session is exported
+    pub session_id: Option<String>,
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+    // Only flows starting this long after a click; defaults to the
+    // correlation window the click was recorded with
+    pub window: Option<Duration>,
+    // None writes to stdout
+    pub output: Option<PathBuf>,
+}
+
+// "2024-05-01T10:00:00+02:00", "2024-05-01 10:00[:00]" or "2024-05-01",
+// the last two in local time
+pub fn parse_time(value: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Local));
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)));
+    naive
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .ok_or_else(|| format!("Invalid time '{}' (expected e.g. 2024-05-01 10:00 or RFC 3339)", value))
+}
+
+// Session to export: the given one, the latest when there is no time range
+// either, or None for every session within the range
+fn selected_session(store: &EventStore, args: &ExportArgs) -> Result<Option<String>, String> {
+    match &args.session_id {
+        Some(session_id) => Ok(Some(session_id.clone())),
+        None if args.from.is_none() && args.to.is_none() => {
+            Ok(Some(store.latest_session_id().map_err(|e| e.to_string())?.ok_or("The event store has no sessions")?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn in_range(args: &ExportArgs, timestamp: DateTime<Local>) -> bool {
+    args.from.is_none_or(|from| timestamp >= from) && args.to.is_none_or(|to| timestamp <= to)
+}
+
+fn write_json<T: serde::Serialize>(value: &T, output: Option<&Path>) -> io::Result<()> {
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+// Read the selection and write it in the requested format
+fn export(store: &EventStore, args: &ExportArgs) -> Result<(), String> {
+    let session_id = selected_session(store, args)?;
+    let mut actions = store.actions(session_id.as_deref()).map_err(|e| e.to_string())?;
+    actions.retain(|action| in_range(args, action.timestamp));
+
+    let written = match args.format {
+        ExportFormat::Har => {
+            let archive = har::build(&actions, args.window);
+            eprintln!("Exporting {} pages and {} entries", archive.log.pages.len(), archive.log.entries.len());
+            write_json(&archive, args.output.as_deref())
+        }
+        ExportFormat::Trace => {
+            let mut samples = store.samples(session_id.as_deref()).map_err(|e| e.to_string())?;
+            samples.retain(|sample| in_range(args, sample.timestamp));
+            let mut checkpoints = store.checkpoints(session_id.as_deref()).map_err(|e| e.to_string())?;
+            checkpoints.retain(|checkpoint| in_range(args, checkpoint.timestamp));
+            let timeline = trace::build(&actions, &samples, &checkpoints, args.window);
+            eprintln!("Exporting {} trace events", timeline.trace_events.len());
+            write_json(&timeline, args.output.as_deref())
+        }
+    };
+    written.map_err(|e| {
+        let target = args.output.as_ref().map_or("stdout".to_string(), |path| path.display().to_string());
+        format!("Failed to write {}: {}", target, e)
+    })
+}
+
+// Entry point for the export subcommand. Returns the process exit code.
+pub fn run_cli(args: ExportArgs) -> i32 {
+    if !args.db_path.exists() {
+        eprintln!("Error: No event store at {}. Run the watcher with --db first.", args.db_path.display());
+        return 1;
+    }
+    let store = match EventStore::open(&args.db_path) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error: Failed to open {}: {}", args.db_path.display(), e);
+            return 1;
+        }
+    };
+    match export(&store, &args) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
@@ -0,0 +1,711 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::correlation::FlowTiming;
+use crate::dissect::{self, DnsQuery, FlowDetails};
+use crate::libpcap::LiveCapture;
+use crate::pcap::{self, Packet, PcapReader, Segment, Transport, TCP_ACK, TCP_FIN, TCP_RST, TCP_SYN};
+use crate::query::format_table;
+use crate::sockets::{self, SocketTable};
+use crate::{resolver, shutdown};
+
+// Only flows are tracked, so everything else is dropped in the kernel
+pub const DEFAULT_CAPTURE_FILTER: &str = "tcp or udp";
+
+// In-order payload kept per direction for the protocol dissectors
+const STREAM_PREFIX_LIMIT: usize = 16 * 1024;
+
+// Out-of-order segments buffered per direction before giving up on the gap
+const MAX_PENDING_SEGMENTS: usize = 64;
+
+// Flows without packets for this long are dropped from the live table
+const FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+// lsof is not run more often than this while unmapped flows show up, and a
+// flow is given up on after this many snapshots without its socket
+const SOCKET_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_SOCKET_LOOKUPS: u8 = 3;
+
+// Flows to these ports are dissected as DNS (unicast and multicast)
+const DNS_PORTS: [u16; 2] = [53, 5353];
+
+// Where packets come from: a live interface or a capture file
+pub trait TrafficSource: Send {
+    fn linktype(&self) -> u32;
+
+    // Ok(None) once the source is exhausted. Live sources fail with
+    // io::ErrorKind::TimedOut when nothing arrived for a while, so callers
+    // get a chance to check for shutdown.
+    fn next_packet(&mut self) -> io::Result<Option<Packet>>;
+
+    fn describe(&self) -> String;
+}
+
+// A .pcap file read without libpcap, so captures can be analysed anywhere
+pub struct OfflineCapture {
+    reader: PcapReader<BufReader<File>>,
+    path: PathBuf,
+}
+
+impl OfflineCapture {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let reader = PcapReader::new(BufReader::new(File::open(path)?))?;
+        Ok(OfflineCapture { reader, path: path.to_path_buf() })
+    }
+}
+
+impl TrafficSource for OfflineCapture {
+    fn linktype(&self) -> u32 {
+        self.reader.linktype()
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        self.reader.next_packet()
+    }
+
+    fn describe(&self) -> String {
+        format!("capture file {}", self.path.display())
+    }
+}
+
+// An existing file is read as a .pcap capture; anything else is an interface
+pub fn open_source(source: &str) -> Result<Box<dyn TrafficSource>, String> {
+    let path = Path::new(source);
+    if path.is_file() {
+        let capture = OfflineCapture::open(path).map_err(|e| format!("Failed to read {}: {}", source, e))?;
+        Ok(Box::new(capture))
+    } else {
+        Ok(Box::new(LiveCapture::open(source, DEFAULT_CAPTURE_FILTER)?))
+    }
+}
+
+// One direction of a TCP stream put back in sequence order. Retransmitted
+// bytes are only counted once; a gap that never fills is skipped once too
+// many segments are waiting behind it.
+#[derive(Clone, Debug, Default)]
+pub struct StreamReassembler {
+    next_sequence: Option<u32>,
+    pending: Vec<(u32, Vec<u8>)>,
+    prefix: Vec<u8>,
+    bytes: u64,
+}
+
+// Signed distance from `from` to `to` in sequence space, across wraparound
+fn sequence_offset(from: u32, to: u32) -> i64 {
+    to.wrapping_sub(from) as i32 as i64
+}
+
+impl StreamReassembler {
+    // Add a segment; returns how many new in-order bytes it made available
+    pub fn push(&mut self, sequence: u32, syn: bool, payload: &[u8]) -> u64 {
+        // The SYN takes up one sequence number
+        let start = if syn { sequence.wrapping_add(1) } else { sequence };
+        let next = *self.next_sequence.get_or_insert(start);
+        if payload.is_empty() {
+            return 0;
+        }
+
+        if sequence_offset(next, start) > 0 {
+            if self.pending.len() >= MAX_PENDING_SEGMENTS {
+                // The capture dropped a segment; carry on from the earliest waiting one
+                let earliest = self.pending.iter().map(|(sequence, _)| *sequence).min_by_key(|sequence| sequence_offset(next, *sequence));
+                self.next_sequence = earliest;
+                let delivered = self.drain_pending();
+                return delivered + self.push(start, false, payload);
+            }
+            self.pending.push((start, payload.to_vec()));
+            return 0;
+        }
+        self.accept(start, payload) + self.drain_pending()
+    }
+
+    // Bytes of a segment starting at or before next_sequence
+    fn accept(&mut self, start: u32, payload: &[u8]) -> u64 {
+        let Some(next) = self.next_sequence else { return 0 };
+        let overlap = (-sequence_offset(next, start)) as usize;
+        if overlap >= payload.len() {
+            return 0; // Retransmission of bytes already seen
+        }
+        let data = &payload[overlap..];
+        let room = STREAM_PREFIX_LIMIT.saturating_sub(self.prefix.len());
+        self.prefix.extend_from_slice(&data[..data.len().min(room)]);
+        self.bytes += data.len() as u64;
+        self.next_sequence = Some(next.wrapping_add(data.len() as u32));
+        data.len() as u64
+    }
+
+    fn drain_pending(&mut self) -> u64 {
+        let mut delivered = 0;
+        while let Some(next) = self.next_sequence {
+            let Some(index) = self.pending.iter().position(|(sequence, _)| sequence_offset(next, *sequence) <= 0) else { break };
+            let (start, payload) = self.pending.swap_remove(index);
+            delivered += self.accept(start, &payload);
+        }
+        delivered
+    }
+
+    // Datagrams have no order to restore
+    pub fn push_datagram(&mut self, payload: &[u8]) -> u64 {
+        let room = STREAM_PREFIX_LIMIT.saturating_sub(self.prefix.len());
+        self.prefix.extend_from_slice(&payload[..payload.len().min(room)]);
+        self.bytes += payload.len() as u64;
+        payload.len() as u64
+    }
+
+    // The first STREAM_PREFIX_LIMIT bytes, in order
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+// Identifies a flow regardless of packet direction
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    transport: Transport,
+    low: SocketAddr,
+    high: SocketAddr,
+}
+
+impl FlowKey {
+    pub fn new(transport: Transport, a: SocketAddr, b: SocketAddr) -> Self {
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        FlowKey { transport, low, high }
+    }
+}
+
+// One TCP connection or UDP conversation. Times are capture timestamps
+// (since the Unix epoch), bytes are transport payload.
+#[derive(Clone, Debug)]
+pub struct Flow {
+    pub transport: Transport,
+    // The side that opened the flow; on this Mac that is usually the app
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    pub opened_at: Duration,
+    pub first_byte: Option<Duration>,
+    pub last_byte: Option<Duration>,
+    // First byte from the server, for time-to-first-byte
+    pub first_response: Option<Duration>,
+    pub last_seen: Duration,
+    pub packets: u64,
+    pub outbound: StreamReassembler,
+    pub inbound: StreamReassembler,
+    // FIN or RST seen
+    pub closed: bool,
+    pub pid: Option<i32>,
+    pub process: Option<String>,
+    // Queries in the client's DNS datagrams; streams are dissected on demand
+    dns_queries: Vec<DnsQuery>,
+    socket_lookups: u8,
+}
+
+impl Flow {
+    fn open(segment: &Segment, at: Duration) -> Self {
+        // A bare SYN comes from the client, a SYN-ACK from the server. Without
+        // a handshake the side with the higher (ephemeral) port is the client.
+        let from_client = match segment.transport {
+            Transport::Tcp if segment.flags & TCP_SYN != 0 => segment.flags & TCP_ACK == 0,
+            _ => segment.source.port() >= segment.destination.port(),
+        };
+        let (local, remote) = if from_client {
+            (segment.source, segment.destination)
+        } else {
+            (segment.destination, segment.source)
+        };
+        Flow {
+            transport: segment.transport,
+            local,
+            remote,
+            opened_at: at,
+            first_byte: None,
+            last_byte: None,
+            first_response: None,
+            last_seen: at,
+            packets: 0,
+            outbound: StreamReassembler::default(),
+            inbound: StreamReassembler::default(),
+            closed: false,
+            pid: None,
+            process: None,
+            dns_queries: Vec::new(),
+            socket_lookups: 0,
+        }
+    }
+
+    fn add(&mut self, segment: &Segment, at: Duration) {
+        let outbound = segment.source == self.local;
+        if outbound && segment.transport == Transport::Udp && DNS_PORTS.contains(&self.remote.port()) {
+            dissect::add_dns_queries(&mut self.dns_queries, segment.payload);
+        }
+        let stream = if outbound { &mut self.outbound } else { &mut self.inbound };
+        let delivered = match segment.transport {
+            Transport::Tcp => stream.push(segment.sequence, segment.flags & TCP_SYN != 0, segment.payload),
+            Transport::Udp => stream.push_datagram(segment.payload),
+        };
+        if delivered > 0 {
+            self.first_byte.get_or_insert(at);
+            self.last_byte = Some(at);
+            if !outbound {
+                self.first_response.get_or_insert(at);
+            }
+        }
+        if segment.flags & (TCP_FIN | TCP_RST) != 0 {
+            self.closed = true;
+        }
+        self.packets += 1;
+        self.last_seen = self.last_seen.max(at);
+    }
+
+    // TLS, HTTP and DNS fields of what the client sent
+    pub fn details(&self) -> FlowDetails {
+        match self.transport {
+            Transport::Tcp if DNS_PORTS.contains(&self.remote.port()) => FlowDetails {
+                dns_queries: dissect::parse_dns_stream(self.outbound.prefix()),
+                ..FlowDetails::default()
+            },
+            Transport::Tcp => dissect::dissect_stream(self.outbound.prefix()),
+            Transport::Udp => FlowDetails { dns_queries: self.dns_queries.clone(), ..FlowDetails::default() },
+        }
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.outbound.bytes()
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.inbound.bytes()
+    }
+}
+
+// Every flow seen in a capture
+#[derive(Clone, Debug, Default)]
+pub struct FlowTable {
+    flows: HashMap<FlowKey, Flow>,
+}
+
+impl FlowTable {
+    // Account one captured frame. Returns the key of its flow, or None for
+    // frames that are not TCP or UDP over IP.
+    pub fn observe(&mut self, linktype: u32, packet: &Packet) -> Option<FlowKey> {
+        let segment = pcap::transport_segment(linktype, &packet.data)?;
+        let key = FlowKey::new(segment.transport, segment.source, segment.destination);
+        let flow = match self.flows.get_mut(&key) {
+            // A new handshake on a closed 4-tuple is a new connection
+            Some(flow) if flow.closed && segment.flags & TCP_SYN != 0 && segment.flags & TCP_ACK == 0 => {
+                *flow = Flow::open(&segment, packet.timestamp);
+                flow
+            }
+            Some(flow) => flow,
+            None => self.flows.entry(key).or_insert_with(|| Flow::open(&segment, packet.timestamp)),
+        };
+        flow.add(&segment, packet.timestamp);
+        Some(key)
+    }
+
+    pub fn flows(&self) -> impl Iterator<Item = &Flow> {
+        self.flows.values()
+    }
+
+    // Drop flows idle since before `now - FLOW_IDLE_TIMEOUT`
+    pub fn prune(&mut self, now: Duration) {
+        let cutoff = now.saturating_sub(FLOW_IDLE_TIMEOUT);
+        self.flows.retain(|_, flow| flow.last_seen >= cutoff);
+    }
+
+    // Flows that still need their process looked up
+    pub fn has_unmapped(&self) -> bool {
+        self.flows.values().any(|flow| flow.pid.is_none() && flow.socket_lookups < MAX_SOCKET_LOOKUPS)
+    }
+
+    // Attribute unmapped flows to the processes owning their sockets.
+    // Returns how many were mapped.
+    pub fn assign_processes(&mut self, sockets: &SocketTable) -> usize {
+        let mut mapped = 0;
+        for flow in self.flows.values_mut().filter(|flow| flow.pid.is_none() && flow.socket_lookups < MAX_SOCKET_LOOKUPS) {
+            flow.socket_lookups += 1;
+            // Captures can see both ends of local traffic; try either side as ours
+            let entry = sockets
+                .lookup(flow.transport, flow.local, flow.remote)
+                .or_else(|| sockets.lookup(flow.transport, flow.remote, flow.local));
+            if let Some(entry) = entry {
+                flow.pid = Some(entry.pid);
+                flow.process = Some(entry.command.clone());
+                mapped += 1;
+            }
+        }
+        mapped
+    }
+}
+
+// --- The live table behind --capture ---
+
+static FLOWS: Mutex<Option<FlowTable>> = Mutex::new(None);
+
+fn with_flows<T>(f: impl FnOnce(&mut FlowTable) -> T) -> T {
+    let mut flows = FLOWS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(flows.get_or_insert_with(FlowTable::default))
+}
+
+fn capture_running() -> bool {
+    FLOWS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).is_some()
+}
+
+// Feed `source` into the live flow table until it ends or the watcher stops
+pub fn start_capture(mut source: Box<dyn TrafficSource>) -> JoinHandle<()> {
+    with_flows(|_| {});
+    thread::spawn(move || {
+        let linktype = source.linktype();
+        let mut last_refresh: Option<Instant> = None;
+        let mut last_prune = Instant::now();
+
+        while !shutdown::is_stopping() {
+            match source.next_packet() {
+                Ok(Some(packet)) => {
+                    with_flows(|flows| flows.observe(linktype, &packet));
+                }
+                Ok(None) => {
+                    log::info!("Reached the end of {}", source.describe());
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => {
+                    log::error!("Packet capture on {} failed: {}", source.describe(), e);
+                    break;
+                }
+            }
+
+            if with_flows(|flows| flows.has_unmapped()) && last_refresh.is_none_or(|at| at.elapsed() >= SOCKET_REFRESH_INTERVAL) {
+                let sockets = sockets::snapshot();
+                with_flows(|flows| flows.assign_processes(&sockets));
+                last_refresh = Some(Instant::now());
+            }
+            if last_prune.elapsed() >= FLOW_IDLE_TIMEOUT {
+                with_flows(|flows| {
+                    let newest = flows.flows().map(|flow| flow.last_seen).max().unwrap_or_default();
+                    flows.prune(newest);
+                });
+                last_prune = Instant::now();
+            }
+        }
+    })
+}
+
+// Flows of `pids` that moved bytes between `from` and `until` (Unix epoch),
+// timed relative to `from`. Empty unless a capture is running.
+pub fn timings_for(pids: &HashSet<i32>, from: Duration, until: Duration) -> Vec<FlowTiming> {
+    if !capture_running() {
+        return Vec::new();
+    }
+    let mut timings: Vec<FlowTiming> = with_flows(|flows| {
+        flows
+            .flows()
+            .filter(|flow| flow.pid.is_some_and(|pid| pids.contains(&pid)))
+            .filter_map(|flow| {
+                let (first, last) = (flow.first_byte?, flow.last_byte?);
+                (last >= from && first <= until).then(|| flow_timing(flow, first, last, from))
+            })
+            .collect()
+    });
+    timings.sort_by_key(|timing| timing.first_byte_ms);
+    // The name the app itself used beats a reverse lookup. Lookups can
+    // block, so they happen after the table is unlocked.
+    for timing in &mut timings {
+        timing.hostname = match timing.details.server_name() {
+            Some(name) => Some(name.to_string()),
+            None => timing.remote.rsplit_once(':').and_then(|(host, _)| resolver::hostname(host.trim_matches(['[', ']']))),
+        };
+    }
+    timings
+}
+
+fn flow_timing(flow: &Flow, first: Duration, last: Duration, origin: Duration) -> FlowTiming {
+    let offset_ms = |at: Duration| at.as_millis() as i64 - origin.as_millis() as i64;
+    FlowTiming {
+        protocol: flow.transport.name().to_string(),
+        local: flow.local.to_string(),
+        remote: flow.remote.to_string(),
+        hostname: None,
+        pid: flow.pid,
+        first_byte_ms: offset_ms(first),
+        first_response_ms: flow.first_response.map(offset_ms),
+        last_byte_ms: offset_ms(last),
+        bytes_in: flow.bytes_in(),
+        bytes_out: flow.bytes_out(),
+        details: flow.details(),
+    }
+}
+
+// --- `macos-watcher flows` ---
+
+// Arguments for `macos-watcher flows`
+pub struct FlowsArgs {
+    pub capture_path: PathBuf,
+}
+
+// Read every flow from a capture file
+pub fn read_flows(source: &mut dyn TrafficSource) -> io::Result<FlowTable> {
+    let linktype = source.linktype();
+    let mut table = FlowTable::default();
+    while let Some(packet) = source.next_packet()? {
+        table.observe(linktype, &packet);
+    }
+    Ok(table)
+}
+
+// Entry point for the flows subcommand. Returns the process exit code.
+pub fn run_cli(args: FlowsArgs) -> i32 {
+    let table = match OfflineCapture::open(&args.capture_path).and_then(|mut capture| read_flows(&mut capture)) {
+        Ok(table) => table,
+        Err(e) => {
+            eprintln!("Error: Failed to read {}: {}", args.capture_path.display(), e);
+            return 1;
+        }
+    };
+    print!("{}", format_flows(&table));
+    0
+}
+
+// One row per flow, in the order they were opened. Times are relative to
+// the first packet in the capture.
+pub fn format_flows(table: &FlowTable) -> String {
+    let mut flows: Vec<&Flow> = table.flows().collect();
+    flows.sort_by_key(|flow| (flow.opened_at, flow.local, flow.remote));
+    let start = flows.first().map_or(Duration::ZERO, |flow| flow.opened_at);
+    let millis = |at: Option<Duration>| at.map_or("-".to_string(), |at| format!("+{}", at.saturating_sub(start).as_millis()));
+
+    let rows: Vec<Vec<String>> = flows
+        .iter()
+        .map(|flow| {
+            vec![
+                flow.transport.name().to_string(),
+                flow.local.to_string(),
+                flow.remote.to_string(),
+                millis(Some(flow.opened_at)),
+                millis(flow.first_byte),
+                millis(flow.last_byte),
+                flow.bytes_out().to_string(),
+                flow.bytes_in().to_string(),
+                flow.packets.to_string(),
+                flow.details().summary().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    format_table(
+        &["Proto", "Local", "Remote", "Opened ms", "First byte ms", "Last byte ms", "Bytes out", "Bytes in", "Packets", "Request"],
+        &rows,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns;
+    use crate::pcap::tests::{pcap_file, tcp_frame, udp_frame};
+    use crate::pcap::LINKTYPE_ETHERNET;
+    use crate::sockets::SocketEntry;
+
+    const CLIENT: &str = "10.0.0.5:52000";
+    const SERVER: &str = "93.184.216.34:80";
+
+    fn at(millis: u64) -> Duration {
+        Duration::from_secs(1_700_000_000) + Duration::from_millis(millis)
+    }
+
+    fn table(packets: &[(Duration, Vec<u8>)]) -> FlowTable {
+        let mut table = FlowTable::default();
+        for (timestamp, data) in packets {
+            table.observe(LINKTYPE_ETHERNET, &Packet { timestamp: *timestamp, data: data.clone() });
+        }
+        table
+    }
+
+    // Handshake, one request and response, then the client closes
+    fn http_exchange() -> Vec<(Duration, Vec<u8>)> {
+        vec![
+            (at(0), tcp_frame(CLIENT, SERVER, 100, TCP_SYN, b"")),
+            (at(20), tcp_frame(SERVER, CLIENT, 500, TCP_SYN | TCP_ACK, b"")),
+            (at(21), tcp_frame(CLIENT, SERVER, 101, TCP_ACK, b"GET /index.html HTTP/1.1\r\nHost: Example.com\r\n\r\n")),
+            (at(60), tcp_frame(SERVER, CLIENT, 501, TCP_ACK, b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")),
+            (at(61), tcp_frame(CLIENT, SERVER, 148, TCP_FIN | TCP_ACK, b"")),
+        ]
+    }
+
+    fn socket(pid: i32, command: &str, transport: Transport, local: &str, remote: Option<&str>) -> SocketEntry {
+        SocketEntry {
+            pid,
+            command: command.to_string(),
+            transport,
+            local: local.parse().unwrap(),
+            remote: remote.map(|remote| remote.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn reassembly_restores_order_and_counts_retransmits_once() {
+        let mut stream = StreamReassembler::default();
+        assert_eq!(stream.push(1000, true, b""), 0);
+        assert_eq!(stream.push(1006, false, b"world"), 0);
+        assert_eq!(stream.push(1001, false, b"hello"), 10);
+        assert_eq!(stream.push(1001, false, b"hello"), 0);
+        // Overlaps the last two bytes already delivered
+        assert_eq!(stream.push(1009, false, b"ldxyz"), 3);
+        assert_eq!(stream.prefix(), b"helloworldxyz");
+        assert_eq!(stream.bytes(), 13);
+    }
+
+    #[test]
+    fn reassembly_follows_sequence_wraparound() {
+        let mut stream = StreamReassembler::default();
+        assert_eq!(stream.push(u32::MAX - 2, false, b"abc"), 3);
+        assert_eq!(stream.push(2, false, b"fg"), 0);
+        assert_eq!(stream.push(0, false, b"de"), 4);
+        assert_eq!(stream.prefix(), b"abcdefg");
+        assert_eq!(sequence_offset(u32::MAX, 1), 2);
+        assert_eq!(sequence_offset(1, u32::MAX), -2);
+    }
+
+    #[test]
+    fn reassembly_skips_a_gap_that_never_fills() {
+        let mut stream = StreamReassembler::default();
+        assert_eq!(stream.push(0, false, b"a"), 1);
+        // Byte 1 was never captured
+        for sequence in 2..2 + MAX_PENDING_SEGMENTS as u32 {
+            assert_eq!(stream.push(sequence, false, b"b"), 0);
+        }
+        assert_eq!(stream.push(2 + MAX_PENDING_SEGMENTS as u32, false, b"c"), MAX_PENDING_SEGMENTS as u64 + 1);
+        assert_eq!(stream.bytes(), MAX_PENDING_SEGMENTS as u64 + 2);
+        assert_eq!(stream.prefix().last(), Some(&b'c'));
+    }
+
+    #[test]
+    fn prefix_stops_at_the_limit_but_bytes_keep_counting() {
+        let mut stream = StreamReassembler::default();
+        assert_eq!(stream.push_datagram(&vec![0; STREAM_PREFIX_LIMIT + 10]), STREAM_PREFIX_LIMIT as u64 + 10);
+        stream.push_datagram(b"more");
+        assert_eq!(stream.prefix().len(), STREAM_PREFIX_LIMIT);
+        assert_eq!(stream.bytes(), STREAM_PREFIX_LIMIT as u64 + 14);
+    }
+
+    #[test]
+    fn tcp_flows_take_their_direction_from_the_handshake() {
+        let table = table(&http_exchange());
+        let flows: Vec<&Flow> = table.flows().collect();
+        assert_eq!(flows.len(), 1);
+        let flow = flows[0];
+        assert_eq!((flow.local, flow.remote), (CLIENT.parse().unwrap(), SERVER.parse().unwrap()));
+        assert_eq!((flow.opened_at, flow.first_byte, flow.first_response, flow.last_byte), (at(0), Some(at(21)), Some(at(60)), Some(at(60))));
+        assert_eq!((flow.bytes_out(), flow.bytes_in(), flow.packets), (47, 40, 5));
+        assert!(flow.closed);
+        assert_eq!(flow.details().summary().as_deref(), Some("example.com GET /index.html"));
+    }
+
+    #[test]
+    fn a_new_handshake_on_a_closed_tuple_starts_a_new_flow() {
+        let mut packets = http_exchange();
+        packets.push((at(5000), tcp_frame(CLIENT, SERVER, 9000, TCP_SYN, b"")));
+        let table = table(&packets);
+        let flow = table.flows().next().unwrap();
+        assert_eq!((flow.opened_at, flow.packets, flow.closed, flow.bytes_out()), (at(5000), 1, false, 0));
+    }
+
+    #[test]
+    fn malformed_and_non_ip_frames_are_ignored() {
+        let mut flows = FlowTable::default();
+        let frame = tcp_frame(CLIENT, SERVER, 100, TCP_SYN, b"");
+        let mut arp = frame.clone();
+        arp[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        for data in [Vec::new(), frame[..20].to_vec(), frame[..40].to_vec(), arp, vec![0xFF; 64]] {
+            assert_eq!(flows.observe(LINKTYPE_ETHERNET, &Packet { timestamp: at(0), data }), None);
+        }
+        assert_eq!(flows.flows().count(), 0);
+        assert!(flows.observe(LINKTYPE_ETHERNET, &Packet { timestamp: at(0), data: frame }).is_some());
+    }
+
+    #[test]
+    fn udp_flows_collect_the_clients_dns_queries() {
+        let query = dns::encode_query(7, "example.com", dns::TYPE_AAAA);
+        let table = table(&[
+            (at(0), udp_frame("10.0.0.5:53000", "10.0.0.1:53", &query)),
+            (at(3), udp_frame("10.0.0.1:53", "10.0.0.5:53000", &dns::tests::response(7, 0, "example.com", dns::TYPE_AAAA, &[]))),
+            (at(4), udp_frame("10.0.0.5:53000", "10.0.0.1:53", b"\x00\x01garbage")),
+        ]);
+        let flow = table.flows().next().unwrap();
+        // No handshake, so the higher (ephemeral) port is the client
+        assert_eq!(flow.local, "10.0.0.5:53000".parse().unwrap());
+        assert_eq!(flow.details().summary().as_deref(), Some("DNS AAAA example.com"));
+        assert_eq!(flow.first_response, Some(at(3)));
+    }
+
+    #[test]
+    fn processes_are_assigned_from_either_side_and_given_up_on() {
+        let mut flows = table(&[
+            (at(0), tcp_frame(CLIENT, SERVER, 100, TCP_SYN, b"")),
+            (at(1), udp_frame("10.0.0.5:5353", "224.0.0.251:5353", b"x")),
+            (at(2), tcp_frame("127.0.0.1:8080", "127.0.0.1:61000", 1, TCP_ACK, b"y")),
+        ]);
+        let sockets = SocketTable::new(vec![
+            socket(412, "Safari", Transport::Tcp, CLIENT, Some(SERVER)),
+            socket(88, "mDNSResponder", Transport::Udp, "0.0.0.0:5353", None),
+        ]);
+        assert_eq!(flows.assign_processes(&sockets), 2);
+        // The loopback flow's socket is only listed from the other end
+        let loopback = SocketTable::new(vec![socket(501, "node", Transport::Tcp, "127.0.0.1:8080", Some("127.0.0.1:61000"))]);
+        assert_eq!(flows.assign_processes(&loopback), 1);
+
+        let mut owners: Vec<(Option<i32>, Option<String>)> = flows.flows().map(|flow| (flow.pid, flow.process.clone())).collect();
+        owners.sort();
+        assert_eq!(owners, [(Some(88), Some("mDNSResponder".to_string())), (Some(412), Some("Safari".to_string())), (Some(501), Some("node".to_string()))]);
+        assert!(!flows.has_unmapped());
+
+        let mut orphan = table(&[(at(0), tcp_frame(CLIENT, SERVER, 100, TCP_SYN, b""))]);
+        for _ in 0..MAX_SOCKET_LOOKUPS {
+            assert!(orphan.has_unmapped());
+            assert_eq!(orphan.assign_processes(&SocketTable::default()), 0);
+        }
+        assert!(!orphan.has_unmapped());
+    }
+
+    #[test]
+    fn prune_drops_idle_flows() {
+        let mut flows = table(&[
+            (at(0), tcp_frame(CLIENT, SERVER, 100, TCP_SYN, b"")),
+            (at(200_000), udp_frame("10.0.0.5:53000", "10.0.0.1:53", b"x")),
+        ]);
+        flows.prune(at(0) + FLOW_IDLE_TIMEOUT);
+        assert_eq!(flows.flows().count(), 2);
+        flows.prune(at(1) + FLOW_IDLE_TIMEOUT);
+        assert_eq!(flows.flows().map(|flow| flow.transport).collect::<Vec<_>>(), [Transport::Udp]);
+    }
+
+    #[test]
+    fn capture_files_are_read_and_formatted() {
+        let path = std::env::temp_dir().join(format!("macos-watcher-flows-{}.pcap", std::process::id()));
+        let mut packets = http_exchange();
+        packets.push((at(100), udp_frame("10.0.0.5:53000", "10.0.0.1:53", &dns::encode_query(1, "example.org", dns::TYPE_A))));
+        std::fs::write(&path, pcap_file(&packets)).unwrap();
+
+        let mut source = open_source(path.to_str().unwrap()).unwrap();
+        assert_eq!(source.describe(), format!("capture file {}", path.display()));
+        let flows = read_flows(source.as_mut()).unwrap();
+        let output = format_flows(&flows);
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("Proto"), "{}", output);
+        let tcp = lines.iter().position(|line| line.contains("example.com GET /index.html")).unwrap();
+        let udp = lines.iter().position(|line| line.contains("DNS A example.org")).unwrap();
+        assert!(tcp < udp, "{}", output);
+        assert!(lines[tcp].contains("+0") && lines[tcp].contains("+21") && lines[tcp].contains("+60"), "{}", output);
+        assert!(lines[udp].contains("+100"), "{}", output);
+    }
+}
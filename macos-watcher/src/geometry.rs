@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::display::DisplayPoint;
+
+// CoreGraphics geometry types, shared by the event tap and the Accessibility helpers.
+// Coordinates are in global display points with the origin at the top left of the main display.
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CGPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CGSize {
+    pub width: f64,
+    pub height: f64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CGRect {
+    pub origin: CGPoint,
+    pub size: CGSize,
+}
+
+impl CGRect {
+    pub fn contains(&self, point: CGPoint) -> bool {
+        point.x >= self.origin.x
+            && point.y >= self.origin.y
+            && point.x < self.origin.x + self.size.width
+            && point.y < self.origin.y + self.size.height
+    }
+
+    // Point relative to the rect's origin
+    pub fn relative_point(&self, point: CGPoint) -> CGPoint {
+        CGPoint {
+            x: point.x - self.origin.x,
+            y: point.y - self.origin.y,
+        }
+    }
+
+    // Nearest point inside the rect. The far edges are exclusive, so points
+    // past them land on the last whole point (origin + size - 1), which is
+    // also where macOS stops the cursor at a display edge.
+    pub fn clamp(&self, point: CGPoint) -> CGPoint {
+        CGPoint {
+            x: point.x.clamp(self.origin.x, self.origin.x + (self.size.width - 1.0).max(0.0)),
+            y: point.y.clamp(self.origin.y, self.origin.y + (self.size.height - 1.0).max(0.0)),
+        }
+    }
+
+    // Distance from a point inside the rect to its nearest edge
+    pub fn edge_distance(&self, point: CGPoint) -> f64 {
+        let relative = self.relative_point(point);
+        relative
+            .x
+            .min(relative.y)
+            .min(self.size.width - relative.x)
+            .min(self.size.height - relative.y)
+    }
+
+    // Inside the rect but within EDGE_CLICK_THRESHOLD of an edge
+    pub fn near_edge(&self, point: CGPoint) -> bool {
+        self.contains(point) && self.edge_distance(point) < EDGE_CLICK_THRESHOLD
+    }
+}
+
+// Clicks closer than this to an element edge are flagged as on_edge
+pub const EDGE_CLICK_THRESHOLD: f64 = 2.0;
+
+// Geometry and window context for a clicked element
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClickGeometry {
+    pub element_frame: Option<CGRect>,
+    // Click position relative to the element's origin
+    pub relative_click_point: Option<CGPoint>,
+    // The click landed on the edge of the element frame, where it may have missed
+    pub on_edge: bool,
+    pub window_title: Option<String>,
+    pub window_id: Option<u32>,
+    pub window_frame: Option<CGRect>,
+    // Display the click landed on, with display-local and backing coordinates
+    pub display: Option<DisplayPoint>,
+}
+
+impl ClickGeometry {
+    // Extra fields appended to the click log lines
+    pub fn log_fields(&self) -> String {
+        format!(
+            "Frame={}, RelPos={}, OnEdge={}, Window='{}', WindowID={}, WindowFrame={}, Display={}, LocalPos={}, Scale={}",
+            format_rect(self.element_frame),
+            format_point(self.relative_click_point),
+            self.on_edge,
+            self.window_title.as_deref().unwrap_or("<No Title>"),
+            self.window_id.map(|id| id.to_string()).unwrap_or_else(|| "<None>".to_string()),
+            format_rect(self.window_frame),
+            self.display.map(|display| display.display_id.to_string()).unwrap_or_else(|| "<None>".to_string()),
+            format_point(self.display.map(|display| display.local)),
+            self.display.map(|display| format!("{:.1}", display.scale)).unwrap_or_else(|| "<None>".to_string())
+        )
+    }
+}
+
+pub fn format_point(point: Option<CGPoint>) -> String {
+    match point {
+        Some(point) => format!("({:.1}, {:.1})", point.x, point.y),
+        None => "<None>".to_string(),
+    }
+}
+
+pub fn format_rect(rect: Option<CGRect>) -> String {
+    match rect {
+        Some(rect) => format!(
+            "({:.1}, {:.1}, {:.1}, {:.1})",
+            rect.origin.x, rect.origin.y, rect.size.width, rect.size.height
+        ),
+        None => "<None>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect() -> CGRect {
+        CGRect { origin: CGPoint { x: 100.0, y: 30.0 }, size: CGSize { width: 60.0, height: 24.0 } }
+    }
+
+    fn point(x: f64, y: f64) -> CGPoint {
+        CGPoint { x, y }
+    }
+
+    #[test]
+    fn points_are_made_relative_to_the_origin() {
+        assert_eq!(rect().relative_point(point(120.0, 45.5)), point(20.0, 15.5));
+        assert_eq!(rect().relative_point(point(100.0, 30.0)), point(0.0, 0.0));
+        assert_eq!(rect().relative_point(point(90.0, 60.0)), point(-10.0, 30.0));
+    }
+
+    #[test]
+    fn clamping_keeps_inside_points_and_pulls_outside_ones_onto_the_last_point() {
+        assert_eq!(rect().clamp(point(120.0, 45.5)), point(120.0, 45.5));
+        assert_eq!(rect().clamp(point(100.0, 30.0)), point(100.0, 30.0));
+        assert_eq!(rect().clamp(point(50.0, 10.0)), point(100.0, 30.0));
+        assert_eq!(rect().clamp(point(160.0, 54.0)), point(159.0, 53.0));
+        assert_eq!(rect().clamp(point(500.0, 40.0)), point(159.0, 40.0));
+        assert!(rect().contains(rect().clamp(point(500.0, 500.0))));
+
+        // A rect narrower than one point clamps onto its origin
+        let sliver = CGRect { origin: point(10.0, 10.0), size: CGSize { width: 0.5, height: 0.0 } };
+        assert_eq!(sliver.clamp(point(20.0, 20.0)), point(10.0, 10.0));
+    }
+
+    #[test]
+    fn edge_distance_is_measured_to_the_nearest_edge() {
+        assert_eq!(rect().edge_distance(point(130.0, 42.0)), 12.0);
+        assert_eq!(rect().edge_distance(point(120.0, 31.0)), 1.0);
+        assert_eq!(rect().edge_distance(point(159.5, 42.0)), 0.5);
+        assert_eq!(rect().edge_distance(point(100.0, 42.0)), 0.0);
+    }
+
+    #[test]
+    fn only_inside_points_close_to_an_edge_are_on_the_edge() {
+        assert!(!rect().near_edge(point(130.0, 42.0)));
+        assert!(!rect().near_edge(point(120.0, 32.0)));
+        assert!(rect().near_edge(point(120.0, 31.9)));
+        assert!(rect().near_edge(point(100.0, 42.0)));
+        assert!(rect().near_edge(point(159.0, 53.0)));
+        // Outside points are misses, not edge clicks
+        assert!(!rect().near_edge(point(99.0, 42.0)));
+        assert!(!rect().near_edge(point(160.0, 42.0)));
+        assert!(!rect().near_edge(point(130.0, 54.0)));
+    }
+
+    #[test]
+    fn log_fields_cover_every_field_and_mark_missing_ones() {
+        let geometry = ClickGeometry {
+            element_frame: Some(rect()),
+            relative_click_point: Some(point(0.5, 15.5)),
+            on_edge: true,
+            window_title: Some("Draft".to_string()),
+            window_id: Some(77),
+            window_frame: Some(CGRect { origin: point(0.0, 0.0), size: CGSize { width: 800.0, height: 600.0 } }),
+            display: Some(DisplayPoint { display_id: 1, local: point(100.5, 45.5), backing: point(201.0, 91.0), scale: 2.0 }),
+        };
+        assert_eq!(
+            geometry.log_fields(),
+            "Frame=(100.0, 30.0, 60.0, 24.0), RelPos=(0.5, 15.5), OnEdge=true, Window='Draft', WindowID=77, \
+             WindowFrame=(0.0, 0.0, 800.0, 600.0), Display=1, LocalPos=(100.5, 45.5), Scale=2.0"
+        );
+        assert_eq!(
+            ClickGeometry::default().log_fields(),
+            "Frame=<None>, RelPos=<None>, OnEdge=false, Window='<No Title>', WindowID=<None>, \
+             WindowFrame=<None>, Display=<None>, LocalPos=<None>, Scale=<None>"
+        );
+    }
+}
@@ -0,0 +1,545 @@
+use chrono::{DateTime, Duration as ChronoDuration, Local, SecondsFormat};
+use serde::Serialize;
+use std::net::IpAddr;
+
+use crate::correlation::{ConnectionDelta, FlowTiming};
+use crate::otel;
+use crate::dashboard::format_bytes;
+use crate::store::StoredAction;
+
+// HTTP Archive 1.2 (http://www.softwareishard.com/blog/har-12-spec/). Browsers
+// write one page per navigation; here every click is a page and every flow of
+// the clicked process during its window is an entry.
+pub const HAR_VERSION: &str = "1.2";
+
+#[derive(Serialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Serialize)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: Creator,
+    pub pages: Vec<Page>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+pub struct Creator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page {
+    pub started_date_time: String,
+    pub id: String,
+    pub title: String,
+    pub page_timings: PageTimings,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+// -1 when unknown, as the spec allows
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageTimings {
+    pub on_content_load: f64,
+    pub on_load: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub pageref: String,
+    pub started_date_time: String,
+    pub time: f64,
+    pub request: Request,
+    pub response: Response,
+    pub cache: Cache,
+    pub timings: Timings,
+    #[serde(rename = "serverIPAddress", skip_serializing_if = "Option::is_none")]
+    pub server_ip_address: Option<String>,
+    // Client port, so entries of the same flow can be told apart
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    pub cookies: Vec<NameValue>,
+    pub headers: Vec<NameValue>,
+    pub query_string: Vec<NameValue>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub status: u16,
+    pub status_text: String,
+    pub http_version: String,
+    pub cookies: Vec<NameValue>,
+    pub headers: Vec<NameValue>,
+    pub content: Content,
+    #[serde(rename = "redirectURL")]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    pub size: i64,
+    pub mime_type: String,
+}
+
+#[derive(Serialize)]
+pub struct NameValue {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+pub struct Cache {}
+
+// Only send, wait and receive are required; the rest are -1 because a
+// flow's connection setup is not separated from its data
+#[derive(Serialize)]
+pub struct Timings {
+    pub blocked: f64,
+    pub dns: f64,
+    pub connect: f64,
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+    pub ssl: f64,
+}
+
+// Build the archive for the clicks among `actions`. `window` limits entries to
+// flows that started this long after their click (see StoredAction::flows_within).
+pub fn build(actions: &[StoredAction], window: Option<std::time::Duration>) -> Har {
+    let mut pages = Vec::new();
+    let mut entries = Vec::new();
+
+    for action in actions.iter().filter(|action| action.kind == "click") {
+        let page_id = format!("page_{}", pages.len() + 1);
+        let traffic = action.traffic.as_ref();
+        let flows = action.flows_within(window);
+        let mut page_entries: Vec<Entry> = flows.iter().map(|flow| flow_entry(&page_id, action.timestamp, flow)).collect();
+        if let Some(traffic) = traffic.filter(|traffic| traffic.flows.is_empty()) {
+            // No packet capture: only nettop's per-connection totals are known
+            page_entries.extend(traffic.connections.iter().map(|connection| connection_entry(&page_id, action, connection)));
+        }
+        let on_load = flows.iter().map(|flow| flow.last_byte_ms).max().map_or(-1.0, |last| last as f64);
+
+        pages.push(Page {
+            started_date_time: har_time(action.timestamp),
+            id: page_id,
+            title: otel::action_name(&action.selector),
+            page_timings: PageTimings { on_content_load: -1.0, on_load },
+            comment: Some(page_comment(action)),
+        });
+        entries.append(&mut page_entries);
+    }
+
+    Har {
+        log: HarLog {
+            version: HAR_VERSION.to_string(),
+            creator: Creator { name: env!("CARGO_PKG_NAME").to_string(), version: env!("CARGO_PKG_VERSION").to_string() },
+            pages,
+            entries,
+        },
+    }
+}
+
+// e.g. "Safari (pid 812) in \"Inbox\", 1.2 KB out, 48.0 KB in"
+fn page_comment(action: &StoredAction) -> String {
+    let mut comment = format!("{} (pid {})", action.app_name, action.pid);
+    if let Some(title) = &action.window_title {
+        comment.push_str(&format!(" in \"{}\"", title));
+    }
+    if let Some(traffic) = &action.traffic {
+        comment.push_str(&format!(", {} out, {} in", format_bytes(traffic.bytes_out as f64), format_bytes(traffic.bytes_in as f64)));
+    }
+    comment
+}
+
+// ISO 8601 with milliseconds, as HAR viewers expect
+fn har_time(time: DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+fn flow_entry(page_id: &str, clicked_at: DateTime<Local>, flow: &FlowTiming) -> Entry {
+    // Everything before the server's first byte is waiting, everything after
+    // is receiving; a flow that never got an answer only waited
+    let first_response = flow.first_response_ms.unwrap_or(flow.last_byte_ms).clamp(flow.first_byte_ms, flow.last_byte_ms);
+    let wait = (first_response - flow.first_byte_ms) as f64;
+    let receive = (flow.last_byte_ms - first_response) as f64;
+
+    let (method, url, http_version) = describe_flow(flow);
+    let request = flow.details.http_requests.first();
+    let extra_requests = flow.details.http_requests.len().saturating_sub(1);
+    let mut comment = format!("{} {} -> {}", flow.protocol, flow.local, flow.remote);
+    if let Some(pid) = flow.pid {
+        comment.push_str(&format!(", pid {}", pid));
+    }
+    if extra_requests > 0 {
+        comment.push_str(&format!(", {} more request(s) on this connection", extra_requests));
+    }
+
+    Entry {
+        pageref: page_id.to_string(),
+        started_date_time: har_time(clicked_at + ChronoDuration::milliseconds(flow.first_byte_ms)),
+        time: wait + receive,
+        request: Request {
+            method,
+            url,
+            http_version: http_version.clone(),
+            cookies: Vec::new(),
+            headers: request
+                .and_then(|request| request.host.as_ref())
+                .map(|host| vec![NameValue { name: "Host".to_string(), value: host.clone() }])
+                .unwrap_or_default(),
+            query_string: request.map(|request| query_string(&request.path)).unwrap_or_default(),
+            headers_size: -1,
+            // Payload bytes on the wire, including headers and TLS records
+            body_size: flow.bytes_out as i64,
+        },
+        response: Response {
+            status: 0,
+            status_text: String::new(),
+            http_version,
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            content: Content { size: flow.bytes_in as i64, mime_type: String::new() },
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: flow.bytes_in as i64,
+        },
+        cache: Cache {},
+        timings: Timings { blocked: -1.0, dns: -1.0, connect: -1.0, send: 0.0, wait, receive, ssl: -1.0 },
+        server_ip_address: remote_ip(&flow.remote),
+        connection: flow.local.rsplit_once(':').map(|(_, port)| port.to_string()),
+        comment: Some(comment),
+    }
+}
+
+// Method, URL and protocol version of an entry, from what was dissected:
+// "GET http://host/path", "CONNECT https://host:443/", "TLS https://sni:443/",
+// "DNS dns://name" or "TCP tcp://192.0.2.1:5222". URLs are always absolute.
+fn describe_flow(flow: &FlowTiming) -> (String, String, String) {
+    let port = flow.remote.rsplit_once(':').map_or("", |(_, port)| port);
+    let host = flow.details.server_name().or(flow.hostname.as_deref());
+
+    if let Some(request) = flow.details.http_requests.first() {
+        let host = request.host.as_deref().or(host).unwrap_or(&flow.remote);
+        let url = if request.path.starts_with('/') {
+            format!("http://{}{}", host, request.path)
+        } else if request.path.contains("://") {
+            request.path.clone()
+        } else if request.method == "CONNECT" {
+            // Authority form ("example.net:443"), the start of a tunnel
+            format!("https://{}/", request.path)
+        } else {
+            // Asterisk form ("OPTIONS *")
+            format!("http://{}/", host)
+        };
+        return (request.method.clone(), url, "HTTP/1.1".to_string());
+    }
+    if let Some(server_name) = &flow.details.tls_server_name {
+        let version = flow.details.tls_alpn.first().cloned().unwrap_or_default();
+        return ("TLS".to_string(), format!("https://{}:{}/", server_name, port), version);
+    }
+    if let Some(query) = flow.details.dns_queries.first() {
+        return ("DNS".to_string(), format!("dns://{}/?type={}", query.name, query.record_type), String::new());
+    }
+    let scheme = flow.protocol.to_ascii_lowercase();
+    let url = match host {
+        Some(host) => format!("{}://{}:{}/", scheme, host, port),
+        None => format!("{}://{}/", scheme, flow.remote),
+    };
+    (flow.protocol.to_ascii_uppercase(), url, String::new())
+}
+
+fn connection_entry(page_id: &str, action: &StoredAction, connection: &ConnectionDelta) -> Entry {
+    let scheme = connection.protocol.to_ascii_lowercase();
+    let url = match connection.remote_port {
+        Some(port) if connection.remote_host.contains(':') && connection.hostname.is_none() => {
+            format!("{}://[{}]:{}/", scheme, connection.remote_host, port)
+        }
+        Some(port) => format!("{}://{}:{}/", scheme, connection.display_host(), port),
+        None => format!("{}://{}/", scheme, connection.display_host()),
+    };
+    Entry {
+        pageref: page_id.to_string(),
+        started_date_time: har_time(action.timestamp),
+        time: 0.0,
+        request: Request {
+            method: connection.protocol.to_ascii_uppercase(),
+            url,
+            http_version: String::new(),
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            query_string: Vec::new(),
+            headers_size: -1,
+            body_size: connection.bytes_out as i64,
+        },
+        response: Response {
+            status: 0,
+            status_text: String::new(),
+            http_version: String::new(),
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            content: Content { size: connection.bytes_in as i64, mime_type: String::new() },
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: connection.bytes_in as i64,
+        },
+        cache: Cache {},
+        timings: Timings { blocked: -1.0, dns: -1.0, connect: -1.0, send: 0.0, wait: 0.0, receive: 0.0, ssl: -1.0 },
+        // nettop shows "*" for sockets that never connected
+        server_ip_address: connection.remote_host.parse::<IpAddr>().is_ok().then(|| connection.remote_host.clone()),
+        connection: None,
+        comment: Some(format!(
+            "Sampled with nettop; timing unknown{}",
+            if connection.is_new { ", new connection" } else { "" }
+        )),
+    }
+}
+
+// "192.0.2.1:443" or "[2001:db8::1]:443" without the port
+fn remote_ip(remote: &str) -> Option<String> {
+    let (ip, _) = remote.rsplit_once(':')?;
+    Some(ip.trim_start_matches('[').trim_end_matches(']').to_string())
+}
+
+fn query_string(path: &str) -> Vec<NameValue> {
+    let Some((_, query)) = path.split_once('?') else { return Vec::new() };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            NameValue { name: name.to_string(), value: value.to_string() }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::{json, Value};
+
+    use crate::dissect::{self, FlowDetails, HttpRequest};
+    use crate::dns;
+    use crate::events::AxSelector;
+    use crate::store::StoredTraffic;
+
+    fn clicked_at() -> DateTime<Local> {
+        Local.timestamp_millis_opt(1_700_000_000_000).unwrap()
+    }
+
+    fn action(kind: &str, traffic: Option<StoredTraffic>) -> StoredAction {
+        StoredAction {
+            timestamp: clicked_at(),
+            kind: kind.to_string(),
+            pid: 812,
+            app_name: "Safari".to_string(),
+            selector: AxSelector { identifier: None, role: Some("AXButton".to_string()), label: Some("Reload".to_string()) },
+            window_title: Some("Inbox".to_string()),
+            keycode: None,
+            ax_lookup_ms: None,
+            traffic,
+        }
+    }
+
+    fn traffic(connections: Vec<ConnectionDelta>, flows: Vec<FlowTiming>) -> StoredTraffic {
+        StoredTraffic { window_ms: 2000, bytes_in: 49_152, bytes_out: 1229, connections, flows }
+    }
+
+    fn flow(remote: &str, first_byte_ms: i64, first_response_ms: Option<i64>, last_byte_ms: i64, details: FlowDetails) -> FlowTiming {
+        FlowTiming {
+            protocol: "tcp".to_string(),
+            local: "10.0.0.5:52000".to_string(),
+            remote: remote.to_string(),
+            hostname: None,
+            pid: Some(812),
+            first_byte_ms,
+            first_response_ms,
+            last_byte_ms,
+            bytes_in: 4000,
+            bytes_out: 300,
+            details,
+        }
+    }
+
+    fn connection(remote_host: &str, remote_port: Option<u16>, hostname: Option<&str>) -> ConnectionDelta {
+        ConnectionDelta {
+            protocol: "tcp".to_string(),
+            remote_host: remote_host.to_string(),
+            remote_port,
+            hostname: hostname.map(str::to_string),
+            bytes_in: 100,
+            bytes_out: 20,
+            is_new: true,
+        }
+    }
+
+    // HAR 1.2 as a JSON schema, so every archive below is checked against the spec
+    const SCHEMA: &str = include_str!("../tests/fixtures/har-1.2.schema.json");
+
+    fn schema_errors(har: &Value) -> Vec<String> {
+        let schema: Value = serde_json::from_str(SCHEMA).unwrap();
+        let validator = jsonschema::draft7::options().should_validate_formats(true).build(&schema).unwrap();
+        validator.iter_errors(har).map(|error| format!("{}: {}", error.instance_path, error)).collect()
+    }
+
+    fn archive(actions: &[StoredAction], window: Option<std::time::Duration>) -> Value {
+        let har = serde_json::to_value(build(actions, window)).unwrap();
+        assert_eq!(schema_errors(&har), Vec::<String>::new());
+        har
+    }
+
+    fn urls(har: &Value) -> Vec<&str> {
+        har["log"]["entries"].as_array().unwrap().iter().map(|entry| entry["request"]["url"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn every_click_is_a_page_and_keys_are_skipped() {
+        let har = archive(&[action("click", None), action("key", None), action("click", Some(traffic(Vec::new(), Vec::new())))], None);
+        let log = &har["log"];
+        assert_eq!(log["version"], HAR_VERSION);
+        assert_eq!(log["creator"]["name"], "macos-watcher");
+        let pages = log["pages"].as_array().unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0]["id"], "page_1");
+        assert_eq!(pages[1]["id"], "page_2");
+        assert_eq!(pages[0]["title"], "Button Clicked Reload");
+        assert_eq!(pages[0]["startedDateTime"], har_time(clicked_at()));
+        assert_eq!(pages[0]["pageTimings"], json!({ "onContentLoad": -1.0, "onLoad": -1.0 }));
+        assert_eq!(pages[0]["comment"], "Safari (pid 812) in \"Inbox\"");
+        assert_eq!(pages[1]["comment"], "Safari (pid 812) in \"Inbox\", 1.2 KB out, 48.0 KB in");
+        assert_eq!(log["entries"], json!([]));
+    }
+
+    #[test]
+    fn flows_become_timed_entries() {
+        let http = FlowDetails {
+            http_requests: vec![
+                HttpRequest { method: "GET".to_string(), path: "/search?q=rust&lang=&flag".to_string(), host: Some("example.com".to_string()) },
+                HttpRequest { method: "GET".to_string(), path: "/favicon.ico".to_string(), host: Some("example.com".to_string()) },
+            ],
+            ..FlowDetails::default()
+        };
+        let flows = vec![flow("93.184.216.34:80", 15, Some(95), 140, http), flow("[2001:db8::1]:443", 30, None, 60, FlowDetails::default())];
+        let har = archive(&[action("click", Some(traffic(vec![connection("192.0.2.1", Some(443), None)], flows)))], None);
+
+        assert_eq!(har["log"]["pages"][0]["pageTimings"]["onLoad"], 140.0);
+        // Captured flows replace nettop's totals
+        assert_eq!(urls(&har), ["http://example.com/search?q=rust&lang=&flag", "tcp://[2001:db8::1]:443/"]);
+
+        let entry = &har["log"]["entries"][0];
+        assert_eq!(entry["pageref"], "page_1");
+        assert_eq!(entry["startedDateTime"], har_time(clicked_at() + ChronoDuration::milliseconds(15)));
+        assert_eq!(entry["time"], 125.0);
+        assert_eq!(entry["timings"], json!({ "blocked": -1.0, "dns": -1.0, "connect": -1.0, "send": 0.0, "wait": 80.0, "receive": 45.0, "ssl": -1.0 }));
+        assert_eq!(entry["request"]["method"], "GET");
+        assert_eq!(entry["request"]["headers"], json!([{ "name": "Host", "value": "example.com" }]));
+        assert_eq!(
+            entry["request"]["queryString"],
+            json!([{ "name": "q", "value": "rust" }, { "name": "lang", "value": "" }, { "name": "flag", "value": "" }])
+        );
+        assert_eq!((&entry["request"]["bodySize"], &entry["response"]["bodySize"]), (&json!(300), &json!(4000)));
+        assert_eq!(entry["serverIPAddress"], "93.184.216.34");
+        assert_eq!(entry["connection"], "52000");
+        assert_eq!(entry["comment"], "tcp 10.0.0.5:52000 -> 93.184.216.34:80, pid 812, 1 more request(s) on this connection");
+
+        // Never answered: all of it was waiting
+        let unanswered = &har["log"]["entries"][1];
+        assert_eq!((&unanswered["timings"]["wait"], &unanswered["timings"]["receive"]), (&json!(30.0), &json!(0.0)));
+        assert_eq!(unanswered["serverIPAddress"], "2001:db8::1");
+        assert_eq!(unanswered["request"]["method"], "TCP");
+    }
+
+    #[test]
+    fn urls_describe_whatever_was_dissected() {
+        let tls = FlowDetails { tls_server_name: Some("api.example.com".to_string()), tls_alpn: vec!["h2".to_string()], ..FlowDetails::default() };
+        let dns_queries = dissect::parse_dns_queries(&dns::encode_query(1, "example.org", dns::TYPE_A));
+        let dns = FlowDetails { dns_queries, ..FlowDetails::default() };
+        let absolute = FlowDetails {
+            http_requests: vec![HttpRequest { method: "CONNECT".to_string(), path: "example.net:443".to_string(), host: None }],
+            ..FlowDetails::default()
+        };
+        let mut resolved = flow("192.0.2.1:5222", 0, None, 0, FlowDetails::default());
+        resolved.hostname = Some("chat.example.com".to_string());
+
+        let flows = vec![
+            flow("192.0.2.1:443", 0, None, 0, tls),
+            flow("192.0.2.53:53", 0, None, 0, dns),
+            flow("192.0.2.2:8080", 0, None, 0, absolute),
+            resolved,
+        ];
+        let har = archive(&[action("click", Some(traffic(Vec::new(), flows)))], None);
+        assert_eq!(urls(&har), ["https://api.example.com:443/", "dns://example.org/?type=A", "https://example.net:443/", "tcp://chat.example.com:5222/"]);
+        let versions: Vec<&Value> = har["log"]["entries"].as_array().unwrap().iter().map(|entry| &entry["request"]["httpVersion"]).collect();
+        assert_eq!(versions, [&json!("h2"), &json!(""), &json!("HTTP/1.1"), &json!("")]);
+    }
+
+    #[test]
+    fn the_schema_rejects_what_the_spec_does_not_allow() {
+        let flows = vec![flow("192.0.2.1:443", 0, None, 0, FlowDetails::default())];
+        let har = archive(&[action("click", Some(traffic(Vec::new(), flows)))], None);
+        let mut relative = har.clone();
+        relative["log"]["entries"][0]["request"]["url"] = json!("example.net:443");
+        assert_eq!(schema_errors(&relative).len(), 1, "{:?}", schema_errors(&relative));
+        let mut incomplete = har.clone();
+        incomplete["log"]["entries"][0]["timings"].as_object_mut().unwrap().remove("wait");
+        assert_eq!(schema_errors(&incomplete).len(), 1);
+        let mut negative = har;
+        negative["log"]["entries"][0]["time"] = json!(-1.0);
+        assert_eq!(schema_errors(&negative).len(), 1);
+    }
+
+    #[test]
+    fn window_limits_the_flows_of_a_click() {
+        let flows = vec![
+            flow("192.0.2.1:80", -500, None, -10, FlowDetails::default()),
+            flow("192.0.2.2:80", 1500, None, 1600, FlowDetails::default()),
+            flow("192.0.2.3:80", 3000, None, 3100, FlowDetails::default()),
+        ];
+        let actions = [action("click", Some(traffic(Vec::new(), flows)))];
+        assert_eq!(urls(&archive(&actions, None)), ["tcp://192.0.2.2:80/"]);
+        assert_eq!(urls(&archive(&actions, Some(std::time::Duration::from_millis(1000)))), Vec::<&str>::new());
+        assert_eq!(urls(&archive(&actions, Some(std::time::Duration::from_secs(5)))).len(), 2);
+    }
+
+    #[test]
+    fn nettop_connections_are_untimed_entries() {
+        let connections = vec![
+            connection("2001:db8::1", Some(443), None),
+            connection("2001:db8::2", Some(443), Some("cdn.example.com")),
+            connection("192.0.2.1", None, None),
+            connection("*", None, None),
+        ];
+        let har = archive(&[action("click", Some(traffic(connections, Vec::new())))], None);
+        assert_eq!(urls(&har), ["tcp://[2001:db8::1]:443/", "tcp://cdn.example.com:443/", "tcp://192.0.2.1/", "tcp://*/"]);
+        assert!(har["log"]["entries"][3].get("serverIPAddress").is_none());
+        let entry = &har["log"]["entries"][0];
+        assert_eq!(entry["time"], 0.0);
+        assert_eq!(entry["serverIPAddress"], "2001:db8::1");
+        assert_eq!(entry["comment"], "Sampled with nettop; timing unknown, new connection");
+        assert!(entry.get("connection").is_none());
+    }
+}
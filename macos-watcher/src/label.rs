@@ -0,0 +1,271 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::accessibility::*;
+
+// The bits of an accessibility element the label chain needs. Implemented for
+// AxElement, and small enough to fake with an in-memory tree.
+pub trait LabelElement: Sized {
+    fn string_attribute(&self, attribute: &str) -> Option<String>;
+    fn children(&self) -> Vec<Self>;
+}
+
+#[cfg(target_os = "macos")]
+impl LabelElement for AxElement {
+    fn string_attribute(&self, attribute: &str) -> Option<String> {
+        AxElement::string_attribute(self, attribute)
+    }
+
+    fn children(&self) -> Vec<Self> {
+        AxElement::children(self)
+    }
+}
+
+// One step of the label resolution chain
+#[derive(Clone, Debug, PartialEq)]
+pub enum LabelStep {
+    // Read a string attribute from the element itself, e.g. AXTitle
+    Attribute(String),
+    // Search descendants (breadth first) for AXStaticText and use its text
+    ChildStaticText { max_depth: usize },
+}
+
+// Where a resolved label came from, logged next to the label itself
+#[derive(Clone, Debug, PartialEq)]
+pub enum LabelSource {
+    Attribute(String),
+    ChildStaticText,
+}
+
+impl fmt::Display for LabelSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelSource::Attribute(attribute) => write!(f, "{}", attribute),
+            LabelSource::ChildStaticText => write!(f, "ChildStaticText"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolvedLabel {
+    pub text: String,
+    pub source: LabelSource,
+}
+
+// Ordered list of places to look for a human readable label.
+// SwiftUI and AppKit are inconsistent about which attribute they fill in,
+// so we try several and keep the first non-empty one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelChain {
+    steps: Vec<LabelStep>,
+}
+
+// Name used for the child static text step in --label-chain
+const CHILD_STATIC_TEXT_STEP: &str = "children";
+const DEFAULT_CHILD_DEPTH: usize = 3;
+
+impl Default for LabelChain {
+    fn default() -> Self {
+        LabelChain {
+            steps: vec![
+                LabelStep::Attribute(K_AX_TITLE_ATTRIBUTE.to_string()),
+                LabelStep::Attribute(K_AX_DESCRIPTION_ATTRIBUTE.to_string()),
+                LabelStep::Attribute(K_AX_VALUE_ATTRIBUTE.to_string()),
+                LabelStep::Attribute(K_AX_HELP_ATTRIBUTE.to_string()),
+                LabelStep::ChildStaticText { max_depth: DEFAULT_CHILD_DEPTH },
+            ],
+        }
+    }
+}
+
+impl LabelChain {
+    // Parse a comma separated chain such as "AXTitle,AXDescription,children:2".
+    // Entries starting with "AX" are attributes; "children[:depth]" searches
+    // descendant static text.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            if let Some(rest) = entry.strip_prefix(CHILD_STATIC_TEXT_STEP) {
+                let max_depth = match rest.strip_prefix(':') {
+                    // Depth 1 is the direct children; 0 would search nothing
+                    Some(depth) => match depth.parse::<usize>() {
+                        Ok(depth) if depth > 0 => depth,
+                        _ => return Err(format!("Invalid child search depth in label chain: '{}' (expected 1 or more)", entry)),
+                    },
+                    None if rest.is_empty() => DEFAULT_CHILD_DEPTH,
+                    None => return Err(format!("Unknown label chain step: '{}'", entry)),
+                };
+                steps.push(LabelStep::ChildStaticText { max_depth });
+            } else if entry.starts_with("AX") {
+                steps.push(LabelStep::Attribute(entry.to_string()));
+            } else {
+                return Err(format!("Unknown label chain step: '{}'", entry));
+            }
+        }
+
+        if steps.is_empty() {
+            return Err("Label chain must contain at least one step".to_string());
+        }
+        Ok(LabelChain { steps })
+    }
+
+    pub fn resolve<E: LabelElement>(&self, element: &E) -> Option<ResolvedLabel> {
+        for step in &self.steps {
+            let resolved = match step {
+                LabelStep::Attribute(attribute) => non_empty(element.string_attribute(attribute))
+                    .map(|text| ResolvedLabel { text, source: LabelSource::Attribute(attribute.clone()) }),
+                LabelStep::ChildStaticText { max_depth } => find_child_static_text(element, *max_depth)
+                    .map(|text| ResolvedLabel { text, source: LabelSource::ChildStaticText }),
+            };
+            if resolved.is_some() {
+                return resolved;
+            }
+        }
+        None
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+// Breadth first so the closest static text wins over deeply nested ones
+fn find_child_static_text<E: LabelElement>(element: &E, max_depth: usize) -> Option<String> {
+    if max_depth == 0 {
+        return None;
+    }
+    let mut queue: VecDeque<(E, usize)> = element.children().into_iter().map(|child| (child, 1)).collect();
+
+    while let Some((node, depth)) = queue.pop_front() {
+        if node.string_attribute(K_AX_ROLE_ATTRIBUTE).as_deref() == Some(K_AX_STATIC_TEXT_ROLE) {
+            // Static text keeps its text in AXValue; some views use AXTitle instead
+            let text = non_empty(node.string_attribute(K_AX_VALUE_ATTRIBUTE))
+                .or_else(|| non_empty(node.string_attribute(K_AX_TITLE_ATTRIBUTE)));
+            if text.is_some() {
+                return text;
+            }
+        }
+        if depth < max_depth {
+            queue.extend(node.children().into_iter().map(|child| (child, depth + 1)));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // In-memory stand-in for an accessibility element
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct FakeElement {
+        pub attributes: HashMap<String, String>,
+        pub children: Vec<FakeElement>,
+    }
+
+    impl FakeElement {
+        pub(crate) fn new(role: &str) -> Self {
+            FakeElement::default().with(K_AX_ROLE_ATTRIBUTE, role)
+        }
+
+        pub(crate) fn with(mut self, attribute: &str, value: &str) -> Self {
+            self.attributes.insert(attribute.to_string(), value.to_string());
+            self
+        }
+
+        pub(crate) fn child(mut self, child: FakeElement) -> Self {
+            self.children.push(child);
+            self
+        }
+    }
+
+    impl LabelElement for FakeElement {
+        fn string_attribute(&self, attribute: &str) -> Option<String> {
+            self.attributes.get(attribute).cloned()
+        }
+
+        fn children(&self) -> Vec<Self> {
+            self.children.clone()
+        }
+    }
+
+    fn static_text(text: &str) -> FakeElement {
+        FakeElement::new(K_AX_STATIC_TEXT_ROLE).with(K_AX_VALUE_ATTRIBUTE, text)
+    }
+
+    #[test]
+    fn first_non_empty_attribute_wins() {
+        let button = FakeElement::new(K_AX_BUTTON_ROLE)
+            .with(K_AX_TITLE_ATTRIBUTE, "  ")
+            .with(K_AX_DESCRIPTION_ATTRIBUTE, " Send ")
+            .with(K_AX_HELP_ATTRIBUTE, "Sends the message");
+
+        let label = LabelChain::default().resolve(&button).unwrap();
+        assert_eq!(label.text, "Send");
+        assert_eq!(label.source, LabelSource::Attribute(K_AX_DESCRIPTION_ATTRIBUTE.to_string()));
+    }
+
+    #[test]
+    fn falls_back_to_the_closest_child_static_text() {
+        let button = FakeElement::new(K_AX_BUTTON_ROLE)
+            .child(FakeElement::new("AXGroup").child(static_text("Nested")))
+            .child(static_text("Direct"));
+
+        let label = LabelChain::default().resolve(&button).unwrap();
+        assert_eq!(label.text, "Direct");
+        assert_eq!(label.source, LabelSource::ChildStaticText);
+    }
+
+    #[test]
+    fn child_search_stops_at_max_depth() {
+        let button = FakeElement::new(K_AX_BUTTON_ROLE)
+            .child(FakeElement::new("AXGroup").child(FakeElement::new("AXGroup").child(static_text("Deep"))));
+
+        assert_eq!(LabelChain::parse("children:2").unwrap().resolve(&button), None);
+        assert_eq!(LabelChain::parse("children:3").unwrap().resolve(&button).unwrap().text, "Deep");
+    }
+
+    #[test]
+    fn static_text_without_value_uses_its_title() {
+        let button = FakeElement::new(K_AX_BUTTON_ROLE)
+            .child(FakeElement::new(K_AX_STATIC_TEXT_ROLE).with(K_AX_TITLE_ATTRIBUTE, "Titled"));
+
+        assert_eq!(LabelChain::parse("children:1").unwrap().resolve(&button).unwrap().text, "Titled");
+    }
+
+    #[test]
+    fn unresolved_when_nothing_matches() {
+        let button = FakeElement::new(K_AX_BUTTON_ROLE).child(FakeElement::new("AXImage"));
+        assert_eq!(LabelChain::default().resolve(&button), None);
+    }
+
+    #[test]
+    fn parses_steps_in_order() {
+        let chain = LabelChain::parse("AXHelp, children:2 ,AXTitle").unwrap();
+        assert_eq!(
+            chain.steps,
+            vec![
+                LabelStep::Attribute("AXHelp".to_string()),
+                LabelStep::ChildStaticText { max_depth: 2 },
+                LabelStep::Attribute("AXTitle".to_string()),
+            ]
+        );
+        assert_eq!(LabelChain::parse("children").unwrap().steps, vec![LabelStep::ChildStaticText { max_depth: DEFAULT_CHILD_DEPTH }]);
+    }
+
+    #[test]
+    fn rejects_invalid_chains() {
+        for spec in ["", " , ", "title", "children:", "children:x", "childrenX", "children:0"] {
+            assert!(LabelChain::parse(spec).is_err(), "{:?} should be rejected", spec);
+        }
+    }
+
+    #[test]
+    fn zero_depth_searches_nothing() {
+        let button = FakeElement::new(K_AX_BUTTON_ROLE).child(static_text("Direct"));
+        assert_eq!(find_child_static_text(&button, 0), None);
+    }
+}
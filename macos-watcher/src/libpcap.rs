@@ -0,0 +1,122 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::time::Duration;
+
+use crate::flows::TrafficSource;
+use crate::pcap::Packet;
+
+// Opaque pcap_t handle
+pub type PcapRef = *mut c_void;
+
+#[repr(C)]
+pub struct PcapPacketHeader {
+    pub ts: libc::timeval,
+    pub caplen: u32,
+    pub len: u32,
+}
+
+#[repr(C)]
+pub struct BpfProgram {
+    pub bf_len: c_uint,
+    pub bf_insns: *mut c_void,
+}
+
+const PCAP_ERRBUF_SIZE: usize = 256;
+const PCAP_NETMASK_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+// Whole packets, so the dissectors see complete TLS and HTTP headers
+const SNAPLEN: c_int = 65535;
+// pcap_next_ex returns after this long without packets, so shutdown is noticed
+const READ_TIMEOUT_MS: c_int = 250;
+
+#[link(name = "pcap")]
+unsafe extern "C" {
+    fn pcap_open_live(device: *const c_char, snaplen: c_int, promisc: c_int, to_ms: c_int, errbuf: *mut c_char) -> PcapRef;
+    fn pcap_datalink(handle: PcapRef) -> c_int;
+    fn pcap_next_ex(handle: PcapRef, header: *mut *mut PcapPacketHeader, data: *mut *const u8) -> c_int;
+    fn pcap_compile(handle: PcapRef, program: *mut BpfProgram, filter: *const c_char, optimize: c_int, netmask: u32) -> c_int;
+    fn pcap_setfilter(handle: PcapRef, program: *mut BpfProgram) -> c_int;
+    fn pcap_freecode(program: *mut BpfProgram);
+    fn pcap_geterr(handle: PcapRef) -> *mut c_char;
+    fn pcap_close(handle: PcapRef);
+}
+
+// Live capture on one interface through libpcap (needs root, or access to /dev/bpf*)
+pub struct LiveCapture {
+    handle: PcapRef,
+    interface: String,
+    linktype: u32,
+}
+
+// The handle is only ever used by the thread that owns the LiveCapture
+unsafe impl Send for LiveCapture {}
+
+impl LiveCapture {
+    pub fn open(interface: &str, filter: &str) -> Result<Self, String> {
+        let device = CString::new(interface).map_err(|_| format!("Invalid interface name '{}'", interface))?;
+        let mut errbuf = [0 as c_char; PCAP_ERRBUF_SIZE];
+
+        let handle = unsafe { pcap_open_live(device.as_ptr(), SNAPLEN, 0, READ_TIMEOUT_MS, errbuf.as_mut_ptr()) };
+        if handle.is_null() {
+            let message = unsafe { CStr::from_ptr(errbuf.as_ptr()) };
+            return Err(format!("Failed to capture on {}: {}", interface, message.to_string_lossy()));
+        }
+        let capture = LiveCapture {
+            handle,
+            interface: interface.to_string(),
+            linktype: unsafe { pcap_datalink(handle) } as u32,
+        };
+
+        let filter = CString::new(filter).map_err(|_| "Invalid capture filter".to_string())?;
+        let mut program = BpfProgram { bf_len: 0, bf_insns: std::ptr::null_mut() };
+        unsafe {
+            if pcap_compile(handle, &mut program, filter.as_ptr(), 1, PCAP_NETMASK_UNKNOWN) != 0 {
+                return Err(format!("Invalid capture filter: {}", capture.last_error()));
+            }
+            let result = pcap_setfilter(handle, &mut program);
+            pcap_freecode(&mut program);
+            if result != 0 {
+                return Err(format!("Failed to set capture filter: {}", capture.last_error()));
+            }
+        }
+        Ok(capture)
+    }
+
+    fn last_error(&self) -> String {
+        unsafe { CStr::from_ptr(pcap_geterr(self.handle)) }.to_string_lossy().into_owned()
+    }
+}
+
+impl TrafficSource for LiveCapture {
+    fn linktype(&self) -> u32 {
+        self.linktype
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut header: *mut PcapPacketHeader = std::ptr::null_mut();
+        let mut data: *const u8 = std::ptr::null();
+        match unsafe { pcap_next_ex(self.handle, &mut header, &mut data) } {
+            1 => {
+                // Both pointers are only valid until the next call, so copy
+                let header = unsafe { &*header };
+                let bytes = unsafe { std::slice::from_raw_parts(data, header.caplen as usize) };
+                let timestamp = Duration::from_secs(header.ts.tv_sec as u64) + Duration::from_micros(header.ts.tv_usec as u64);
+                Ok(Some(Packet { timestamp, data: bytes.to_vec() }))
+            }
+            0 => Err(io::Error::new(io::ErrorKind::TimedOut, "no packets")),
+            -2 => Ok(None),
+            _ => Err(io::Error::other(self.last_error())),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("interface {}", self.interface)
+    }
+}
+
+impl Drop for LiveCapture {
+    fn drop(&mut self) {
+        unsafe { pcap_close(self.handle) };
+    }
+}
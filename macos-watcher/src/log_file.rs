@@ -0,0 +1,340 @@
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+// When to roll a file over and how many old ones to keep
+#[derive(Clone, Debug, PartialEq)]
+pub struct RotationPolicy {
+    // Rotate once the file reaches this many bytes
+    pub max_bytes: Option<u64>,
+    // Rotate once the file has been written to for this long
+    pub max_age: Option<Duration>,
+    // Rotated files to keep; older ones are deleted
+    pub retain: usize,
+    // Gzip rotated files
+    pub compress: bool,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        RotationPolicy {
+            max_bytes: Some(10 * 1024 * 1024),
+            max_age: Some(Duration::from_secs(24 * 60 * 60)),
+            retain: 5,
+            compress: true,
+        }
+    }
+}
+
+// An append-mode file that rotates itself according to a RotationPolicy.
+// Rotated files are renamed to "<name>.<timestamp>" (plus ".gz" once
+// compressed), so a crash or restart never loses earlier output.
+pub struct RotatingFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    // Written at the start of every line, e.g. the session ID
+    line_prefix: Option<String>,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+    at_line_start: bool,
+    // Rotated files go to one housekeeping thread, which compresses and
+    // prunes them in order; taken on drop so it sees the channel close
+    rotated: Option<Sender<PathBuf>>,
+    housekeeping: Option<JoinHandle<()>>,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, policy: RotationPolicy) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        // Age counts from the file's creation so restarts do not reset it
+        let opened_at = metadata.created().or_else(|_| metadata.modified()).unwrap_or_else(|_| SystemTime::now());
+        let (sender, receiver) = mpsc::channel();
+        let housekeeping = {
+            let (path, policy) = (path.to_path_buf(), policy.clone());
+            thread::spawn(move || run_housekeeping(&path, &policy, receiver))
+        };
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            policy,
+            line_prefix: None,
+            file,
+            size: metadata.len(),
+            opened_at,
+            at_line_start: true,
+            rotated: Some(sender),
+            housekeeping: Some(housekeeping),
+        })
+    }
+
+    pub fn with_line_prefix(mut self, prefix: String) -> Self {
+        self.line_prefix = Some(prefix);
+        self
+    }
+
+    fn should_rotate(&self) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self.policy.max_bytes.is_some_and(|max| self.size >= max);
+        let too_old = self.policy.max_age.is_some_and(|max| {
+            SystemTime::now().duration_since(self.opened_at).unwrap_or_default() >= max
+        });
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = rotated_path(&self.path);
+        fs::rename(&self.path, &rotated)?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.opened_at = SystemTime::now();
+
+        // Compressing a large file can take a while; keep it off the caller's thread
+        if let Some(sender) = &self.rotated {
+            let _ = sender.send(rotated);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RotatingFile {
+    fn drop(&mut self) {
+        drop(self.rotated.take());
+        if let Some(housekeeping) = self.housekeeping.take() {
+            let _ = housekeeping.join();
+        }
+    }
+}
+
+// Compress, then prune, one rotated file at a time, so prune never sees (or
+// deletes) a file that is still being compressed
+fn run_housekeeping(path: &Path, policy: &RotationPolicy, receiver: Receiver<PathBuf>) {
+    for rotated in receiver {
+        if policy.compress {
+            if let Err(e) = compress(&rotated) {
+                eprintln!("Failed to compress {}: {}", rotated.display(), e);
+            }
+        }
+        if let Err(e) = prune(path, policy.retain) {
+            eprintln!("Failed to prune rotated files for {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Only rotate between lines so no line is split across files
+        if self.at_line_start {
+            if self.should_rotate() {
+                self.rotate()?;
+            }
+            if let Some(prefix) = &self.line_prefix {
+                self.file.write_all(prefix.as_bytes())?;
+                self.size += prefix.len() as u64;
+            }
+        }
+
+        // Write at most one line per call so every line gets its prefix
+        let end = buf.iter().position(|&b| b == b'\n').map_or(buf.len(), |index| index + 1);
+        let written = self.file.write(&buf[..end])?;
+        self.size += written as u64;
+        self.at_line_start = written == end && buf[end - 1] == b'\n';
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let stamp = Local::now().format("%Y%m%dT%H%M%S").to_string();
+    let mut candidate = PathBuf::from(format!("{}.{}", path.display(), stamp));
+    let mut counter = 1;
+    while candidate.exists() || with_gz(&candidate).exists() {
+        candidate = PathBuf::from(format!("{}.{}-{}", path.display(), stamp, counter));
+        counter += 1;
+    }
+    candidate
+}
+
+fn with_gz(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.gz", path.display()))
+}
+
+// Replace `path` with a gzipped copy at "<path>.gz"
+fn compress(path: &Path) -> io::Result<()> {
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(with_gz(path))?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+// Order of a rotated file name after "<name>.": its timestamp, then the
+// collision counter ("20240102T030405" before "20240102T030405-1"). None for
+// files that were not written by rotate.
+fn rotation_key(suffix: &str) -> Option<(&str, u32)> {
+    let suffix = suffix.strip_suffix(".gz").unwrap_or(suffix);
+    let (stamp, counter) = match suffix.split_once('-') {
+        Some((stamp, counter)) => (stamp, counter.parse().ok()?),
+        None => (suffix, 0),
+    };
+    chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%dT%H%M%S").ok()?;
+    Some((stamp, counter))
+}
+
+// Delete the oldest rotated files beyond `retain`
+fn prune(path: &Path, retain: usize) -> io::Result<()> {
+    let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let prefix = match path.file_name() {
+        Some(name) => format!("{}.", name.to_string_lossy()),
+        None => return Ok(()),
+    };
+
+    let mut rotated: Vec<(String, PathBuf)> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let suffix = entry.file_name().to_string_lossy().strip_prefix(&prefix)?.to_string();
+            Some((suffix, entry.path()))
+        })
+        .filter(|(suffix, _)| rotation_key(suffix).is_some())
+        .collect();
+    rotated.sort_by(|(a, _), (b, _)| rotation_key(a).cmp(&rotation_key(b)));
+
+    let excess = rotated.len().saturating_sub(retain);
+    for (_, old) in rotated.into_iter().take(excess) {
+        fs::remove_file(&old)?;
+    }
+    Ok(())
+}
+
+// "10M", "512K", "1G" or plain bytes
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&value[..index], 1024),
+        Some((index, 'M' | 'm')) => (&value[..index], 1024 * 1024),
+        Some((index, 'G' | 'g')) => (&value[..index], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size '{}' (expected e.g. 512K, 10M, 1G)", value))
+}
+
+// "30m", "12h", "7d" or plain seconds
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit_secs) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], 1),
+        Some((index, 'm')) => (&value[..index], 60),
+        Some((index, 'h')) => (&value[..index], 60 * 60),
+        Some((index, 'd')) => (&value[..index], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit_secs))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Invalid duration '{}' (expected e.g. 30m, 12h, 7d)", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("macos-watcher-log-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> =
+            fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotation_keys_order_collisions_after_their_stamp() {
+        assert!(rotation_key("20240102T030405") < rotation_key("20240102T030405-1"));
+        assert!(rotation_key("20240102T030405-2.gz") < rotation_key("20240102T030405-10"));
+        assert!(rotation_key("20240102T030405-10.gz") < rotation_key("20240102T030406"));
+        assert_eq!(rotation_key("bak"), None);
+        assert_eq!(rotation_key("20240102T030405-x"), None);
+    }
+
+    #[test]
+    fn prune_keeps_the_newest_rotations_and_ignores_other_files() {
+        let dir = temp_dir("prune");
+        let path = dir.join("watcher.log");
+        for name in ["watcher.log", "watcher.log.20240102T030405-1.gz", "watcher.log.20240102T030405.gz",
+                     "watcher.log.20240101T000000", "watcher.log.20240102T030405-2", "watcher.log.bak"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        prune(&path, 2).unwrap();
+        assert_eq!(names(&dir), ["watcher.log", "watcher.log.20240102T030405-1.gz", "watcher.log.20240102T030405-2", "watcher.log.bak"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotates_compresses_and_prunes_in_order() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("watcher.log");
+        let policy = RotationPolicy { max_bytes: Some(4), max_age: None, retain: 2, compress: true };
+        let mut file = RotatingFile::open(&path, policy).unwrap().with_line_prefix("[s] ".to_string());
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        // Waits for the housekeeping thread
+        drop(file);
+
+        let names = names(&dir);
+        assert_eq!(names.len(), 3, "{:?}", names);
+        assert_eq!(names.iter().filter(|name| name.ends_with(".gz")).count(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[s] four\n");
+
+        let newest = names.iter().filter_map(|name| name.strip_prefix("watcher.log.")).max_by_key(|suffix| rotation_key(suffix)).unwrap();
+        let newest = dir.join(format!("watcher.log.{}", newest));
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(File::open(newest).unwrap()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "[s] three\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn parses_sizes_and_durations() {
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("10m"), Ok(10 * 1024 * 1024));
+        assert!(parse_size("ten").is_err());
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("d").is_err());
+        // Too large for a u64 once multiplied
+        assert_eq!(parse_size("99999999999G"), Err("Invalid size '99999999999G' (expected e.g. 512K, 10M, 1G)".to_string()));
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(parse_duration("999999999999999999d"), Err("Invalid duration '999999999999999999d' (expected e.g. 30m, 12h, 7d)".to_string()));
+    }
+}
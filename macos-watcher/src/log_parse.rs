@@ -0,0 +1,651 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::events::AxSelector;
+
+// Reads the watcher's own output back: the log file (every line prefixed with
+// the session ID and an RFC 3339 time) or what it printed to the terminal
+// (time of day only). Older versions printed 📡 nettop lines without a time.
+
+// A click as logged by the event tap ("Button Clicked: ..." or "Element Clicked: ...")
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedClick {
+    pub app_name: String,
+    pub pid: i32,
+    pub selector: AxSelector,
+    // Screen coordinates of the click
+    pub position: Option<(f64, f64)>,
+    pub window_title: Option<String>,
+    // x, y, width, height
+    pub window_frame: Option<(f64, f64, f64, f64)>,
+}
+
+// One 📡 line: the change of a process's nettop counters
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedSample {
+    // nettop's "<name>.<pid>" row key
+    pub process: String,
+    pub pid: i32,
+    pub delta_out: u64,
+    pub delta_in: u64,
+}
+
+// One 🔗 line: the traffic of a click's correlation window
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedCorrelation {
+    // AxSelector::describe() of the clicked element
+    pub target: String,
+    pub app_name: String,
+    pub pid: i32,
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+    pub new_connections: usize,
+    pub window_secs: f64,
+    pub remotes: Vec<String>,
+}
+
+// One ⏱ line following a 🔗 line
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedFlow {
+    pub protocol: String,
+    pub local: String,
+    pub remote: String,
+    pub hostname: Option<String>,
+    pub first_byte_ms: i64,
+    pub last_byte_ms: i64,
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+    // SNI, HTTP request or DNS query
+    pub summary: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogEntry {
+    Click(LoggedClick),
+    Key { app_name: String, pid: i32, keycode: i64 },
+    Sample(LoggedSample),
+    Correlation(LoggedCorrelation),
+    Flow(LoggedFlow),
+}
+
+// The input side of an action
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoggedInput {
+    Click(LoggedClick),
+    Key { keycode: i64 },
+}
+
+// What the network did after a click
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedOutcome {
+    pub correlation: LoggedCorrelation,
+    pub flows: Vec<LoggedFlow>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedAction {
+    pub time: DateTime<Local>,
+    pub app_name: String,
+    pub pid: i32,
+    pub input: LoggedInput,
+    pub outcome: Option<LoggedOutcome>,
+}
+
+impl LoggedAction {
+    pub fn selector(&self) -> Option<&AxSelector> {
+        match &self.input {
+            LoggedInput::Click(click) => Some(&click.selector),
+            LoggedInput::Key { .. } => None,
+        }
+    }
+}
+
+// Everything one watcher run logged, in time order
+#[derive(Clone, Debug, Default)]
+pub struct LogSession {
+    pub id: Option<String>,
+    pub actions: Vec<LoggedAction>,
+    pub samples: Vec<(DateTime<Local>, LoggedSample)>,
+}
+
+// Log text of a file; rotated logs are gzipped
+pub fn read_log_file(path: &Path) -> Result<String, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut reader: Box<dyn Read> = if path.extension().is_some_and(|extension| extension == "gz") {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// One session of a log: `session_id` if given, otherwise the last one
+pub fn read_session(text: &str, session_id: Option<&str>) -> Result<LogSession, String> {
+    let sessions = parse_sessions(text);
+    match session_id {
+        Some(id) => sessions
+            .into_iter()
+            .find(|session| session.id.as_deref() == Some(id))
+            .ok_or_else(|| format!("The log has no session '{}'", id)),
+        None => sessions.into_iter().last().ok_or_else(|| "The log has no clicks, key presses or nettop samples".to_string()),
+    }
+}
+
+// Group lines by the session that wrote them and assemble actions
+pub fn parse_sessions(text: &str) -> Vec<LogSession> {
+    let mut sessions: Vec<LogSession> = Vec::new();
+    let mut clock = LineClock::default();
+    // Where the ⏱ lines of the last 🔗 line go
+    let mut last_outcome: Option<usize> = None;
+
+    for raw in text.lines() {
+        let line = strip_ansi(raw);
+        let (session_id, rest) = split_session(&line);
+        let (time, message) = clock.split_time(rest);
+        let Some(entry) = parse_entry(message) else { continue };
+
+        // Console output has no session IDs; it belongs to whichever run came last
+        if sessions.is_empty() || (session_id.is_some() && sessions.last().unwrap().id.as_deref() != session_id) {
+            sessions.push(LogSession { id: session_id.map(str::to_string), ..LogSession::default() });
+            last_outcome = None;
+        }
+        let session = sessions.last_mut().unwrap();
+
+        match entry {
+            LogEntry::Click(click) => session.actions.push(LoggedAction {
+                time,
+                app_name: click.app_name.clone(),
+                pid: click.pid,
+                input: LoggedInput::Click(click),
+                outcome: None,
+            }),
+            LogEntry::Key { app_name, pid, keycode } => session.actions.push(LoggedAction {
+                time,
+                app_name,
+                pid,
+                input: LoggedInput::Key { keycode },
+                outcome: None,
+            }),
+            LogEntry::Sample(sample) => session.samples.push((time, sample)),
+            LogEntry::Correlation(correlation) => last_outcome = Some(attach_correlation(session, time, correlation)),
+            LogEntry::Flow(flow) => {
+                let outcome = last_outcome.and_then(|index| session.actions.get_mut(index)).and_then(|action| action.outcome.as_mut());
+                if let Some(outcome) = outcome {
+                    outcome.flows.push(flow);
+                }
+            }
+        }
+    }
+
+    for session in &mut sessions {
+        session.actions.sort_by_key(|action| action.time);
+    }
+    sessions
+}
+
+// A 🔗 line is logged when its window closes, and windows close in click
+// order, so it belongs to the oldest click on the same element that has no
+// outcome yet. Clicks that were not logged themselves (no identifier and not
+// a button) are added from it.
+fn attach_correlation(session: &mut LogSession, logged_at: DateTime<Local>, correlation: LoggedCorrelation) -> usize {
+    let clicked = session.actions.iter().position(|action| {
+        action.pid == correlation.pid
+            && action.outcome.is_none()
+            && action.selector().is_some_and(|selector| selector.describe() == correlation.target)
+    });
+    let index = clicked.unwrap_or_else(|| {
+        let window = chrono::Duration::milliseconds((correlation.window_secs * 1000.0) as i64);
+        session.actions.push(LoggedAction {
+            time: logged_at - window,
+            app_name: correlation.app_name.clone(),
+            pid: correlation.pid,
+            input: LoggedInput::Click(LoggedClick {
+                app_name: correlation.app_name.clone(),
+                pid: correlation.pid,
+                selector: parse_target(&correlation.target),
+                position: None,
+                window_title: None,
+                window_frame: None,
+            }),
+            outcome: None,
+        });
+        session.actions.len() - 1
+    });
+    session.actions[index].outcome = Some(LoggedOutcome { correlation, flows: Vec::new() });
+    index
+}
+
+// The reverse of AxSelector::describe(): "#id", "role 'label'", "'label'" or "role"
+fn parse_target(target: &str) -> AxSelector {
+    if let Some(identifier) = target.strip_prefix('#') {
+        return AxSelector { identifier: Some(identifier.to_string()), ..AxSelector::default() };
+    }
+    if target == "<Any>" {
+        return AxSelector::default();
+    }
+    match target.split_once('\'') {
+        Some((role, label)) => AxSelector {
+            identifier: None,
+            role: Some(role.trim().to_string()).filter(|role| !role.is_empty()),
+            label: Some(label.trim_end_matches('\'').to_string()),
+        },
+        None => AxSelector { role: Some(target.to_string()), ..AxSelector::default() },
+    }
+}
+
+// Terminal output may carry color codes around the level
+fn strip_ansi(line: &str) -> String {
+    let mut clean = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip to the end of the escape sequence (a letter)
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            clean.push(c);
+        }
+    }
+    clean
+}
+
+// "[20261018T091500-4242] rest" written by RotatingFile::with_line_prefix
+fn split_session(line: &str) -> (Option<&str>, &str) {
+    if let Some(rest) = line.strip_prefix('[') {
+        if let Some((id, rest)) = rest.split_once("] ") {
+            if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') && id.contains('T') {
+                return (Some(id), rest);
+            }
+        }
+    }
+    (None, line)
+}
+
+// Times of lines that carry none (or only the time of day) are taken from the
+// lines before them
+#[derive(Default)]
+struct LineClock {
+    last: Option<DateTime<Utc>>,
+}
+
+impl LineClock {
+    // Split "<time> [LEVEL] message" into the time and the message. The
+    // logger writes UTC: RFC 3339 in the file, HH:MM:SS on the terminal.
+    fn split_time<'a>(&mut self, line: &'a str) -> (DateTime<Local>, &'a str) {
+        let (first, rest) = line.split_once(' ').unwrap_or((line, ""));
+        if let Ok(time) = DateTime::parse_from_rfc3339(first) {
+            self.last = Some(time.with_timezone(&Utc));
+            return (time.with_timezone(&Local), strip_level(rest));
+        }
+        if let Ok(time_of_day) = NaiveTime::parse_from_str(first, "%H:%M:%S") {
+            let date = self.last.map_or(NaiveDate::default(), |last| last.date_naive());
+            let time = Utc.from_utc_datetime(&date.and_time(time_of_day));
+            self.last = Some(time);
+            return (time.with_timezone(&Local), strip_level(rest));
+        }
+        (self.last.unwrap_or_default().with_timezone(&Local), strip_level(line))
+    }
+}
+
+// "[INFO] message"; debug lines also carry "(thread) target: " after the level
+fn strip_level(line: &str) -> &str {
+    let line = line.trim_start();
+    match line.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+        Some((level, message)) if level.chars().all(|c| c.is_ascii_uppercase()) => message,
+        _ => line,
+    }
+}
+
+// Recognize the lines a report needs; everything else is None
+pub fn parse_entry(message: &str) -> Option<LogEntry> {
+    let trimmed = message.trim_start();
+    if let Some(fields) = message.strip_prefix("Button Clicked: ") {
+        return parse_click(fields, Some("AXButton")).map(LogEntry::Click);
+    }
+    if let Some(fields) = message.strip_prefix("Element Clicked: ") {
+        return parse_click(fields, None).map(LogEntry::Click);
+    }
+    if let Some(fields) = message.strip_prefix("Key Down: ") {
+        let fields = parse_fields(fields);
+        return Some(LogEntry::Key {
+            app_name: field(&fields, "App")?.to_string(),
+            pid: field(&fields, "PID")?.parse().ok()?,
+            keycode: field(&fields, "KeyCode")?.parse().ok()?,
+        });
+    }
+    if let Some(rest) = trimmed.strip_prefix("📡 ") {
+        return parse_sample(rest).map(LogEntry::Sample);
+    }
+    if let Some(rest) = trimmed.strip_prefix("🔗 ") {
+        return parse_correlation(rest).map(LogEntry::Correlation);
+    }
+    if let Some(rest) = trimmed.strip_prefix("⏱ ") {
+        return parse_flow(rest).map(LogEntry::Flow);
+    }
+    None
+}
+
+fn parse_click(fields: &str, role: Option<&str>) -> Option<LoggedClick> {
+    let fields = parse_fields(fields);
+    let present = |key: &str| field(&fields, key).filter(|value| !value.starts_with('<')).map(str::to_string);
+    Some(LoggedClick {
+        app_name: field(&fields, "App")?.to_string(),
+        pid: field(&fields, "PID")?.parse().ok()?,
+        selector: AxSelector {
+            identifier: present("ID"),
+            role: role.map(str::to_string).or_else(|| present("Role")),
+            label: present("Label"),
+        },
+        position: field(&fields, "Pos").and_then(parse_point),
+        window_title: present("Window"),
+        window_frame: field(&fields, "WindowFrame").and_then(parse_rect),
+    })
+}
+
+// "Key='quoted', Key=(x, y), Key=value" pairs. A quoted value may be followed
+// by a parenthesized group of more pairs: "App='Safari' (PID=812)".
+fn parse_fields(text: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut rest = text.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim_start_matches([',', ' ', '(']).to_string();
+        let (value, remaining) = if let Some(quoted) = after.strip_prefix('\'') {
+            // The closing quote is the one followed by a separator or the end
+            let end = ["', ", "' ("]
+                .iter()
+                .filter_map(|separator| quoted.find(separator))
+                .min()
+                .unwrap_or_else(|| quoted.trim_end().len().saturating_sub(1));
+            (&quoted[..end.min(quoted.len())], quoted.get(end + 1..).unwrap_or(""))
+        } else if after.starts_with('(') {
+            let end = after.find(')').map_or(after.len(), |end| end + 1);
+            (&after[..end], &after[end..])
+        } else {
+            let end = after.find([',', ')']).unwrap_or(after.len());
+            (after[..end].trim(), &after[end..])
+        };
+        fields.push((key, value.to_string()));
+        rest = remaining.trim_start_matches([',', ' ', ')']);
+    }
+    fields
+}
+
+fn field<'a>(fields: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    fields.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+}
+
+fn parse_numbers(text: &str) -> Option<Vec<f64>> {
+    text.trim_start_matches('(').trim_end_matches(')').split(',').map(|number| number.trim().parse().ok()).collect()
+}
+
+fn parse_point(text: &str) -> Option<(f64, f64)> {
+    match parse_numbers(text)?[..] {
+        [x, y] => Some((x, y)),
+        _ => None,
+    }
+}
+
+fn parse_rect(text: &str) -> Option<(f64, f64, f64, f64)> {
+    match parse_numbers(text)?[..] {
+        [x, y, width, height] => Some((x, y, width, height)),
+        _ => None,
+    }
+}
+
+// "Safari.812 ↑ 6092 B ↓ 0 B (Δ ↑ 6092 ↓ 0) <rates>"
+fn parse_sample(text: &str) -> Option<LoggedSample> {
+    let (process, rest) = text.split_once(' ')?;
+    let (_, pid) = process.rsplit_once('.')?;
+    let delta = rest.split_once("(Δ ↑ ")?.1;
+    let (delta_out, delta) = delta.split_once(" ↓ ")?;
+    let delta_in = delta.split(')').next()?;
+    Some(LoggedSample {
+        process: process.to_string(),
+        pid: pid.parse().ok()?,
+        delta_out: delta_out.trim().parse().ok()?,
+        delta_in: delta_in.trim().parse().ok()?,
+    })
+}
+
+// "#ButtonA in 'app' (PID=47727) → ↑ 6092 B ↓ 1460 B, 1 new connection(s) within 3.0s [a.com, b.com]"
+fn parse_correlation(text: &str) -> Option<LoggedCorrelation> {
+    let (head, traffic) = text.split_once(") → ")?;
+    let (head, pid) = head.rsplit_once(" (PID=")?;
+    let (target, app) = head.rsplit_once(" in '")?;
+    let (bytes, rest) = traffic.split_once(", ")?;
+    let (bytes_out, bytes_in) = bytes.strip_prefix("↑ ")?.split_once(" ↓ ")?;
+    let (new_connections, rest) = rest.split_once(" new connection(s) within ")?;
+    let (window, remotes) = rest.split_once('s').unwrap_or((rest, ""));
+    let remotes = remotes.trim().trim_start_matches('[').trim_end_matches(']');
+    Some(LoggedCorrelation {
+        target: target.to_string(),
+        app_name: app.trim_end_matches('\'').to_string(),
+        pid: pid.parse().ok()?,
+        bytes_out: bytes_out.trim_end_matches(" B").parse().ok()?,
+        bytes_in: bytes_in.trim_end_matches(" B").parse().ok()?,
+        new_connections: new_connections.parse().ok()?,
+        window_secs: window.parse().ok()?,
+        remotes: remotes.split(", ").filter(|remote| !remote.is_empty()).map(str::to_string).collect(),
+    })
+}
+
+// "tcp 10.0.0.5:52345 → api.example.com (93.184.216.34:443) first byte +41 ms,
+// last byte +388 ms, ↑ 6092 B ↓ 1460 B: api.example.com (TLS, h2)"
+fn parse_flow(text: &str) -> Option<LoggedFlow> {
+    let (protocol, rest) = text.split_once(' ')?;
+    let (local, rest) = rest.split_once(" → ")?;
+    let (peer, rest) = rest.split_once(" first byte ")?;
+    let (hostname, remote) = match peer.split_once(" (") {
+        Some((hostname, remote)) => (Some(hostname.to_string()), remote.trim_end_matches(')').to_string()),
+        None => (None, peer.to_string()),
+    };
+    let (first_byte, rest) = rest.split_once(" ms, last byte ")?;
+    let (last_byte, rest) = rest.split_once(" ms, ↑ ")?;
+    let (bytes_out, rest) = rest.split_once(" B ↓ ")?;
+    let (bytes_in, summary) = rest.split_once(" B").unwrap_or((rest, ""));
+    Some(LoggedFlow {
+        protocol: protocol.to_string(),
+        local: local.to_string(),
+        remote,
+        hostname,
+        first_byte_ms: first_byte.trim_start_matches('+').parse().ok()?,
+        last_byte_ms: last_byte.trim_start_matches('+').parse().ok()?,
+        bytes_out: bytes_out.parse().ok()?,
+        bytes_in: bytes_in.parse().ok()?,
+        summary: summary.strip_prefix(": ").map(str::to_string),
+    })
+}
+
+// Shared with the report and diff tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Two watcher runs appended to one log file. The second click on
+    // Archive was not logged itself (no identifier, not a button). The 📡
+    // lines are nettop samples taken at later clicks.
+    pub(crate) const LOG: &str = "\
+[20261018T091500-4242] 2026-10-18T09:15:00.100Z [INFO] ----- Starting macOS Watcher daemon (version 2.0) -----
+[20261018T091500-4242] 2026-10-18T09:15:01Z [INFO] Button Clicked: App='Mail' (PID=812), ID='send', Label='Send', LabelSource='title', Pos=(120.0, 45.5), Frame=(100.0, 30.0, 60.0, 24.0), RelPos=(20.0, 15.5), OnEdge=false, Window='Draft - it's done', WindowID=77, WindowFrame=(0.0, 0.0, 800.0, 600.0), Display=1, LocalPos=(120.0, 45.5), Scale=2.0
+[20261018T091500-4242] 2026-10-18T09:15:02Z [INFO] Key Down: App='Mail' (PID=812), KeyCode=36
+[20261018T091500-4242] 2026-10-18T09:15:04Z [INFO] 🔗 #send in 'Mail' (PID=812) → ↑ 6092 B ↓ 1460 B, 1 new connection(s) within 3.0s [smtp.example.com]
+[20261018T091500-4242] 2026-10-18T09:15:04Z [INFO]    ⏱ tcp 10.0.0.5:52345 → smtp.example.com (192.0.2.1:465) first byte +41 ms, last byte +388 ms, ↑ 6092 B ↓ 1460 B: smtp.example.com (TLS)
+[20261018T091500-4242] 2026-10-18T09:15:05Z [INFO] 🔗 AXCell 'Archive' in 'Mail' (PID=812) → ↑ 0 B ↓ 0 B, 0 new connection(s) within 2.0s
+[20261018T091500-4242] 2026-10-18T09:15:05Z [DEBUG] (3) macos_watcher::correlation:    tcp smtp.example.com (192.0.2.1:465) ↑ 6092 B ↓ 1460 B (new)
+[20261018T091500-4242] 2026-10-18T09:15:05.500Z [INFO] 📡 Mail.812 ↑ 12184 B ↓ 2920 B (Δ ↑ 6092 ↓ 1460) ↑ 6.1k/609/102 B/s ↓ 1.5k/146/24 B/s (1s/10s/60s), peak ↑ 6.1k ↓ 1.5k B/s
+[20261018T091500-4242] 2026-10-18T09:15:06Z [INFO] 📡 Dropbox.4242 ↑ 5000 B ↓ 100 B (Δ ↑ 5000 ↓ 100)
+[20261018T100000-5555] 2026-10-18T10:00:00Z [INFO] Element Clicked: App='Safari' (PID=900), ID='url', Role='AXTextField', Pos=(10.0, 10.0), Frame=<None>, RelPos=<None>, OnEdge=false, Window='<No Title>', WindowID=<None>, WindowFrame=<None>, Display=<None>, LocalPos=<None>, Scale=<None>
+";
+
+    pub(crate) fn utc(hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Utc.with_ymd_and_hms(2026, 10, 18, hour, minute, second).unwrap().with_timezone(&Local)
+    }
+
+    fn names(session: &LogSession) -> Vec<String> {
+        session.actions.iter().map(|action| format!("{} {:?}", action.app_name, action.selector().map(AxSelector::describe))).collect()
+    }
+
+    #[test]
+    fn log_files_are_split_into_sessions_and_actions() {
+        let sessions = parse_sessions(LOG);
+        assert_eq!(sessions.iter().map(|session| session.id.as_deref()).collect::<Vec<_>>(), [Some("20261018T091500-4242"), Some("20261018T100000-5555")]);
+
+        let session = &sessions[0];
+        assert_eq!(names(session), ["Mail Some(\"#send\")", "Mail None", "Mail Some(\"AXCell 'Archive'\")"]);
+        let times: Vec<DateTime<Local>> = session.actions.iter().map(|action| action.time).collect();
+        // The unlogged click is placed a window before its 🔗 line
+        assert_eq!(times, [utc(9, 15, 1), utc(9, 15, 2), utc(9, 15, 3)]);
+
+        let LoggedInput::Click(click) = &session.actions[0].input else { panic!("not a click") };
+        assert_eq!(
+            *click,
+            LoggedClick {
+                app_name: "Mail".to_string(),
+                pid: 812,
+                selector: AxSelector { identifier: Some("send".to_string()), role: Some("AXButton".to_string()), label: Some("Send".to_string()) },
+                position: Some((120.0, 45.5)),
+                window_title: Some("Draft - it's done".to_string()),
+                window_frame: Some((0.0, 0.0, 800.0, 600.0)),
+            }
+        );
+        assert_eq!(session.actions[1].input, LoggedInput::Key { keycode: 36 });
+
+        let outcome = session.actions[0].outcome.as_ref().unwrap();
+        assert_eq!((outcome.correlation.bytes_out, outcome.correlation.bytes_in, outcome.correlation.new_connections), (6092, 1460, 1));
+        assert_eq!((outcome.correlation.window_secs, &outcome.correlation.remotes[..]), (3.0, &["smtp.example.com".to_string()][..]));
+        assert_eq!(
+            outcome.flows,
+            [LoggedFlow {
+                protocol: "tcp".to_string(),
+                local: "10.0.0.5:52345".to_string(),
+                remote: "192.0.2.1:465".to_string(),
+                hostname: Some("smtp.example.com".to_string()),
+                first_byte_ms: 41,
+                last_byte_ms: 388,
+                bytes_out: 6092,
+                bytes_in: 1460,
+                summary: Some("smtp.example.com (TLS)".to_string()),
+            }]
+        );
+        assert!(session.actions[2].outcome.as_ref().unwrap().flows.is_empty());
+
+        let samples: Vec<(&str, u64, u64)> = session.samples.iter().map(|(_, sample)| (sample.process.as_str(), sample.delta_out, sample.delta_in)).collect();
+        assert_eq!(samples, [("Mail.812", 6092, 1460), ("Dropbox.4242", 5000, 100)]);
+        assert_eq!(session.samples[1].0, utc(9, 15, 6));
+
+        let LoggedInput::Click(click) = &sessions[1].actions[0].input else { panic!("not a click") };
+        assert_eq!((&click.selector.role, &click.window_title, click.window_frame), (&Some("AXTextField".to_string()), &None, None));
+    }
+
+    #[test]
+    fn sessions_are_picked_by_id_or_the_last() {
+        assert_eq!(read_session(LOG, None).unwrap().id.as_deref(), Some("20261018T100000-5555"));
+        assert_eq!(read_session(LOG, Some("20261018T091500-4242")).unwrap().actions.len(), 3);
+        assert_eq!(read_session(LOG, Some("nope")).unwrap_err(), "The log has no session 'nope'");
+        assert!(read_session("nothing to see\n", None).is_err());
+    }
+
+    #[test]
+    fn terminal_output_takes_times_from_earlier_lines() {
+        let console = "\
+\u{1b}[2m09:15:01\u{1b}[0m [\u{1b}[32mINFO\u{1b}[0m] Button Clicked: App='Mail' (PID=812), ID='send', Label='<No Label>', LabelSource='<None>', Pos=(1.0, 2.0), Window='Inbox'
+📡 Mail.812 ↑ 6092 B ↓ 1460 B (Δ ↑ 92 ↓ 60) ↑ 92/9/2 B/s ↓ 60/6/1 B/s (1s/10s/60s), peak ↑ 92 ↓ 60 B/s
+09:15:03 [INFO] Key Down: App='Mail' (PID=812), KeyCode=36
+📡 MailHelper.4.2.1.813 ↑ 10 B ↓ 0 B (Δ ↑ 10 ↓ 0)
+";
+        let session = read_session(console, None).unwrap();
+        assert_eq!(session.id, None);
+        let LoggedInput::Click(click) = &session.actions[0].input else { panic!("not a click") };
+        assert_eq!((&click.selector.label, click.window_frame), (&None, None));
+
+        let time = |hour, minute, second| Utc.from_utc_datetime(&NaiveDate::default().and_hms_opt(hour, minute, second).unwrap()).with_timezone(&Local);
+        let samples: Vec<(DateTime<Local>, &str, i32, u64, u64)> =
+            session.samples.iter().map(|(time, sample)| (*time, sample.process.as_str(), sample.pid, sample.delta_out, sample.delta_in)).collect();
+        assert_eq!(samples, [(time(9, 15, 1), "Mail.812", 812, 92, 60), (time(9, 15, 3), "MailHelper.4.2.1.813", 813, 10, 0)]);
+    }
+
+    #[test]
+    fn correlations_go_to_the_oldest_unanswered_click_on_the_element() {
+        let log = "\
+2026-10-18T09:15:01Z [INFO] Button Clicked: App='Mail' (PID=812), ID='send', Label='Send', LabelSource='title', Pos=(1.0, 2.0)
+2026-10-18T09:15:02Z [INFO] Button Clicked: App='Mail' (PID=812), ID='send', Label='Send', LabelSource='title', Pos=(1.0, 2.0)
+2026-10-18T09:15:04Z [INFO] 🔗 #send in 'Mail' (PID=812) → ↑ 1 B ↓ 0 B, 0 new connection(s) within 3.0s
+2026-10-18T09:15:05Z [INFO] 🔗 #send in 'Mail' (PID=812) → ↑ 2 B ↓ 0 B, 0 new connection(s) within 3.0s
+2026-10-18T09:15:05Z [INFO]    ⏱ udp 10.0.0.5:53000 → 10.0.0.1:53 first byte -5 ms, last byte +2 ms, ↑ 30 B ↓ 90 B
+2026-10-18T09:15:09Z [INFO] 🔗 #send in 'Mail' (PID=999) → ↑ 3 B ↓ 0 B, 0 new connection(s) within 3.0s
+";
+        let session = read_session(log, None).unwrap();
+        let sent: Vec<(i32, u64, usize)> = session
+            .actions
+            .iter()
+            .map(|action| {
+                let outcome = action.outcome.as_ref().unwrap();
+                (action.pid, outcome.correlation.bytes_out, outcome.flows.len())
+            })
+            .collect();
+        // Another process clicking the same element gets a click of its own
+        assert_eq!(sent, [(812, 1, 0), (812, 2, 1), (999, 3, 0)]);
+        let flow = &session.actions[1].outcome.as_ref().unwrap().flows[0];
+        assert_eq!((flow.hostname.as_deref(), flow.first_byte_ms, flow.summary.as_deref()), (None, -5, None));
+    }
+
+    #[test]
+    fn malformed_lines_are_not_entries() {
+        for message in [
+            "",
+            "Logging to: \"watcher.log\"",
+            "Button Clicked: App='Mail'",
+            "Button Clicked: App='Mail' (PID=abc), ID='send'",
+            "Key Down: App='Mail' (PID=812), KeyCode=enter",
+            "📡 Mail ↑ 1 B ↓ 2 B (Δ ↑ 1 ↓ 2)",
+            "📡 Mail.812 ↑ 1 B ↓ 2 B",
+            "📡 Mail.812 ↑ 1 B ↓ 2 B (Δ ↑ -1 ↓ 2)",
+            "🔗 #send in 'Mail' (PID=812)",
+            "🔗 #send in 'Mail' (PID=812) → ↑ 1 B ↓ 0 B, some new connection(s) within 3.0s",
+            "⏱ tcp 10.0.0.5:1 → 192.0.2.1:443",
+            "⏱ tcp 10.0.0.5:1 → 192.0.2.1:443 first byte soon ms, last byte +1 ms, ↑ 1 B ↓ 1 B",
+        ] {
+            assert_eq!(parse_entry(message), None, "{}", message);
+        }
+        assert_eq!(parse_sessions("garbage\n[not-a-session] 2026-10-18T09:15:01Z [INFO] nothing\n").len(), 0);
+    }
+
+    #[test]
+    fn targets_parse_back_into_selectors() {
+        for selector in [
+            AxSelector { identifier: Some("send".to_string()), ..AxSelector::default() },
+            AxSelector { identifier: None, role: Some("AXCell".to_string()), label: Some("Re: it's done".to_string()) },
+            AxSelector { identifier: None, role: None, label: Some("Archive".to_string()) },
+            AxSelector { identifier: None, role: Some("AXGroup".to_string()), label: None },
+            AxSelector::default(),
+        ] {
+            assert_eq!(parse_target(&selector.describe()), selector);
+        }
+    }
+
+    #[test]
+    fn rotated_logs_are_read_through_gzip() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("macos-watcher-log-parse-{}.log.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(LOG.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let text = read_log_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.unwrap(), LOG);
+
+        let missing = Path::new("/nonexistent/watcher.log");
+        assert!(read_log_file(missing).unwrap_err().starts_with("Failed to open /nonexistent/watcher.log"));
+    }
+}
@@ -1,24 +1,93 @@
+// Off macOS only the offline tools (query, flows, export, report, diff, ...)
+// are useful, which leaves parts of the live watcher unused there
+#![cfg_attr(not(target_os = "macos"), allow(dead_code))]
+
+#[cfg(target_os = "macos")]
 use core_foundation::runloop::{CFRunLoopRun, CFRunLoopGetCurrent, CFRunLoopAddSource};
+#[cfg(target_os = "macos")]
 use core_foundation::base::CFRelease;
 use std::process;
-use std::fs::File;
-use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(target_os = "macos")]
 use std::os::raw::c_void;
+#[cfg(target_os = "macos")]
 use std::ptr;
-use simplelog::{CombinedLogger, TermLogger, WriteLogger, Config, LevelFilter, TerminalMode, ColorChoice};
+use simplelog::{CombinedLogger, TermLogger, WriteLogger, Config, ConfigBuilder, LevelFilter, TerminalMode, ColorChoice};
 
 // Declare the modules
 mod utils;
+#[cfg(target_os = "macos")]
 mod cf_utils;
 mod accessibility;
+#[cfg(target_os = "macos")]
 mod event_tap;
 mod network;
+#[cfg(target_os = "macos")]
+mod ax_observer;
+mod label;
+mod cli;
+mod geometry;
+mod display;
+mod events;
+mod capture;
+mod replay;
+mod correlation;
+mod verify;
+mod session;
+mod store;
+mod query;
+mod log_file;
+mod otel;
+mod metrics;
+mod control;
+mod shutdown;
+mod summary;
+mod dashboard;
+mod tui;
+mod traffic;
+mod dns;
+mod pcap;
+mod resolver;
+mod libpcap;
+mod sockets;
+mod flows;
+mod dissect;
+mod har;
+mod trace;
+mod export;
+mod log_parse;
+mod report;
+mod rules;
+mod background;
+mod diff;
+mod webhook;
 
 // Import necessary items
+#[cfg(target_os = "macos")]
 use utils::open_accessibility_preferences;
+#[cfg(target_os = "macos")]
 use cf_utils::core_foundation_private::kCFRunLoopCommonModes;
+#[cfg(target_os = "macos")]
 use accessibility::*;
+#[cfg(target_os = "macos")]
 use event_tap::*;
+use cli::{Command, Options};
+use capture::CaptureRecorder;
+use correlation::CorrelationSink;
+use store::{EventStore, StoreSink};
+use log_file::RotatingFile;
+use otel::{OtlpConfig, OtlpSink};
+use metrics::MetricsSink;
+use control::WatcherController;
+use summary::SummarySink;
+use background::BackgroundMonitor;
+#[cfg(target_os = "macos")]
+use tui::TuiSink;
+use webhook::WebhookSink;
+use events::{Shutdown, WatcherEvent};
+#[cfg(target_os = "macos")]
+use events::Startup;
+use resolver::{DnsServerResolver, HostnameCache, HostnameResolver, Resolver, SystemResolver};
 
 // Type Aliases & Structs for C Types are now in accessibility.rs and event_tap.rs
 
@@ -31,24 +100,212 @@ use event_tap::*;
 // get_app_name_from_pid and open_accessibility_preferences are now in utils.rs
 
 fn main() {
-    // Determine log path
-    let home_dir = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    let log_path = PathBuf::from(home_dir).join("macos_watcher.log");
-
-    // Initialize simplelog
-    let log_file = File::create(&log_path).expect("Failed to create log file");
-    CombinedLogger::init(
-        vec![
-            TermLogger::new(LevelFilter::Info, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
-            WriteLogger::new(LevelFilter::Debug, Config::default(), log_file),
-        ]
-    ).expect("Failed to initialize logger");
+    let command = match cli::parse_args() {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            cli::print_usage();
+            process::exit(2);
+        }
+    };
+
+    match command {
+        Command::Watch(options) => run_watcher(options),
+        Command::Replay(args) => {
+            TermLogger::init(LevelFilter::Info, Config::default(), TerminalMode::Mixed, ColorChoice::Auto)
+                .expect("Failed to initialize logger");
+            process::exit(replay::run_cli(args));
+        }
+        Command::Query(args) => process::exit(query::run_cli(args)),
+        Command::Ctl(args) => process::exit(control::run_cli(args)),
+        Command::Flows(args) => process::exit(flows::run_cli(args)),
+        Command::Export(args) => process::exit(export::run_cli(args)),
+        Command::Report(args) => process::exit(report::run_cli(args)),
+        Command::Diff(args) => process::exit(diff::run_cli(args)),
+        Command::Verify(args, options) => {
+            if let Err(message) = verify::start(args) {
+                eprintln!("Error: {}", message);
+                process::exit(2);
+            }
+            run_watcher(options);
+            process::exit(verify::exit_code());
+        }
+    }
+}
+
+fn run_watcher(options: Options) {
+    let log_path = options.log_path.clone();
+
+    // Initialize simplelog. The file is appended to and rotated, and every
+    // line carries the session ID so restarts can be told apart.
+    let log_file = RotatingFile::open(&log_path, options.rotation.clone())
+        .expect("Failed to open log file")
+        .with_line_prefix(format!("[{}] ", session::session_id()));
+    let file_config = ConfigBuilder::new().set_time_format_rfc3339().build();
+    let mut loggers: Vec<Box<dyn simplelog::SharedLogger>> = vec![
+        WriteLogger::new(LevelFilter::Debug, file_config, log_file),
+    ];
+    // The dashboard owns the terminal, so log lines only go to the file then
+    if !options.tui {
+        loggers.push(TermLogger::new(LevelFilter::Info, Config::default(), TerminalMode::Mixed, ColorChoice::Auto));
+    }
+    CombinedLogger::init(loggers).expect("Failed to initialize logger");
 
     // Log initial messages using the new logger
     log::info!("----- Starting macOS Watcher daemon (version 2.0) -----");
-    log::info!("Log file created at: {:?}", log_path);
+    log::info!("Logging to: {:?} (session {})", log_path, session::session_id());
     log::info!("Current executable: {:?}", std::env::current_exe().unwrap_or_default());
 
+    if let Err(e) = shutdown::install_signal_handlers() {
+        log::warn!("Failed to install signal handlers, Ctrl+C will not shut down cleanly: {}", e);
+    }
+
+    // Registered first so the summary sees every event
+    let summary_sink = SummarySink::default();
+    events::add_sink(Box::new(summary_sink.clone()));
+
+    if let Some(record_path) = &options.record_path {
+        match CaptureRecorder::create(record_path, options.rotation.clone()) {
+            Ok(recorder) => {
+                events::add_sink(Box::new(recorder));
+                log::info!("Recording clicks and key presses to {:?}", record_path);
+            }
+            Err(e) => {
+                log::error!("Failed to create capture file {:?}: {}", record_path, e);
+                eprintln!("Error: Failed to create capture file {}: {}", record_path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(db_path) = &options.db_path {
+        match EventStore::open(db_path).and_then(|store| store.start_session().map(|_| store)) {
+            Ok(store) => {
+                events::add_sink(Box::new(StoreSink::new(store)));
+                log::info!("Storing events in {:?} (session {})", db_path, session::session_id());
+            }
+            Err(e) => {
+                log::error!("Failed to open event store {:?}: {}", db_path, e);
+                eprintln!("Error: Failed to open event store {}: {}", db_path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Before correlation, which annotates connections with hostnames
+    if options.resolve_hosts {
+        let lookup: Box<dyn Resolver> = match options.dns_server {
+            Some(server) => {
+                log::info!("Resolving hostnames with DNS server {}", server);
+                Box::new(DnsServerResolver::new(server))
+            }
+            None => Box::new(SystemResolver),
+        };
+        resolver::install(HostnameResolver::new(lookup, HostnameCache::default()));
+    }
+    if let Some(source) = &options.dns_snoop {
+        match resolver::start_snooping(source) {
+            Ok(()) => log::info!("Learning hostnames from DNS responses in {}", source),
+            Err(e) => log::warn!("Not learning hostnames from {}: {}", source, e),
+        }
+    }
+
+    if let Some(source) = &options.capture {
+        match flows::open_source(source) {
+            Ok(source) => {
+                log::info!("Capturing packets from {}", source.describe());
+                flows::start_capture(source);
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(rules_path) = &options.rules_path {
+        match rules::load_rules(rules_path) {
+            Ok(rules) => {
+                log::info!("Loaded {} alert rule(s) from {:?}", rules.len(), rules_path);
+                rules::install(rules);
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if !options.correlation_window.is_zero() {
+        events::add_sink(Box::new(CorrelationSink::new(options.correlation_window)));
+    }
+
+    // Traffic within the correlation window of a UI action counts as user-initiated
+    let background = (!options.background_interval.is_zero()).then(|| {
+        let window = if options.correlation_window.is_zero() {
+            Duration::from_secs_f64(cli::DEFAULT_CORRELATION_WINDOW_SECS)
+        } else {
+            options.correlation_window
+        };
+        let monitor = BackgroundMonitor::start(window, options.background_interval);
+        events::add_sink(Box::new(monitor.clone()));
+        log::info!("Sampling all processes every {:.1}s to find background traffic", options.background_interval.as_secs_f64());
+        monitor
+    });
+
+    if let Some(metrics_addr) = &options.metrics_addr {
+        match metrics::serve(metrics_addr) {
+            Ok(address) => {
+                events::add_sink(Box::new(MetricsSink));
+                log::info!("Serving Prometheus metrics at http://{}/metrics", address);
+            }
+            Err(e) => {
+                log::error!("Failed to listen on {}: {}", metrics_addr, e);
+                eprintln!("Error: Failed to serve metrics on {}: {}", metrics_addr, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(socket_path) = &options.control_socket {
+        // Not fatal: the watcher is still useful without remote control
+        match control::serve(socket_path, WatcherController::new(options.rotation.clone())) {
+            Ok(()) => log::info!("Listening for control commands on {:?}", socket_path),
+            Err(e) => log::warn!("Control socket {:?} unavailable: {}", socket_path, e),
+        }
+    }
+
+    if let Some(endpoint) = &options.otlp_endpoint {
+        let config = OtlpConfig::new(endpoint, &options.otlp_service_name);
+        log::info!("Exporting UI actions as OpenTelemetry spans to {}", config.endpoint);
+        events::add_sink(Box::new(OtlpSink::new(config, !options.correlation_window.is_zero())));
+    }
+
+    if let Some(url) = &options.webhook.url {
+        match WebhookSink::start(url, &options.webhook) {
+            Ok(sink) => {
+                events::add_sink(Box::new(sink));
+                log::info!("Posting {} events to webhook {}", options.webhook.event_types.join(", "), url);
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    run_event_loop(&options);
+
+    finish_session(&options, &summary_sink, background.as_ref());
+}
+
+// Install the event tap and run the main run loop until a stop is requested
+#[cfg(target_os = "macos")]
+fn run_event_loop(options: &Options) {
+    let log_path = &options.log_path;
     unsafe {
         // Check if accessibility is enabled using functions from accessibility module
         let api_enabled = ax_api_enabled();
@@ -85,13 +342,19 @@ fn main() {
         log::debug!("Setting up CGEventTap for mouse clicks... ");
         let event_mask = (1 << K_CG_EVENT_LEFT_MOUSE_DOWN) | (1 << K_CG_EVENT_KEY_DOWN);
 
+        // Lives for the rest of the process; the callback reads it on every event
+        let tap_context = Box::into_raw(Box::new(TapContext {
+            system_wide,
+            label_chain: options.label_chain.clone(),
+        }));
+
         let event_tap = CGEventTapCreate(
             K_CG_HID_EVENT_TAP,
             K_CG_HEAD_INSERT_EVENT_TAP,
             K_CG_EVENT_TAP_DEFAULT,
             event_mask,
             event_callback,
-            system_wide as *mut c_void,
+            tap_context as *mut c_void,
         );
 
         if event_tap.is_null() {
@@ -121,8 +384,19 @@ fn main() {
         println!("Successfully running with accessibility permissions!");
         println!("Monitoring input events (clicks, keys). Check logs at: {}", log_path.display());
         
-        log::info!("Starting main run loop...");
-        CFRunLoopRun();
+        if options.tui {
+            events::add_sink(Box::new(TuiSink::start()));
+        }
+        events::emit(&WatcherEvent::Startup(Startup {
+            timestamp: chrono::Local::now(),
+            session_id: session::session_id().to_string(),
+        }));
+
+        // A signal that arrived during setup could not stop a loop that was not running yet
+        if !shutdown::is_stopping() {
+            log::info!("Starting main run loop...");
+            CFRunLoopRun();
+        }
         
         log::info!("Run loop finished. Exiting...");
         
@@ -132,4 +406,37 @@ fn main() {
     }
 }
 
+#[cfg(not(target_os = "macos"))]
+fn run_event_loop(_options: &Options) {
+    log::error!("Watching input needs macOS (CGEventTap and the Accessibility API).");
+    eprintln!("Error: Watching input needs macOS. The query, flows, export, report and diff commands work anywhere.");
+    process::exit(1);
+}
+
+// Flush every sink and write the session summary
+fn finish_session(options: &Options, summary_sink: &SummarySink, background: Option<&BackgroundMonitor>) {
+    let mut summary = summary_sink.summary(chrono::Local::now(), events::tap_disabled_count());
+    if let Some(background) = background {
+        summary.traffic_per_app = background.report(summary.duration_secs);
+    }
+    // Before the sinks finish, so exporters still send it
+    events::emit(&WatcherEvent::Shutdown(Box::new(Shutdown { timestamp: summary.ended_at, summary: summary.clone() })));
+    events::finish_all();
+
+    let text = summary.format_text();
+    println!("{}", text);
+    for line in text.lines() {
+        log::info!("{}", line);
+    }
+    match summary::append_json(&options.summary_path, &summary) {
+        Ok(()) => log::info!("Session summary appended to {:?}", options.summary_path),
+        Err(e) => log::error!("Failed to write session summary to {:?}: {}", options.summary_path, e),
+    }
+
+    if let Some(socket_path) = &options.control_socket {
+        let _ = std::fs::remove_file(socket_path);
+    }
+    log::logger().flush();
+}
+
 // CoreFoundation_Private module is now in cf_utils.rs
@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{EventSink, WatcherEvent};
+use crate::network::split_process_name;
+
+// --- Metric names ---
+
+pub const EVENTS_TOTAL: &str = "macos_watcher_events_total";
+pub const TAP_DISABLED_TOTAL: &str = "macos_watcher_tap_disabled_total";
+pub const AX_ERRORS_TOTAL: &str = "macos_watcher_ax_errors_total";
+pub const COMMAND_DURATION: &str = "macos_watcher_command_duration_seconds";
+pub const CALLBACK_DURATION: &str = "macos_watcher_callback_duration_seconds";
+// Only clicks sample nettop for the clicked app, so this undercounts apps
+// that keep talking after their last click
+pub const CLICK_SAMPLED_BYTES_TOTAL: &str = "macos_watcher_click_sampled_bytes_total";
+pub const ALERTS_TOTAL: &str = "macos_watcher_alerts_total";
+pub const BACKGROUND_BYTES_TOTAL: &str = "macos_watcher_background_bytes_total";
+pub const APP_BYTES_PER_SECOND: &str = "macos_watcher_app_bytes_per_second";
+
+// Name, type and help text of every metric, in the order they are rendered
+const METRICS: &[(&str, &str, &str)] = &[
+    (EVENTS_TOTAL, "counter", "Events emitted by the watcher, by type and app."),
+    (TAP_DISABLED_TOTAL, "counter", "Times macOS disabled the event tap, by reason."),
+    (AX_ERRORS_TOTAL, "counter", "Failed Accessibility API queries, by AXError code."),
+    (COMMAND_DURATION, "histogram", "Wall time of external commands (ps, nettop, lsof)."),
+    (CALLBACK_DURATION, "histogram", "Wall time of one event tap callback."),
+    (CLICK_SAMPLED_BYTES_TOTAL, "counter", "Bytes per app between the nettop samples taken at its clicks, by direction."),
+    (ALERTS_TOTAL, "counter", "Alerts raised by --rules, by rule and severity."),
+    (BACKGROUND_BYTES_TOTAL, "counter", "Bytes per app not preceded by a UI action in that app, by direction."),
+    (APP_BYTES_PER_SECOND, "gauge", "Recent bytes per second per app, by origin (user or background) and direction."),
+];
+
+// Upper bounds (seconds) of the histogram buckets. External commands take
+// tens to hundreds of milliseconds, the callback should stay well below 1s
+// or macOS disables the tap.
+const BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Clone, Debug, Default)]
+struct HistogramData {
+    // Cumulative count per entry of BUCKETS
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<&'static str, BTreeMap<Labels, u64>>,
+    histograms: BTreeMap<&'static str, BTreeMap<Labels, HistogramData>>,
+    gauges: BTreeMap<&'static str, BTreeMap<Labels, f64>>,
+}
+
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+fn with_registry<T>(f: impl FnOnce(&mut Registry) -> T) -> T {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(registry.get_or_insert_with(Registry::default))
+}
+
+fn owned_labels(labels: &[(&'static str, &str)]) -> Labels {
+    labels.iter().map(|(name, value)| (*name, value.to_string())).collect()
+}
+
+pub fn add_counter(name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+    with_registry(|registry| {
+        *registry.counters.entry(name).or_default().entry(owned_labels(labels)).or_default() += value;
+    });
+}
+
+pub fn inc_counter(name: &'static str, labels: &[(&'static str, &str)]) {
+    add_counter(name, labels, 1);
+}
+
+pub fn set_gauge(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    with_registry(|registry| {
+        registry.gauges.entry(name).or_default().insert(owned_labels(labels), value);
+    });
+}
+
+pub fn observe(name: &'static str, labels: &[(&'static str, &str)], seconds: f64) {
+    with_registry(|registry| {
+        let histogram = registry
+            .histograms
+            .entry(name)
+            .or_default()
+            .entry(owned_labels(labels))
+            .or_insert_with(|| HistogramData { buckets: vec![0; BUCKETS.len()], ..Default::default() });
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    });
+}
+
+// Records the time until it is dropped into a histogram, so early returns are timed too
+pub struct Timer {
+    name: &'static str,
+    labels: Vec<(&'static str, &'static str)>,
+    started: Instant,
+}
+
+impl Timer {
+    pub fn start(name: &'static str, labels: &[(&'static str, &'static str)]) -> Self {
+        Timer { name, labels: labels.to_vec(), started: Instant::now() }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        observe(self.name, &self.labels, self.started.elapsed().as_secs_f64());
+    }
+}
+
+pub fn ax_error(code: i32) {
+    inc_counter(AX_ERRORS_TOTAL, &[("code", &code.to_string())]);
+}
+
+// --- Exposition ---
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(&'static str, String)], extra: Option<(&str, String)>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect();
+    if let Some((name, value)) = extra {
+        parts.push(format!("{}=\"{}\"", name, value));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+// Everything recorded so far in the Prometheus text exposition format (0.0.4)
+pub fn render() -> String {
+    with_registry(|registry| {
+        let mut output = String::new();
+        for (name, kind, help) in METRICS {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+
+            if let Some(series) = registry.counters.get(name) {
+                for (labels, value) in series {
+                    let _ = writeln!(output, "{}{} {}", name, format_labels(labels, None), value);
+                }
+            }
+            if let Some(series) = registry.gauges.get(name) {
+                for (labels, value) in series {
+                    let _ = writeln!(output, "{}{} {}", name, format_labels(labels, None), value);
+                }
+            }
+            if let Some(series) = registry.histograms.get(name) {
+                for (labels, histogram) in series {
+                    for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+                        let le = format_labels(labels, Some(("le", bound.to_string())));
+                        let _ = writeln!(output, "{}_bucket{} {}", name, le, count);
+                    }
+                    let le = format_labels(labels, Some(("le", "+Inf".to_string())));
+                    let _ = writeln!(output, "{}_bucket{} {}", name, le, histogram.count);
+                    let _ = writeln!(output, "{}_sum{} {}", name, format_labels(labels, None), histogram.sum);
+                    let _ = writeln!(output, "{}_count{} {}", name, format_labels(labels, None), histogram.count);
+                }
+            }
+        }
+        output
+    })
+}
+
+// Serve GET /metrics on `address` from a background thread. Returns the bound
+// address, which is useful when asking for port 0.
+pub fn serve(address: &str) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_request(stream) {
+                        log::debug!("Metrics request failed: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("Metrics listener error: {}", e),
+            }
+        }
+    });
+    Ok(local_address)
+}
+
+fn handle_request(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Drain the headers; the request has no body we care about
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header != "\r\n" && header != "\n" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", render()),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found. Try /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Only GET is supported\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// Counts events by type and app, and the bytes of the nettop samples taken at clicks
+pub struct MetricsSink;
+
+impl EventSink for MetricsSink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        let mut app = event.app_name().unwrap_or("");
+        if let WatcherEvent::NetworkSample(sample) = event {
+            app = split_process_name(&sample.process).map_or(app, |(name, _)| name);
+            add_counter(CLICK_SAMPLED_BYTES_TOTAL, &[("app", app), ("direction", "in")], sample.delta_in);
+            add_counter(CLICK_SAMPLED_BYTES_TOTAL, &[("app", app), ("direction", "out")], sample.delta_out);
+        }
+        if let WatcherEvent::Alert(alert) = event {
+            inc_counter(ALERTS_TOTAL, &[("rule", &alert.rule), ("severity", &alert.severity.to_string())]);
+        }
+        inc_counter(EVENTS_TOTAL, &[("type", event.kind()), ("app", app)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{click, key, sample};
+    use std::io::Read;
+
+    // The registry is shared by every test, so each test uses apps of its own
+    fn line_for(rendered: &str, prefix: &str) -> Option<String> {
+        rendered.lines().find(|line| line.starts_with(prefix)).map(str::to_string)
+    }
+
+    #[test]
+    fn sink_counts_events_and_click_sampled_bytes() {
+        let mut sink = MetricsSink;
+        sink.handle(&WatcherEvent::Click(Box::new(click(0, 42, "MetricsApp", "Send"))));
+        sink.handle(&key(10, 42, "MetricsApp", 36));
+        sink.handle(&key(20, 42, "MetricsApp", 36));
+        sink.handle(&sample(30, "MetricsApp.42", 42, 700, 300));
+        sink.handle(&sample(40, "MetricsApp.42", 42, 100, 0));
+
+        let rendered = render();
+        assert_eq!(line_for(&rendered, "macos_watcher_events_total{type=\"key\",app=\"MetricsApp\"}").as_deref(), Some("macos_watcher_events_total{type=\"key\",app=\"MetricsApp\"} 2"));
+        assert_eq!(
+            line_for(&rendered, "macos_watcher_click_sampled_bytes_total{app=\"MetricsApp\",direction=\"in\"}").as_deref(),
+            Some("macos_watcher_click_sampled_bytes_total{app=\"MetricsApp\",direction=\"in\"} 800")
+        );
+        assert!(rendered.contains("macos_watcher_events_total{type=\"network_sample\",app=\"MetricsApp\"} 2"));
+        assert!(rendered.contains("# TYPE macos_watcher_click_sampled_bytes_total counter"));
+    }
+
+    #[test]
+    fn histograms_render_cumulative_buckets() {
+        observe(COMMAND_DURATION, &[("command", "metrics-test")], 0.02);
+        observe(COMMAND_DURATION, &[("command", "metrics-test")], 3.0);
+
+        let rendered = render();
+        let bucket = |le: &str| line_for(&rendered, &format!("{}_bucket{{command=\"metrics-test\",le=\"{}\"}}", COMMAND_DURATION, le));
+        assert!(bucket("0.01").unwrap().ends_with(" 0"));
+        assert!(bucket("0.025").unwrap().ends_with(" 1"));
+        assert!(bucket("5").unwrap().ends_with(" 2"));
+        assert!(bucket("+Inf").unwrap().ends_with(" 2"));
+        assert!(rendered.contains(&format!("{}_sum{{command=\"metrics-test\"}} 3.02", COMMAND_DURATION)));
+    }
+
+    #[test]
+    fn gauges_keep_the_last_value() {
+        let labels = [("app", "GaugeApp"), ("origin", "background"), ("direction", "out")];
+        set_gauge(APP_BYTES_PER_SECOND, &labels, 12.5);
+        set_gauge(APP_BYTES_PER_SECOND, &labels, 0.0);
+        let rendered = render();
+        assert!(rendered.contains("# TYPE macos_watcher_app_bytes_per_second gauge"));
+        assert_eq!(
+            line_for(&rendered, "macos_watcher_app_bytes_per_second{app=\"GaugeApp\"").as_deref(),
+            Some("macos_watcher_app_bytes_per_second{app=\"GaugeApp\",origin=\"background\",direction=\"out\"} 0")
+        );
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        inc_counter(ALERTS_TOTAL, &[("rule", "say \"hi\"\\n"), ("severity", "info")]);
+        assert!(render().contains(r#"macos_watcher_alerts_total{rule="say \"hi\"\\n",severity="info"} 1"#));
+    }
+
+    #[test]
+    fn serves_metrics_over_http() {
+        add_counter(EVENTS_TOTAL, &[("type", "click"), ("app", "ServedApp")], 3);
+        let address = serve("127.0.0.1:0").unwrap();
+        let get = |request: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = get("GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.contains("macos_watcher_events_total{type=\"click\",app=\"ServedApp\"} 3"));
+        assert!(get("GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(get("POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+    }
+}
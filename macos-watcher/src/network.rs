@@ -1,36 +1,345 @@
+use std::collections::HashSet;
 use std::process::Command;
-use std::collections::HashMap;
+use std::time::Instant;
 
-pub fn check_nettop_for_pid(target: &str, cache: &mut HashMap<String, (u64, u64)>) {
+use crate::events::{self, NetworkSample, WatcherEvent};
+use crate::metrics::{self, Timer};
+use crate::traffic::{format_rates, TrafficHistory};
+use crate::utils::process_tree;
+
+// One process row of `nettop -P -l 1`, e.g. "example-mac-app.47727  1234  5678"
+#[derive(Clone, Debug, PartialEq)]
+pub struct NettopRow {
+  // nettop's "<name>.<pid>" key
+  pub process: String,
+  pub name: String,
+  pub pid: i32,
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+}
+
+// Parse the per-process text output of `nettop -P -J bytes_in,bytes_out -x -l 1`.
+// The two counters are the last columns; everything before them is the
+// "<name>.<pid>" key, which may itself contain spaces ("Google Chrome He.812").
+// A leading sample time ("11:54:09.432471 ") is dropped. Header and malformed
+// lines are skipped.
+pub fn parse_nettop_rows(output: &str) -> Vec<NettopRow> {
+  output
+      .lines()
+      .filter_map(|line| {
+        let line = line.trim_end();
+        let (rest, bytes_out) = line.rsplit_once(char::is_whitespace)?;
+        let rest = rest.trim_end();
+        let (process, bytes_in) = rest.rsplit_once(char::is_whitespace)?;
+        let process = strip_sample_time(process.trim());
+        let (name, pid) = split_process_name(process)?;
+        Some(NettopRow {
+          process: process.to_string(),
+          name: name.to_string(),
+          pid,
+          bytes_in: bytes_in.parse().ok()?,
+          bytes_out: bytes_out.parse().ok()?,
+        })
+      })
+      .collect()
+}
+
+// Run nettop once for every process
+pub fn sample_nettop_rows() -> Vec<NettopRow> {
+  let _timer = Timer::start(metrics::COMMAND_DURATION, &[("command", "nettop")]);
   let output = Command::new("nettop")
-      .args(&["-P", "-J", "bytes_in,bytes_out", "-x", "-l", "1"])
-      .output()
-      .expect("Failed to run nettop");
-
-  let stdout = String::from_utf8_lossy(&output.stdout);
-
-  for line in stdout.lines() {
-      if line.to_lowercase().contains(target) {
-          let parts: Vec<&str> = line.split_whitespace().collect();
-
-          if parts.len() >= 3 {
-              let key = parts[0]; // e.g. example-mac-app.17759
-              let bytes_in = parts[1].parse::<u64>().unwrap_or(0);
-              let bytes_out = parts[2].parse::<u64>().unwrap_or(0);
-
-              let previous = cache.get(key).copied().unwrap_or((0, 0));
-              if bytes_in != previous.0 || bytes_out != previous.1 {
-                  let delta_in = bytes_in.saturating_sub(previous.0);
-                  let delta_out = bytes_out.saturating_sub(previous.1);
-
-                  println!(
-                      "📡 {} ↑ {} B ↓ {} B (Δ ↑ {} ↓ {})",
-                      key, bytes_out, bytes_in, delta_out, delta_in
-                  );
-
-                  cache.insert(key.to_string(), (bytes_in, bytes_out));
-              }
-          }
+      .args(["-P", "-J", "bytes_in,bytes_out", "-x", "-l", "1"])
+      .output();
+
+  match output {
+    Ok(output) if output.status.success() => parse_nettop_rows(&String::from_utf8_lossy(&output.stdout)),
+    Ok(output) => {
+      log::debug!("nettop exited with {}", output.status);
+      Vec::new()
+    }
+    Err(e) => {
+      log::error!("Failed to run nettop: {}", e);
+      Vec::new()
+    }
+  }
+}
+
+// Log and emit the traffic of `pid` and its child processes, and return the
+// sample so it can serve as the baseline of a correlation window
+pub fn check_nettop_for_pid(pid: i32, history: &mut TrafficHistory) -> Vec<ProcessTraffic> {
+  let now = Instant::now();
+  let samples = sample_process_tree(pid);
+
+  for sample in &samples {
+      let key = sample.key(); // e.g. example-mac-app.17759
+      let key = key.as_str();
+      let changed = history.last_counters(key) != Some((sample.bytes_in, sample.bytes_out));
+      let recorded = history.record(key, now, sample.bytes_in, sample.bytes_out);
+      if recorded.reset {
+          let resets = history.process(key).map_or(0, |process| process.resets());
+          log::debug!("nettop counters for {} went backwards (reset #{}); treating it as a new process", key, resets);
+      }
+      if changed {
+          let rates = history.process(key).map(|process| process.rates(now)).unwrap_or_default();
+
+          // Logged rather than printed, so the log file has them for `report`
+          log::info!(
+              "📡 {} ↑ {} B ↓ {} B (Δ ↑ {} ↓ {}) {}",
+              key, sample.bytes_out, sample.bytes_in, recorded.delta_out, recorded.delta_in, format_rates(&rates)
+          );
+
+          events::emit(&WatcherEvent::NetworkSample(NetworkSample {
+              timestamp: chrono::Local::now(),
+              pid: sample.pid,
+              process: key.to_string(),
+              bytes_in: sample.bytes_in,
+              bytes_out: sample.bytes_out,
+              delta_in: recorded.delta_in,
+              delta_out: recorded.delta_out,
+              rates: Some(rates),
+          }));
+      }
+  }
+  samples
+}
+
+// One connection row from nettop, e.g. "tcp4 10.0.0.5:52345<->93.184.216.34:443"
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionSample {
+  pub protocol: String,
+  pub local: String,
+  pub remote_host: String,
+  pub remote_port: Option<u16>,
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+}
+
+impl ConnectionSample {
+  // Identifies the same connection across samples
+  pub fn key(&self) -> String {
+    format!("{} {}<->{}:{}", self.protocol, self.local, self.remote_host, self.remote_port.unwrap_or(0))
+  }
+}
+
+// A process row from nettop plus the connection rows listed under it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessTraffic {
+  pub name: String,
+  pub pid: i32,
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+  pub connections: Vec<ConnectionSample>,
+}
+
+impl ProcessTraffic {
+  // nettop's "<name>.<pid>" row key
+  pub fn key(&self) -> String {
+    format!("{}.{}", self.name, self.pid)
+  }
+}
+
+// Take a single per-connection sample of every process and keep those in `pids`.
+// -L gives CSV output, -n skips DNS lookups so remote addresses stay numeric.
+pub fn sample_processes(pids: &HashSet<i32>) -> Vec<ProcessTraffic> {
+  let timer = Timer::start(metrics::COMMAND_DURATION, &[("command", "nettop")]);
+  let output = Command::new("nettop")
+      .args(["-L", "1", "-n", "-x", "-J", "bytes_in,bytes_out"])
+      .output();
+  drop(timer);
+
+  match output {
+    Ok(output) if output.status.success() => {
+      let stdout = String::from_utf8_lossy(&output.stdout);
+      parse_nettop_csv(&stdout).into_iter().filter(|process| pids.contains(&process.pid)).collect()
+    }
+    Ok(output) => {
+      log::debug!("nettop exited with {}", output.status);
+      Vec::new()
+    }
+    Err(e) => {
+      log::error!("Failed to run nettop: {}", e);
+      Vec::new()
+    }
+  }
+}
+
+// `pid` and every process it spawned
+pub fn sample_process_tree(pid: i32) -> Vec<ProcessTraffic> {
+  sample_processes(&process_tree(pid))
+}
+
+// Parse `nettop -L` CSV output ("time,name,bytes_in,bytes_out,").
+// Process rows are named "<name>.<pid>"; connection rows that follow belong to them.
+pub fn parse_nettop_csv(output: &str) -> Vec<ProcessTraffic> {
+  let mut processes: Vec<ProcessTraffic> = Vec::new();
+
+  for line in output.lines() {
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.len() < 4 {
+      continue;
+    }
+    let name = fields[1].trim();
+    let (Ok(bytes_in), Ok(bytes_out)) = (fields[2].trim().parse::<u64>(), fields[3].trim().parse::<u64>()) else {
+      continue; // Header row or a column we did not ask for
+    };
+
+    if let Some(connection) = parse_connection_name(name, bytes_in, bytes_out) {
+      if let Some(process) = processes.last_mut() {
+        process.connections.push(connection);
       }
+    } else if let Some((process_name, pid)) = split_process_name(name) {
+      processes.push(ProcessTraffic {
+        name: process_name.to_string(),
+        pid,
+        bytes_in,
+        bytes_out,
+        connections: Vec::new(),
+      });
+    }
+  }
+  processes
+}
+
+// "11:54:09.432471 apsd.375" -> "apsd.375"
+fn strip_sample_time(row: &str) -> &str {
+  let is_time = |token: &str| token.contains(':') && token.bytes().all(|b| b.is_ascii_digit() || b == b':' || b == b'.');
+  match row.split_once(' ') {
+    Some((time, rest)) if is_time(time) => rest.trim_start(),
+    _ => row,
+  }
+}
+
+// "example-mac-app.47727" -> ("example-mac-app", 47727)
+pub fn split_process_name(name: &str) -> Option<(&str, i32)> {
+  let (process_name, pid) = name.rsplit_once('.')?;
+  if pid.is_empty() || !pid.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  Some((process_name, pid.parse().ok()?))
+}
+
+fn parse_connection_name(name: &str, bytes_in: u64, bytes_out: u64) -> Option<ConnectionSample> {
+  let (protocol, endpoints) = name.split_once(' ')?;
+  if !(protocol.starts_with("tcp") || protocol.starts_with("udp")) {
+    return None;
+  }
+  let (local, remote) = endpoints.trim().split_once("<->")?;
+  let (remote_host, remote_port) = split_host_port(remote.trim());
+
+  Some(ConnectionSample {
+    protocol: protocol.to_string(),
+    local: local.trim().to_string(),
+    remote_host: remote_host.to_string(),
+    remote_port,
+    bytes_in,
+    bytes_out,
+  })
+}
+
+// nettop writes IPv4 as "1.2.3.4:443" but IPv6 as "2606:2800::1.443"
+fn split_host_port(address: &str) -> (&str, Option<u16>) {
+  let separator = if address.matches(':').count() == 1 { ':' } else { '.' };
+  match address.rsplit_once(separator) {
+    Some((host, port)) => match port.parse::<u16>() {
+      Ok(port) => (host, Some(port)),
+      Err(_) => (host, None), // "*" for unconnected sockets
+    },
+    None => (address, None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // `nettop -L 1 -n -x -J bytes_in,bytes_out`, trimmed
+  const NETTOP_CSV: &str = "time,,bytes_in,bytes_out,
+11:54:09.432471,apsd.375,4566,2343,
+11:54:09.432480,tcp4 10.0.0.5:52345<->17.57.146.20:5223,4566,2343,
+11:54:09.433002,Google Chrome He.812,98765,4321,
+11:54:09.433010,tcp6 2001:db8::5.52346<->2606:4700::6810:85e5.443,98000,4000,
+11:54:09.433015,tcp6 fe80::1%en0.49152<->fe80::aede:48ff:fe00:1122%en0.8080,765,0,
+11:54:09.433020,udp4 *:5353<->*:*,765,321,
+11:54:09.433030,com.apple.WebKit.Networking.47727,10,20,
+11:54:09.433040,udp6 *.*<->*.*,0,0,
+11:54:09.433050,example-mac-app.47,1,2,
+";
+
+  // `nettop -P -J bytes_in,bytes_out -x -l 1`, trimmed
+  const NETTOP_ROWS: &str = "                                                    bytes_in       bytes_out
+launchd.1                                                  0               0
+mDNSResponder.195                                    1234567          234567
+Google Chrome He.812                                98765432         1234567
+11:54:09.433030 com.apple.WebKit.Networking.47727        4321            1234
+kernel_task.0                                       1 2
+not a row
+";
+
+  #[test]
+  fn csv_rows_keep_names_with_dots_and_spaces() {
+    let processes = parse_nettop_csv(NETTOP_CSV);
+    let keys: Vec<String> = processes.iter().map(ProcessTraffic::key).collect();
+    assert_eq!(keys, ["apsd.375", "Google Chrome He.812", "com.apple.WebKit.Networking.47727", "example-mac-app.47"]);
+    assert_eq!((processes[1].bytes_in, processes[1].bytes_out), (98765, 4321));
+    assert_eq!(processes[3].pid, 47);
+  }
+
+  #[test]
+  fn csv_connections_belong_to_the_process_above_them() {
+    let processes = parse_nettop_csv(NETTOP_CSV);
+    assert_eq!(processes[0].connections[0], ConnectionSample {
+      protocol: "tcp4".to_string(),
+      local: "10.0.0.5:52345".to_string(),
+      remote_host: "17.57.146.20".to_string(),
+      remote_port: Some(5223),
+      bytes_in: 4566,
+      bytes_out: 2343,
+    });
+
+    let chrome = &processes[1].connections;
+    assert_eq!((chrome[0].remote_host.as_str(), chrome[0].remote_port), ("2606:4700::6810:85e5", Some(443)));
+    assert_eq!(chrome[0].local, "2001:db8::5.52346");
+    assert_eq!((chrome[1].remote_host.as_str(), chrome[1].remote_port), ("fe80::aede:48ff:fe00:1122%en0", Some(8080)));
+    // Unconnected sockets have no remote port
+    assert_eq!((chrome[2].remote_host.as_str(), chrome[2].remote_port), ("*", None));
+    assert_eq!((processes[2].connections[0].remote_host.as_str(), processes[2].connections[0].remote_port), ("*", None));
+    assert!(processes[3].connections.is_empty());
+  }
+
+  #[test]
+  fn pids_match_exactly() {
+    let pids = HashSet::from([47]);
+    let matching: Vec<String> =
+        parse_nettop_csv(NETTOP_CSV).into_iter().filter(|process| pids.contains(&process.pid)).map(|process| process.key()).collect();
+    assert_eq!(matching, ["example-mac-app.47"]);
+  }
+
+  #[test]
+  fn text_rows_take_the_last_two_columns_as_counters() {
+    let rows = parse_nettop_rows(NETTOP_ROWS);
+    let keys: Vec<&str> = rows.iter().map(|row| row.process.as_str()).collect();
+    assert_eq!(keys, ["launchd.1", "mDNSResponder.195", "Google Chrome He.812", "com.apple.WebKit.Networking.47727", "kernel_task.0"]);
+    assert_eq!((rows[2].name.as_str(), rows[2].pid, rows[2].bytes_in, rows[2].bytes_out), ("Google Chrome He", 812, 98765432, 1234567));
+    assert_eq!((rows[3].name.as_str(), rows[3].pid), ("com.apple.WebKit.Networking", 47727));
+    assert_eq!((rows[4].bytes_in, rows[4].bytes_out), (1, 2));
+  }
+
+  #[test]
+  fn process_names_need_a_numeric_pid_suffix() {
+    assert_eq!(split_process_name("example-mac-app.47727"), Some(("example-mac-app", 47727)));
+    assert_eq!(split_process_name("Google Chrome He.812"), Some(("Google Chrome He", 812)));
+    assert_eq!(split_process_name("com.apple.Safari"), None);
+    assert_eq!(split_process_name("trailing."), None);
+    assert_eq!(split_process_name("no-pid"), None);
+    assert_eq!(split_process_name("huge.99999999999"), None);
+  }
+
+  #[test]
+  fn host_ports_split_on_the_last_separator() {
+    assert_eq!(split_host_port("93.184.216.34:443"), ("93.184.216.34", Some(443)));
+    assert_eq!(split_host_port("2606:2800::1.443"), ("2606:2800::1", Some(443)));
+    assert_eq!(split_host_port("*:*"), ("*", None));
+    assert_eq!(split_host_port("*.*"), ("*", None));
+    assert_eq!(split_host_port("localhost"), ("localhost", None));
   }
 }
@@ -0,0 +1,450 @@
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::events::{AxSelector, ClickEvent, Correlation, EventSink, WatcherEvent};
+use crate::session;
+
+// Spans are sent in batches of at most this many, or whatever arrived within FLUSH_INTERVAL
+const MAX_BATCH: usize = 256;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+// OTLP span kinds (opentelemetry.proto.trace.v1.Span.SpanKind)
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_CLIENT: u8 = 3;
+
+// Where and as whom spans are exported
+#[derive(Clone, Debug)]
+pub struct OtlpConfig {
+    // Full traces URL, e.g. http://localhost:4318/v1/traces
+    pub endpoint: String,
+    pub service_name: String,
+}
+
+impl OtlpConfig {
+    // Accepts either a collector base URL or the full /v1/traces URL
+    pub fn new(endpoint: &str, service_name: &str) -> Self {
+        let endpoint = endpoint.trim_end_matches('/');
+        let endpoint = if endpoint.ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/traces", endpoint)
+        };
+        OtlpConfig { endpoint, service_name: service_name.to_string() }
+    }
+}
+
+// --- OTLP/JSON data model (only the parts we send) ---
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum AnyValue {
+    #[serde(rename = "stringValue")]
+    String(String),
+    // int64 is a string in the protobuf JSON mapping
+    #[serde(rename = "intValue")]
+    Int(String),
+    #[serde(rename = "boolValue")]
+    Bool(bool),
+    #[serde(rename = "doubleValue")]
+    Double(f64),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: AnyValue,
+}
+
+impl KeyValue {
+    fn string(key: &str, value: impl Into<String>) -> Self {
+        KeyValue { key: key.to_string(), value: AnyValue::String(value.into()) }
+    }
+
+    fn int(key: &str, value: i64) -> Self {
+        KeyValue { key: key.to_string(), value: AnyValue::Int(value.to_string()) }
+    }
+
+    fn bool(key: &str, value: bool) -> Self {
+        KeyValue { key: key.to_string(), value: AnyValue::Bool(value) }
+    }
+
+    fn double(key: &str, value: f64) -> Self {
+        KeyValue { key: key.to_string(), value: AnyValue::Double(value) }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanEvent {
+    pub time_unix_nano: String,
+    pub name: String,
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    pub kind: u8,
+    pub start_time_unix_nano: String,
+    pub end_time_unix_nano: String,
+    pub attributes: Vec<KeyValue>,
+    pub events: Vec<SpanEvent>,
+}
+
+// Body of a POST to /v1/traces
+pub fn export_request(service_name: &str, spans: Vec<Span>) -> serde_json::Value {
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    KeyValue::string("service.name", service_name),
+                    KeyValue::string("service.version", env!("CARGO_PKG_VERSION")),
+                    KeyValue::string("session.id", session::session_id()),
+                ]
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }]
+        }]
+    })
+}
+
+// --- Building spans from watcher events ---
+
+// Random hex ID of `bytes` bytes. RandomState is seeded per instance, which
+// together with a counter is plenty for trace and span IDs.
+fn random_id(bytes: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut id = String::with_capacity(bytes * 2);
+    while id.len() < bytes * 2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id.truncate(bytes * 2);
+    id
+}
+
+fn unix_nanos(time: DateTime<Local>) -> String {
+    time.timestamp_nanos_opt().unwrap_or_default().to_string()
+}
+
+// e.g. "Button Clicked ButtonA", matching the log line for the same click
+pub fn click_span_name(click: &ClickEvent) -> String {
+    action_name(&click.selector)
+}
+
+// The span name for a clicked element; exported HAR pages use it too
+pub fn action_name(selector: &AxSelector) -> String {
+    let target = selector
+        .identifier
+        .clone()
+        .or_else(|| selector.label.clone())
+        .unwrap_or_else(|| selector.describe());
+    match selector.role.as_deref() {
+        Some("AXButton") => format!("Button Clicked {}", target),
+        _ => format!("Element Clicked {}", target),
+    }
+}
+
+fn click_attributes(click: &ClickEvent) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::string("app.name", &click.app_name),
+        KeyValue::int("process.pid", click.pid as i64),
+        KeyValue::double("ui.position.x", click.position.x),
+        KeyValue::double("ui.position.y", click.position.y),
+        KeyValue::bool("ui.on_edge", click.geometry.on_edge),
+    ];
+    let optional = [
+        ("ui.identifier", &click.selector.identifier),
+        ("ui.role", &click.selector.role),
+        ("ui.label", &click.selector.label),
+        ("ui.label_source", &click.label_source),
+        ("ui.window.title", &click.geometry.window_title),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            attributes.push(KeyValue::string(key, value));
+        }
+    }
+    attributes
+}
+
+// A root span for the click. With a correlation it covers the
+// whole window and gets one child span per connection that moved bytes, plus
+// an event for every nettop delta seen along the way.
+pub fn click_spans(click: &ClickEvent, correlation: Option<&Correlation>) -> Vec<Span> {
+    let trace_id = random_id(16);
+    let root_id = random_id(8);
+    let start = click.timestamp;
+    let end = correlation.map_or(start, |c| start + ChronoDuration::milliseconds(c.window_ms as i64));
+
+    let mut root = Span {
+        trace_id: trace_id.clone(),
+        span_id: root_id.clone(),
+        parent_span_id: None,
+        name: click_span_name(click),
+        kind: SPAN_KIND_INTERNAL,
+        start_time_unix_nano: unix_nanos(start),
+        end_time_unix_nano: unix_nanos(end),
+        attributes: click_attributes(click),
+        events: Vec::new(),
+    };
+    let Some(correlation) = correlation else { return vec![root] };
+
+    let traffic = &correlation.traffic;
+    root.attributes.extend([
+        KeyValue::int("net.bytes_in", traffic.bytes_in as i64),
+        KeyValue::int("net.bytes_out", traffic.bytes_out as i64),
+        KeyValue::int("net.new_connections", traffic.new_connections().count() as i64),
+        KeyValue::int("watcher.window_ms", correlation.window_ms as i64),
+    ]);
+    root.events = traffic
+        .timeline
+        .iter()
+        .map(|point| SpanEvent {
+            time_unix_nano: unix_nanos(start + ChronoDuration::milliseconds(point.offset_ms as i64)),
+            name: "nettop delta".to_string(),
+            attributes: vec![
+                KeyValue::int("net.bytes_in", point.delta_in as i64),
+                KeyValue::int("net.bytes_out", point.delta_out as i64),
+            ],
+        })
+        .collect();
+
+    let mut spans = vec![root];
+    for connection in &traffic.connections {
+        let mut attributes = vec![
+            KeyValue::string("network.transport", &connection.protocol),
+            KeyValue::string("server.address", connection.display_host()),
+            KeyValue::string("network.peer.address", &connection.remote_host),
+            KeyValue::int("net.bytes_in", connection.bytes_in as i64),
+            KeyValue::int("net.bytes_out", connection.bytes_out as i64),
+            KeyValue::bool("net.connection.new", connection.is_new),
+        ];
+        if let Some(port) = connection.remote_port {
+            attributes.push(KeyValue::int("server.port", port as i64));
+        }
+        let target = match connection.remote_port {
+            Some(port) => format!("{}:{}", connection.display_host(), port),
+            None => connection.display_host().to_string(),
+        };
+        spans.push(Span {
+            trace_id: trace_id.clone(),
+            span_id: random_id(8),
+            parent_span_id: Some(root_id.clone()),
+            name: format!("{} {}", connection.protocol, target),
+            kind: SPAN_KIND_CLIENT,
+            start_time_unix_nano: unix_nanos(start),
+            end_time_unix_nano: unix_nanos(end),
+            attributes,
+            events: Vec::new(),
+        });
+    }
+    spans
+}
+
+// --- Export ---
+
+// POST one batch to the collector
+pub fn send_batch(config: &OtlpConfig, spans: Vec<Span>) -> Result<(), String> {
+    let body = export_request(&config.service_name, spans);
+    ureq::post(&config.endpoint)
+        .set("Content-Type", "application/json")
+        .timeout(Duration::from_secs(10))
+        .send_string(&body.to_string())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// Batches spans on a background thread so a slow collector never blocks the event tap
+fn run_exporter(config: OtlpConfig, receiver: Receiver<Vec<Span>>) {
+    let mut batch: Vec<Span> = Vec::new();
+    loop {
+        let disconnected = match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(spans) => {
+                batch.extend(spans);
+                if batch.len() < MAX_BATCH {
+                    continue;
+                }
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if !batch.is_empty() {
+            let count = batch.len();
+            match send_batch(&config, std::mem::take(&mut batch)) {
+                Ok(()) => log::debug!("Exported {} span(s) to {}", count, config.endpoint),
+                Err(e) => log::warn!("Failed to export {} span(s) to {}: {}", count, config.endpoint, e),
+            }
+        }
+        if disconnected {
+            return;
+        }
+    }
+}
+
+// Turns clicks into spans and exports them over OTLP/HTTP. Key presses are
+// never exported: what was typed stays on this machine. When correlation is
+// on, a click is exported once its Correlation arrives so the span can carry
+// the traffic that followed it.
+pub struct OtlpSink {
+    // Taken on finish so the exporter sees the channel close
+    sender: Option<Sender<Vec<Span>>>,
+    exporter: Option<JoinHandle<()>>,
+    correlated: bool,
+}
+
+impl OtlpSink {
+    pub fn new(config: OtlpConfig, correlated: bool) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let exporter = thread::spawn(move || run_exporter(config, receiver));
+        OtlpSink { sender: Some(sender), exporter: Some(exporter), correlated }
+    }
+}
+
+impl EventSink for OtlpSink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        let spans = match event {
+            WatcherEvent::Click(click) if !self.correlated => click_spans(click, None),
+            WatcherEvent::Correlation(correlation) => click_spans(&correlation.click, Some(correlation)),
+            _ => return,
+        };
+        // The exporter only goes away if its thread panicked or we are finishing
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(spans);
+        }
+    }
+
+    // Send whatever is still batched before the process exits
+    fn finish(&mut self) {
+        drop(self.sender.take());
+        if let Some(exporter) = self.exporter.take() {
+            let _ = exporter.join();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::correlation::{ConnectionDelta, TrafficPoint, WindowTraffic};
+    use crate::events::tests::{click, correlation, key};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // An in-process HTTP server answering every request with `status`.
+    // Yields (request line, body) of each request.
+    pub(crate) fn http_receiver(status: u16) -> (String, Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                let mut content_length = 0;
+                let mut line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let response = format!("HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+                if sender.send((request_line.trim().to_string(), String::from_utf8(body).unwrap())).is_err() {
+                    return;
+                }
+            }
+        });
+        (address, receiver)
+    }
+
+    fn traffic() -> WindowTraffic {
+        WindowTraffic {
+            pid: 42,
+            bytes_in: 800,
+            bytes_out: 200,
+            connections: vec![ConnectionDelta {
+                protocol: "tcp4".to_string(),
+                remote_host: "93.184.216.34".to_string(),
+                remote_port: Some(443),
+                hostname: Some("example.com".to_string()),
+                bytes_in: 800,
+                bytes_out: 200,
+                is_new: true,
+            }],
+            timeline: vec![TrafficPoint { offset_ms: 500, delta_in: 800, delta_out: 200 }],
+            flows: Vec::new(),
+        }
+    }
+
+    fn attribute<'a>(span: &'a Span, key: &str) -> Option<&'a AnyValue> {
+        span.attributes.iter().find(|attribute| attribute.key == key).map(|attribute| &attribute.value)
+    }
+
+    #[test]
+    fn endpoint_gets_the_traces_path_once() {
+        assert_eq!(OtlpConfig::new("http://localhost:4318/", "w").endpoint, "http://localhost:4318/v1/traces");
+        assert_eq!(OtlpConfig::new("http://localhost:4318/v1/traces", "w").endpoint, "http://localhost:4318/v1/traces");
+    }
+
+    #[test]
+    fn correlated_clicks_get_a_child_span_per_connection() {
+        let WatcherEvent::Correlation(correlation) = correlation(click(0, 42, "Chat", "Send"), 3000, traffic()) else { unreachable!() };
+        let spans = click_spans(&correlation.click, Some(&correlation));
+
+        assert_eq!(spans.len(), 2);
+        let (root, child) = (&spans[0], &spans[1]);
+        assert_eq!(root.name, "Button Clicked Send");
+        assert_eq!(root.events.len(), 1);
+        let duration = root.end_time_unix_nano.parse::<i64>().unwrap() - root.start_time_unix_nano.parse::<i64>().unwrap();
+        assert_eq!(duration, 3_000_000_000);
+        assert_eq!(attribute(root, "net.bytes_in"), Some(&AnyValue::Int("800".to_string())));
+
+        assert_eq!(child.parent_span_id.as_ref(), Some(&root.span_id));
+        assert_eq!(child.trace_id, root.trace_id);
+        assert_eq!(child.kind, SPAN_KIND_CLIENT);
+        assert_eq!(attribute(child, "server.address"), Some(&AnyValue::String("example.com".to_string())));
+        assert_eq!(attribute(child, "server.port"), Some(&AnyValue::Int("443".to_string())));
+    }
+
+    #[test]
+    fn sink_exports_clicks_but_never_key_presses() {
+        let (address, requests) = http_receiver(200);
+        let mut sink = OtlpSink::new(OtlpConfig::new(&address, "watcher-test"), false);
+        sink.handle(&key(0, 42, "Chat", 36));
+        sink.handle(&WatcherEvent::Click(Box::new(click(10, 42, "Chat", "Send"))));
+        sink.handle(&key(20, 42, "Chat", 0));
+        sink.finish();
+
+        let (request_line, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request_line, "POST /v1/traces HTTP/1.1");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let spans = body["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0]["name"], "Button Clicked Send");
+        assert!(!body.to_string().contains("keycode"));
+        assert_eq!(body["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"], "watcher-test");
+    }
+}
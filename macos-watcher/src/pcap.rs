@@ -0,0 +1,400 @@
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+// --- Link types (https://www.tcpdump.org/linktypes.html) ---
+
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LOOP: u32 = 108;
+// DLT_RAW as written by some BSDs, macOS included
+const LINKTYPE_RAW_BSD: u32 = 12;
+
+const MAGIC_MICROS: u32 = 0xA1B2C3D4;
+const MAGIC_NANOS: u32 = 0xA1B23C4D;
+
+// Records larger than this are treated as a corrupt file
+const MAX_RECORD_LEN: u32 = 256 * 1024;
+
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+// --- TCP flags ---
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_ACK: u8 = 0x10;
+
+// One captured frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    // Capture time since the Unix epoch
+    pub timestamp: Duration,
+    pub data: Vec<u8>,
+}
+
+// Reads the classic libpcap file format, as written by `tcpdump -w`.
+// Works on files as well as on a live `tcpdump -U -w -` pipe.
+pub struct PcapReader<R: Read> {
+    input: R,
+    linktype: u32,
+    swapped: bool,
+    nanos: bool,
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        input.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap file (pcapng is not supported)")),
+        };
+        let mut reader = PcapReader { input, linktype: 0, swapped, nanos };
+        reader.linktype = reader.u32_at(&header, 20);
+        Ok(reader)
+    }
+
+    pub fn linktype(&self) -> u32 {
+        self.linktype
+    }
+
+    fn u32_at(&self, bytes: &[u8], offset: usize) -> u32 {
+        let value = u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        if self.swapped { value.swap_bytes() } else { value }
+    }
+
+    // The next record, or None at a clean end of file
+    pub fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut header = [0u8; 16];
+        match self.input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let seconds = self.u32_at(&header, 0) as u64;
+        let fraction = self.u32_at(&header, 4) as u64;
+        let captured_len = self.u32_at(&header, 8);
+        if captured_len > MAX_RECORD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("pcap record of {} bytes", captured_len)));
+        }
+
+        let mut data = vec![0u8; captured_len as usize];
+        self.input.read_exact(&mut data)?;
+        let timestamp = if self.nanos {
+            Duration::from_secs(seconds) + Duration::from_nanos(fraction)
+        } else {
+            Duration::from_secs(seconds) + Duration::from_micros(fraction)
+        };
+        Ok(Some(Packet { timestamp, data }))
+    }
+}
+
+// A UDP datagram pulled out of a captured frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct UdpDatagram<'a> {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub payload: &'a [u8],
+}
+
+// The IP packet inside a frame of the given link type
+pub fn ip_payload(linktype: u32, frame: &[u8]) -> Option<&[u8]> {
+    match linktype {
+        // 4 byte address family in host (NULL) or network (LOOP) byte order
+        LINKTYPE_NULL | LINKTYPE_LOOP => frame.get(4..),
+        LINKTYPE_RAW | LINKTYPE_RAW_BSD => Some(frame),
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+            // Skip 802.1Q / QinQ tags
+            while ethertype == 0x8100 || ethertype == 0x88A8 {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+            }
+            match ethertype {
+                0x0800 | 0x86DD => frame.get(offset + 2..),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Source, destination, protocol and payload of an IPv4 or IPv6 packet.
+// Fragments other than the first and IPv6 extension headers are skipped.
+pub fn split_ip(packet: &[u8]) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    match packet.first()? >> 4 {
+        4 => {
+            let header_len = ((packet[0] & 0x0F) as usize) * 4;
+            let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+            let fragment_offset = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x1FFF;
+            if header_len < 20 || fragment_offset != 0 {
+                return None;
+            }
+            let header = packet.get(..header_len)?;
+            let source = Ipv4Addr::new(header[12], header[13], header[14], header[15]);
+            let destination = Ipv4Addr::new(header[16], header[17], header[18], header[19]);
+            // Ethernet pads short frames; trust the IP length when it is smaller
+            let end = total_len.clamp(header_len, packet.len());
+            Some((source.into(), destination.into(), header[9], &packet[header_len..end]))
+        }
+        6 => {
+            let header = packet.get(..40)?;
+            let payload_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let mut source = [0u8; 16];
+            let mut destination = [0u8; 16];
+            source.copy_from_slice(&header[8..24]);
+            destination.copy_from_slice(&header[24..40]);
+            let end = (40 + payload_len).min(packet.len());
+            Some((Ipv6Addr::from(source).into(), Ipv6Addr::from(destination).into(), header[6], &packet[40..end]))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+impl Transport {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::Tcp => "tcp",
+            Transport::Udp => "udp",
+        }
+    }
+}
+
+// A TCP segment or UDP datagram pulled out of a captured frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment<'a> {
+    pub transport: Transport,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    // TCP only; zero for UDP
+    pub sequence: u32,
+    pub flags: u8,
+    pub payload: &'a [u8],
+}
+
+pub fn transport_segment(linktype: u32, frame: &[u8]) -> Option<Segment<'_>> {
+    let (source, destination, protocol, segment) = split_ip(ip_payload(linktype, frame)?)?;
+    match protocol {
+        IP_PROTOCOL_TCP => {
+            let header_len = ((*segment.get(12)? >> 4) as usize) * 4;
+            if header_len < 20 || header_len > segment.len() {
+                return None;
+            }
+            Some(Segment {
+                transport: Transport::Tcp,
+                source: SocketAddr::new(source, u16::from_be_bytes([segment[0], segment[1]])),
+                destination: SocketAddr::new(destination, u16::from_be_bytes([segment[2], segment[3]])),
+                sequence: u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]),
+                flags: segment[13],
+                payload: &segment[header_len..],
+            })
+        }
+        IP_PROTOCOL_UDP => {
+            let datagram = udp_from_segment(source, destination, segment)?;
+            Some(Segment {
+                transport: Transport::Udp,
+                source: datagram.source,
+                destination: datagram.destination,
+                sequence: 0,
+                flags: 0,
+                payload: datagram.payload,
+            })
+        }
+        _ => None,
+    }
+}
+
+pub fn udp_datagram(linktype: u32, frame: &[u8]) -> Option<UdpDatagram<'_>> {
+    let (source, destination, protocol, segment) = split_ip(ip_payload(linktype, frame)?)?;
+    if protocol != IP_PROTOCOL_UDP {
+        return None;
+    }
+    udp_from_segment(source, destination, segment)
+}
+
+fn udp_from_segment(source: IpAddr, destination: IpAddr, segment: &[u8]) -> Option<UdpDatagram<'_>> {
+    let header = segment.get(..8)?;
+    let source_port = u16::from_be_bytes([header[0], header[1]]);
+    let destination_port = u16::from_be_bytes([header[2], header[3]]);
+    let len = (u16::from_be_bytes([header[4], header[5]]) as usize).clamp(8, segment.len());
+    Some(UdpDatagram {
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        payload: &segment[8..len],
+    })
+}
+
+// Frame and file builders, shared with the resolver, flow and dissector tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn ipv4(source: SocketAddr, destination: SocketAddr, protocol: u8, segment: &[u8]) -> Vec<u8> {
+        let (IpAddr::V4(source), IpAddr::V4(destination)) = (source.ip(), destination.ip()) else {
+            panic!("ipv4 needs IPv4 addresses");
+        };
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, protocol, 0, 0];
+        packet[2..4].copy_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&source.octets());
+        packet.extend_from_slice(&destination.octets());
+        packet.extend_from_slice(segment);
+        packet
+    }
+
+    fn ethernet(ip_packet: Vec<u8>) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend(ip_packet);
+        frame
+    }
+
+    // An Ethernet frame carrying one UDP datagram over IPv4
+    pub(crate) fn udp_frame(source: &str, destination: &str, payload: &[u8]) -> Vec<u8> {
+        let (source, destination): (SocketAddr, SocketAddr) = (source.parse().unwrap(), destination.parse().unwrap());
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&source.port().to_be_bytes());
+        segment.extend_from_slice(&destination.port().to_be_bytes());
+        segment.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(payload);
+        ethernet(ipv4(source, destination, IP_PROTOCOL_UDP, &segment))
+    }
+
+    // An Ethernet frame carrying one TCP segment over IPv4
+    pub(crate) fn tcp_frame(source: &str, destination: &str, sequence: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let (source, destination): (SocketAddr, SocketAddr) = (source.parse().unwrap(), destination.parse().unwrap());
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&source.port().to_be_bytes());
+        segment.extend_from_slice(&destination.port().to_be_bytes());
+        segment.extend_from_slice(&sequence.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xFF, 0xFF, 0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        ethernet(ipv4(source, destination, IP_PROTOCOL_TCP, &segment))
+    }
+
+    // A little-endian, microsecond pcap file of Ethernet frames
+    pub(crate) fn pcap_file(packets: &[(Duration, Vec<u8>)]) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(&MAGIC_MICROS.to_le_bytes());
+        file.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0, 0]);
+        file.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for (timestamp, data) in packets {
+            file.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+            file.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+            file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    #[test]
+    fn reads_packets_until_a_clean_end() {
+        let frame = udp_frame("10.0.0.5:5353", "224.0.0.251:5353", b"hi");
+        let file = pcap_file(&[(Duration::from_micros(1_500_000), frame.clone())]);
+        let mut reader = PcapReader::new(&file[..]).unwrap();
+        assert_eq!(reader.linktype(), LINKTYPE_ETHERNET);
+        assert_eq!(reader.next_packet().unwrap(), Some(Packet { timestamp: Duration::from_millis(1500), data: frame }));
+        assert_eq!(reader.next_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn reads_big_endian_nanosecond_files() {
+        let mut file = MAGIC_NANOS.to_be_bytes().to_vec();
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&LINKTYPE_RAW.to_be_bytes());
+        for value in [3u32, 250, 1, 1] {
+            file.extend_from_slice(&value.to_be_bytes());
+        }
+        file.push(0x45);
+        let mut reader = PcapReader::new(&file[..]).unwrap();
+        assert_eq!(reader.linktype(), LINKTYPE_RAW);
+        assert_eq!(reader.next_packet().unwrap().unwrap().timestamp, Duration::new(3, 250));
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        assert!(PcapReader::new(&b"\x0a\x0d\x0d\x0a pcapng"[..]).is_err());
+        assert!(PcapReader::new(&[0xD4, 0xC3, 0xB2][..]).is_err());
+
+        let file = pcap_file(&[(Duration::ZERO, udp_frame("10.0.0.5:1", "10.0.0.6:2", b"payload"))]);
+        // A record cut off in the middle
+        let mut reader = PcapReader::new(&file[..file.len() - 3]).unwrap();
+        assert!(reader.next_packet().is_err());
+
+        let mut oversized = pcap_file(&[]);
+        oversized.extend_from_slice(&[0; 8]);
+        oversized.extend_from_slice(&(MAX_RECORD_LEN + 1).to_le_bytes());
+        oversized.extend_from_slice(&[0; 4]);
+        assert!(PcapReader::new(&oversized[..]).unwrap().next_packet().is_err());
+    }
+
+    #[test]
+    fn splits_udp_and_tcp_frames() {
+        let frame = udp_frame("10.0.0.5:53000", "1.1.1.1:53", b"query");
+        let datagram = udp_datagram(LINKTYPE_ETHERNET, &frame).unwrap();
+        assert_eq!((datagram.source, datagram.destination), ("10.0.0.5:53000".parse().unwrap(), "1.1.1.1:53".parse().unwrap()));
+        assert_eq!(datagram.payload, b"query");
+
+        let frame = tcp_frame("10.0.0.5:52345", "93.184.216.34:443", 1000, TCP_SYN | TCP_ACK, b"");
+        let segment = transport_segment(LINKTYPE_ETHERNET, &frame).unwrap();
+        assert_eq!((segment.transport, segment.sequence, segment.flags), (Transport::Tcp, 1000, TCP_SYN | TCP_ACK));
+        assert!(udp_datagram(LINKTYPE_ETHERNET, &frame).is_none());
+
+        // Loopback captures prefix the IP packet with a 4 byte address family
+        let mut loopback = vec![2, 0, 0, 0];
+        loopback.extend_from_slice(&frame[14..]);
+        assert_eq!(transport_segment(LINKTYPE_NULL, &loopback).unwrap().destination.port(), 443);
+    }
+
+    #[test]
+    fn vlan_tags_are_skipped() {
+        let frame = udp_frame("10.0.0.5:1", "10.0.0.6:2", b"x");
+        let mut tagged = frame[..12].to_vec();
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x05]);
+        tagged.extend_from_slice(&frame[12..]);
+        assert_eq!(udp_datagram(LINKTYPE_ETHERNET, &tagged).unwrap().payload, b"x");
+    }
+
+    #[test]
+    fn truncated_frames_are_skipped_not_panics() {
+        let frames = [
+            udp_frame("10.0.0.5:53000", "1.1.1.1:53", b"query"),
+            tcp_frame("10.0.0.5:52345", "93.184.216.34:443", 1, TCP_ACK, b"GET / HTTP/1.1\r\n"),
+        ];
+        for frame in &frames {
+            for len in 0..frame.len() {
+                let _ = transport_segment(LINKTYPE_ETHERNET, &frame[..len]);
+                let _ = udp_datagram(LINKTYPE_ETHERNET, &frame[..len]);
+            }
+            // Ethernet header plus a bare IPv4 header
+            assert!(transport_segment(LINKTYPE_ETHERNET, &frame[..34]).is_none());
+        }
+
+        // IPv4 header length below 20, a later fragment, and an unknown IP version
+        let mut short_header = frames[0][14..].to_vec();
+        short_header[0] = 0x44;
+        assert!(split_ip(&short_header).is_none());
+        let mut fragment = frames[0][14..].to_vec();
+        fragment[7] = 1;
+        assert!(split_ip(&fragment).is_none());
+        assert!(split_ip(&[0x75; 40]).is_none());
+        assert!(split_ip(&[0x60; 39]).is_none());
+        assert!(ip_payload(LINKTYPE_ETHERNET, &[0; 14]).is_none());
+        assert!(ip_payload(999, &frames[0]).is_none());
+    }
+}
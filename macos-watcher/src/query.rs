@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use crate::store::EventStore;
+
+// Canned reports over the SQLite event store
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Report {
+    Sessions,
+    TopTalkers,
+    SilentClicks,
+    BytesPerButton,
+    Alerts,
+}
+
+impl Report {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "sessions" => Ok(Report::Sessions),
+            "top-talkers" => Ok(Report::TopTalkers),
+            "silent-clicks" => Ok(Report::SilentClicks),
+            "bytes-per-button" => Ok(Report::BytesPerButton),
+            "alerts" => Ok(Report::Alerts),
+            _ => Err(format!(
+                "Unknown report '{}' (expected sessions, top-talkers, silent-clicks, bytes-per-button or alerts)",
+                name
+            )),
+        }
+    }
+
+    fn headers(&self) -> &'static [&'static str] {
+        match self {
+            Report::Sessions => &["Session", "Started", "Ended", "UI events"],
+            Report::TopTalkers => &["App", "Bytes out", "Bytes in", "Total"],
+            Report::SilentClicks => &["Time", "App", "ID", "Label", "Role"],
+            Report::BytesPerButton => &["Button", "Clicks", "Bytes out", "Bytes in", "New connections"],
+            Report::Alerts => &["Time", "Severity", "Rule", "App", "Message"],
+        }
+    }
+}
+
+// Arguments for `macos-watcher query`
+pub struct QueryArgs {
+    pub report: Report,
+    pub db_path: PathBuf,
+    // Limit the report to one session (see the sessions report for IDs)
+    pub session_id: Option<String>,
+    pub limit: usize,
+}
+
+// Entry point for the query subcommand. Returns the process exit code.
+pub fn run_cli(args: QueryArgs) -> i32 {
+    if !args.db_path.exists() {
+        eprintln!("Error: No event store at {}. Run the watcher with --db first.", args.db_path.display());
+        return 1;
+    }
+    let store = match EventStore::open(&args.db_path) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error: Failed to open {}: {}", args.db_path.display(), e);
+            return 1;
+        }
+    };
+
+    let session_id = args.session_id.as_deref();
+    let rows = match args.report {
+        Report::Sessions => store.sessions(session_id, args.limit),
+        Report::TopTalkers => store.top_talkers(session_id, args.limit),
+        Report::SilentClicks => store.silent_clicks(session_id, args.limit),
+        Report::BytesPerButton => store.bytes_per_button(session_id, args.limit),
+        Report::Alerts => store.alerts(session_id, args.limit),
+    };
+
+    match rows {
+        Ok(rows) => {
+            print!("{}", format_table(args.report.headers(), &rows));
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: Query failed: {}", e);
+            1
+        }
+    }
+}
+
+// Plain text table with columns padded to their widest cell
+pub fn format_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+    for row in rows {
+        for (index, cell) in row.iter().enumerate() {
+            if let Some(width) = widths.get_mut(index) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        format!("{}\n", padded.join("  ").trim_end())
+    };
+
+    let mut table = format_row(headers.to_vec());
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    table.push_str(&format_row(separator.iter().map(String::as_str).collect()));
+    for row in rows {
+        table.push_str(&format_row(row.iter().map(String::as_str).collect()));
+    }
+    if rows.is_empty() {
+        table.push_str("(no rows)\n");
+    }
+    table
+}
@@ -0,0 +1,607 @@
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+#[cfg(target_os = "macos")]
+use std::ptr;
+#[cfg(target_os = "macos")]
+use std::thread;
+use std::time::Duration;
+
+#[cfg(target_os = "macos")]
+use core_foundation::base::CFRelease;
+#[cfg(target_os = "macos")]
+use std::os::raw::c_void;
+
+use crate::accessibility::*;
+use crate::capture::read_capture;
+#[cfg(target_os = "macos")]
+use crate::event_tap::*;
+use crate::events::{AxSelector, WatcherEvent};
+use crate::geometry::{CGPoint, CGRect};
+use crate::label::{LabelChain, LabelElement};
+#[cfg(target_os = "macos")]
+use crate::utils::find_pid_by_app_name;
+
+// Stop searching huge trees (web views, long tables) after this many nodes
+const MAX_SEARCH_NODES: usize = 5000;
+
+// A node of an application's UI tree, as far as replay is concerned
+pub trait ReplayElement: LabelElement {
+    fn frame(&self) -> Option<CGRect>;
+}
+
+#[cfg(target_os = "macos")]
+impl ReplayElement for AxElement {
+    fn frame(&self) -> Option<CGRect> {
+        AxElement::frame(self)
+    }
+}
+
+// Everything replay needs from the outside world. MacDriver posts real
+// CGEvents; a fake driver with an in-memory tree exercises the same logic.
+pub trait UiDriver {
+    type Element: ReplayElement;
+
+    // Root of the UI tree for the recorded app, if it is running
+    fn app_root(&mut self, app_name: &str) -> Option<Self::Element>;
+    fn click(&mut self, point: CGPoint);
+    // Deliver the key to the recorded app, whichever app is frontmost.
+    // Returns false if the app is not running.
+    fn key(&mut self, app_name: &str, keycode: i64, flags: u64) -> bool;
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct ReplayOptions {
+    // 2.0 replays twice as fast as recorded
+    pub speed: f64,
+    // How long to wait for a target element to show up
+    pub element_timeout: Duration,
+    pub poll_interval: Duration,
+    // Must match the chain used while recording, or labels will not line up
+    pub label_chain: LabelChain,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            speed: 1.0,
+            element_timeout: Duration::from_secs(5),
+            poll_interval: Duration::from_millis(100),
+            label_chain: LabelChain::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayAction {
+    Click {
+        app_name: String,
+        selector: AxSelector,
+        // Where inside the element the original click landed
+        relative_point: Option<CGPoint>,
+    },
+    Key {
+        app_name: String,
+        keycode: i64,
+        flags: u64,
+    },
+}
+
+impl ReplayAction {
+    pub fn describe(&self) -> String {
+        match self {
+            ReplayAction::Click { app_name, selector, .. } => format!("Click {} in '{}'", selector.describe(), app_name),
+            ReplayAction::Key { app_name, keycode, flags } => {
+                format!("Key {} (flags={:#x}) in '{}'", keycode, flags, app_name)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayStep {
+    // Time to wait after the previous step
+    pub delay: Duration,
+    pub action: ReplayAction,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub performed: usize,
+    // Descriptions of steps whose target element never appeared, or whose app was not running
+    pub missing: Vec<String>,
+}
+
+// Turn captured events into steps, keeping the recorded gaps divided by `speed`
+pub fn schedule(events: &[WatcherEvent], speed: f64) -> Vec<ReplayStep> {
+    let speed = if speed > 0.0 { speed } else { 1.0 };
+    let mut steps = Vec::new();
+    let mut previous: Option<DateTime<Local>> = None;
+
+    for event in events {
+        let gap = previous
+            .map(|previous| (event.timestamp() - previous).to_std().unwrap_or(Duration::ZERO))
+            .unwrap_or(Duration::ZERO);
+        previous = Some(event.timestamp());
+
+        let action = match event {
+            WatcherEvent::Click(click) => ReplayAction::Click {
+                app_name: click.app_name.clone(),
+                selector: click.selector.clone(),
+                relative_point: click.geometry.relative_click_point,
+            },
+            WatcherEvent::Key(key) => ReplayAction::Key {
+                app_name: key.app_name.clone(),
+                keycode: key.keycode,
+                flags: key.flags,
+            },
+            _ => continue,
+        };
+        steps.push(ReplayStep { delay: gap.div_f64(speed), action });
+    }
+    steps
+}
+
+pub fn run<D: UiDriver>(driver: &mut D, steps: &[ReplayStep], options: &ReplayOptions) -> ReplayReport {
+    let mut report = ReplayReport::default();
+
+    for step in steps {
+        driver.sleep(step.delay);
+        match &step.action {
+            ReplayAction::Click { app_name, selector, relative_point } => {
+                match wait_for_element(driver, app_name, selector, options) {
+                    Some(frame) => {
+                        let point = click_point(frame, *relative_point);
+                        log::info!("Replay: {} at ({:.1}, {:.1})", step.action.describe(), point.x, point.y);
+                        driver.click(point);
+                        report.performed += 1;
+                    }
+                    None => {
+                        log::warn!("Replay: element not found for {}", step.action.describe());
+                        report.missing.push(step.action.describe());
+                    }
+                }
+            }
+            ReplayAction::Key { app_name, keycode, flags } => {
+                if driver.key(app_name, *keycode, *flags) {
+                    log::info!("Replay: {}", step.action.describe());
+                    report.performed += 1;
+                } else {
+                    log::warn!("Replay: app not running for {}", step.action.describe());
+                    report.missing.push(step.action.describe());
+                }
+            }
+        }
+    }
+    report
+}
+
+// Poll the app's UI tree until the selector matches or the timeout runs out.
+// Time is counted in driver sleeps, so fake drivers do not have to really wait.
+fn wait_for_element<D: UiDriver>(
+    driver: &mut D,
+    app_name: &str,
+    selector: &AxSelector,
+    options: &ReplayOptions,
+) -> Option<CGRect> {
+    if !selector.is_locatable() {
+        return None;
+    }
+    let mut waited = Duration::ZERO;
+    loop {
+        let frame = driver
+            .app_root(app_name)
+            .and_then(|root| find_element(root, selector, &options.label_chain))
+            .and_then(|element| element.frame());
+        if frame.is_some() {
+            return frame;
+        }
+        if waited >= options.element_timeout {
+            return None;
+        }
+        driver.sleep(options.poll_interval);
+        waited += options.poll_interval;
+    }
+}
+
+// Breadth first search for the first element matching the selector.
+// The AX identifier wins when it was recorded; otherwise role and label must match.
+pub fn find_element<E: ReplayElement>(root: E, selector: &AxSelector, label_chain: &LabelChain) -> Option<E> {
+    let mut queue = VecDeque::from([root]);
+    let mut visited = 0;
+
+    while let Some(node) = queue.pop_front() {
+        if matches_selector(&node, selector, label_chain) {
+            return Some(node);
+        }
+        visited += 1;
+        if visited >= MAX_SEARCH_NODES {
+            log::debug!("Gave up searching for {} after {} elements", selector.describe(), visited);
+            return None;
+        }
+        queue.extend(node.children());
+    }
+    None
+}
+
+fn matches_selector<E: ReplayElement>(node: &E, selector: &AxSelector, label_chain: &LabelChain) -> bool {
+    if let Some(identifier) = &selector.identifier {
+        return node.string_attribute(K_AX_IDENTIFIER_ATTRIBUTE).as_ref() == Some(identifier);
+    }
+    // One attribute read rules out most nodes; the label chain (which may
+    // search the node's children) only runs for the rest
+    if let Some(role) = &selector.role {
+        if node.string_attribute(K_AX_ROLE_ATTRIBUTE).as_ref() != Some(role) {
+            return false;
+        }
+    }
+    match &selector.label {
+        Some(label) => label_chain.resolve(node).is_some_and(|resolved| &resolved.text == label),
+        None => true,
+    }
+}
+
+// Keep the original offset inside the element when it still fits, else use the center
+pub fn click_point(frame: CGRect, relative_point: Option<CGPoint>) -> CGPoint {
+    match relative_point {
+        Some(relative)
+            if relative.x >= 0.0
+                && relative.y >= 0.0
+                && relative.x < frame.size.width
+                && relative.y < frame.size.height =>
+        {
+            CGPoint { x: frame.origin.x + relative.x, y: frame.origin.y + relative.y }
+        }
+        _ => CGPoint {
+            x: frame.origin.x + frame.size.width / 2.0,
+            y: frame.origin.y + frame.size.height / 2.0,
+        },
+    }
+}
+
+// Drives real apps by posting CGEvents to the HID event tap
+#[cfg(target_os = "macos")]
+pub struct MacDriver;
+
+#[cfg(target_os = "macos")]
+impl MacDriver {
+    unsafe fn post(event: CGEventRef) {
+        if event.is_null() {
+            log::error!("Failed to create CGEvent for replay");
+            return;
+        }
+        unsafe { CGEventPost(K_CG_HID_EVENT_TAP, event) };
+        unsafe { CFRelease(event as *const c_void) };
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl UiDriver for MacDriver {
+    type Element = AxElement;
+
+    fn app_root(&mut self, app_name: &str) -> Option<AxElement> {
+        let pid = find_pid_by_app_name(app_name)?;
+        unsafe { AxElement::from_create_rule(ax_ui_element_create_application(pid)) }
+    }
+
+    fn click(&mut self, point: CGPoint) {
+        unsafe {
+            let down = CGEventCreateMouseEvent(ptr::null_mut(), K_CG_EVENT_LEFT_MOUSE_DOWN, point, K_CG_MOUSE_BUTTON_LEFT);
+            MacDriver::post(down);
+            let up = CGEventCreateMouseEvent(ptr::null_mut(), K_CG_EVENT_LEFT_MOUSE_UP, point, K_CG_MOUSE_BUTTON_LEFT);
+            MacDriver::post(up);
+        }
+    }
+
+    fn key(&mut self, app_name: &str, keycode: i64, flags: u64) -> bool {
+        let Some(pid) = find_pid_by_app_name(app_name) else { return false };
+        unsafe {
+            for key_down in [true, false] {
+                let event = CGEventCreateKeyboardEvent(ptr::null_mut(), keycode as u16, key_down);
+                if event.is_null() {
+                    log::error!("Failed to create CGEvent for replay");
+                    continue;
+                }
+                CGEventSetFlags(event, flags);
+                // Straight to the app, so keys never land in whatever happens to be frontmost
+                CGEventPostToPid(pid, event);
+                CFRelease(event as *const c_void);
+            }
+        }
+        true
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        if !duration.is_zero() {
+            thread::sleep(duration);
+        }
+    }
+}
+
+// Arguments for `macos-watcher replay`
+pub struct ReplayArgs {
+    pub capture_path: PathBuf,
+    // Captures are appended to; pick one recorded session (default: the last)
+    pub session_id: Option<String>,
+    // Without --drive the schedule is only printed
+    pub drive: bool,
+    pub options: ReplayOptions,
+}
+
+// Entry point for the replay subcommand. Returns the process exit code.
+pub fn run_cli(args: ReplayArgs) -> i32 {
+    let events = match read_capture(&args.capture_path, args.session_id.as_deref()) {
+        Ok(events) => events,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            return 1;
+        }
+    };
+    let steps = schedule(&events, args.options.speed);
+    println!("Loaded {} steps from {}", steps.len(), args.capture_path.display());
+
+    if !args.drive {
+        for step in &steps {
+            println!("+{:>6}ms  {}", step.delay.as_millis(), step.action.describe());
+        }
+        println!("Dry run only. Pass --drive to post these events.");
+        return 0;
+    }
+
+    let report = match drive(&steps, &args.options) {
+        Ok(report) => report,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            return 1;
+        }
+    };
+    println!("Replayed {} of {} steps", report.performed, steps.len());
+    for missing in &report.missing {
+        println!("  Missing target: {}", missing);
+    }
+    if report.missing.is_empty() { 0 } else { 1 }
+}
+
+#[cfg(target_os = "macos")]
+fn drive(steps: &[ReplayStep], options: &ReplayOptions) -> Result<ReplayReport, String> {
+    if !unsafe { ax_is_process_trusted() } {
+        return Err("Accessibility permissions are required to drive other apps.".to_string());
+    }
+    Ok(run(&mut MacDriver, steps, options))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn drive(_steps: &[ReplayStep], _options: &ReplayOptions) -> Result<ReplayReport, String> {
+    Err("Driving apps needs macOS. Drop --drive to print the schedule.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ClickEvent, KeyEvent};
+    use crate::geometry::{CGSize, ClickGeometry};
+    use chrono::TimeZone;
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct FakeNode {
+        attributes: HashMap<String, String>,
+        frame: Option<CGRect>,
+        children: Vec<FakeNode>,
+        // Shared by the whole tree: how often any node was asked for its children
+        children_calls: Rc<Cell<usize>>,
+    }
+
+    impl FakeNode {
+        fn new(role: &str, calls: &Rc<Cell<usize>>) -> Self {
+            let mut node = FakeNode { children_calls: calls.clone(), ..FakeNode::default() };
+            node.attributes.insert(K_AX_ROLE_ATTRIBUTE.to_string(), role.to_string());
+            node
+        }
+
+        fn with(mut self, attribute: &str, value: &str) -> Self {
+            self.attributes.insert(attribute.to_string(), value.to_string());
+            self
+        }
+
+        fn at(mut self, x: f64, y: f64, width: f64, height: f64) -> Self {
+            self.frame = Some(CGRect { origin: CGPoint { x, y }, size: CGSize { width, height } });
+            self
+        }
+
+        fn child(mut self, child: FakeNode) -> Self {
+            self.children.push(child);
+            self
+        }
+    }
+
+    impl LabelElement for FakeNode {
+        fn string_attribute(&self, attribute: &str) -> Option<String> {
+            self.attributes.get(attribute).cloned()
+        }
+
+        fn children(&self) -> Vec<Self> {
+            self.children_calls.set(self.children_calls.get() + 1);
+            self.children.clone()
+        }
+    }
+
+    impl ReplayElement for FakeNode {
+        fn frame(&self) -> Option<CGRect> {
+            self.frame
+        }
+    }
+
+    // One app whose UI tree shows up after `appears_after` polls
+    #[derive(Default)]
+    struct FakeDriver {
+        app_name: String,
+        root: FakeNode,
+        appears_after: usize,
+        polls: usize,
+        clicks: Vec<CGPoint>,
+        keys: Vec<(String, i64, u64)>,
+        slept: Duration,
+    }
+
+    impl UiDriver for FakeDriver {
+        type Element = FakeNode;
+
+        fn app_root(&mut self, app_name: &str) -> Option<FakeNode> {
+            if app_name != self.app_name {
+                return None;
+            }
+            self.polls += 1;
+            (self.polls > self.appears_after).then(|| self.root.clone())
+        }
+
+        fn click(&mut self, point: CGPoint) {
+            self.clicks.push(point);
+        }
+
+        fn key(&mut self, app_name: &str, keycode: i64, flags: u64) -> bool {
+            if app_name != self.app_name {
+                return false;
+            }
+            self.keys.push((app_name.to_string(), keycode, flags));
+            true
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.slept += duration;
+        }
+    }
+
+    fn window(calls: &Rc<Cell<usize>>) -> FakeNode {
+        FakeNode::new("AXWindow", calls).child(
+            FakeNode::new("AXGroup", calls)
+                .child(FakeNode::new(K_AX_BUTTON_ROLE, calls).with(K_AX_TITLE_ATTRIBUTE, "Cancel").at(10.0, 10.0, 80.0, 20.0))
+                .child(
+                    FakeNode::new(K_AX_BUTTON_ROLE, calls)
+                        .at(100.0, 10.0, 80.0, 20.0)
+                        .child(FakeNode::new(K_AX_STATIC_TEXT_ROLE, calls).with(K_AX_VALUE_ATTRIBUTE, "Send")),
+                )
+                .child(FakeNode::new("AXTextField", calls).with(K_AX_IDENTIFIER_ATTRIBUTE, "message").at(10.0, 40.0, 200.0, 20.0)),
+        )
+    }
+
+    fn driver() -> FakeDriver {
+        FakeDriver { app_name: "/Applications/Chat.app/Contents/MacOS/Chat".to_string(), root: window(&Rc::default()), ..FakeDriver::default() }
+    }
+
+    fn click(selector: AxSelector, relative_point: Option<CGPoint>) -> ReplayAction {
+        ReplayAction::Click { app_name: driver().app_name, selector, relative_point }
+    }
+
+    fn labelled(role: &str, label: &str) -> AxSelector {
+        AxSelector { identifier: None, role: Some(role.to_string()), label: Some(label.to_string()) }
+    }
+
+    fn step(action: ReplayAction) -> ReplayStep {
+        ReplayStep { delay: Duration::ZERO, action }
+    }
+
+    #[test]
+    fn schedule_keeps_recorded_gaps_divided_by_speed() {
+        let at = |millis: i64| Local.timestamp_millis_opt(1_700_000_000_000 + millis).unwrap();
+        let events = vec![
+            WatcherEvent::Click(Box::new(ClickEvent {
+                timestamp: at(0),
+                pid: 42,
+                app_name: "Chat".to_string(),
+                selector: labelled(K_AX_BUTTON_ROLE, "Send"),
+                label_source: None,
+                position: CGPoint { x: 110.0, y: 15.0 },
+                geometry: ClickGeometry { relative_click_point: Some(CGPoint { x: 10.0, y: 5.0 }), ..ClickGeometry::default() },
+                ax_lookup_ms: None,
+                traffic_before: Vec::new(),
+            })),
+            WatcherEvent::Checkpoint(crate::events::Checkpoint { timestamp: at(500), label: "ignored".to_string() }),
+            WatcherEvent::Key(KeyEvent { timestamp: at(1000), pid: 42, app_name: "Chat".to_string(), keycode: 36, flags: 0 }),
+        ];
+
+        let steps = schedule(&events, 2.0);
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].delay, Duration::ZERO);
+        assert_eq!(steps[0].action, ReplayAction::Click {
+            app_name: "Chat".to_string(),
+            selector: labelled(K_AX_BUTTON_ROLE, "Send"),
+            relative_point: Some(CGPoint { x: 10.0, y: 5.0 }),
+        });
+        // 500ms until the (skipped) checkpoint plus 500ms until the key, at double speed
+        assert_eq!(steps[1].delay, Duration::from_millis(250));
+        assert_eq!(steps[1].action, ReplayAction::Key { app_name: "Chat".to_string(), keycode: 36, flags: 0 });
+    }
+
+    #[test]
+    fn clicks_elements_by_label_and_identifier() {
+        let mut driver = driver();
+        let steps = [
+            step(click(labelled(K_AX_BUTTON_ROLE, "Send"), Some(CGPoint { x: 5.0, y: 5.0 }))),
+            step(click(AxSelector { identifier: Some("message".to_string()), ..AxSelector::default() }, None)),
+        ];
+
+        let report = run(&mut driver, &steps, &ReplayOptions::default());
+        assert_eq!(report.performed, 2);
+        assert!(report.missing.is_empty());
+        assert_eq!(driver.clicks, vec![CGPoint { x: 105.0, y: 15.0 }, CGPoint { x: 110.0, y: 50.0 }]);
+    }
+
+    #[test]
+    fn waits_for_elements_to_appear() {
+        let mut driver = FakeDriver { appears_after: 3, ..driver() };
+        let report = run(&mut driver, &[step(click(labelled(K_AX_BUTTON_ROLE, "Cancel"), None))], &ReplayOptions::default());
+        assert_eq!(report.performed, 1);
+        assert_eq!(driver.slept, ReplayOptions::default().poll_interval * 3);
+    }
+
+    #[test]
+    fn reports_missing_targets_after_the_timeout() {
+        let mut driver = driver();
+        let options = ReplayOptions { element_timeout: Duration::from_secs(1), ..ReplayOptions::default() };
+        let report = run(&mut driver, &[step(click(labelled(K_AX_BUTTON_ROLE, "Delete"), None))], &options);
+        assert_eq!(report.performed, 0);
+        assert_eq!(report.missing, vec!["Click AXButton 'Delete' in '/Applications/Chat.app/Contents/MacOS/Chat'".to_string()]);
+        assert!(driver.clicks.is_empty());
+        assert_eq!(driver.slept, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn keys_go_to_the_recorded_app_or_are_reported_missing() {
+        let mut driver = driver();
+        let steps = [
+            step(ReplayAction::Key { app_name: driver.app_name.clone(), keycode: 36, flags: 0x100 }),
+            step(ReplayAction::Key { app_name: "Mail".to_string(), keycode: 0, flags: 0 }),
+        ];
+
+        let report = run(&mut driver, &steps, &ReplayOptions::default());
+        assert_eq!(report.performed, 1);
+        assert_eq!(report.missing, vec!["Key 0 (flags=0x0) in 'Mail'".to_string()]);
+        assert_eq!(driver.keys, vec![(driver.app_name.clone(), 36, 0x100)]);
+    }
+
+    #[test]
+    fn label_chain_only_runs_for_nodes_with_the_recorded_role() {
+        let calls = Rc::new(Cell::new(0));
+        let tree = window(&calls);
+
+        let found = find_element(tree.clone(), &labelled("AXCheckBox", "Send"), &LabelChain::default());
+        assert!(found.is_none());
+        // Only the breadth first walk itself, once for each of the six nodes
+        assert_eq!(calls.get(), 6);
+
+        calls.set(0);
+        let found = find_element(tree, &labelled(K_AX_BUTTON_ROLE, "Send"), &LabelChain::default()).unwrap();
+        assert_eq!(found.frame().unwrap().origin, CGPoint { x: 100.0, y: 10.0 });
+    }
+
+    #[test]
+    fn click_point_keeps_the_offset_only_when_it_fits() {
+        let frame = CGRect { origin: CGPoint { x: 100.0, y: 10.0 }, size: CGSize { width: 80.0, height: 20.0 } };
+        assert_eq!(click_point(frame, Some(CGPoint { x: 5.0, y: 5.0 })), CGPoint { x: 105.0, y: 15.0 });
+        assert_eq!(click_point(frame, Some(CGPoint { x: 90.0, y: 5.0 })), CGPoint { x: 140.0, y: 20.0 });
+        assert_eq!(click_point(frame, None), CGPoint { x: 140.0, y: 20.0 });
+    }
+}
@@ -0,0 +1,612 @@
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::dashboard::format_bytes;
+use crate::log_parse::{self, LogSession, LoggedAction, LoggedClick, LoggedInput};
+use crate::otel;
+
+// Rows in the top remote hosts table
+const MAX_HOSTS: usize = 20;
+
+// Timeline geometry, in SVG user units
+const TIMELINE_WIDTH: f64 = 960.0;
+const TIMELINE_LABEL_WIDTH: f64 = 170.0;
+const TIMELINE_ROW_HEIGHT: f64 = 28.0;
+const TIMELINE_AXIS_HEIGHT: f64 = 24.0;
+const HEATMAP_WIDTH: f64 = 300.0;
+
+const STYLE: &str = "body{font:14px -apple-system,BlinkMacSystemFont,'Helvetica Neue',sans-serif;margin:2em;color:#222}
+h1{font-size:1.5em;margin-bottom:.2em}h2{font-size:1.15em;margin-top:2em;border-bottom:1px solid #ddd;padding-bottom:.2em}
+.meta{color:#666}table{border-collapse:collapse;margin-top:.5em}th,td{padding:3px 10px;text-align:left;vertical-align:top;border-bottom:1px solid #eee}
+th{background:#f5f5f5}td.num{text-align:right;font-variant-numeric:tabular-nums}.silent{color:#999}
+ul.flows{margin:0;padding-left:1.2em;font-size:.9em;color:#555}.legend span{margin-right:1.5em}
+.swatch{display:inline-block;width:10px;height:10px;margin-right:4px;vertical-align:middle}
+.heatmaps{display:flex;flex-wrap:wrap;gap:1.5em}figure{margin:0}figcaption{font-size:.9em;color:#555;max-width:300px}
+svg text{font:11px sans-serif;fill:#444}";
+
+// Arguments for `macos-watcher report`
+pub struct ReportArgs {
+    pub log_path: PathBuf,
+    // Logs are appended to; pick one watcher run (default: the last)
+    pub session_id: Option<String>,
+    // None writes to stdout
+    pub output: Option<PathBuf>,
+}
+
+// Entry point for the report subcommand. Returns the process exit code.
+pub fn run_cli(args: ReportArgs) -> i32 {
+    let session = log_parse::read_log_file(&args.log_path)
+        .and_then(|text| log_parse::read_session(&text, args.session_id.as_deref()));
+    let session = match session {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+
+    let html = render(&session);
+    let written = match &args.output {
+        Some(path) => std::fs::write(path, html).map_err(|e| format!("Failed to write {}: {}", path.display(), e)),
+        None => {
+            print!("{}", html);
+            Ok(())
+        }
+    };
+    match written {
+        Ok(()) => {
+            if let Some(path) = &args.output {
+                eprintln!("Wrote a report of {} actions to {}", session.actions.len(), path.display());
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn clock(time: DateTime<Local>) -> String {
+    time.format("%H:%M:%S%.3f").to_string()
+}
+
+// "Button Clicked ButtonA" or "Key Pressed (36)"
+fn action_name(action: &LoggedAction) -> String {
+    match &action.input {
+        LoggedInput::Click(click) => otel::action_name(&click.selector),
+        LoggedInput::Key { keycode } => format!("Key Pressed ({})", keycode),
+    }
+}
+
+// The whole report as one self-contained HTML document. The output only
+// depends on the session, so reports of the same log are identical.
+pub fn render(session: &LogSession) -> String {
+    let title = session.id.as_deref().map_or("macos-watcher session".to_string(), |id| format!("macos-watcher session {}", id));
+    let clicks = session.actions.iter().filter(|action| matches!(action.input, LoggedInput::Click(_))).count();
+    let keys = session.actions.len() - clicks;
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(&title),
+        STYLE,
+        escape(&title)
+    );
+    let (start, end) = time_span(session);
+    let _ = writeln!(
+        html,
+        "<p class=\"meta\">{} to {} &middot; {} click(s) &middot; {} key press(es) &middot; {} nettop sample(s)</p>",
+        start.format("%Y-%m-%d %H:%M:%S"),
+        end.format("%H:%M:%S"),
+        clicks,
+        keys,
+        session.samples.len()
+    );
+
+    html.push_str("<h2>Timeline</h2>\n");
+    html.push_str(&render_timeline(session, start, end));
+    html.push_str("<h2>Apps</h2>\n");
+    html.push_str(&render_apps(session));
+    html.push_str("<h2>UI actions</h2>\n");
+    html.push_str(&render_actions(session));
+    html.push_str("<h2>Top remote hosts</h2>\n");
+    html.push_str(&render_hosts(session));
+    html.push_str("<h2>Click heatmaps</h2>\n");
+    html.push_str(&render_heatmaps(session));
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// First and last moment of the session, including correlation windows
+fn time_span(session: &LogSession) -> (DateTime<Local>, DateTime<Local>) {
+    let window_ends = session.actions.iter().filter_map(|action| {
+        let outcome = action.outcome.as_ref()?;
+        Some(action.time + ChronoDuration::milliseconds((outcome.correlation.window_secs * 1000.0) as i64))
+    });
+    let times: Vec<DateTime<Local>> = session
+        .actions
+        .iter()
+        .map(|action| action.time)
+        .chain(session.samples.iter().map(|(time, _)| *time))
+        .chain(window_ends)
+        .collect();
+    let start = times.iter().min().copied().unwrap_or_default();
+    let end = times.iter().max().copied().unwrap_or(start);
+    (start, end)
+}
+
+// App name of every PID seen, from UI events first and nettop row keys after
+fn app_names(session: &LogSession) -> BTreeMap<i32, String> {
+    let mut names = BTreeMap::new();
+    for action in &session.actions {
+        names.entry(action.pid).or_insert_with(|| action.app_name.clone());
+    }
+    for (_, sample) in &session.samples {
+        let name = sample.process.rsplit_once('.').map_or(sample.process.as_str(), |(name, _)| name);
+        names.entry(sample.pid).or_insert_with(|| name.to_string());
+    }
+    names
+}
+
+fn render_timeline(session: &LogSession, start: DateTime<Local>, end: DateTime<Local>) -> String {
+    let names = app_names(session);
+    // One row per app, in order of first appearance
+    let mut rows: Vec<&str> = Vec::new();
+    let app_rows = session.actions.iter().map(|action| action.app_name.as_str());
+    let sample_rows = session.samples.iter().map(|(_, sample)| names[&sample.pid].as_str());
+    for name in app_rows.chain(sample_rows) {
+        if !rows.contains(&name) {
+            rows.push(name);
+        }
+    }
+    if rows.is_empty() {
+        return "<p class=\"silent\">Nothing to show.</p>\n".to_string();
+    }
+
+    let span_ms = (end - start).num_milliseconds().max(1) as f64;
+    let plot_width = TIMELINE_WIDTH - TIMELINE_LABEL_WIDTH - 10.0;
+    let x = |time: DateTime<Local>| TIMELINE_LABEL_WIDTH + (time - start).num_milliseconds() as f64 / span_ms * plot_width;
+    let row_y = |name: &str| rows.iter().position(|row| *row == name).unwrap_or(0) as f64 * TIMELINE_ROW_HEIGHT;
+    let height = rows.len() as f64 * TIMELINE_ROW_HEIGHT + TIMELINE_AXIS_HEIGHT;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = TIMELINE_WIDTH,
+        h = height
+    );
+    for (index, row) in rows.iter().enumerate() {
+        let y = index as f64 * TIMELINE_ROW_HEIGHT;
+        if index % 2 == 0 {
+            let _ = writeln!(svg, "<rect x=\"0\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#fafafa\"/>", y, TIMELINE_WIDTH, TIMELINE_ROW_HEIGHT);
+        }
+        let _ = writeln!(svg, "<text x=\"4\" y=\"{:.1}\">{}</text>", y + TIMELINE_ROW_HEIGHT / 2.0 + 4.0, escape(row));
+    }
+
+    // Correlation windows under everything else
+    for action in &session.actions {
+        let Some(outcome) = &action.outcome else { continue };
+        let until = action.time + ChronoDuration::milliseconds((outcome.correlation.window_secs * 1000.0) as i64);
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#4a90d9\" fill-opacity=\"0.15\"><title>{}: ↑ {} ↓ {}</title></rect>",
+            x(action.time),
+            row_y(&action.app_name) + 4.0,
+            (x(until) - x(action.time)).max(1.0),
+            TIMELINE_ROW_HEIGHT - 8.0,
+            escape(&action_name(action)),
+            format_bytes(outcome.correlation.bytes_out as f64),
+            format_bytes(outcome.correlation.bytes_in as f64)
+        );
+    }
+
+    // Traffic bursts: bar height grows with the logarithm of the bytes moved
+    let largest = session.samples.iter().map(|(_, sample)| sample.delta_in + sample.delta_out).max().unwrap_or(0);
+    for (time, sample) in &session.samples {
+        let bytes = sample.delta_in + sample.delta_out;
+        let scale = if largest > 0 { ((bytes + 1) as f64).ln() / ((largest + 1) as f64).ln() } else { 0.0 };
+        let bar = 3.0 + scale * (TIMELINE_ROW_HEIGHT - 9.0);
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"2\" height=\"{:.1}\" fill=\"#e8a33d\"><title>{} {}: ↑ {} ↓ {}</title></rect>",
+            x(*time) - 1.0,
+            row_y(&names[&sample.pid]) + TIMELINE_ROW_HEIGHT - 3.0 - bar,
+            bar,
+            clock(*time),
+            escape(&sample.process),
+            format_bytes(sample.delta_out as f64),
+            format_bytes(sample.delta_in as f64)
+        );
+    }
+
+    for action in &session.actions {
+        let y = row_y(&action.app_name) + TIMELINE_ROW_HEIGHT / 2.0;
+        let tooltip = format!("{} {}", clock(action.time), action_name(action));
+        match action.input {
+            LoggedInput::Click(_) => {
+                let _ = writeln!(svg, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"#d9534f\"><title>{}</title></circle>", x(action.time), y, escape(&tooltip));
+            }
+            LoggedInput::Key { .. } => {
+                let _ = writeln!(
+                    svg,
+                    "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"2\" height=\"10\" fill=\"#555\"><title>{}</title></rect>",
+                    x(action.time) - 1.0,
+                    y - 5.0,
+                    escape(&tooltip)
+                );
+            }
+        }
+    }
+
+    // Time axis with five labels
+    let axis_y = rows.len() as f64 * TIMELINE_ROW_HEIGHT;
+    let _ = writeln!(svg, "<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#999\"/>", TIMELINE_LABEL_WIDTH, axis_y, TIMELINE_LABEL_WIDTH + plot_width, axis_y);
+    for step in 0..5 {
+        let offset = span_ms * step as f64 / 4.0;
+        let time = start + ChronoDuration::milliseconds(offset as i64);
+        let anchor = match step {
+            0 => "start",
+            4 => "end",
+            _ => "middle",
+        };
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\">{}</text>", x(time), axis_y + 16.0, anchor, time.format("%H:%M:%S"));
+    }
+    svg.push_str("</svg>\n");
+    svg.push_str(
+        "<p class=\"legend\"><span><i class=\"swatch\" style=\"background:#d9534f\"></i>click</span>\
+         <span><i class=\"swatch\" style=\"background:#555\"></i>key press</span>\
+         <span><i class=\"swatch\" style=\"background:#4a90d9;opacity:.4\"></i>correlation window</span>\
+         <span><i class=\"swatch\" style=\"background:#e8a33d\"></i>nettop delta</span></p>\n",
+    );
+    svg
+}
+
+#[derive(Default)]
+struct AppTotals {
+    clicks: usize,
+    keys: usize,
+    silent_clicks: usize,
+    correlated_out: u64,
+    correlated_in: u64,
+    sampled_out: u64,
+    sampled_in: u64,
+}
+
+fn render_apps(session: &LogSession) -> String {
+    let names = app_names(session);
+    let mut apps: BTreeMap<&str, AppTotals> = BTreeMap::new();
+    for action in &session.actions {
+        let totals = apps.entry(&action.app_name).or_default();
+        match (&action.input, &action.outcome) {
+            (LoggedInput::Key { .. }, _) => totals.keys += 1,
+            (LoggedInput::Click(_), Some(outcome)) => {
+                totals.clicks += 1;
+                totals.correlated_out += outcome.correlation.bytes_out;
+                totals.correlated_in += outcome.correlation.bytes_in;
+                if outcome.correlation.bytes_out + outcome.correlation.bytes_in == 0 {
+                    totals.silent_clicks += 1;
+                }
+            }
+            (LoggedInput::Click(_), None) => totals.clicks += 1,
+        }
+    }
+    for (_, sample) in &session.samples {
+        let totals = apps.entry(&names[&sample.pid]).or_default();
+        totals.sampled_out += sample.delta_out;
+        totals.sampled_in += sample.delta_in;
+    }
+
+    let mut rows: Vec<(&str, AppTotals)> = apps.into_iter().collect();
+    rows.sort_by_key(|(name, totals)| (std::cmp::Reverse(totals.sampled_out + totals.sampled_in + totals.correlated_out + totals.correlated_in), *name));
+
+    let mut table = String::from(
+        "<table>\n<tr><th>App</th><th>Clicks</th><th>Silent clicks</th><th>Key presses</th>\
+         <th>↑ After clicks</th><th>↓ After clicks</th><th>↑ nettop</th><th>↓ nettop</th></tr>\n",
+    );
+    for (name, totals) in rows {
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+            escape(name),
+            totals.clicks,
+            totals.silent_clicks,
+            totals.keys,
+            format_bytes(totals.correlated_out as f64),
+            format_bytes(totals.correlated_in as f64),
+            format_bytes(totals.sampled_out as f64),
+            format_bytes(totals.sampled_in as f64)
+        );
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+fn render_actions(session: &LogSession) -> String {
+    if session.actions.is_empty() {
+        return "<p class=\"silent\">No clicks or key presses.</p>\n".to_string();
+    }
+    let mut table = String::from(
+        "<table>\n<tr><th>Time</th><th>App</th><th>Action</th><th>↑ Out</th><th>↓ In</th><th>New connections</th><th>Hosts</th><th>Flows</th></tr>\n",
+    );
+    for action in &session.actions {
+        let name = escape(&action_name(action));
+        let _ = write!(table, "<tr><td>{}</td><td>{}</td>", clock(action.time), escape(&action.app_name));
+        let Some(outcome) = &action.outcome else {
+            let _ = writeln!(table, "<td>{}</td><td colspan=\"5\" class=\"silent\">not correlated</td></tr>", name);
+            continue;
+        };
+        let correlation = &outcome.correlation;
+        let class = if correlation.bytes_out + correlation.bytes_in == 0 { " class=\"silent\"" } else { "" };
+        let flows: String = if outcome.flows.is_empty() {
+            String::new()
+        } else {
+            let items: Vec<String> = outcome
+                .flows
+                .iter()
+                .map(|flow| {
+                    let peer = flow.hostname.as_deref().unwrap_or(&flow.remote);
+                    format!(
+                        "<li>{} {} {:+} to {:+} ms, ↑ {} ↓ {}{}</li>",
+                        escape(&flow.protocol),
+                        escape(peer),
+                        flow.first_byte_ms,
+                        flow.last_byte_ms,
+                        format_bytes(flow.bytes_out as f64),
+                        format_bytes(flow.bytes_in as f64),
+                        flow.summary.as_deref().map_or(String::new(), |summary| format!(": {}", escape(summary)))
+                    )
+                })
+                .collect();
+            format!("<ul class=\"flows\">{}</ul>", items.join(""))
+        };
+        let _ = writeln!(
+            table,
+            "<td{}>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td><td>{}</td></tr>",
+            class,
+            name,
+            format_bytes(correlation.bytes_out as f64),
+            format_bytes(correlation.bytes_in as f64),
+            correlation.new_connections,
+            escape(&correlation.remotes.join(", ")),
+            flows
+        );
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+#[derive(Default)]
+struct HostTotals {
+    actions: usize,
+    flows: usize,
+    bytes_out: u64,
+    bytes_in: u64,
+}
+
+fn render_hosts(session: &LogSession) -> String {
+    let mut hosts: BTreeMap<&str, HostTotals> = BTreeMap::new();
+    for outcome in session.actions.iter().filter_map(|action| action.outcome.as_ref()) {
+        for remote in &outcome.correlation.remotes {
+            hosts.entry(remote).or_default().actions += 1;
+        }
+        // Byte counts per host are only known from captured flows
+        for flow in &outcome.flows {
+            let totals = hosts.entry(flow.hostname.as_deref().unwrap_or(&flow.remote)).or_default();
+            totals.flows += 1;
+            totals.bytes_out += flow.bytes_out;
+            totals.bytes_in += flow.bytes_in;
+        }
+    }
+    if hosts.is_empty() {
+        return "<p class=\"silent\">No correlated traffic.</p>\n".to_string();
+    }
+
+    let mut rows: Vec<(&str, HostTotals)> = hosts.into_iter().collect();
+    rows.sort_by_key(|(host, totals)| (std::cmp::Reverse((totals.actions, totals.bytes_out + totals.bytes_in)), *host));
+    let mut table = String::from("<table>\n<tr><th>Host</th><th>Actions</th><th>Flows</th><th>↑ Out</th><th>↓ In</th></tr>\n");
+    for (host, totals) in rows.into_iter().take(MAX_HOSTS) {
+        let bytes = |bytes: u64| if totals.flows == 0 { "-".to_string() } else { format_bytes(bytes as f64) };
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+            escape(host),
+            totals.actions,
+            totals.flows,
+            bytes(totals.bytes_out),
+            bytes(totals.bytes_in)
+        );
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+// Every window that was clicked in, drawn to scale with a dot per click
+fn render_heatmaps(session: &LogSession) -> String {
+    let mut windows: BTreeMap<(&str, &str), Vec<&LoggedClick>> = BTreeMap::new();
+    for action in &session.actions {
+        let LoggedInput::Click(click) = &action.input else { continue };
+        if click.position.is_none() || click.window_frame.is_none() {
+            continue;
+        }
+        let title = click.window_title.as_deref().unwrap_or("Untitled window");
+        windows.entry((&click.app_name, title)).or_default().push(click);
+    }
+    if windows.is_empty() {
+        return "<p class=\"silent\">No clicks with window geometry.</p>\n".to_string();
+    }
+
+    let mut html = String::from("<div class=\"heatmaps\">\n");
+    for ((app, title), clicks) in windows {
+        // The window may have moved or been resized; use its latest frame
+        let Some((frame_x, frame_y, frame_width, frame_height)) = clicks.last().and_then(|click| click.window_frame) else { continue };
+        if frame_width <= 0.0 || frame_height <= 0.0 {
+            continue;
+        }
+        let scale = HEATMAP_WIDTH / frame_width;
+        let height = frame_height * scale;
+        let _ = writeln!(
+            html,
+            "<figure><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h:.0}\" viewBox=\"0 0 {w} {h:.1}\">\
+             <rect x=\"0.5\" y=\"0.5\" width=\"{rw}\" height=\"{rh:.1}\" fill=\"#fcfcfc\" stroke=\"#bbb\"/>",
+            w = HEATMAP_WIDTH,
+            h = height,
+            rw = HEATMAP_WIDTH - 1.0,
+            rh = height - 1.0
+        );
+        for click in &clicks {
+            let Some((x, y)) = click.position else { continue };
+            let _ = writeln!(
+                html,
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"7\" fill=\"#d9534f\" fill-opacity=\"0.35\"><title>{}</title></circle>",
+                ((x - frame_x) * scale).clamp(0.0, HEATMAP_WIDTH),
+                ((y - frame_y) * scale).clamp(0.0, height),
+                escape(&click.selector.describe())
+            );
+        }
+        let _ = writeln!(html, "</svg><figcaption>{} &middot; {} &middot; {} click(s)</figcaption></figure>", escape(app), escape(title), clicks.len());
+    }
+    html.push_str("</div>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_parse::tests::{utc, LOG};
+
+    // The report of the first session of LOG, rendered in UTC
+    const GOLDEN: &str = include_str!("../tests/fixtures/report.html");
+
+    fn session() -> LogSession {
+        log_parse::read_session(LOG, Some("20261018T091500-4242")).unwrap()
+    }
+
+    // The HTML under a heading, up to the next one
+    fn section<'a>(html: &'a str, heading: &str) -> &'a str {
+        let start = html.find(&format!("<h2>{}</h2>", heading)).unwrap();
+        let rest = &html[start..];
+        let end = rest[4..].find("<h2>").map_or(rest.len(), |end| end + 4);
+        &rest[..end]
+    }
+
+    // GOLDEN with its times (all between 09:15:00 and 09:15:09 UTC) shown
+    // in the local time zone, like render does
+    fn golden_in_local_time() -> String {
+        let mut golden = GOLDEN.replace("2026-10-18 09:15:", &utc(9, 15, 0).format("%Y-%m-%d %H:%M:").to_string());
+        for second in 0..10 {
+            golden = golden.replace(&format!("09:15:0{}", second), &utc(9, 15, second).format("%H:%M:%S").to_string());
+        }
+        golden
+    }
+
+    #[test]
+    fn reports_match_the_golden_file() {
+        let html = render(&session());
+        assert_eq!(html, render(&session()));
+        let golden = golden_in_local_time();
+        if html != golden {
+            let line = html.lines().zip(golden.lines()).position(|(rendered, expected)| rendered != expected).unwrap_or(0);
+            panic!("line {} differs:\n{}\nexpected:\n{}", line + 1, html.lines().nth(line).unwrap_or(""), golden.lines().nth(line).unwrap_or(""));
+        }
+    }
+
+    #[test]
+    fn reports_cover_every_section() {
+        let html = render(&session());
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>macos-watcher session 20261018T091500-4242</title>"));
+        assert!(html.contains("2 click(s) &middot; 1 key press(es) &middot; 2 nettop sample(s)"), "{}", html);
+
+        let apps = section(&html, "Apps");
+        assert!(apps.contains("<tr><td>Mail</td><td class=\"num\">2</td><td class=\"num\">1</td><td class=\"num\">1</td>"), "{}", apps);
+
+        let actions = section(&html, "UI actions");
+        assert!(actions.contains("Button Clicked send"), "{}", actions);
+        assert!(actions.contains("<td>Key Pressed (36)</td><td colspan=\"5\" class=\"silent\">not correlated</td>"), "{}", actions);
+        assert!(actions.contains("<td class=\"silent\">Element Clicked Archive</td>"), "{}", actions);
+        assert!(actions.contains("<li>tcp smtp.example.com +41 to +388 ms, ↑ 5.9 KB ↓ 1.4 KB: smtp.example.com (TLS)</li>"), "{}", actions);
+
+        let hosts = section(&html, "Top remote hosts");
+        assert!(hosts.contains("<tr><td>smtp.example.com</td><td class=\"num\">1</td><td class=\"num\">1</td>"), "{}", hosts);
+
+        // Only the Send click was logged with window geometry; its apostrophe is escaped
+        let heatmaps = section(&html, "Click heatmaps");
+        assert_eq!(heatmaps.matches("<circle").count(), 1, "{}", heatmaps);
+        assert!(heatmaps.contains("<circle cx=\"45.0\" cy=\"17.1\""), "{}", heatmaps);
+        assert!(heatmaps.contains("Mail &middot; Draft - it&#39;s done &middot; 1 click(s)"), "{}", heatmaps);
+    }
+
+    #[test]
+    fn timelines_have_a_row_per_app() {
+        let log = format!(
+            "{}\n📡 Dropbox.4242 ↑ 100 B ↓ 5000 B (Δ ↑ 100 ↓ 5000)\n📡 Mail.812 ↑ 1 B ↓ 0 B (Δ ↑ 1 ↓ 0)\n",
+            LOG.lines().take(6).collect::<Vec<_>>().join("\n")
+        );
+        let session = log_parse::read_session(&log, None).unwrap();
+        let timeline = render_timeline(&session, time_span(&session).0, time_span(&session).1);
+        let labels: Vec<&str> = timeline.lines().filter_map(|line| line.strip_prefix("<text x=\"4\"")).collect();
+        assert_eq!(labels.len(), 2, "{}", timeline);
+        assert!(labels[0].ends_with(">Mail</text>") && labels[1].ends_with(">Dropbox</text>"), "{:?}", labels);
+        // Two clicks, one key press, one correlation window and two bursts
+        assert_eq!(timeline.matches("<circle").count(), 2);
+        assert_eq!(timeline.matches("fill=\"#555\"").count(), 1);
+        assert_eq!(timeline.matches("fill-opacity=\"0.15\"").count(), 2);
+        assert_eq!(timeline.matches("fill=\"#e8a33d\"").count(), 2);
+        // The session runs until the last window closes
+        assert_eq!(time_span(&session), (utc(9, 15, 1), utc(9, 15, 5)));
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        let log = "2026-10-18T09:15:01Z [INFO] Key Down: App='<script>\"&' (PID=1), KeyCode=0\n";
+        let html = render(&log_parse::read_session(log, None).unwrap());
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;&quot;&amp;"));
+    }
+
+    #[test]
+    fn empty_sections_say_so() {
+        let html = render(&LogSession::default());
+        for text in ["Nothing to show.", "No clicks or key presses.", "No correlated traffic.", "No clicks with window geometry."] {
+            assert!(html.contains(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn the_cli_writes_the_report_or_fails() {
+        let dir = std::env::temp_dir();
+        let log_path = dir.join(format!("macos-watcher-report-{}.log", std::process::id()));
+        let output = dir.join(format!("macos-watcher-report-{}.html", std::process::id()));
+        std::fs::write(&log_path, LOG).unwrap();
+
+        let args = |session_id: &str| ReportArgs { log_path: log_path.clone(), session_id: Some(session_id.to_string()), output: Some(output.clone()) };
+        assert_eq!(run_cli(args("20261018T100000-5555")), 0);
+        let html = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(run_cli(args("missing")), 1);
+        std::fs::remove_file(&log_path).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        assert!(html.contains("Element Clicked url"));
+        assert_eq!(run_cli(ReportArgs { log_path: log_path.clone(), session_id: None, output: None }), 1);
+    }
+}
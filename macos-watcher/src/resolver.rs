@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::dns::{self, RecordData};
+use crate::pcap::{self, PcapReader};
+
+// How long lookup results are kept. Failed lookups are retried sooner.
+pub const DEFAULT_POSITIVE_TTL: Duration = Duration::from_secs(3600);
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(300);
+
+// Names learned from DNS responses are kept at least this long, even when the
+// record TTL is shorter: the app keeps talking to the address after it expires
+const MIN_LEARNED_TTL: Duration = Duration::from_secs(60);
+
+// Per-query timeout of DnsServerResolver
+const DNS_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Turns a remote address into a hostname. Ok(None) means the address has no name.
+pub trait Resolver: Send + Sync {
+    fn reverse(&self, ip: IpAddr) -> Result<Option<String>, String>;
+}
+
+// The system resolver (getnameinfo), which honours /etc/hosts and mDNS
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn reverse(&self, ip: IpAddr) -> Result<Option<String>, String> {
+        let address = SocketAddr::new(ip, 0);
+        let (storage, len) = socket_address(&address);
+        let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+        let result = unsafe {
+            libc::getnameinfo(
+                &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+                len,
+                host.as_mut_ptr(),
+                host.len() as libc::socklen_t,
+                std::ptr::null_mut(),
+                0,
+                libc::NI_NAMEREQD,
+            )
+        };
+        match result {
+            0 => {
+                let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+                Ok(Some(name.to_string_lossy().trim_end_matches('.').to_string()))
+            }
+            libc::EAI_NONAME => Ok(None),
+            code => {
+                let message = unsafe { CStr::from_ptr(libc::gai_strerror(code)) };
+                Err(message.to_string_lossy().into_owned())
+            }
+        }
+    }
+}
+
+fn socket_address(address: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match address {
+        SocketAddr::V4(address) => {
+            let sin = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = address.port().to_be();
+            sin.sin_addr = libc::in_addr { s_addr: u32::from_ne_bytes(address.ip().octets()) };
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(address) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = address.port().to_be();
+            sin6.sin6_addr = libc::in6_addr { s6_addr: address.ip().octets() };
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    #[cfg(target_os = "macos")]
+    {
+        storage.ss_len = len as u8;
+    }
+    (storage, len as libc::socklen_t)
+}
+
+// Sends PTR queries straight to one DNS server over UDP, bypassing the
+// system configuration. Point it at a local stand-in server to get
+// deterministic answers.
+pub struct DnsServerResolver {
+    server: SocketAddr,
+    timeout: Duration,
+}
+
+impl DnsServerResolver {
+    pub fn new(server: SocketAddr) -> Self {
+        DnsServerResolver { server, timeout: DNS_QUERY_TIMEOUT }
+    }
+}
+
+impl Resolver for DnsServerResolver {
+    fn reverse(&self, ip: IpAddr) -> Result<Option<String>, String> {
+        let bind: SocketAddr = if self.server.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { (std::net::Ipv6Addr::UNSPECIFIED, 0).into() };
+        let socket = UdpSocket::bind(bind).map_err(|e| format!("Failed to open DNS socket: {}", e))?;
+        socket.set_read_timeout(Some(self.timeout)).map_err(|e| e.to_string())?;
+        socket.connect(self.server).map_err(|e| format!("Failed to reach DNS server {}: {}", self.server, e))?;
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        let id = (nanos ^ std::process::id()) as u16;
+        socket
+            .send(&dns::encode_query(id, &dns::reverse_name(ip), dns::TYPE_PTR))
+            .map_err(|e| format!("Failed to send DNS query to {}: {}", self.server, e))?;
+
+        let mut buffer = [0u8; 1500];
+        loop {
+            let len = socket.recv(&mut buffer).map_err(|e| format!("No answer from DNS server {}: {}", self.server, e))?;
+            let Ok(message) = dns::parse_message(&buffer[..len]) else { continue };
+            if message.id != id || !message.is_response {
+                continue; // Late answer to an earlier query
+            }
+            // NXDOMAIN and empty answers both mean "no name"
+            return Ok(message.answers.into_iter().find_map(|record| match record.data {
+                RecordData::Ptr(name) => Some(name),
+                _ => None,
+            }));
+        }
+    }
+}
+
+// Where a cached hostname came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HostnameSource {
+    // Reverse lookup (PTR) by the watcher
+    Lookup,
+    // A DNS response seen on the wire: the name the app actually asked for
+    Observed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CacheEntry {
+    hostname: Option<String>,
+    source: HostnameSource,
+    expires_at: Instant,
+}
+
+// Hostnames per address, each with its own expiry
+#[derive(Clone, Debug)]
+pub struct HostnameCache {
+    entries: HashMap<IpAddr, CacheEntry>,
+    positive_ttl: Duration,
+    negative_ttl: Duration,
+}
+
+impl Default for HostnameCache {
+    fn default() -> Self {
+        HostnameCache::new(DEFAULT_POSITIVE_TTL, DEFAULT_NEGATIVE_TTL)
+    }
+}
+
+impl HostnameCache {
+    pub fn new(positive_ttl: Duration, negative_ttl: Duration) -> Self {
+        HostnameCache { entries: HashMap::new(), positive_ttl, negative_ttl }
+    }
+
+    // Some(None) is a cached "no name"; None means the address must be looked up
+    pub fn get(&self, ip: IpAddr, now: Instant) -> Option<Option<String>> {
+        self.entries
+            .get(&ip)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.hostname.clone())
+    }
+
+    pub fn insert_lookup(&mut self, ip: IpAddr, hostname: Option<String>, now: Instant) {
+        // A name the app resolved itself is better than whatever PTR says
+        if self.entries.get(&ip).is_some_and(|entry| entry.source == HostnameSource::Observed && entry.expires_at > now) {
+            return;
+        }
+        let ttl = if hostname.is_some() { self.positive_ttl } else { self.negative_ttl };
+        self.entries.insert(ip, CacheEntry { hostname, source: HostnameSource::Lookup, expires_at: now + ttl });
+    }
+
+    pub fn insert_observed(&mut self, ip: IpAddr, hostname: String, ttl: Duration, now: Instant) {
+        let expires_at = now + ttl.max(MIN_LEARNED_TTL);
+        self.entries.insert(ip, CacheEntry { hostname: Some(hostname), source: HostnameSource::Observed, expires_at });
+    }
+
+    // Remember the addresses in a DNS response under the name that was asked
+    // for, so "api.example.com → CNAME edge.cdn.net → 1.2.3.4" maps 1.2.3.4 to
+    // api.example.com. Returns how many addresses were learned.
+    pub fn learn(&mut self, message: &dns::Message, now: Instant) -> usize {
+        if !message.is_response || message.rcode != 0 {
+            return 0;
+        }
+        let Some(question) = message.questions.first() else { return 0 };
+        let mut learned = 0;
+        for record in &message.answers {
+            let ip: IpAddr = match record.data {
+                RecordData::A(ip) => ip.into(),
+                RecordData::Aaaa(ip) => ip.into(),
+                _ => continue,
+            };
+            self.insert_observed(ip, question.name.clone(), Duration::from_secs(record.ttl as u64), now);
+            learned += 1;
+        }
+        learned
+    }
+
+    // Drop expired entries
+    pub fn prune(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| entry.expires_at > now);
+    }
+}
+
+// A Resolver in front of a HostnameCache
+pub struct HostnameResolver {
+    resolver: Box<dyn Resolver>,
+    cache: Mutex<HostnameCache>,
+}
+
+impl HostnameResolver {
+    pub fn new(resolver: Box<dyn Resolver>, cache: HostnameCache) -> Self {
+        HostnameResolver { resolver, cache: Mutex::new(cache) }
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, HostnameCache> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Hostname of `ip`, from the cache or a (blocking) lookup
+    pub fn hostname(&self, ip: IpAddr) -> Option<String> {
+        if let Some(cached) = self.cache().get(ip, Instant::now()) {
+            return cached;
+        }
+        // The cache is not locked during the lookup, so a slow server does not
+        // hold up other threads. Two threads may look up the same address.
+        let hostname = match self.resolver.reverse(ip) {
+            Ok(hostname) => hostname,
+            Err(e) => {
+                log::debug!("Reverse lookup of {} failed: {}", ip, e);
+                None
+            }
+        };
+        let mut cache = self.cache();
+        let now = Instant::now();
+        cache.insert_lookup(ip, hostname, now);
+        cache.get(ip, now).flatten()
+    }
+
+    // Learn from one raw DNS message; malformed ones are ignored
+    pub fn learn_message(&self, payload: &[u8]) -> usize {
+        match dns::parse_message(payload) {
+            Ok(message) => self.cache().learn(&message, Instant::now()),
+            Err(_) => 0,
+        }
+    }
+
+    // Learn from every DNS response in a pcap stream, until it ends
+    pub fn learn_from_pcap<R: Read>(&self, input: R) -> io::Result<usize> {
+        let mut reader = PcapReader::new(input)?;
+        let mut learned = 0;
+        let mut packets = 0u64;
+        while let Some(packet) = reader.next_packet()? {
+            packets += 1;
+            // A live capture never ends, so expire old names as it goes
+            if packets.is_multiple_of(PRUNE_EVERY_PACKETS) {
+                self.cache().prune(Instant::now());
+            }
+            let Some(datagram) = pcap::udp_datagram(reader.linktype(), &packet.data) else { continue };
+            if datagram.source.port() == DNS_PORT || datagram.source.port() == MDNS_PORT {
+                learned += self.learn_message(datagram.payload);
+            }
+        }
+        self.cache().prune(Instant::now());
+        Ok(learned)
+    }
+}
+
+const DNS_PORT: u16 = 53;
+const PRUNE_EVERY_PACKETS: u64 = 1000;
+const MDNS_PORT: u16 = 5353;
+
+static RESOLVER: OnceLock<HostnameResolver> = OnceLock::new();
+
+// Make `resolver` the one used by hostname(). Only the first call has an effect.
+pub fn install(resolver: HostnameResolver) {
+    if RESOLVER.set(resolver).is_err() {
+        log::warn!("Hostname resolver already installed");
+    }
+}
+
+// Hostname for a remote address as nettop prints it ("93.184.216.34",
+// "2606:2800:220:1::1"). None if resolution is off, the host is not an IP
+// address or it has no name. May block on a lookup, so stay off the run loop.
+pub fn hostname(host: &str) -> Option<String> {
+    let resolver = RESOLVER.get()?;
+    // nettop adds the interface to link-local IPv6 addresses ("fe80::1%en0")
+    let ip: IpAddr = host.split('%').next()?.parse().ok()?;
+    if ip.is_unspecified() {
+        return None;
+    }
+    resolver.hostname(ip)
+}
+
+// Feed DNS responses from `source` into the installed resolver. A path to an
+// existing file is read as a pcap capture; anything else is taken as an
+// interface name and captured live with tcpdump (which needs root).
+pub fn start_snooping(source: &str) -> Result<(), String> {
+    if RESOLVER.get().is_none() {
+        return Err("Hostname resolution is off".to_string());
+    }
+
+    if Path::new(source).is_file() {
+        let file = File::open(source).map_err(|e| format!("Failed to open {}: {}", source, e))?;
+        let source = source.to_string();
+        thread::spawn(move || match RESOLVER.get().map(|resolver| resolver.learn_from_pcap(BufReader::new(file))) {
+            Some(Ok(learned)) => log::info!("Learned {} hostname(s) from {}", learned, source),
+            Some(Err(e)) => log::error!("Failed to read DNS responses from {}: {}", source, e),
+            None => {}
+        });
+        return Ok(());
+    }
+
+    let mut child = Command::new("tcpdump")
+        .args(["-i", source, "-n", "-U", "-s", "0", "-w", "-", "udp src port 53 or udp src port 5353"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to start tcpdump: {}", e))?;
+    let stdout = child.stdout.take().ok_or("tcpdump has no output")?;
+    let source = source.to_string();
+    thread::spawn(move || {
+        if let Some(resolver) = RESOLVER.get() {
+            match resolver.learn_from_pcap(stdout) {
+                Ok(learned) => log::info!("DNS capture on {} ended after learning {} hostname(s)", source, learned),
+                Err(e) => log::error!("DNS capture on {} failed: {}", source, e),
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::tests::{record, response};
+    use crate::pcap::tests::{pcap_file, udp_frame};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Answers from a table and counts how often it was asked
+    struct FakeResolver {
+        names: HashMap<IpAddr, String>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Resolver for FakeResolver {
+        fn reverse(&self, ip: IpAddr) -> Result<Option<String>, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if ip.is_loopback() {
+                return Err("server failure".to_string());
+            }
+            Ok(self.names.get(&ip).cloned())
+        }
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn fake(names: &[(&str, &str)]) -> (HostnameResolver, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let names = names.iter().map(|(address, name)| (ip(address), name.to_string())).collect();
+        let resolver = FakeResolver { names, calls: calls.clone() };
+        (HostnameResolver::new(Box::new(resolver), HostnameCache::default()), calls)
+    }
+
+    #[test]
+    fn cache_entries_expire_after_their_ttl() {
+        let mut cache = HostnameCache::new(Duration::from_secs(10), Duration::from_secs(2));
+        let now = Instant::now();
+        cache.insert_lookup(ip("1.1.1.1"), Some("one.one.one.one".to_string()), now);
+        cache.insert_lookup(ip("10.0.0.9"), None, now);
+
+        assert_eq!(cache.get(ip("1.1.1.1"), now), Some(Some("one.one.one.one".to_string())));
+        assert_eq!(cache.get(ip("10.0.0.9"), now), Some(None));
+        assert_eq!(cache.get(ip("10.0.0.9"), now + Duration::from_secs(2)), None);
+        assert_eq!(cache.get(ip("1.1.1.1"), now + Duration::from_secs(10)), None);
+        assert_eq!(cache.get(ip("8.8.8.8"), now), None);
+
+        cache.prune(now + Duration::from_secs(5));
+        assert_eq!(cache.entries.len(), 1);
+    }
+
+    #[test]
+    fn observed_names_win_over_lookups_until_they_expire() {
+        let mut cache = HostnameCache::default();
+        let now = Instant::now();
+        cache.insert_observed(ip("93.184.216.34"), "api.example.com".to_string(), Duration::from_secs(5), now);
+        cache.insert_lookup(ip("93.184.216.34"), Some("edge-1.cdn.net".to_string()), now);
+        assert_eq!(cache.get(ip("93.184.216.34"), now), Some(Some("api.example.com".to_string())));
+
+        // Short record TTLs are stretched to MIN_LEARNED_TTL
+        let later = now + MIN_LEARNED_TTL - Duration::from_secs(1);
+        assert_eq!(cache.get(ip("93.184.216.34"), later), Some(Some("api.example.com".to_string())));
+        cache.insert_lookup(ip("93.184.216.34"), Some("edge-1.cdn.net".to_string()), now + MIN_LEARNED_TTL);
+        assert_eq!(cache.get(ip("93.184.216.34"), now + MIN_LEARNED_TTL), Some(Some("edge-1.cdn.net".to_string())));
+    }
+
+    #[test]
+    fn responses_map_addresses_to_the_queried_name() {
+        let mut cache = HostnameCache::default();
+        let now = Instant::now();
+        let answers = [
+            record("api.example.com", 300, RecordData::Cname("edge.cdn.net".to_string())),
+            record("edge.cdn.net", 300, RecordData::A(Ipv4Addr::new(93, 184, 216, 34))),
+            record("edge.cdn.net", 300, RecordData::Aaaa("2606:2800:220:1::1".parse().unwrap())),
+        ];
+        let message = dns::parse_message(&response(1, 0, "api.example.com", dns::TYPE_A, &answers)).unwrap();
+        assert_eq!(cache.learn(&message, now), 2);
+        assert_eq!(cache.get(ip("2606:2800:220:1::1"), now), Some(Some("api.example.com".to_string())));
+
+        // Failed responses and queries teach nothing
+        let failed = dns::parse_message(&response(2, 3, "gone.example.com", dns::TYPE_A, &answers[1..2])).unwrap();
+        assert_eq!(cache.learn(&failed, now), 0);
+        let query = dns::parse_message(&dns::encode_query(3, "api.example.com", dns::TYPE_A)).unwrap();
+        assert_eq!(cache.learn(&query, now), 0);
+    }
+
+    #[test]
+    fn lookups_are_cached_including_failures() {
+        let (resolver, calls) = fake(&[("1.1.1.1", "one.one.one.one")]);
+        assert_eq!(resolver.hostname(ip("1.1.1.1")).as_deref(), Some("one.one.one.one"));
+        assert_eq!(resolver.hostname(ip("1.1.1.1")).as_deref(), Some("one.one.one.one"));
+        assert_eq!(resolver.hostname(ip("10.0.0.9")), None);
+        assert_eq!(resolver.hostname(ip("10.0.0.9")), None);
+        assert_eq!(resolver.hostname(ip("127.0.0.1")), None);
+        assert_eq!(resolver.hostname(ip("127.0.0.1")), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn learns_from_dns_responses_in_a_capture() {
+        let (resolver, calls) = fake(&[]);
+        let answer = [record("api.example.com", 300, RecordData::A(Ipv4Addr::new(93, 184, 216, 34)))];
+        let payload = response(9, 0, "api.example.com", dns::TYPE_A, &answer);
+        let capture = pcap_file(&[
+            (Duration::ZERO, udp_frame("10.0.0.5:53000", "1.1.1.1:53", &dns::encode_query(9, "api.example.com", dns::TYPE_A))),
+            (Duration::ZERO, udp_frame("1.1.1.1:53", "10.0.0.5:53000", &payload)),
+            (Duration::ZERO, udp_frame("1.1.1.1:53", "10.0.0.5:53000", &payload[..20])),
+            (Duration::ZERO, udp_frame("10.0.0.7:5353", "224.0.0.251:5353", b"not dns")),
+        ]);
+
+        assert_eq!(resolver.learn_from_pcap(&capture[..]).unwrap(), 1);
+        assert_eq!(resolver.hostname(ip("93.184.216.34")).as_deref(), Some("api.example.com"));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn dns_server_resolver_sends_ptr_queries() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            for answer in [Some("one.one.one.one"), None] {
+                let (len, client) = server.recv_from(&mut buffer).unwrap();
+                let query = dns::parse_message(&buffer[..len]).unwrap();
+                let name = &query.questions[0].name;
+                let answers: Vec<_> = answer.into_iter().map(|target| record(name, 60, RecordData::Ptr(target.to_string()))).collect();
+                // A stale answer to another query first, which must be skipped
+                server.send_to(&response(query.id.wrapping_add(1), 0, name, dns::TYPE_PTR, &[]), client).unwrap();
+                server.send_to(&response(query.id, if answer.is_some() { 0 } else { 3 }, name, dns::TYPE_PTR, &answers), client).unwrap();
+            }
+        });
+
+        let resolver = DnsServerResolver::new(address);
+        assert_eq!(resolver.reverse(ip("1.1.1.1")), Ok(Some("one.one.one.one".to_string())));
+        assert_eq!(resolver.reverse(ip("10.0.0.9")), Ok(None));
+    }
+}
@@ -0,0 +1,580 @@
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, app_matches, Alert, ClickEvent, Correlation, NetworkSample, WatcherEvent};
+use crate::network::{sample_nettop_rows, NettopRow};
+use crate::shutdown;
+use crate::traffic::TrafficHistory;
+
+// While an idle_traffic rule is loaded, every process is sampled this often
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn default_idle() -> f64 {
+    5.0
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+// A YAML file of alert rules, loaded with --rules
+#[derive(Clone, Debug, Deserialize)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default)]
+    pub severity: Severity,
+    // Only this app (full path, executable name or nettop's "<name>.<pid>")
+    pub app: Option<String>,
+    #[serde(flatten)]
+    pub condition: Condition,
+}
+
+// What a rule looks for, selected by its `when` key
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "when", rename_all = "snake_case")]
+pub enum Condition {
+    // The process sends more than `bytes_out` bytes while no click or key
+    // press has reached it for `idle` seconds. Alerts once per idle stretch.
+    // Fed by a poll of every process, so apps nobody clicks in are seen too.
+    IdleTraffic {
+        bytes_out: u64,
+        #[serde(default = "default_idle")]
+        idle: f64,
+    },
+    // A connection after a click goes to a host that matches none of the
+    // patterns ("api.example.com", "*.apple.com", "17.*"). Alerts once per
+    // app and host.
+    HostNotAllowed { allow: Vec<String> },
+    // A click on an element whose label matches `label` (case-insensitive,
+    // `*` and `?` wildcards) is followed by more than `min_bytes` bytes
+    TrafficAfterClick {
+        label: String,
+        #[serde(default)]
+        min_bytes: u64,
+    },
+}
+
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let rule_set: RuleSet = serde_yaml::from_str(&contents).map_err(|e| format!("Invalid rules {}: {}", path.display(), e))?;
+    if rule_set.rules.is_empty() {
+        return Err(format!("{} has no rules", path.display()));
+    }
+    if let Some(rule) = rule_set.rules.iter().find(|rule| matches!(rule.condition, Condition::IdleTraffic { idle, .. } if !idle.is_finite() || idle < 0.0)) {
+        return Err(format!("Rule '{}': idle must be a number of seconds", rule.name));
+    }
+    Ok(rule_set.rules)
+}
+
+// Shell-style match of `text` against `pattern`, ignoring case: `*` is any
+// run of characters, `?` exactly one
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was and how much of the text it covers so far
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Local traffic and nettop's "*" for unconnected sockets are never reported
+fn is_local(host: &str) -> bool {
+    host.is_empty()
+        || host == "*"
+        || host.split('%').next().and_then(|ip| ip.parse::<IpAddr>().ok()).is_some_and(|ip| ip.is_loopback() || ip.is_unspecified())
+}
+
+// Sent bytes a process accumulated since it last went idle
+#[derive(Default)]
+struct IdleState {
+    bytes_out: u64,
+    alerted: bool,
+}
+
+// Evaluates rules against the event stream. Only the events themselves are
+// consulted, so feeding the same events gives the same alerts.
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+    // Last click or key press per PID
+    last_input: HashMap<i32, DateTime<Local>>,
+    // Keyed by rule index and PID
+    idle: HashMap<(usize, i32), IdleState>,
+    // Rule index, app and host already reported
+    reported_hosts: HashSet<(usize, String, String)>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        RuleEngine { rules, last_input: HashMap::new(), idle: HashMap::new(), reported_hosts: HashSet::new() }
+    }
+
+    pub fn evaluate(&mut self, event: &WatcherEvent) -> Vec<Alert> {
+        match event {
+            WatcherEvent::Click(click) => {
+                self.note_input(click.pid, click.timestamp);
+                Vec::new()
+            }
+            WatcherEvent::Key(key) => {
+                self.note_input(key.pid, key.timestamp);
+                Vec::new()
+            }
+            // Samples taken at a click only cover the clicked app; idle
+            // traffic comes from check_idle_samples instead
+            WatcherEvent::Correlation(correlation) => self.check_correlation(correlation),
+            _ => Vec::new(),
+        }
+    }
+
+    fn note_input(&mut self, pid: i32, timestamp: DateTime<Local>) {
+        self.last_input.insert(pid, timestamp);
+        self.idle.retain(|(_, idle_pid), _| *idle_pid != pid);
+    }
+
+    // Check one reading of the all-process poll against the idle_traffic rules
+    fn check_sample(&mut self, sample: &NetworkSample) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let Condition::IdleTraffic { bytes_out, idle } = rule.condition else { continue };
+            if rule.app.as_ref().is_some_and(|app| !app_matches(&sample.process, app)) {
+                continue;
+            }
+            let idle_for = ChronoDuration::milliseconds((idle * 1000.0) as i64);
+            if self.last_input.get(&sample.pid).is_some_and(|input| sample.timestamp - *input < idle_for) {
+                continue;
+            }
+            let state = self.idle.entry((index, sample.pid)).or_default();
+            state.bytes_out += sample.delta_out;
+            if state.alerted || state.bytes_out <= bytes_out {
+                continue;
+            }
+            state.alerted = true;
+            alerts.push(Alert {
+                timestamp: sample.timestamp,
+                rule: rule.name.clone(),
+                severity: rule.severity,
+                pid: Some(sample.pid),
+                app_name: Some(sample.process.clone()),
+                message: format!("{} sent {} B with no UI action in the last {}s", sample.process, state.bytes_out, idle),
+            });
+        }
+        alerts
+    }
+
+    fn check_correlation(&mut self, correlation: &Correlation) -> Vec<Alert> {
+        let click = &correlation.click;
+        let traffic = &correlation.traffic;
+        let mut alerts = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.app.as_ref().is_some_and(|app| !app_matches(&click.app_name, app)) {
+                continue;
+            }
+            match &rule.condition {
+                Condition::HostNotAllowed { allow } => {
+                    // Addresses and the names seen for them; either may be allowed
+                    let mut hosts: Vec<(String, Vec<&str>)> = Vec::new();
+                    for connection in &traffic.connections {
+                        let names = [Some(connection.remote_host.as_str()), connection.hostname.as_deref()];
+                        hosts.push((connection.display_host().to_string(), names.into_iter().flatten().collect()));
+                    }
+                    for flow in &traffic.flows {
+                        let address = flow.remote.rsplit_once(':').map_or(flow.remote.as_str(), |(ip, _)| ip.trim_matches(['[', ']']));
+                        let name = flow.details.server_name().or(flow.hostname.as_deref());
+                        let names = [Some(address), name];
+                        hosts.push((name.unwrap_or(address).to_string(), names.into_iter().flatten().collect()));
+                    }
+                    for (host, names) in hosts {
+                        if names.iter().any(|name| is_local(name) || allow.iter().any(|pattern| glob_matches(pattern, name))) {
+                            continue;
+                        }
+                        if !self.reported_hosts.insert((index, click.app_name.clone(), host.clone())) {
+                            continue;
+                        }
+                        alerts.push(alert(rule, click, correlation.timestamp, format!("{} connected to {}, which is not on the allowlist", app_label(click), host)));
+                    }
+                }
+                Condition::TrafficAfterClick { label, min_bytes } => {
+                    let Some(clicked) = click.selector.label.as_deref().filter(|clicked| glob_matches(label, clicked)) else { continue };
+                    let bytes = traffic.bytes_in + traffic.bytes_out;
+                    if bytes <= *min_bytes {
+                        continue;
+                    }
+                    let remotes = traffic.remote_names();
+                    alerts.push(alert(
+                        rule,
+                        click,
+                        correlation.timestamp,
+                        format!(
+                            "Click on '{}' in {} was followed by ↑ {} B ↓ {} B{}",
+                            clicked,
+                            app_label(click),
+                            traffic.bytes_out,
+                            traffic.bytes_in,
+                            if remotes.is_empty() { String::new() } else { format!(" to {}", remotes.join(", ")) }
+                        ),
+                    ));
+                }
+                Condition::IdleTraffic { .. } => {}
+            }
+        }
+        alerts
+    }
+}
+
+fn app_label(click: &ClickEvent) -> String {
+    format!("'{}' (PID={})", click.app_name, click.pid)
+}
+
+fn alert(rule: &Rule, click: &ClickEvent, timestamp: DateTime<Local>, message: String) -> Alert {
+    Alert {
+        timestamp,
+        rule: rule.name.clone(),
+        severity: rule.severity,
+        pid: Some(click.pid),
+        app_name: Some(click.app_name.clone()),
+        message,
+    }
+}
+
+static ENGINE: Mutex<Option<RuleEngine>> = Mutex::new(None);
+
+// Evaluate `rules` against every event from now on, and start polling every
+// process if one of them looks for idle traffic
+pub fn install(rules: Vec<Rule>) {
+    let polls = rules.iter().any(|rule| matches!(rule.condition, Condition::IdleTraffic { .. }));
+    *ENGINE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(RuleEngine::new(rules));
+    if polls {
+        thread::spawn(poll_idle_traffic);
+    }
+}
+
+// Alerts raised by `event`, already logged. Empty when no rules are installed.
+pub fn check(event: &WatcherEvent) -> Vec<Alert> {
+    let alerts = {
+        let mut engine = ENGINE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match engine.as_mut() {
+            Some(engine) => engine.evaluate(event),
+            None => return Vec::new(),
+        }
+    };
+    log_alerts(&alerts);
+    alerts
+}
+
+// Alerts raised by one all-process reading, already logged
+fn check_idle_samples(samples: &[NetworkSample]) -> Vec<Alert> {
+    let alerts: Vec<Alert> = {
+        let mut engine = ENGINE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match engine.as_mut() {
+            Some(engine) => samples.iter().flat_map(|sample| engine.check_sample(sample)).collect(),
+            None => return Vec::new(),
+        }
+    };
+    log_alerts(&alerts);
+    alerts
+}
+
+// What each process sent and received since the previous reading. The
+// first reading of a process is only its baseline.
+fn idle_samples(history: &mut TrafficHistory, now: Instant, timestamp: DateTime<Local>, rows: &[NettopRow]) -> Vec<NetworkSample> {
+    rows.iter()
+        .filter_map(|row| {
+            let recorded = history.record(&row.process, now, row.bytes_in, row.bytes_out);
+            (recorded.delta_in > 0 || recorded.delta_out > 0).then(|| NetworkSample {
+                timestamp,
+                pid: row.pid,
+                process: row.process.clone(),
+                bytes_in: row.bytes_in,
+                bytes_out: row.bytes_out,
+                delta_in: recorded.delta_in,
+                delta_out: recorded.delta_out,
+                rates: None,
+            })
+        })
+        .collect()
+}
+
+fn poll_idle_traffic() {
+    let mut history = TrafficHistory::default();
+    while !shutdown::is_stopping() {
+        let rows = sample_nettop_rows();
+        let samples = idle_samples(&mut history, Instant::now(), Local::now(), &rows);
+        if !events::is_paused() {
+            for alert in check_idle_samples(&samples) {
+                events::emit(&WatcherEvent::Alert(alert));
+            }
+        }
+        thread::sleep(IDLE_POLL_INTERVAL);
+    }
+}
+
+fn log_alerts(alerts: &[Alert]) {
+    for alert in alerts {
+        match alert.severity {
+            Severity::Info => log::info!("🚨 [{}] {}: {}", alert.severity, alert.rule, alert.message),
+            Severity::Warning | Severity::Critical => log::warn!("🚨 [{}] {}: {}", alert.severity, alert.rule, alert.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::correlation::{ConnectionDelta, FlowTiming, WindowTraffic};
+    use crate::dissect::FlowDetails;
+    use crate::events::tests::{at, click, correlation, key, sample};
+
+    // The example from the README
+    const RULES: &str = r#"
+rules:
+  - name: background-upload
+    when: idle_traffic          # sends more than bytes_out while idle
+    app: Dropbox                # optional: only this app
+    bytes_out: 1000000
+    idle: 5                     # seconds without a click or key press (default 5)
+  - name: unknown-host
+    severity: critical          # info, warning (default) or critical
+    when: host_not_allowed      # a connection after a click goes elsewhere
+    allow: ["*.apple.com", "api.example.com", "17.*"]
+  - name: cancel-still-sends
+    when: traffic_after_click   # the click's correlation window saw traffic
+    label: "cancel*"            # case-insensitive, * and ? wildcards
+    min_bytes: 0                # more than this many bytes (default 0)
+"#;
+
+    fn rules(yaml: &str) -> Vec<Rule> {
+        serde_yaml::from_str::<RuleSet>(yaml).unwrap().rules
+    }
+
+    fn load(name: &str, yaml: &str) -> Result<Vec<Rule>, String> {
+        let path = std::env::temp_dir().join(format!("macos-watcher-rules-{}-{}.yaml", std::process::id(), name));
+        fs::write(&path, yaml).unwrap();
+        let loaded = load_rules(&path);
+        fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    fn connection(remote_host: &str, hostname: Option<&str>) -> ConnectionDelta {
+        ConnectionDelta {
+            protocol: "tcp4".to_string(),
+            remote_host: remote_host.to_string(),
+            remote_port: Some(443),
+            hostname: hostname.map(str::to_string),
+            bytes_in: 10,
+            bytes_out: 5,
+            is_new: true,
+        }
+    }
+
+    fn flow(remote: &str, server_name: Option<&str>) -> FlowTiming {
+        FlowTiming {
+            protocol: "tcp".to_string(),
+            local: "10.0.0.5:52000".to_string(),
+            remote: remote.to_string(),
+            hostname: None,
+            pid: Some(42),
+            first_byte_ms: 10,
+            first_response_ms: None,
+            last_byte_ms: 20,
+            bytes_in: 10,
+            bytes_out: 5,
+            details: FlowDetails { tls_server_name: server_name.map(str::to_string), ..FlowDetails::default() },
+        }
+    }
+
+    fn messages(alerts: Vec<Alert>) -> Vec<String> {
+        alerts.into_iter().map(|alert| format!("{} {} {}", alert.rule, alert.severity, alert.message)).collect()
+    }
+
+    #[test]
+    fn the_readme_example_loads() {
+        let loaded = load("readme", RULES).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert!(matches!(loaded[0].condition, Condition::IdleTraffic { bytes_out: 1_000_000, idle } if idle == 5.0));
+        assert_eq!((loaded[0].app.as_deref(), loaded[0].severity), (Some("Dropbox"), Severity::Warning));
+        assert!(matches!(&loaded[1].condition, Condition::HostNotAllowed { allow } if allow.len() == 3));
+        assert_eq!(loaded[1].severity, Severity::Critical);
+        assert!(matches!(&loaded[2].condition, Condition::TrafficAfterClick { label, min_bytes: 0 } if label == "cancel*"));
+
+        let defaults = rules("rules:\n  - name: idle\n    when: idle_traffic\n    bytes_out: 10\n");
+        assert!(matches!(defaults[0].condition, Condition::IdleTraffic { idle, .. } if idle == default_idle()));
+    }
+
+    #[test]
+    fn bad_rule_files_are_rejected() {
+        assert!(load("empty", "rules: []\n").unwrap_err().ends_with("empty.yaml has no rules"));
+        let errors = [
+            ("negative", "rules:\n  - name: idle\n    when: idle_traffic\n    bytes_out: 10\n    idle: -1\n"),
+            ("unknown", "rules:\n  - name: odd\n    when: full_moon\n"),
+            ("missing", "rules:\n  - name: hosts\n    when: host_not_allowed\n"),
+            ("severity", "rules:\n  - name: hosts\n    severity: loud\n    when: host_not_allowed\n    allow: []\n"),
+            ("yaml", "rules: [\n"),
+        ];
+        for (name, yaml) in errors {
+            assert!(load(name, yaml).is_err(), "{}", name);
+        }
+        assert_eq!(load("negative", errors[0].1).unwrap_err(), "Rule 'idle': idle must be a number of seconds");
+        assert!(load_rules(Path::new("/nonexistent/rules.yaml")).unwrap_err().starts_with("Failed to read"));
+    }
+
+    #[test]
+    fn globs_match_like_a_shell_ignoring_case() {
+        let matching = [("*.apple.com", "gs.Apple.com"), ("17.*", "17.253.1.2"), ("cancel*", "Cancel"), ("c?ncel", "cancel"), ("*", ""), ("a*b*c", "aXbYbZc"), ("**x", "x")];
+        for (pattern, text) in matching {
+            assert!(glob_matches(pattern, text), "{} {}", pattern, text);
+        }
+        for (pattern, text) in [("*.apple.com", "apple.com"), ("17.*", "117.0.0.1"), ("c?ncel", "cncel"), ("a*b", "aXbY"), ("", "x")] {
+            assert!(!glob_matches(pattern, text), "{} {}", pattern, text);
+        }
+    }
+
+    // Feed a sample of the all-process poll to the engine
+    fn poll(engine: &mut RuleEngine, event: WatcherEvent) -> Vec<Alert> {
+        let WatcherEvent::NetworkSample(sample) = event else { unreachable!() };
+        engine.check_sample(&sample)
+    }
+
+    fn row(process: &str, bytes_out: u64) -> NettopRow {
+        let (name, pid) = process.rsplit_once('.').unwrap();
+        NettopRow { process: process.to_string(), name: name.to_string(), pid: pid.parse().unwrap(), bytes_in: 0, bytes_out }
+    }
+
+    #[test]
+    fn idle_traffic_alerts_once_per_idle_stretch() {
+        let mut engine = RuleEngine::new(rules(RULES));
+        assert!(poll(&mut engine, sample(0, "Dropbox.42", 42, 0, 600_000)).is_empty());
+        assert_eq!(
+            messages(poll(&mut engine, sample(1000, "Dropbox.42", 42, 0, 600_000))),
+            ["background-upload warning Dropbox.42 sent 1200000 B with no UI action in the last 5s"]
+        );
+        assert!(poll(&mut engine, sample(2000, "Dropbox.42", 42, 0, 2_000_000)).is_empty());
+        // Other apps never match the rule
+        assert!(poll(&mut engine, sample(2000, "Backup.43", 43, 0, 5_000_000)).is_empty());
+
+        // Input starts a new stretch; nothing counts until it has been idle again
+        assert!(engine.evaluate(&key(3000, 42, "Dropbox", 36)).is_empty());
+        assert!(poll(&mut engine, sample(4000, "Dropbox.42", 42, 0, 5_000_000)).is_empty());
+        assert!(poll(&mut engine, sample(8000, "Dropbox.42", 42, 0, 900_000)).is_empty());
+        assert_eq!(poll(&mut engine, sample(9000, "Dropbox.42", 42, 0, 200_000)).len(), 1);
+
+        // Samples taken at a click are left to the poll, so nothing is counted twice
+        assert!(engine.evaluate(&sample(20_000, "Dropbox.42", 42, 0, 5_000_000)).is_empty());
+    }
+
+    #[test]
+    fn the_poll_finds_idle_traffic_of_apps_nobody_clicks_in() {
+        let mut engine = RuleEngine::new(rules("rules:\n  - name: idle-upload\n    when: idle_traffic\n    bytes_out: 1000\n    idle: 5\n"));
+        let mut history = TrafficHistory::default();
+        let start = Instant::now();
+        let mut alerts = Vec::new();
+        // Only Mail gets clicks; the daemon uploads in the background throughout
+        engine.evaluate(&WatcherEvent::Click(Box::new(click(0, 812, "Mail", "Send"))));
+        for (second, daemon_out, mail_out) in [(0, 10_000, 0), (2, 10_600, 5000), (4, 11_200, 9000), (6, 11_800, 9000)] {
+            let samples = idle_samples(&mut history, start + Duration::from_secs(second), at(second as i64 * 1000), &[row("syncd.50", daemon_out), row("Mail.812", mail_out)]);
+            alerts.extend(samples.iter().flat_map(|sample| engine.check_sample(sample)));
+        }
+        // The first reading is the baseline: 600 B, then 1200 B, then 1800 B sent
+        assert_eq!(messages(alerts), ["idle-upload warning syncd.50 sent 1200 B with no UI action in the last 5s"]);
+
+        let samples = idle_samples(&mut history, start + Duration::from_secs(8), at(8000), &[row("syncd.50", 11_800)]);
+        assert!(samples.is_empty());
+    }
+
+    #[test]
+    fn hosts_off_the_allowlist_alert_once_per_app() {
+        let mut engine = RuleEngine::new(rules(RULES));
+        let traffic = WindowTraffic {
+            pid: 42,
+            connections: vec![
+                connection("17.253.1.2", None),
+                connection("203.0.113.9", Some("gs.apple.com")),
+                connection("127.0.0.1", None),
+                connection("*", None),
+                connection("198.51.100.7", Some("tracker.example.net")),
+            ],
+            flows: vec![flow("[2001:db8::1]:443", Some("api.example.com")), flow("192.0.2.1:443", None), flow("[::1]:8080", None)],
+            ..WindowTraffic::default()
+        };
+        let event = correlation(click(0, 42, "Mail", "Send"), 2000, traffic);
+        assert_eq!(
+            messages(engine.evaluate(&event)),
+            [
+                "unknown-host critical 'Mail' (PID=42) connected to tracker.example.net, which is not on the allowlist",
+                "unknown-host critical 'Mail' (PID=42) connected to 192.0.2.1, which is not on the allowlist",
+            ]
+        );
+        assert!(engine.evaluate(&event).is_empty());
+        let WatcherEvent::Correlation(mut other_app) = event else { unreachable!() };
+        other_app.click.app_name = "Safari".to_string();
+        assert_eq!(engine.evaluate(&WatcherEvent::Correlation(other_app)).len(), 2);
+    }
+
+    #[test]
+    fn clicks_matching_the_label_alert_when_traffic_follows() {
+        let yaml = "rules:\n  - name: cancel\n    severity: info\n    when: traffic_after_click\n    label: \"cancel*\"\n    min_bytes: 100\n";
+        let mut engine = RuleEngine::new(rules(yaml));
+        let traffic = |bytes_out| WindowTraffic { pid: 42, bytes_out, connections: vec![connection("192.0.2.1", Some("api.example.com"))], ..WindowTraffic::default() };
+
+        assert_eq!(
+            messages(engine.evaluate(&correlation(click(0, 42, "Mail", "Cancel Upload"), 2000, traffic(150)))),
+            ["cancel info Click on 'Cancel Upload' in 'Mail' (PID=42) was followed by ↑ 150 B ↓ 0 B to api.example.com"]
+        );
+        assert!(engine.evaluate(&correlation(click(0, 42, "Mail", "Cancel"), 2000, traffic(100))).is_empty());
+        assert!(engine.evaluate(&correlation(click(0, 42, "Mail", "Send"), 2000, traffic(5000))).is_empty());
+        // Clicks and samples alone never alert for this rule
+        assert!(engine.evaluate(&WatcherEvent::Click(Box::new(click(0, 42, "Mail", "Cancel")))).is_empty());
+        assert!(engine.evaluate(&sample(0, "Mail.42", 42, 0, 5000)).is_empty());
+    }
+
+    #[test]
+    fn installed_rules_are_checked_for_every_event() {
+        install(rules("rules:\n  - name: any-host\n    app: RulesTestApp\n    when: host_not_allowed\n    allow: []\n"));
+        let traffic = WindowTraffic { pid: 7, connections: vec![connection("192.0.2.1", None)], ..WindowTraffic::default() };
+        let alerts = check(&correlation(click(0, 7, "/Applications/RulesTestApp.app/Contents/MacOS/RulesTestApp", "Go"), 1000, traffic));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!((alerts[0].pid, alerts[0].severity), (Some(7), Severity::Warning));
+        assert!(check(&key(0, 7, "RulesTestApp", 1)).is_empty());
+    }
+}
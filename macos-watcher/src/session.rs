@@ -0,0 +1,29 @@
+use chrono::{DateTime, Local};
+use std::sync::OnceLock;
+
+// Identity of this watcher run, shared by every sink that persists events
+struct Session {
+    id: String,
+    started_at: DateTime<Local>,
+}
+
+static SESSION: OnceLock<Session> = OnceLock::new();
+
+fn session() -> &'static Session {
+    SESSION.get_or_init(|| {
+        let started_at = Local::now();
+        Session {
+            // Sortable by start time, unique per process
+            id: format!("{}-{}", started_at.format("%Y%m%dT%H%M%S"), std::process::id()),
+            started_at,
+        }
+    })
+}
+
+pub fn session_id() -> &'static str {
+    &session().id
+}
+
+pub fn started_at() -> DateTime<Local> {
+    session().started_at
+}
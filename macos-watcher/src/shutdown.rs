@@ -0,0 +1,40 @@
+#[cfg(target_os = "macos")]
+use core_foundation::runloop::{CFRunLoopGetMain, CFRunLoopStop};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+// Ask the main run loop to return so run_watcher can clean up and write the
+// session summary. Safe to call from any thread, any number of times.
+pub fn request_stop() {
+    STOPPING.store(true, Ordering::SeqCst);
+    #[cfg(target_os = "macos")]
+    unsafe { CFRunLoopStop(CFRunLoopGetMain()) };
+}
+
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+// Turn SIGINT/SIGTERM into a graceful stop. The signals are handled on a
+// normal thread, not in the signal handler, so stopping the run loop is safe.
+// A second signal exits immediately in case shutdown hangs.
+pub fn install_signal_handlers() -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if is_stopping() {
+                eprintln!("Received signal {} again, exiting immediately.", signal);
+                process::exit(130);
+            }
+            log::info!("Received signal {}, shutting down...", signal);
+            request_stop();
+        }
+    });
+    Ok(())
+}
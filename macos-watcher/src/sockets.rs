@@ -0,0 +1,200 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::process::Command;
+
+use crate::metrics::{self, Timer};
+use crate::pcap::Transport;
+
+// One open socket as listed by lsof
+#[derive(Clone, Debug, PartialEq)]
+pub struct SocketEntry {
+    pub pid: i32,
+    pub command: String,
+    pub transport: Transport,
+    pub local: SocketAddr,
+    // None for listening and unconnected sockets
+    pub remote: Option<SocketAddr>,
+}
+
+// Parse `lsof -nP -iTCP -iUDP -F pcPn` output. Each process starts with a
+// "p<pid>" line followed by "c<command>"; every socket then has a "P<protocol>"
+// and an "n<local>-><remote>" line.
+pub fn parse_lsof(output: &str) -> Vec<SocketEntry> {
+    let mut entries = Vec::new();
+    let mut pid = None;
+    let mut command = String::new();
+    let mut transport = None;
+
+    for line in output.lines() {
+        let Some(field) = line.chars().next() else { continue };
+        let value = &line[field.len_utf8()..];
+        match field {
+            'p' => {
+                pid = value.parse().ok();
+                command.clear();
+            }
+            'c' => command = value.to_string(),
+            'f' => transport = None,
+            'P' => {
+                transport = match value {
+                    "TCP" => Some(Transport::Tcp),
+                    "UDP" => Some(Transport::Udp),
+                    _ => None,
+                }
+            }
+            'n' => {
+                let (Some(pid), Some(transport)) = (pid, transport) else { continue };
+                let (local, remote) = match value.split_once("->") {
+                    Some((local, remote)) => (local, Some(remote)),
+                    None => (value, None),
+                };
+                let Some(local) = parse_endpoint(local) else { continue };
+                entries.push(SocketEntry {
+                    pid,
+                    command: command.clone(),
+                    transport,
+                    local,
+                    remote: remote.and_then(parse_endpoint),
+                });
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+// "10.0.0.5:52345", "[2001:db8::5]:443", "[fe80::1%en0]:5353" or "*:5353"
+fn parse_endpoint(endpoint: &str) -> Option<SocketAddr> {
+    let (host, port) = endpoint.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let ip = match host {
+        "*" => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        _ => host.split('%').next()?.parse().ok()?,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// Which process owns which socket, at one point in time
+#[derive(Clone, Debug, Default)]
+pub struct SocketTable {
+    entries: Vec<SocketEntry>,
+}
+
+impl SocketTable {
+    pub fn new(entries: Vec<SocketEntry>) -> Self {
+        SocketTable { entries }
+    }
+
+    // The socket of a flow: the connected socket if there is one, otherwise an
+    // unconnected (UDP) socket bound to the local port, on the address or on any.
+    // Sockets shared by several processes belong to the first one listed.
+    pub fn lookup(&self, transport: Transport, local: SocketAddr, remote: SocketAddr) -> Option<&SocketEntry> {
+        let same_transport = || self.entries.iter().filter(move |entry| entry.transport == transport);
+        same_transport()
+            .find(|entry| entry.local == local && entry.remote == Some(remote))
+            .or_else(|| same_transport().find(|entry| entry.remote.is_none() && entry.local == local))
+            .or_else(|| {
+                same_transport().find(|entry| entry.remote.is_none() && entry.local.port() == local.port() && entry.local.ip().is_unspecified())
+            })
+    }
+}
+
+// Current sockets of every process
+pub fn snapshot() -> SocketTable {
+    let _timer = Timer::start(metrics::COMMAND_DURATION, &[("command", "lsof")]);
+    match Command::new("lsof").args(["-nP", "-iTCP", "-iUDP", "-F", "pcPn"]).output() {
+        // lsof exits with 1 when some process could not be inspected
+        Ok(output) => SocketTable::new(parse_lsof(&String::from_utf8_lossy(&output.stdout))),
+        Err(e) => {
+            log::error!("Failed to run lsof: {}", e);
+            SocketTable::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `lsof -nP -iTCP -iUDP -F pcPn`, with a few lines it should never print
+    const LSOF: &str = "\
+p412
+cSafari
+f23
+PTCP
+n10.0.0.5:52345->93.184.216.34:443
+f24
+PUDP
+n*:5353
+f25
+PTCP
+nnot-an-endpoint
+p88
+cmDNSResponder
+f5
+PUDP
+n[fe80::1%en0]:5353
+f6
+PTCP
+n[2001:db8::5]:52000->[2001:db8::1]:443
+f7
+Pgarbage
+n10.0.0.5:1
+pnope
+cbroken
+f3
+PTCP
+n10.0.0.5:2->10.0.0.1:80
+";
+
+    fn endpoint(endpoint: &str) -> SocketAddr {
+        endpoint.parse().unwrap()
+    }
+
+    #[test]
+    fn lsof_output_lists_every_socket() {
+        let entries = parse_lsof(LSOF);
+        let summary: Vec<(i32, &str, Transport, SocketAddr, Option<SocketAddr>)> =
+            entries.iter().map(|entry| (entry.pid, entry.command.as_str(), entry.transport, entry.local, entry.remote)).collect();
+        assert_eq!(
+            summary,
+            [
+                (412, "Safari", Transport::Tcp, endpoint("10.0.0.5:52345"), Some(endpoint("93.184.216.34:443"))),
+                (412, "Safari", Transport::Udp, endpoint("0.0.0.0:5353"), None),
+                (88, "mDNSResponder", Transport::Udp, endpoint("[fe80::1]:5353"), None),
+                (88, "mDNSResponder", Transport::Tcp, endpoint("[2001:db8::5]:52000"), Some(endpoint("[2001:db8::1]:443"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_endpoints_are_skipped() {
+        for bad in ["", "10.0.0.5", "10.0.0.5:port", "10.0.0.5:70000", "[::1", "host.local:80", "*:*"] {
+            assert_eq!(parse_endpoint(bad), None, "{}", bad);
+        }
+        assert_eq!(parse_lsof("n10.0.0.5:1\nPTCP\n\u{e9}\n"), []);
+    }
+
+    #[test]
+    fn lookups_prefer_connected_then_bound_then_wildcard_sockets() {
+        let socket = |pid, local: &str, remote: Option<&str>| SocketEntry {
+            pid,
+            command: format!("app{}", pid),
+            transport: Transport::Udp,
+            local: endpoint(local),
+            remote: remote.map(endpoint),
+        };
+        let table = SocketTable::new(vec![
+            socket(1, "0.0.0.0:5353", None),
+            socket(2, "10.0.0.5:5353", None),
+            socket(3, "10.0.0.5:5353", Some("10.0.0.1:5353")),
+            socket(4, "0.0.0.0:5353", None),
+        ]);
+        let pid = |local: &str, remote: &str| table.lookup(Transport::Udp, endpoint(local), endpoint(remote)).map(|entry| entry.pid);
+        assert_eq!(pid("10.0.0.5:5353", "10.0.0.1:5353"), Some(3));
+        assert_eq!(pid("10.0.0.5:5353", "224.0.0.251:5353"), Some(2));
+        assert_eq!(pid("192.168.1.9:5353", "224.0.0.251:5353"), Some(1));
+        assert_eq!(pid("10.0.0.5:5354", "224.0.0.251:5353"), None);
+        assert_eq!(table.lookup(Transport::Tcp, endpoint("10.0.0.5:5353"), endpoint("10.0.0.1:5353")), None);
+    }
+}
@@ -0,0 +1,669 @@
+use chrono::{DateTime, Local};
+use rusqlite::types::ValueRef;
+use rusqlite::{named_params, params, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::correlation::{ConnectionDelta, FlowTiming};
+use crate::events::{Alert, AxSelector, Checkpoint, ClickEvent, Correlation, EventSink, KeyEvent, NetworkSample, WatcherEvent};
+use crate::session;
+
+// Schema migrations, applied in order. PRAGMA user_version records how many
+// have run, so new entries must only ever be appended.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        started_at TEXT NOT NULL,
+        ended_at TEXT,
+        version TEXT NOT NULL
+    );
+    CREATE TABLE processes (
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        pid INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        first_seen TEXT NOT NULL,
+        last_seen TEXT NOT NULL,
+        PRIMARY KEY (session_id, pid)
+    );
+    CREATE TABLE ui_events (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        timestamp TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        pid INTEGER NOT NULL,
+        app_name TEXT NOT NULL,
+        identifier TEXT,
+        role TEXT,
+        label TEXT,
+        label_source TEXT,
+        x REAL,
+        y REAL,
+        window_title TEXT,
+        keycode INTEGER,
+        flags INTEGER
+    );
+    CREATE INDEX ui_events_session ON ui_events (session_id, timestamp);
+    CREATE TABLE network_samples (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        timestamp TEXT NOT NULL,
+        pid INTEGER NOT NULL,
+        process TEXT NOT NULL,
+        bytes_in INTEGER NOT NULL,
+        bytes_out INTEGER NOT NULL,
+        delta_in INTEGER NOT NULL,
+        delta_out INTEGER NOT NULL
+    );
+    CREATE INDEX network_samples_session ON network_samples (session_id, timestamp);
+    CREATE TABLE correlations (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        ui_event_id INTEGER REFERENCES ui_events(id),
+        timestamp TEXT NOT NULL,
+        pid INTEGER NOT NULL,
+        window_ms INTEGER NOT NULL,
+        bytes_in INTEGER NOT NULL,
+        bytes_out INTEGER NOT NULL,
+        new_connections INTEGER NOT NULL,
+        connections TEXT NOT NULL
+    );",
+    "CREATE TABLE checkpoints (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        timestamp TEXT NOT NULL,
+        label TEXT NOT NULL
+    );",
+    "ALTER TABLE correlations ADD COLUMN flows TEXT NOT NULL DEFAULT '[]';",
+    "ALTER TABLE ui_events ADD COLUMN ax_lookup_ms REAL;",
+    "CREATE TABLE alerts (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        timestamp TEXT NOT NULL,
+        rule TEXT NOT NULL,
+        severity TEXT NOT NULL,
+        pid INTEGER,
+        app_name TEXT,
+        message TEXT NOT NULL
+    );",
+    // `name` follows whatever last reported the pid (an executable path from
+    // ps, or nettop's "<name>.<pid>"); app_name is set once and grouped on
+    "ALTER TABLE processes ADD COLUMN app_name TEXT;",
+];
+
+// SQLite-backed history of watcher sessions
+pub struct EventStore {
+    connection: Connection,
+    session_id: String,
+}
+
+impl EventStore {
+    // Open (or create) the database and bring its schema up to date
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        Self::from_connection(connection)
+    }
+
+    pub fn from_connection(mut connection: Connection) -> rusqlite::Result<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection)?;
+        Ok(EventStore { connection, session_id: session::session_id().to_string() })
+    }
+
+    // Register the current watcher run; events are recorded against it
+    pub fn start_session(&self) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT OR IGNORE INTO sessions (id, started_at, version) VALUES (?1, ?2, ?3)",
+            params![self.session_id, session::started_at().to_rfc3339(), env!("CARGO_PKG_VERSION")],
+        )?;
+        Ok(())
+    }
+
+    // Record when the current watcher run ended
+    pub fn end_session(&self) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "UPDATE sessions SET ended_at = ?2 WHERE id = ?1",
+            params![self.session_id, chrono::Local::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_event(&self, event: &WatcherEvent) -> rusqlite::Result<()> {
+        match event {
+            WatcherEvent::Click(click) => self.insert_click(click),
+            WatcherEvent::Key(key) => self.insert_key(key),
+            WatcherEvent::NetworkSample(sample) => self.insert_sample(sample),
+            WatcherEvent::Correlation(correlation) => self.insert_correlation(correlation),
+            WatcherEvent::Checkpoint(checkpoint) => self.insert_checkpoint(checkpoint),
+            WatcherEvent::Alert(alert) => self.insert_alert(alert),
+            // The sessions table records start and end; the tap count is in the summary
+            WatcherEvent::TapDisabled(_) | WatcherEvent::Startup(_) | WatcherEvent::Shutdown(_) => Ok(()),
+        }
+    }
+
+    fn touch_process(&self, pid: i32, name: &str, timestamp: &str) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO processes (session_id, pid, name, app_name, first_seen, last_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+             ON CONFLICT (session_id, pid) DO UPDATE SET name = excluded.name, last_seen = excluded.last_seen,
+                 app_name = COALESCE(processes.app_name, excluded.app_name)",
+            params![self.session_id, pid, name, stable_app_name(name), timestamp],
+        )?;
+        Ok(())
+    }
+
+    fn insert_click(&self, click: &ClickEvent) -> rusqlite::Result<()> {
+        let timestamp = click.timestamp.to_rfc3339();
+        self.touch_process(click.pid, &click.app_name, &timestamp)?;
+        self.connection.execute(
+            "INSERT INTO ui_events (session_id, timestamp, kind, pid, app_name, identifier, role, label, label_source, x, y, window_title, ax_lookup_ms)
+             VALUES (?1, ?2, 'click', ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                self.session_id,
+                timestamp,
+                click.pid,
+                click.app_name,
+                click.selector.identifier,
+                click.selector.role,
+                click.selector.label,
+                click.label_source,
+                click.position.x,
+                click.position.y,
+                click.geometry.window_title,
+                click.ax_lookup_ms,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert_key(&self, key: &KeyEvent) -> rusqlite::Result<()> {
+        let timestamp = key.timestamp.to_rfc3339();
+        self.touch_process(key.pid, &key.app_name, &timestamp)?;
+        self.connection.execute(
+            "INSERT INTO ui_events (session_id, timestamp, kind, pid, app_name, keycode, flags)
+             VALUES (?1, ?2, 'key', ?3, ?4, ?5, ?6)",
+            params![self.session_id, timestamp, key.pid, key.app_name, key.keycode, key.flags as i64],
+        )?;
+        Ok(())
+    }
+
+    fn insert_sample(&self, sample: &NetworkSample) -> rusqlite::Result<()> {
+        let timestamp = sample.timestamp.to_rfc3339();
+        self.touch_process(sample.pid, &sample.process, &timestamp)?;
+        self.connection.execute(
+            "INSERT INTO network_samples (session_id, timestamp, pid, process, bytes_in, bytes_out, delta_in, delta_out)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                self.session_id,
+                timestamp,
+                sample.pid,
+                sample.process,
+                sample.bytes_in as i64,
+                sample.bytes_out as i64,
+                sample.delta_in as i64,
+                sample.delta_out as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert_correlation(&self, correlation: &Correlation) -> rusqlite::Result<()> {
+        // The click was stored when it happened; find it by its exact timestamp
+        let ui_event_id: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT id FROM ui_events WHERE session_id = ?1 AND kind = 'click' AND timestamp = ?2 AND pid = ?3",
+                params![self.session_id, correlation.click.timestamp.to_rfc3339(), correlation.click.pid],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let connections = serde_json::to_string(&correlation.traffic.connections).unwrap_or_else(|_| "[]".to_string());
+        let flows = serde_json::to_string(&correlation.traffic.flows).unwrap_or_else(|_| "[]".to_string());
+
+        self.connection.execute(
+            "INSERT INTO correlations (session_id, ui_event_id, timestamp, pid, window_ms, bytes_in, bytes_out, new_connections, connections, flows)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                self.session_id,
+                ui_event_id,
+                correlation.timestamp.to_rfc3339(),
+                correlation.traffic.pid,
+                correlation.window_ms as i64,
+                correlation.traffic.bytes_in as i64,
+                correlation.traffic.bytes_out as i64,
+                correlation.traffic.new_connections().count() as i64,
+                connections,
+                flows,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert_checkpoint(&self, checkpoint: &Checkpoint) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO checkpoints (session_id, timestamp, label) VALUES (?1, ?2, ?3)",
+            params![self.session_id, checkpoint.timestamp.to_rfc3339(), checkpoint.label],
+        )?;
+        Ok(())
+    }
+
+    fn insert_alert(&self, alert: &Alert) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO alerts (session_id, timestamp, rule, severity, pid, app_name, message)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                self.session_id,
+                alert.timestamp.to_rfc3339(),
+                alert.rule,
+                alert.severity.to_string(),
+                alert.pid,
+                alert.app_name,
+                alert.message
+            ],
+        )?;
+        Ok(())
+    }
+
+    // --- Reading sessions back for exporters ---
+
+    // The most recently started session, if any
+    pub fn latest_session_id(&self) -> rusqlite::Result<Option<String>> {
+        self.connection
+            .query_row("SELECT id FROM sessions ORDER BY started_at DESC LIMIT 1", [], |row| row.get(0))
+            .optional()
+    }
+
+    // Every click and key press (of one session, or of all) in time order,
+    // with the correlated traffic of clicks
+    pub fn actions(&self, session_id: Option<&str>) -> rusqlite::Result<Vec<StoredAction>> {
+        let mut statement = self.connection.prepare(
+            "SELECT u.timestamp, u.kind, u.pid, u.app_name, u.identifier, u.role, u.label, u.window_title,
+                    c.window_ms, c.bytes_in, c.bytes_out, c.connections, c.flows, u.keycode, u.ax_lookup_ms
+             FROM ui_events u LEFT JOIN correlations c ON c.ui_event_id = u.id
+             WHERE (:session IS NULL OR u.session_id = :session)
+             ORDER BY u.timestamp, u.id",
+        )?;
+        let rows = statement.query_map(named_params! { ":session": session_id }, stored_action)?;
+        rows.collect()
+    }
+
+    // nettop samples (of one session, or of all) in time order
+    pub fn samples(&self, session_id: Option<&str>) -> rusqlite::Result<Vec<StoredSample>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, pid, process, delta_in, delta_out FROM network_samples
+             WHERE (:session IS NULL OR session_id = :session)
+             ORDER BY timestamp, id",
+        )?;
+        let rows = statement.query_map(named_params! { ":session": session_id }, |row| {
+            Ok(StoredSample {
+                timestamp: parse_timestamp(row, 0)?,
+                pid: row.get(1)?,
+                process: row.get(2)?,
+                delta_in: row.get::<_, i64>(3)? as u64,
+                delta_out: row.get::<_, i64>(4)? as u64,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn checkpoints(&self, session_id: Option<&str>) -> rusqlite::Result<Vec<Checkpoint>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, label FROM checkpoints
+             WHERE (:session IS NULL OR session_id = :session)
+             ORDER BY timestamp, id",
+        )?;
+        let rows = statement.query_map(named_params! { ":session": session_id }, |row| {
+            Ok(Checkpoint { timestamp: parse_timestamp(row, 0)?, label: row.get(1)? })
+        })?;
+        rows.collect()
+    }
+
+    // --- Canned reports for `macos-watcher query` ---
+
+    pub fn sessions(&self, session_id: Option<&str>, limit: usize) -> rusqlite::Result<Vec<Vec<String>>> {
+        self.report(
+            "SELECT s.id, s.started_at, COALESCE(s.ended_at, ''),
+                    (SELECT COUNT(*) FROM ui_events u WHERE u.session_id = s.id)
+             FROM sessions s WHERE (:session IS NULL OR s.id = :session)
+             ORDER BY s.started_at DESC LIMIT :limit",
+            session_id,
+            limit,
+        )
+    }
+
+    // Bytes observed per app, busiest first
+    pub fn top_talkers(&self, session_id: Option<&str>, limit: usize) -> rusqlite::Result<Vec<Vec<String>>> {
+        self.report(
+            "SELECT COALESCE(p.app_name, p.name, n.process), SUM(n.delta_out), SUM(n.delta_in), SUM(n.delta_out + n.delta_in) AS total
+             FROM network_samples n
+             LEFT JOIN processes p ON p.session_id = n.session_id AND p.pid = n.pid
+             WHERE (:session IS NULL OR n.session_id = :session)
+             GROUP BY 1 ORDER BY total DESC LIMIT :limit",
+            session_id,
+            limit,
+        )
+    }
+
+    // Clicks whose correlation window saw no traffic at all
+    pub fn silent_clicks(&self, session_id: Option<&str>, limit: usize) -> rusqlite::Result<Vec<Vec<String>>> {
+        self.report(
+            "SELECT u.timestamp, u.app_name, COALESCE(u.identifier, ''), COALESCE(u.label, ''), COALESCE(u.role, '')
+             FROM ui_events u JOIN correlations c ON c.ui_event_id = u.id
+             WHERE u.kind = 'click' AND c.bytes_in + c.bytes_out = 0 AND (:session IS NULL OR u.session_id = :session)
+             ORDER BY u.timestamp DESC LIMIT :limit",
+            session_id,
+            limit,
+        )
+    }
+
+    // Clicks and correlated bytes per button, keyed by AX identifier (or label)
+    pub fn bytes_per_button(&self, session_id: Option<&str>, limit: usize) -> rusqlite::Result<Vec<Vec<String>>> {
+        self.report(
+            "SELECT COALESCE(u.identifier, u.label, '<unknown>') AS button, COUNT(*),
+                    SUM(c.bytes_out), SUM(c.bytes_in), SUM(c.new_connections)
+             FROM ui_events u JOIN correlations c ON c.ui_event_id = u.id
+             WHERE u.kind = 'click' AND (:session IS NULL OR u.session_id = :session)
+             GROUP BY button ORDER BY SUM(c.bytes_out + c.bytes_in) DESC LIMIT :limit",
+            session_id,
+            limit,
+        )
+    }
+
+    // Alerts raised by --rules, newest first
+    pub fn alerts(&self, session_id: Option<&str>, limit: usize) -> rusqlite::Result<Vec<Vec<String>>> {
+        self.report(
+            "SELECT timestamp, severity, rule, COALESCE(app_name, ''), message
+             FROM alerts WHERE (:session IS NULL OR session_id = :session)
+             ORDER BY timestamp DESC, id DESC LIMIT :limit",
+            session_id,
+            limit,
+        )
+    }
+
+    // Run a report query (with :session and :limit parameters) and render every column as text
+    fn report(&self, sql: &str, session_id: Option<&str>, limit: usize) -> rusqlite::Result<Vec<Vec<String>>> {
+        let mut statement = self.connection.prepare(sql)?;
+        let column_count = statement.column_count();
+        let rows = statement.query_map(named_params! { ":session": session_id, ":limit": limit as i64 }, |row| {
+            (0..column_count)
+                .map(|index| {
+                    Ok(match row.get_ref(index)? {
+                        ValueRef::Null => String::new(),
+                        ValueRef::Integer(value) => value.to_string(),
+                        ValueRef::Real(value) => format!("{:.1}", value),
+                        ValueRef::Text(text) => String::from_utf8_lossy(text).to_string(),
+                        ValueRef::Blob(_) => "<blob>".to_string(),
+                    })
+                })
+                .collect()
+        })?;
+        rows.collect()
+    }
+}
+
+// A UI event read back from the store
+#[derive(Clone, Debug)]
+pub struct StoredAction {
+    pub timestamp: DateTime<Local>,
+    // "click" or "key"
+    pub kind: String,
+    pub pid: i32,
+    pub app_name: String,
+    pub selector: AxSelector,
+    pub window_title: Option<String>,
+    pub keycode: Option<i64>,
+    pub ax_lookup_ms: Option<f64>,
+    // Clicks that were correlated with their traffic
+    pub traffic: Option<StoredTraffic>,
+}
+
+impl StoredAction {
+    // Flows of the correlated traffic that started within `window` of the
+    // click (None: the window it was recorded with). Flows already busy at
+    // the click count if they moved bytes after it.
+    pub fn flows_within(&self, window: Option<Duration>) -> Vec<&FlowTiming> {
+        let Some(traffic) = &self.traffic else { return Vec::new() };
+        let window_ms = window.map_or(traffic.window_ms as i64, |window| window.as_millis() as i64);
+        traffic
+            .flows
+            .iter()
+            .filter(|flow| flow.first_byte_ms <= window_ms && flow.last_byte_ms >= 0)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StoredTraffic {
+    pub window_ms: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub connections: Vec<ConnectionDelta>,
+    pub flows: Vec<FlowTiming>,
+}
+
+// Change counters of one process from one nettop sample
+#[derive(Clone, Debug)]
+pub struct StoredSample {
+    pub timestamp: DateTime<Local>,
+    pub pid: i32,
+    pub process: String,
+    pub delta_in: u64,
+    pub delta_out: u64,
+}
+
+// The name both ps and nettop agree on: "/Applications/Foo.app/Contents/MacOS/Foo"
+// and nettop's "Foo.4242" are both "Foo"
+fn stable_app_name(name: &str) -> &str {
+    let name = crate::network::split_process_name(name).map_or(name, |(process_name, _)| process_name);
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+fn parse_timestamp(row: &Row, index: usize) -> rusqlite::Result<DateTime<Local>> {
+    let timestamp: String = row.get(index)?;
+    DateTime::parse_from_rfc3339(&timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Local))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn stored_action(row: &Row) -> rusqlite::Result<StoredAction> {
+    let timestamp = parse_timestamp(row, 0)?;
+    let window_ms: Option<i64> = row.get(8)?;
+    let traffic = match window_ms {
+        Some(window_ms) => {
+            let connections: String = row.get(11)?;
+            let flows: String = row.get(12)?;
+            Some(StoredTraffic {
+                window_ms: window_ms as u64,
+                bytes_in: row.get::<_, i64>(9)? as u64,
+                bytes_out: row.get::<_, i64>(10)? as u64,
+                // Rows written by older versions may lack fields; skip what does not parse
+                connections: serde_json::from_str(&connections).unwrap_or_default(),
+                flows: serde_json::from_str(&flows).unwrap_or_default(),
+            })
+        }
+        None => None,
+    };
+    Ok(StoredAction {
+        timestamp,
+        kind: row.get(1)?,
+        pid: row.get(2)?,
+        app_name: row.get(3)?,
+        selector: AxSelector { identifier: row.get(4)?, role: row.get(5)?, label: row.get(6)? },
+        window_title: row.get(7)?,
+        keycode: row.get(13)?,
+        ax_lookup_ms: row.get(14)?,
+        traffic,
+    })
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let applied: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+        log::debug!("Applied event store migration {}", index + 1);
+    }
+    Ok(())
+}
+
+// Inserts events on a background thread, a transaction per burst, so SQLite
+// never runs inside the event tap callback
+fn run_writer(store: EventStore, receiver: Receiver<WatcherEvent>) {
+    while let Ok(first) = receiver.recv() {
+        let burst: Vec<WatcherEvent> = std::iter::once(first).chain(receiver.try_iter()).collect();
+        let result = store.connection.unchecked_transaction().and_then(|transaction| {
+            for event in &burst {
+                if let Err(e) = store.insert_event(event) {
+                    log::error!("Failed to store event: {}", e);
+                }
+            }
+            transaction.commit()
+        });
+        if let Err(e) = result {
+            log::error!("Failed to store {} event(s): {}", burst.len(), e);
+        }
+    }
+    if let Err(e) = store.end_session() {
+        log::error!("Failed to close session in event store: {}", e);
+    }
+}
+
+// Writes every event into the store
+pub struct StoreSink {
+    // Taken on finish so the writer thread sees the channel close
+    sender: Option<Sender<WatcherEvent>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl StoreSink {
+    pub fn new(store: EventStore) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let writer = thread::spawn(move || run_writer(store, receiver));
+        StoreSink { sender: Some(sender), writer: Some(writer) }
+    }
+}
+
+impl EventSink for StoreSink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(event.clone());
+        }
+    }
+
+    fn finish(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{at, click, correlation, key, sample};
+    use std::path::PathBuf;
+
+    // A database file of its own per test, removed when dropped
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("macos-watcher-store-{}-{}.sqlite", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            TempDb(path)
+        }
+
+        fn open(&self) -> EventStore {
+            let store = EventStore::open(&self.0).unwrap();
+            store.start_session().unwrap();
+            store
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn traffic(pid: i32, bytes_in: u64, bytes_out: u64) -> crate::correlation::WindowTraffic {
+        crate::correlation::WindowTraffic { pid, bytes_in, bytes_out, connections: Vec::new(), timeline: Vec::new(), flows: Vec::new() }
+    }
+
+    #[test]
+    fn migrations_apply_once() {
+        let db = TempDb::new("migrations");
+        drop(db.open());
+        let store = db.open();
+        let version: usize = store.connection.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn correlations_attach_to_their_click() {
+        let db = TempDb::new("correlations");
+        let store = db.open();
+        let sent = click(0, 42, "/Applications/Chat.app/Contents/MacOS/Chat", "Send");
+        store.insert_event(&WatcherEvent::Click(Box::new(sent.clone()))).unwrap();
+        store.insert_event(&WatcherEvent::Click(Box::new(click(100, 42, "/Applications/Chat.app/Contents/MacOS/Chat", "Cancel")))).unwrap();
+        store.insert_event(&correlation(sent, 3000, traffic(42, 800, 200))).unwrap();
+
+        let actions = store.actions(Some(session::session_id())).unwrap();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0].selector.label.as_deref(), Some("Send"));
+        let stored = actions[0].traffic.as_ref().unwrap();
+        assert_eq!((stored.window_ms, stored.bytes_in, stored.bytes_out), (3000, 800, 200));
+        assert!(actions[1].traffic.is_none());
+
+        let silent = store.silent_clicks(None, 10).unwrap();
+        assert!(silent.is_empty());
+    }
+
+    #[test]
+    fn top_talkers_group_on_the_app_not_the_last_reported_name() {
+        let db = TempDb::new("talkers");
+        let store = db.open();
+        store.insert_event(&WatcherEvent::Click(Box::new(click(0, 42, "/Applications/Chat.app/Contents/MacOS/Chat", "Send")))).unwrap();
+        store.insert_event(&sample(1000, "Chat.42", 42, 500, 100)).unwrap();
+        // A second Chat process, only ever seen by nettop
+        store.insert_event(&sample(1000, "Chat.43", 43, 200, 0)).unwrap();
+        store.insert_event(&sample(1000, "Mail.50", 50, 10, 10)).unwrap();
+        // The next click rewrites processes.name back to the path
+        store.insert_event(&WatcherEvent::Click(Box::new(click(2000, 42, "/Applications/Chat.app/Contents/MacOS/Chat", "Send")))).unwrap();
+
+        let talkers = store.top_talkers(None, 10).unwrap();
+        assert_eq!(talkers, vec![
+            vec!["Chat".to_string(), "100".to_string(), "700".to_string(), "800".to_string()],
+            vec!["Mail".to_string(), "10".to_string(), "10".to_string(), "20".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn stable_app_names_strip_paths_and_pids() {
+        assert_eq!(stable_app_name("/Applications/Chat.app/Contents/MacOS/Chat"), "Chat");
+        assert_eq!(stable_app_name("Chat.42"), "Chat");
+        assert_eq!(stable_app_name("com.apple.WebKit.Networking.812"), "com.apple.WebKit.Networking");
+        assert_eq!(stable_app_name("Chat"), "Chat");
+    }
+
+    #[test]
+    fn sink_writes_on_its_own_thread_and_closes_the_session() {
+        let db = TempDb::new("sink");
+        let mut sink = StoreSink::new(db.open());
+        sink.handle(&WatcherEvent::Click(Box::new(click(0, 42, "Chat", "Send"))));
+        sink.handle(&key(10, 42, "Chat", 36));
+        sink.handle(&WatcherEvent::Checkpoint(Checkpoint { timestamp: at(20), label: "login".to_string() }));
+        sink.finish();
+
+        let store = EventStore::open(&db.0).unwrap();
+        let actions = store.actions(None).unwrap();
+        assert_eq!(actions.iter().map(|action| action.kind.as_str()).collect::<Vec<_>>(), ["click", "key"]);
+        assert_eq!(actions[1].keycode, Some(36));
+        assert_eq!(store.checkpoints(None).unwrap()[0].label, "login");
+        let sessions = store.sessions(None, 10).unwrap();
+        assert!(!sessions[0][2].is_empty(), "session was not closed: {:?}", sessions);
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::background::AppTraffic;
+use crate::events::{EventSink, WatcherEvent};
+use crate::session;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ByteTotals {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+// What one watcher run saw, written when it shuts down
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub started_at: DateTime<Local>,
+    pub ended_at: DateTime<Local>,
+    pub duration_secs: f64,
+    pub clicks_per_app: BTreeMap<String, u64>,
+    pub keys_per_app: BTreeMap<String, u64>,
+    // Keyed by nettop's "<name>.<pid>". Only bytes moved while the watcher
+    // sampled the process: its first sample is the baseline.
+    pub bytes_per_process: BTreeMap<String, ByteTotals>,
+    pub tap_disabled: u64,
+    // Alerts raised by --rules, keyed by rule name
+    #[serde(default)]
+    pub alerts_per_rule: BTreeMap<String, u64>,
+    // User-initiated and background traffic per app, keyed by process name
+    #[serde(default)]
+    pub traffic_per_app: BTreeMap<String, AppTraffic>,
+}
+
+#[derive(Default)]
+struct Tally {
+    clicks_per_app: BTreeMap<String, u64>,
+    keys_per_app: BTreeMap<String, u64>,
+    bytes_per_process: BTreeMap<String, ByteTotals>,
+    alerts_per_rule: BTreeMap<String, u64>,
+}
+
+// Collects the numbers for the session summary. Clone it before handing it
+// to events::add_sink; the clones share one tally.
+#[derive(Clone, Default)]
+pub struct SummarySink {
+    tally: Arc<Mutex<Tally>>,
+}
+
+impl SummarySink {
+    pub fn summary(&self, ended_at: DateTime<Local>, tap_disabled: u64) -> SessionSummary {
+        let tally = self.tally.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let started_at = session::started_at();
+        SessionSummary {
+            session_id: session::session_id().to_string(),
+            started_at,
+            ended_at,
+            duration_secs: (ended_at - started_at).num_milliseconds().max(0) as f64 / 1000.0,
+            clicks_per_app: tally.clicks_per_app.clone(),
+            keys_per_app: tally.keys_per_app.clone(),
+            bytes_per_process: tally.bytes_per_process.clone(),
+            tap_disabled,
+            alerts_per_rule: tally.alerts_per_rule.clone(),
+            traffic_per_app: BTreeMap::new(),
+        }
+    }
+}
+
+impl EventSink for SummarySink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        let mut tally = self.tally.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match event {
+            WatcherEvent::Click(click) => *tally.clicks_per_app.entry(click.app_name.clone()).or_default() += 1,
+            WatcherEvent::Key(key) => *tally.keys_per_app.entry(key.app_name.clone()).or_default() += 1,
+            WatcherEvent::NetworkSample(sample) => {
+                let totals = tally.bytes_per_process.entry(sample.process.clone()).or_default();
+                totals.bytes_in += sample.delta_in;
+                totals.bytes_out += sample.delta_out;
+            }
+            WatcherEvent::Alert(alert) => *tally.alerts_per_rule.entry(alert.rule.clone()).or_default() += 1,
+            _ => {}
+        }
+    }
+}
+
+impl SessionSummary {
+    // Human readable form printed on shutdown
+    pub fn format_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "----- Session summary ({}) -----", self.session_id);
+        let _ = writeln!(text, "Duration: {:.1}s", self.duration_secs);
+        let _ = writeln!(text, "Event tap disabled: {} time(s)", self.tap_disabled);
+
+        let _ = writeln!(text, "Clicks per app:");
+        write_counts(&mut text, &self.clicks_per_app);
+        let _ = writeln!(text, "Keys per app:");
+        write_counts(&mut text, &self.keys_per_app);
+
+        let _ = writeln!(text, "Bytes per process:");
+        if self.bytes_per_process.is_empty() {
+            let _ = writeln!(text, "  (none)");
+        }
+        for (process, totals) in &self.bytes_per_process {
+            let _ = writeln!(text, "  {} ↑ {} B ↓ {} B", process, totals.bytes_out, totals.bytes_in);
+        }
+        if !self.traffic_per_app.is_empty() {
+            let _ = writeln!(text, "Background traffic per app:");
+            let background: Vec<(&String, &AppTraffic)> =
+                self.traffic_per_app.iter().filter(|(_, traffic)| traffic.background_bursts > 0).collect();
+            if background.is_empty() {
+                let _ = writeln!(text, "  (none)");
+            }
+            for (app, traffic) in background {
+                let _ = writeln!(
+                    text,
+                    "  {} ↑ {} B ↓ {} B in {} burst(s), ↑ {:.1} ↓ {:.1} B/s over the session (user-initiated ↑ {} B ↓ {} B)",
+                    app,
+                    traffic.background.bytes_out,
+                    traffic.background.bytes_in,
+                    traffic.background_bursts,
+                    traffic.background_rate_out,
+                    traffic.background_rate_in,
+                    traffic.user.bytes_out,
+                    traffic.user.bytes_in
+                );
+            }
+        }
+        if !self.alerts_per_rule.is_empty() {
+            let _ = writeln!(text, "Alerts per rule:");
+            write_counts(&mut text, &self.alerts_per_rule);
+        }
+        text
+    }
+}
+
+fn write_counts(text: &mut String, counts: &BTreeMap<String, u64>) {
+    if counts.is_empty() {
+        let _ = writeln!(text, "  (none)");
+    }
+    for (app, count) in counts {
+        let _ = writeln!(text, "  {}: {}", app, count);
+    }
+}
+
+// Append the summary as one JSON line, so the file keeps every session
+pub fn append_json(path: &Path, summary: &SessionSummary) -> io::Result<()> {
+    let mut line = serde_json::to_vec(summary).map_err(io::Error::from)?;
+    line.push(b'\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{at, click, key};
+    use crate::events::NetworkSample;
+    use crate::traffic::TrafficHistory;
+    use std::time::{Duration, Instant};
+
+    // Feed nettop readings through a TrafficHistory the way network.rs does
+    fn readings(sink: &mut SummarySink, readings: &[(&str, u64, u64)]) {
+        let mut history = TrafficHistory::default();
+        let start = Instant::now();
+        for (index, (process, raw_in, raw_out)) in readings.iter().enumerate() {
+            let recorded = history.record(process, start + Duration::from_secs(index as u64), *raw_in, *raw_out);
+            sink.handle(&WatcherEvent::NetworkSample(NetworkSample {
+                timestamp: at(index as i64 * 1000),
+                pid: 42,
+                process: process.to_string(),
+                bytes_in: *raw_in,
+                bytes_out: *raw_out,
+                delta_in: recorded.delta_in,
+                delta_out: recorded.delta_out,
+                rates: None,
+            }));
+        }
+    }
+
+    #[test]
+    fn lifetime_counters_of_the_first_sample_are_not_counted() {
+        let mut sink = SummarySink::default();
+        readings(&mut sink, &[("Chat.42", 9_000_000, 4_000_000), ("Chat.42", 9_000_500, 4_000_100), ("Mail.50", 70_000, 1_000)]);
+
+        let summary = sink.summary(Local::now(), 0);
+        assert_eq!(summary.bytes_per_process["Chat.42"], ByteTotals { bytes_in: 500, bytes_out: 100 });
+        assert_eq!(summary.bytes_per_process["Mail.50"], ByteTotals::default());
+    }
+
+    #[test]
+    fn clones_share_one_tally() {
+        let sink = SummarySink::default();
+        let mut registered = sink.clone();
+        registered.handle(&WatcherEvent::Click(Box::new(click(0, 42, "Chat", "Send"))));
+        registered.handle(&WatcherEvent::Click(Box::new(click(10, 42, "Chat", "Send"))));
+        registered.handle(&key(20, 50, "Mail", 36));
+
+        let summary = sink.summary(Local::now(), 2);
+        assert_eq!(summary.clicks_per_app, BTreeMap::from([("Chat".to_string(), 2)]));
+        assert_eq!(summary.keys_per_app, BTreeMap::from([("Mail".to_string(), 1)]));
+        let text = summary.format_text();
+        assert!(text.contains("Event tap disabled: 2 time(s)"));
+        assert!(text.contains("Clicks per app:\n  Chat: 2\n"));
+        assert!(text.contains("Bytes per process:\n  (none)\n"));
+        assert!(!text.contains("Alerts per rule"));
+    }
+
+    #[test]
+    fn summaries_append_as_json_lines() {
+        let path = std::env::temp_dir().join(format!("macos-watcher-summary-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let summary = SummarySink::default().summary(Local::now(), 0);
+        append_json(&path, &summary).unwrap();
+        append_json(&path, &summary).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<SessionSummary> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines, vec![summary.clone(), summary]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::correlation::FlowTiming;
+use crate::events::Checkpoint;
+use crate::otel;
+use crate::store::{StoredAction, StoredSample};
+
+// Checkpoints are session-wide, so they get a track of their own
+const WATCHER_PID: i32 = 0;
+
+// Chrome Trace Event format (JSON object form), as read by chrome://tracing
+// and ui.perfetto.dev. Every app is a process track with one thread that
+// carries its input, AX lookups and nettop samples; correlation windows and
+// flows are async slices because they overlap.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trace {
+    pub trace_events: Vec<TraceEvent>,
+    pub display_time_unit: String,
+}
+
+#[derive(Serialize)]
+pub struct TraceEvent {
+    pub name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub cat: String,
+    // Phase: X (complete), i (instant), b/e (async begin/end), C (counter), M (metadata)
+    pub ph: &'static str,
+    // Microseconds since the Unix epoch
+    pub ts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dur: Option<i64>,
+    pub pid: i32,
+    pub tid: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // Scope of instant events: t(hread), p(rocess) or g(lobal)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s: Option<&'static str>,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub args: Map<String, Value>,
+}
+
+impl TraceEvent {
+    fn new(name: impl Into<String>, cat: &str, ph: &'static str, ts: i64, pid: i32) -> Self {
+        TraceEvent {
+            name: name.into(),
+            cat: cat.to_string(),
+            ph,
+            ts,
+            dur: None,
+            pid,
+            tid: pid,
+            id: None,
+            s: None,
+            args: Map::new(),
+        }
+    }
+
+    fn instant(name: impl Into<String>, cat: &str, ts: i64, pid: i32) -> Self {
+        TraceEvent { s: Some("t"), ..TraceEvent::new(name, cat, "i", ts, pid) }
+    }
+
+    fn metadata(name: &str, pid: i32, value: &str) -> Self {
+        TraceEvent::new(name, "", "M", 0, pid).with_args(json!({ "name": value }))
+    }
+
+    // Null fields (a missing label, no response) are left out
+    fn with_args(mut self, args: Value) -> Self {
+        if let Value::Object(args) = args {
+            self.args = args.into_iter().filter(|(_, value)| !value.is_null()).collect();
+        }
+        self
+    }
+}
+
+fn micros(time: DateTime<Local>) -> i64 {
+    time.timestamp_micros()
+}
+
+// An async slice: a begin and an end event sharing an id
+fn async_slice(name: &str, cat: &str, id: String, pid: i32, start: i64, end: i64, args: Value) -> [TraceEvent; 2] {
+    let begin = TraceEvent { id: Some(id.clone()), ..TraceEvent::new(name, cat, "b", start, pid) }.with_args(args);
+    let end = TraceEvent { id: Some(id), ..TraceEvent::new(name, cat, "e", end.max(start), pid) };
+    [begin, end]
+}
+
+// "api.example.com POST /v1/items", or the protocol and remote end
+fn flow_name(flow: &FlowTiming) -> String {
+    flow.details
+        .summary()
+        .unwrap_or_else(|| format!("{} {}", flow.protocol, flow.hostname.as_deref().unwrap_or(&flow.remote)))
+}
+
+// Build the timeline. `window` limits correlation windows and their flows
+// like for HAR export (see StoredAction::flows_within).
+pub fn build(actions: &[StoredAction], samples: &[StoredSample], checkpoints: &[Checkpoint], window: Option<Duration>) -> Trace {
+    let mut events = Vec::new();
+    // Track names; the app name from a UI event wins over nettop's row key
+    let mut processes: BTreeMap<i32, String> = BTreeMap::new();
+    let mut next_id = 0;
+
+    for action in actions {
+        let ts = micros(action.timestamp);
+        processes.entry(action.pid).or_insert_with(|| action.app_name.clone());
+
+        if action.kind == "key" {
+            events.push(TraceEvent::instant("Key Pressed", "input", ts, action.pid).with_args(json!({ "keycode": action.keycode })));
+            continue;
+        }
+
+        let name = otel::action_name(&action.selector);
+        // The click's accessibility queries, summed and drawn as one slice ending at the click
+        if let Some(lookup_ms) = action.ax_lookup_ms {
+            let dur = (lookup_ms * 1000.0).round() as i64;
+            events.push(TraceEvent { dur: Some(dur), ..TraceEvent::new("AX lookup", "ax", "X", ts - dur, action.pid) });
+        }
+        events.push(TraceEvent::instant(name.clone(), "input", ts, action.pid).with_args(json!({
+            "identifier": action.selector.identifier,
+            "role": action.selector.role,
+            "label": action.selector.label,
+            "window": action.window_title,
+        })));
+
+        let Some(traffic) = &action.traffic else { continue };
+        let window_us = window.map_or(traffic.window_ms as i64 * 1000, |window| window.as_micros() as i64);
+        next_id += 1;
+        events.extend(async_slice(
+            &name,
+            "action",
+            format!("action-{}", next_id),
+            action.pid,
+            ts,
+            ts + window_us,
+            json!({
+                "bytes_in": traffic.bytes_in,
+                "bytes_out": traffic.bytes_out,
+                "new_connections": traffic.connections.iter().filter(|connection| connection.is_new).count(),
+            }),
+        ));
+
+        for flow in action.flows_within(window) {
+            next_id += 1;
+            events.extend(async_slice(
+                &flow_name(flow),
+                "flow",
+                format!("flow-{}", next_id),
+                flow.pid.unwrap_or(action.pid),
+                ts + flow.first_byte_ms * 1000,
+                ts + flow.last_byte_ms * 1000,
+                json!({
+                    "local": flow.local,
+                    "remote": flow.remote,
+                    "hostname": flow.hostname,
+                    "first_response_ms": flow.first_response_ms,
+                    "bytes_in": flow.bytes_in,
+                    "bytes_out": flow.bytes_out,
+                }),
+            ));
+            if let Some(pid) = flow.pid {
+                processes.entry(pid).or_insert_with(|| action.app_name.clone());
+            }
+        }
+    }
+
+    for sample in samples {
+        let ts = micros(sample.timestamp);
+        let process_name = sample.process.rsplit_once('.').map_or(sample.process.as_str(), |(name, _)| name);
+        processes.entry(sample.pid).or_insert_with(|| process_name.to_string());
+        events.push(
+            TraceEvent::instant("nettop sample", "nettop", ts, sample.pid)
+                .with_args(json!({ "delta_in": sample.delta_in, "delta_out": sample.delta_out })),
+        );
+        events.push(
+            TraceEvent::new("bytes", "nettop", "C", ts, sample.pid)
+                .with_args(json!({ "in": sample.delta_in, "out": sample.delta_out })),
+        );
+    }
+
+    for checkpoint in checkpoints {
+        events.push(TraceEvent { s: Some("g"), ..TraceEvent::instant(checkpoint.label.clone(), "checkpoint", micros(checkpoint.timestamp), WATCHER_PID) });
+    }
+    if !checkpoints.is_empty() {
+        processes.insert(WATCHER_PID, env!("CARGO_PKG_NAME").to_string());
+    }
+
+    events.sort_by_key(|event| event.ts);
+    let mut trace_events: Vec<TraceEvent> = processes
+        .iter()
+        .flat_map(|(pid, name)| {
+            let label = if *pid == WATCHER_PID { name.clone() } else { format!("{} ({})", name, pid) };
+            [TraceEvent::metadata("process_name", *pid, &label), TraceEvent::metadata("thread_name", *pid, "events")]
+        })
+        .collect();
+    trace_events.extend(events);
+
+    Trace { trace_events, display_time_unit: "ms".to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration as ChronoDuration, TimeZone};
+
+    use crate::dissect::FlowDetails;
+    use crate::events::AxSelector;
+    use crate::store::StoredTraffic;
+
+    fn at(millis: i64) -> DateTime<Local> {
+        Local.timestamp_millis_opt(1_700_000_000_000).unwrap() + ChronoDuration::milliseconds(millis)
+    }
+
+    fn action(kind: &str, millis: i64, pid: i32, traffic: Option<StoredTraffic>) -> StoredAction {
+        StoredAction {
+            timestamp: at(millis),
+            kind: kind.to_string(),
+            pid,
+            app_name: "Mail".to_string(),
+            selector: AxSelector { identifier: Some("send".to_string()), role: Some("AXButton".to_string()), label: None },
+            window_title: Some("Inbox".to_string()),
+            keycode: (kind == "key").then_some(36),
+            ax_lookup_ms: (kind == "click").then_some(2.5),
+            traffic,
+        }
+    }
+
+    fn flow(pid: Option<i32>, first_byte_ms: i64, last_byte_ms: i64) -> FlowTiming {
+        FlowTiming {
+            protocol: "tcp".to_string(),
+            local: "10.0.0.5:52000".to_string(),
+            remote: "192.0.2.1:443".to_string(),
+            hostname: Some("smtp.example.com".to_string()),
+            pid,
+            first_byte_ms,
+            first_response_ms: None,
+            last_byte_ms,
+            bytes_in: 10,
+            bytes_out: 20,
+            details: FlowDetails::default(),
+        }
+    }
+
+    fn json_events(trace: &Trace) -> Vec<Value> {
+        trace.trace_events.iter().map(|event| serde_json::to_value(event).unwrap()).collect()
+    }
+
+    fn named<'a>(events: &'a [Value], name: &str) -> Vec<&'a Value> {
+        events.iter().filter(|event| event["name"] == name).collect()
+    }
+
+    #[test]
+    fn clicks_and_keys_are_instants_after_their_ax_lookup() {
+        let trace = build(&[action("click", 0, 42, None), action("key", 100, 42, None)], &[], &[], None);
+        let events = json_events(&trace);
+        let base = micros(at(0));
+
+        let lookup = json!({ "name": "AX lookup", "cat": "ax", "ph": "X", "ts": base - 2500, "dur": 2500, "pid": 42, "tid": 42 });
+        assert_eq!(named(&events, "AX lookup"), [&lookup]);
+        let click = named(&events, "Button Clicked send");
+        assert_eq!(click.len(), 1);
+        assert_eq!((&click[0]["ph"], &click[0]["s"], &click[0]["ts"]), (&json!("i"), &json!("t"), &json!(base)));
+        // The missing label is left out rather than written as null
+        assert_eq!(click[0]["args"], json!({ "identifier": "send", "role": "AXButton", "window": "Inbox" }));
+        assert_eq!(named(&events, "Key Pressed")[0]["args"], json!({ "keycode": 36 }));
+    }
+
+    #[test]
+    fn correlated_clicks_get_window_and_flow_slices() {
+        let traffic = StoredTraffic {
+            window_ms: 2000,
+            bytes_in: 10,
+            bytes_out: 20,
+            connections: Vec::new(),
+            flows: vec![flow(Some(77), 50, 400), flow(None, 2500, 2600), flow(Some(42), 100, 50)],
+        };
+        let trace = build(&[action("click", 0, 42, Some(traffic.clone()))], &[], &[], None);
+        let events = json_events(&trace);
+        let base = micros(at(0));
+
+        let window: Vec<&Value> = named(&events, "Button Clicked send").into_iter().filter(|event| event["cat"] == "action").collect();
+        let bounds: Vec<(&Value, &Value)> = window.iter().map(|event| (&event["ph"], &event["ts"])).collect();
+        assert_eq!(bounds, [(&json!("b"), &json!(base)), (&json!("e"), &json!(base + 2_000_000))]);
+        assert_eq!(window[0]["id"], window[1]["id"]);
+        assert_eq!(window[0]["args"]["bytes_out"], 20);
+
+        // The flow outside the window is left out; a helper's flow goes on its own track
+        let flows = named(&events, "tcp smtp.example.com");
+        let spans: Vec<(&Value, &Value, &Value)> = flows.iter().map(|event| (&event["ph"], &event["pid"], &event["ts"])).collect();
+        assert_eq!(
+            spans,
+            [
+                (&json!("b"), &json!(77), &json!(base + 50_000)),
+                (&json!("b"), &json!(42), &json!(base + 100_000)),
+                // A flow that ends before it starts is clamped to zero length
+                (&json!("e"), &json!(42), &json!(base + 100_000)),
+                (&json!("e"), &json!(77), &json!(base + 400_000)),
+            ]
+        );
+        let tracks: Vec<&Value> = named(&events, "process_name").iter().map(|event| &event["args"]["name"]).collect();
+        assert_eq!(tracks, [&json!("Mail (42)"), &json!("Mail (77)")]);
+
+        let narrow = json_events(&build(&[action("click", 0, 42, Some(traffic))], &[], &[], Some(Duration::from_millis(10))));
+        assert!(named(&narrow, "tcp smtp.example.com").is_empty());
+    }
+
+    #[test]
+    fn samples_and_checkpoints_get_tracks_of_their_own() {
+        let samples = [
+            StoredSample { timestamp: at(20), pid: 4242, process: "Dropbox.4242".to_string(), delta_in: 5, delta_out: 6 },
+            StoredSample { timestamp: at(10), pid: 42, process: "MailHelper.42".to_string(), delta_in: 1, delta_out: 0 },
+        ];
+        let checkpoints = [Checkpoint { timestamp: at(5), label: "before send".to_string() }];
+        let trace = build(&[action("click", 30, 42, None)], &samples, &checkpoints, None);
+        let events = json_events(&trace);
+
+        // Metadata first, then everything else in time order
+        let metadata = events.iter().take_while(|event| event["ph"] == "M").count();
+        assert_eq!(metadata, 6);
+        let times: Vec<i64> = events[metadata..].iter().map(|event| event["ts"].as_i64().unwrap()).collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", times);
+
+        let names: Vec<&Value> = named(&events, "process_name").iter().map(|event| &event["args"]["name"]).collect();
+        assert_eq!(names, [&json!("macos-watcher"), &json!("Mail (42)"), &json!("Dropbox (4242)")]);
+        assert_eq!(named(&events, "bytes")[1]["args"], json!({ "in": 5, "out": 6 }));
+        let checkpoint = named(&events, "before send");
+        assert_eq!((&checkpoint[0]["s"], &checkpoint[0]["pid"]), (&json!("g"), &json!(WATCHER_PID)));
+    }
+}
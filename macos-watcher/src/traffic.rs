@@ -0,0 +1,312 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Windows the 📡 lines report rates over
+pub const RATE_WINDOWS: [Duration; 3] = [Duration::from_secs(1), Duration::from_secs(10), Duration::from_secs(60)];
+
+// Samples older than the longest window are dropped (one is kept as the
+// baseline for interpolating the window start)
+const HISTORY_SPAN: Duration = Duration::from_secs(60);
+
+// Bytes per second in each direction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rate {
+    pub bytes_in: f64,
+    pub bytes_out: f64,
+}
+
+// Rates over RATE_WINDOWS plus the highest 1s rate seen so far
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrafficRates {
+    pub last_1s: Rate,
+    pub last_10s: Rate,
+    pub last_60s: Rate,
+    pub peak: Rate,
+}
+
+// One nettop reading, with counters made monotonic across resets
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sample {
+    at: Instant,
+    total_in: u64,
+    total_out: u64,
+}
+
+// What one new reading changed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Recorded {
+    pub delta_in: u64,
+    pub delta_out: u64,
+    // The raw counters went backwards (process restarted or PID reused)
+    pub reset: bool,
+}
+
+// Time-stamped byte counters of one nettop process row
+#[derive(Clone, Debug, Default)]
+pub struct ProcessHistory {
+    samples: VecDeque<Sample>,
+    // Last raw counters as nettop reported them
+    last_raw: Option<(u64, u64)>,
+    // Counter values accumulated before the last reset
+    offset_in: u64,
+    offset_out: u64,
+    peak: Rate,
+    resets: u32,
+}
+
+impl ProcessHistory {
+    // Add a reading of nettop's cumulative counters taken at `at`
+    pub fn record(&mut self, at: Instant, raw_in: u64, raw_out: u64) -> Recorded {
+        let mut recorded = Recorded::default();
+        match self.last_raw {
+            // Either counter going backwards means a new process is behind
+            // the row; everything it reports so far is new traffic
+            Some((last_in, last_out)) if raw_in < last_in || raw_out < last_out => {
+                self.offset_in += last_in;
+                self.offset_out += last_out;
+                self.resets += 1;
+                recorded = Recorded { delta_in: raw_in, delta_out: raw_out, reset: true };
+            }
+            Some((last_in, last_out)) => {
+                recorded.delta_in = raw_in - last_in;
+                recorded.delta_out = raw_out - last_out;
+            }
+            // The first reading is the baseline: its counters hold whatever
+            // the process moved before the watcher saw it
+            None => {}
+        }
+        self.last_raw = Some((raw_in, raw_out));
+
+        // Readings must be in order; a late one only updates the counters
+        if self.samples.back().is_some_and(|last| at < last.at) {
+            return recorded;
+        }
+        self.samples.push_back(Sample {
+            at,
+            total_in: self.offset_in + raw_in,
+            total_out: self.offset_out + raw_out,
+        });
+        self.prune(at);
+
+        let current = self.rate(RATE_WINDOWS[0], at);
+        self.peak.bytes_in = self.peak.bytes_in.max(current.bytes_in);
+        self.peak.bytes_out = self.peak.bytes_out.max(current.bytes_out);
+        recorded
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let Some(cutoff) = now.checked_sub(HISTORY_SPAN) else { return };
+        // Keep the newest sample at or before the cutoff as the baseline
+        while self.samples.len() > 1 && self.samples[1].at <= cutoff {
+            self.samples.pop_front();
+        }
+    }
+
+    // Counter totals at `at`, linearly interpolated between samples. nettop is
+    // only read now and then, so bytes are spread evenly over the gap between
+    // two readings. Before the first sample the first reading is used.
+    fn totals_at(&self, at: Instant) -> Option<(f64, f64)> {
+        let first = self.samples.front()?;
+        if at <= first.at {
+            return Some((first.total_in as f64, first.total_out as f64));
+        }
+        for (before, after) in self.samples.iter().zip(self.samples.iter().skip(1)) {
+            if at <= after.at {
+                let span = after.at.duration_since(before.at).as_secs_f64();
+                let fraction = if span > 0.0 { at.duration_since(before.at).as_secs_f64() / span } else { 1.0 };
+                let interpolate = |from: u64, to: u64| from as f64 + (to - from) as f64 * fraction;
+                return Some((interpolate(before.total_in, after.total_in), interpolate(before.total_out, after.total_out)));
+            }
+        }
+        let last = self.samples.back()?;
+        Some((last.total_in as f64, last.total_out as f64))
+    }
+
+    // Average rate over the `window` ending at `now`. When the history is
+    // shorter than the window, the rate is over the time actually covered.
+    pub fn rate(&self, window: Duration, now: Instant) -> Rate {
+        let (Some(first), Some(last)) = (self.samples.front(), self.samples.back()) else {
+            return Rate::default();
+        };
+        let end = now.min(last.at);
+        let start = now.checked_sub(window).map_or(first.at, |start| start.max(first.at));
+        if end <= start {
+            return Rate::default();
+        }
+        let (Some((start_in, start_out)), Some((end_in, end_out))) = (self.totals_at(start), self.totals_at(end)) else {
+            return Rate::default();
+        };
+        // Bytes are only known up to the last reading, but the window runs to `now`
+        let seconds = now.duration_since(start).as_secs_f64();
+        Rate {
+            bytes_in: (end_in - start_in) / seconds,
+            bytes_out: (end_out - start_out) / seconds,
+        }
+    }
+
+    pub fn rates(&self, now: Instant) -> TrafficRates {
+        TrafficRates {
+            last_1s: self.rate(RATE_WINDOWS[0], now),
+            last_10s: self.rate(RATE_WINDOWS[1], now),
+            last_60s: self.rate(RATE_WINDOWS[2], now),
+            peak: self.peak,
+        }
+    }
+
+    pub fn resets(&self) -> u32 {
+        self.resets
+    }
+}
+
+// Histories of every nettop row seen, keyed by "<name>.<pid>"
+#[derive(Clone, Debug, Default)]
+pub struct TrafficHistory {
+    processes: HashMap<String, ProcessHistory>,
+}
+
+impl TrafficHistory {
+    pub fn record(&mut self, key: &str, at: Instant, raw_in: u64, raw_out: u64) -> Recorded {
+        self.processes.entry(key.to_string()).or_default().record(at, raw_in, raw_out)
+    }
+
+    pub fn process(&self, key: &str) -> Option<&ProcessHistory> {
+        self.processes.get(key)
+    }
+
+    // Raw counters from the last reading, to tell whether anything changed
+    pub fn last_counters(&self, key: &str) -> Option<(u64, u64)> {
+        self.processes.get(key).and_then(|history| history.last_raw)
+    }
+}
+
+// "↑ 1.2k/840/96 B/s ↓ 0/12/3 B/s (1s/10s/60s), peak ↑ 5.1k ↓ 44 B/s"
+pub fn format_rates(rates: &TrafficRates) -> String {
+    format!(
+        "↑ {}/{}/{} B/s ↓ {}/{}/{} B/s (1s/10s/60s), peak ↑ {} ↓ {} B/s",
+        compact(rates.last_1s.bytes_out),
+        compact(rates.last_10s.bytes_out),
+        compact(rates.last_60s.bytes_out),
+        compact(rates.last_1s.bytes_in),
+        compact(rates.last_10s.bytes_in),
+        compact(rates.last_60s.bytes_in),
+        compact(rates.peak.bytes_out),
+        compact(rates.peak.bytes_in),
+    )
+}
+
+fn compact(value: f64) -> String {
+    if value >= 1_000_000.0 {
+        format!("{:.1}M", value / 1_000_000.0)
+    } else if value >= 1_000.0 {
+        format!("{:.1}k", value / 1_000.0)
+    } else {
+        format!("{:.0}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(start: Instant, seconds: u64) -> Instant {
+        start + Duration::from_secs(seconds)
+    }
+
+    fn rate(bytes_in: f64, bytes_out: f64) -> Rate {
+        Rate { bytes_in, bytes_out }
+    }
+
+    #[test]
+    fn first_reading_is_only_a_baseline() {
+        let mut history = ProcessHistory::default();
+        let start = Instant::now();
+        assert_eq!(history.record(start, 50_000, 20_000), Recorded::default());
+        assert_eq!(history.record(secs(start, 1), 50_400, 20_100), Recorded { delta_in: 400, delta_out: 100, reset: false });
+    }
+
+    #[test]
+    fn a_single_sample_has_no_rate() {
+        let mut history = ProcessHistory::default();
+        let start = Instant::now();
+        history.record(start, 1_000, 1_000);
+        assert_eq!(history.rate(RATE_WINDOWS[0], start), Rate::default());
+        assert_eq!(history.rates(secs(start, 5)), TrafficRates::default());
+        assert_eq!(ProcessHistory::default().rate(RATE_WINDOWS[2], start), Rate::default());
+    }
+
+    #[test]
+    fn windows_are_interpolated_between_readings() {
+        let mut history = ProcessHistory::default();
+        let start = Instant::now();
+        history.record(start, 0, 0);
+        history.record(secs(start, 10), 10_000, 5_000);
+
+        // The last second of an even 1000 B/s stretch
+        assert_eq!(history.rate(Duration::from_secs(1), secs(start, 10)), rate(1_000.0, 500.0));
+        // Longer than the history: the rate is over the 10s actually covered
+        assert_eq!(history.rate(Duration::from_secs(60), secs(start, 10)), rate(1_000.0, 500.0));
+        // Nothing is known after the last reading, so quiet time lowers the rate
+        assert_eq!(history.rate(Duration::from_secs(10), secs(start, 15)), rate(500.0, 250.0));
+        // A window that starts after the last reading saw nothing
+        assert_eq!(history.rate(Duration::from_secs(1), secs(start, 12)), Rate::default());
+        assert_eq!(history.rates(secs(start, 10)).peak, rate(1_000.0, 500.0));
+    }
+
+    #[test]
+    fn counter_resets_count_as_new_traffic() {
+        let mut history = ProcessHistory::default();
+        let start = Instant::now();
+        history.record(start, 1_000, 100);
+        history.record(secs(start, 1), 1_500, 100);
+        let recorded = history.record(secs(start, 2), 200, 300);
+
+        assert_eq!(recorded, Recorded { delta_in: 200, delta_out: 300, reset: true });
+        assert_eq!(history.resets(), 1);
+        // Totals stay monotonic across the reset
+        assert_eq!(history.rate(Duration::from_secs(1), secs(start, 2)), rate(200.0, 300.0));
+        assert_eq!(history.record(secs(start, 3), 250, 300), Recorded { delta_in: 50, delta_out: 0, reset: false });
+    }
+
+    #[test]
+    fn late_readings_only_update_the_counters() {
+        let mut history = ProcessHistory::default();
+        let start = secs(Instant::now(), 10);
+        history.record(start, 0, 0);
+        history.record(secs(start, 2), 2_000, 0);
+        let recorded = history.record(secs(start, 1), 2_100, 0);
+
+        assert_eq!(recorded.delta_in, 100);
+        assert_eq!(history.samples.len(), 2);
+        assert_eq!(history.last_raw, Some((2_100, 0)));
+    }
+
+    #[test]
+    fn history_keeps_one_baseline_before_the_longest_window() {
+        let mut history = ProcessHistory::default();
+        let start = Instant::now();
+        for second in (0..=120).step_by(10) {
+            history.record(secs(start, second), second * 100, 0);
+        }
+
+        assert_eq!(history.samples.front().map(|sample| sample.at), Some(secs(start, 60)));
+        assert_eq!(history.rate(RATE_WINDOWS[2], secs(start, 120)), rate(100.0, 0.0));
+    }
+
+    #[test]
+    fn histories_are_kept_per_nettop_row() {
+        let mut traffic = TrafficHistory::default();
+        let start = Instant::now();
+        traffic.record("Chat.42", start, 10, 10);
+        traffic.record("Chat.43", start, 99, 99);
+        assert_eq!(traffic.record("Chat.42", secs(start, 1), 15, 10).delta_in, 5);
+        assert_eq!(traffic.last_counters("Chat.43"), Some((99, 99)));
+        assert_eq!(traffic.last_counters("Mail.50"), None);
+    }
+
+    #[test]
+    fn formats_rates_compactly() {
+        let rates = TrafficRates { last_1s: rate(12.0, 1_234.0), last_10s: rate(3.0, 2_500_000.0), ..TrafficRates::default() };
+        assert_eq!(format_rates(&rates), "↑ 1.2k/2.5M/0 B/s ↓ 12/3/0 B/s (1s/10s/60s), peak ↑ 0 ↓ 0 B/s");
+    }
+}
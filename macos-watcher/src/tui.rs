@@ -0,0 +1,231 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table};
+use ratatui::Frame;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::dashboard::{format_bytes, format_rate, Dashboard, DashboardKey, DashboardView};
+use crate::events::{self, EventSink, WatcherEvent};
+use crate::{session, shutdown, utils};
+
+// How long to wait for a key press before redrawing anyway
+const TICK: Duration = Duration::from_millis(250);
+
+// Feeds the dashboard and owns the thread that draws it
+pub struct TuiSink {
+    dashboard: Arc<Mutex<Dashboard>>,
+    stop: Arc<AtomicBool>,
+    renderer: Option<JoinHandle<()>>,
+}
+
+impl TuiSink {
+    // Take over the terminal. Plain console output is switched off until finish().
+    pub fn start() -> Self {
+        let dashboard = Arc::new(Mutex::new(Dashboard::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        utils::set_console_output(false);
+
+        let renderer = {
+            let dashboard = dashboard.clone();
+            let stop = stop.clone();
+            thread::spawn(move || run(dashboard, stop))
+        };
+        TuiSink { dashboard, stop, renderer: Some(renderer) }
+    }
+}
+
+impl EventSink for TuiSink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        self.dashboard.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).handle_event(event);
+    }
+
+    // Give the terminal back before the session summary is printed
+    fn finish(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(renderer) = self.renderer.take() {
+            let _ = renderer.join();
+        }
+        utils::set_console_output(true);
+    }
+}
+
+fn run(dashboard: Arc<Mutex<Dashboard>>, stop: Arc<AtomicBool>) {
+    let mut terminal = ratatui::init();
+    while !stop.load(Ordering::SeqCst) {
+        let view = dashboard.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).view(
+            chrono::Local::now(),
+            session::started_at(),
+            events::is_paused(),
+            events::tap_disabled_count(),
+        );
+        if let Err(e) = terminal.draw(|frame| render(frame, &view)) {
+            log::error!("Failed to draw dashboard: {}", e);
+            break;
+        }
+
+        match event::poll(TICK) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                log::error!("Failed to read terminal input: {}", e);
+                break;
+            }
+        }
+        let Ok(Event::Key(key)) = event::read() else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let key = match key.code {
+            // Raw mode swallows SIGINT, so Ctrl+C arrives as a key press
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => DashboardKey::Quit,
+            KeyCode::Char(c) => DashboardKey::Char(c),
+            KeyCode::Backspace => DashboardKey::Backspace,
+            KeyCode::Enter => DashboardKey::Enter,
+            KeyCode::Esc => DashboardKey::Escape,
+            _ => continue,
+        };
+        if dashboard.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).handle_key(key) {
+            shutdown::request_stop();
+            // Keep drawing until finish() so the terminal is restored in one place
+        }
+    }
+    ratatui::restore();
+}
+
+pub fn render(frame: &mut Frame, view: &DashboardView) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(5), Constraint::Length(1)])
+        .split(frame.area());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .split(rows[1]);
+    let right = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(columns[1]);
+
+    render_health(frame, rows[0], view);
+    render_ui_events(frame, columns[0], view);
+    render_processes(frame, right[0], view);
+    render_correlations(frame, right[1], view);
+    render_footer(frame, rows[2], view);
+}
+
+fn render_health(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let health = &view.health;
+    let counts: Vec<String> = health.events_by_kind.iter().map(|(kind, count)| format!("{} {}", kind, count)).collect();
+    let state = if health.paused {
+        Span::styled("PAUSED", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+    } else {
+        Span::styled("RUNNING", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+    };
+    let tap_style = if health.tap_disabled > 0 { Style::default().fg(Color::Red) } else { Style::default() };
+    let line = Line::from(vec![
+        state,
+        Span::raw(format!("  uptime {}s  ", health.uptime_secs)),
+        Span::styled(format!("tap disabled {}", health.tap_disabled), tap_style),
+        Span::raw(format!("  events: {}", if counts.is_empty() { "none".to_string() } else { counts.join(", ") })),
+    ]);
+    frame.render_widget(Paragraph::new(line).block(Block::default().borders(Borders::ALL).title(" Watcher ")), area);
+}
+
+fn render_ui_events(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let items: Vec<ListItem> = view
+        .ui_events
+        .iter()
+        .map(|row| {
+            let kind_style = match row.kind {
+                "click" => Style::default().fg(Color::Cyan),
+                "checkpoint" => Style::default().fg(Color::Magenta),
+                _ => Style::default().fg(Color::Gray),
+            };
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{} ", row.time.format("%H:%M:%S"))),
+                Span::styled(format!("{:<10} ", row.kind), kind_style),
+                Span::raw(format!("{} {}", app_basename(&row.app), row.description)),
+            ]))
+        })
+        .collect();
+    frame.render_widget(List::new(items).block(Block::default().borders(Borders::ALL).title(" UI events ")), area);
+}
+
+fn render_processes(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let rows: Vec<Row> = view
+        .processes
+        .iter()
+        .map(|row| {
+            Row::new(vec![
+                Cell::from(row.process.clone()),
+                Cell::from(format_rate(row.rate_out)),
+                Cell::from(format_rate(row.rate_in)),
+                Cell::from(format_bytes(row.bytes_out as f64)),
+                Cell::from(format_bytes(row.bytes_in as f64)),
+            ])
+        })
+        .collect();
+    let widths = [
+        Constraint::Min(20),
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(10),
+        Constraint::Length(10),
+    ];
+    let header = Row::new(vec!["Process", "↑ rate", "↓ rate", "↑ total", "↓ total"]).style(Style::default().add_modifier(Modifier::BOLD));
+    let table = Table::new(rows, widths).header(header).block(Block::default().borders(Borders::ALL).title(" Network "));
+    frame.render_widget(table, area);
+}
+
+fn render_correlations(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let rows: Vec<Row> = view
+        .correlations
+        .iter()
+        .map(|row| {
+            let style = if row.bytes_in + row.bytes_out == 0 { Style::default().fg(Color::DarkGray) } else { Style::default() };
+            Row::new(vec![
+                Cell::from(row.time.format("%H:%M:%S").to_string()),
+                Cell::from(if row.remotes.is_empty() {
+                    format!("{} {}", app_basename(&row.app), row.target)
+                } else {
+                    format!("{} {} → {}", app_basename(&row.app), row.target, row.remotes.join(", "))
+                }),
+                Cell::from(format_bytes(row.bytes_out as f64)),
+                Cell::from(format_bytes(row.bytes_in as f64)),
+                Cell::from(row.new_connections.to_string()),
+            ])
+            .style(style)
+        })
+        .collect();
+    let widths = [
+        Constraint::Length(8),
+        Constraint::Min(20),
+        Constraint::Length(10),
+        Constraint::Length(10),
+        Constraint::Length(5),
+    ];
+    let header = Row::new(vec!["Time", "Click", "↑", "↓", "New"]).style(Style::default().add_modifier(Modifier::BOLD));
+    let table = Table::new(rows, widths).header(header).block(Block::default().borders(Borders::ALL).title(" Clicks → traffic "));
+    frame.render_widget(table, area);
+}
+
+fn render_footer(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let text = if view.editing_filter {
+        format!("Filter by app: {}▏  (Enter apply, Esc clear)", view.app_filter)
+    } else if view.app_filter.is_empty() {
+        "q quit  / filter by app".to_string()
+    } else {
+        format!("q quit  / edit filter  Esc clear  [app ~ '{}']", view.app_filter)
+    };
+    frame.render_widget(Paragraph::new(text).style(Style::default().fg(Color::DarkGray)), area);
+}
+
+// ps reports full executable paths; the last component is enough on screen
+fn app_basename(app: &str) -> &str {
+    app.rsplit('/').next().unwrap_or(app)
+}
@@ -1,9 +1,33 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::metrics::{self, Timer};
+
+// Plain println! output from the event tap and nettop checks. The TUI turns
+// it off while it owns the terminal.
+static CONSOLE_OUTPUT: AtomicBool = AtomicBool::new(true);
+
+pub fn set_console_output(enabled: bool) {
+    CONSOLE_OUTPUT.store(enabled, Ordering::SeqCst);
+}
+
+pub fn console_output() -> bool {
+    CONSOLE_OUTPUT.load(Ordering::SeqCst)
+}
+
+// Files the watcher keeps between runs (log, event store) live in $HOME
+pub fn home_file(file_name: &str) -> PathBuf {
+    let home_dir = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home_dir).join(file_name)
+}
 
 // Helper function to get App Name from PID (simplified)
 // A full implementation might use NSWorkspace or other methods
 pub fn get_app_name_from_pid(pid: i32) -> String {
     // Use ps command for a simple lookup
+    let _timer = Timer::start(metrics::COMMAND_DURATION, &[("command", "ps")]);
     let output = Command::new("ps")
         .arg("-p")
         .arg(pid.to_string())
@@ -19,12 +43,119 @@ pub fn get_app_name_from_pid(pid: i32) -> String {
     }
 }
 
+// Reverse of get_app_name_from_pid: find a running process by the name `ps` reports.
+// Recorded names are usually full executable paths, so the basename is accepted too.
+pub fn find_pid_by_app_name(app_name: &str) -> Option<i32> {
+    let _timer = Timer::start(metrics::COMMAND_DURATION, &[("command", "ps")]);
+    let output = Command::new("ps")
+        .args(["-axo", "pid=,comm="])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let wanted_basename = app_name.rsplit('/').next().unwrap_or(app_name);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut basename_match = None;
+
+    for line in stdout.lines() {
+        let line = line.trim();
+        let Some((pid, comm)) = line.split_once(char::is_whitespace) else { continue };
+        let Ok(pid) = pid.parse::<i32>() else { continue };
+        let comm = comm.trim();
+
+        if comm == app_name {
+            return Some(pid);
+        }
+        if basename_match.is_none() && comm.rsplit('/').next() == Some(wanted_basename) {
+            basename_match = Some(pid);
+        }
+    }
+    basename_match
+}
+
+// `pid` plus all of its descendants, from `ps -axo pid=,ppid=`
+pub fn process_tree(pid: i32) -> HashSet<i32> {
+    let _timer = Timer::start(metrics::COMMAND_DURATION, &[("command", "ps")]);
+    let output = Command::new("ps").args(["-axo", "pid=,ppid="]).output();
+    match output {
+        Ok(output) if output.status.success() => {
+            descendants(&parse_parent_table(&String::from_utf8_lossy(&output.stdout)), pid)
+        }
+        _ => HashSet::from([pid]),
+    }
+}
+
+// "  PID  PPID" lines into (pid, parent) pairs
+pub fn parse_parent_table(output: &str) -> Vec<(i32, i32)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let pid = columns.next()?.parse().ok()?;
+            let parent = columns.next()?.parse().ok()?;
+            Some((pid, parent))
+        })
+        .collect()
+}
+
+// `root` and everything below it in the parent table
+pub fn descendants(table: &[(i32, i32)], root: i32) -> HashSet<i32> {
+    let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+    for (pid, parent) in table {
+        // launchd (PID 1) is everyone's parent; never walk below it by accident
+        if pid != parent {
+            children.entry(*parent).or_default().push(*pid);
+        }
+    }
+
+    let mut tree = HashSet::from([root]);
+    let mut pending = vec![root];
+    while let Some(pid) = pending.pop() {
+        for child in children.get(&pid).into_iter().flatten() {
+            if tree.insert(*child) {
+                pending.push(*child);
+            }
+        }
+    }
+    tree
+}
+
 pub fn open_accessibility_preferences() {
     // This will open the accessibility section of System Settings
     match Command::new("open")
-        .args(&["x-apple.systempreferences:com.apple.preference.security?Privacy_Accessibility"])
+        .args(["x-apple.systempreferences:com.apple.preference.security?Privacy_Accessibility"])
         .spawn() {
             Ok(_) => println!("Opened accessibility preferences"),
             Err(e) => println!("Failed to open accessibility preferences: {}", e)
         }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ps_output_is_parsed_into_parent_pairs() {
+        let output = "    1     0\n  812     1\n 4242   812\n\n  garbage line\n 4243\n 4244  4242  extra\n";
+        assert_eq!(parse_parent_table(output), vec![(1, 0), (812, 1), (4242, 812), (4244, 4242)]);
+        assert!(parse_parent_table("").is_empty());
+    }
+
+    #[test]
+    fn descendants_follow_nested_children() {
+        let table = [(1, 0), (812, 1), (900, 1), (4242, 812), (4243, 4242), (4244, 4243), (950, 900)];
+        assert_eq!(descendants(&table, 812), HashSet::from([812, 4242, 4243, 4244]));
+        assert_eq!(descendants(&table, 4244), HashSet::from([4244]));
+        // A PID missing from the table is its own only descendant
+        assert_eq!(descendants(&table, 7), HashSet::from([7]));
+    }
+
+    #[test]
+    fn descendants_stop_at_cycles() {
+        // PID reuse can leave a parent table with loops; walking them must end
+        let table = [(812, 4242), (4242, 812), (4243, 4242), (5000, 5000)];
+        assert_eq!(descendants(&table, 812), HashSet::from([812, 4242, 4243]));
+        assert_eq!(descendants(&table, 5000), HashSet::from([5000]));
+    }
+}
@@ -0,0 +1,487 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::correlation::{ConnectionDelta, WindowSampler, WindowTraffic, DEFAULT_SAMPLE_INTERVAL};
+use crate::events::{AxSelector, ClickEvent, EventSink, WatcherEvent};
+use crate::shutdown;
+
+// Exit code reported once the scenario finished; 0 until then
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+fn default_timeout() -> f64 {
+    60.0
+}
+
+fn default_within() -> f64 {
+    2.0
+}
+
+// A YAML file of expectations to check against live input and traffic
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    // Seconds to wait for the clicks the expectations refer to
+    #[serde(default = "default_timeout")]
+    pub timeout: f64,
+    pub expectations: Vec<Expectation>,
+}
+
+// "Within `within` seconds of a click on `click`, the process opens a
+// connection to `host` and sends at least `min_bytes_out` bytes."
+#[derive(Clone, Debug, Deserialize)]
+pub struct Expectation {
+    pub name: Option<String>,
+    // Only the fields that are set have to match
+    pub click: AxSelector,
+    // Restrict to clicks in this app (full path or executable name)
+    pub app: Option<String>,
+    #[serde(default = "default_within")]
+    pub within: f64,
+    // Watch this PID instead of the one that received the click
+    pub pid: Option<i32>,
+    // "api.example.com" or "api.example.com:443"
+    pub host: Option<String>,
+    // Exact number of new connections (to `host`, if set)
+    pub connections: Option<usize>,
+    pub min_bytes_out: Option<u64>,
+    pub max_bytes_out: Option<u64>,
+    pub min_bytes_in: Option<u64>,
+    pub max_bytes_in: Option<u64>,
+}
+
+impl Expectation {
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("click {}", self.click.describe()))
+    }
+
+    pub fn matches_click(&self, click: &ClickEvent) -> bool {
+        let field_matches = |expected: &Option<String>, actual: &Option<String>| {
+            expected.is_none() || expected == actual
+        };
+        let app_matches = self.app.as_ref().is_none_or(|app| {
+            click.app_name == *app || click.app_name.rsplit('/').next() == Some(app.as_str())
+        });
+
+        app_matches
+            && field_matches(&self.click.identifier, &click.selector.identifier)
+            && field_matches(&self.click.role, &click.selector.role)
+            && field_matches(&self.click.label, &click.selector.label)
+    }
+}
+
+pub fn load_scenario(path: &Path) -> Result<Scenario, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let scenario: Scenario =
+        serde_yaml::from_str(&contents).map_err(|e| format!("Invalid scenario {}: {}", path.display(), e))?;
+    if scenario.expectations.is_empty() {
+        return Err(format!("Scenario {} has no expectations", path.display()));
+    }
+    // Both become Durations; negative, NaN and absurdly large values would panic there
+    if Duration::try_from_secs_f64(scenario.timeout).is_err() {
+        return Err(format!("Scenario {}: timeout must be a number of seconds", path.display()));
+    }
+    if let Some(expectation) = scenario.expectations.iter().find(|expectation| Duration::try_from_secs_f64(expectation.within).is_err()) {
+        return Err(format!("Expectation '{}': within must be a number of seconds", expectation.display_name()));
+    }
+    Ok(scenario)
+}
+
+// Matches nettop's numeric remote addresses against a host from the scenario
+#[derive(Clone, Debug, Default)]
+pub struct HostMatcher {
+    host: String,
+    port: Option<u16>,
+    addresses: HashSet<IpAddr>,
+}
+
+impl HostMatcher {
+    // Resolves the host once up front; nettop only shows IP addresses
+    pub fn resolve(spec: &str) -> Self {
+        let (host, port) = match spec.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host, port.parse::<u16>().ok()),
+            _ => (spec, None),
+        };
+        let addresses = (host, port.unwrap_or(443))
+            .to_socket_addrs()
+            .map(|addresses| addresses.map(|address| address.ip()).collect())
+            .unwrap_or_else(|e| {
+                log::warn!("Could not resolve '{}': {}", host, e);
+                HashSet::new()
+            });
+        HostMatcher::new(host, port, addresses)
+    }
+
+    pub fn new(host: &str, port: Option<u16>, addresses: HashSet<IpAddr>) -> Self {
+        HostMatcher { host: host.to_string(), port, addresses }
+    }
+
+    pub fn matches(&self, connection: &ConnectionDelta) -> bool {
+        let host_matches = connection.remote_host == self.host
+            || connection
+                .remote_host
+                .parse::<IpAddr>()
+                .is_ok_and(|address| self.addresses.contains(&address));
+        host_matches && (self.port.is_none() || self.port == connection.remote_port)
+    }
+}
+
+// Check one expectation against the traffic seen after its click.
+// Returns the list of failed conditions; empty means the expectation passed.
+pub fn evaluate(expectation: &Expectation, host: Option<&HostMatcher>, traffic: &WindowTraffic) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    let (bytes_in, bytes_out, new_connections) = match host {
+        Some(host) => {
+            let matching: Vec<&ConnectionDelta> = traffic.connections.iter().filter(|c| host.matches(c)).collect();
+            (
+                matching.iter().map(|c| c.bytes_in).sum::<u64>(),
+                matching.iter().map(|c| c.bytes_out).sum::<u64>(),
+                matching.iter().filter(|c| c.is_new).count(),
+            )
+        }
+        None => (traffic.bytes_in, traffic.bytes_out, traffic.new_connections().count()),
+    };
+    let target = expectation.host.as_deref().unwrap_or("any host");
+
+    match expectation.connections {
+        Some(expected) if new_connections != expected => failures.push(format!(
+            "expected {} new connection(s) to {}, saw {}",
+            expected, target, new_connections
+        )),
+        // Apps usually reuse a keep-alive or HTTP/2 connection, so any bytes count as contact
+        None if expectation.host.is_some() && bytes_in == 0 && bytes_out == 0 => {
+            failures.push(format!("expected traffic to {}, saw none", target))
+        }
+        _ => {}
+    }
+
+    let limits = [
+        ("bytes out", bytes_out, expectation.min_bytes_out, expectation.max_bytes_out),
+        ("bytes in", bytes_in, expectation.min_bytes_in, expectation.max_bytes_in),
+    ];
+    for (what, actual, min, max) in limits {
+        if let Some(min) = min.filter(|min| actual < *min) {
+            failures.push(format!("expected at least {} {} to {}, saw {}", min, what, target, actual));
+        }
+        if let Some(max) = max.filter(|max| actual > *max) {
+            failures.push(format!("expected at most {} {} to {}, saw {}", max, what, target, actual));
+        }
+    }
+    failures
+}
+
+#[derive(Clone, Debug)]
+pub struct ExpectationResult {
+    pub name: String,
+    pub failures: Vec<String>,
+    pub duration: Duration,
+}
+
+enum State {
+    WaitingForClick,
+    Observing { started: Instant },
+    Done(ExpectationResult),
+}
+
+enum Message {
+    Click(Box<ClickEvent>),
+    Window(usize, WindowTraffic),
+}
+
+// Forwards clicks from the event tap to the verification thread
+struct VerifySink {
+    sender: Sender<Message>,
+}
+
+impl EventSink for VerifySink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        if let WatcherEvent::Click(click) = event {
+            let _ = self.sender.send(Message::Click(click.clone()));
+        }
+    }
+}
+
+// Arguments for `macos-watcher verify`
+pub struct VerifyArgs {
+    pub scenario_path: PathBuf,
+    pub junit_path: PathBuf,
+}
+
+// Load the scenario and start checking it in the background. The caller runs
+// the normal watcher loop, which is stopped once every expectation has a result.
+pub fn start(args: VerifyArgs) -> Result<(), String> {
+    let scenario = load_scenario(&args.scenario_path)?;
+    let hosts: Vec<Option<HostMatcher>> = scenario
+        .expectations
+        .iter()
+        .map(|expectation| expectation.host.as_deref().map(HostMatcher::resolve))
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    crate::events::add_sink(Box::new(VerifySink { sender: sender.clone() }));
+
+    println!(
+        "Verifying scenario '{}' ({} expectations, timeout {}s)",
+        scenario.name,
+        scenario.expectations.len(),
+        scenario.timeout
+    );
+    thread::spawn(move || {
+        let results = run_scenario(&scenario, &hosts, sender, receiver);
+        finish(&scenario, &results, &args.junit_path);
+    });
+    Ok(())
+}
+
+pub fn exit_code() -> i32 {
+    EXIT_CODE.load(Ordering::SeqCst)
+}
+
+fn run_scenario(
+    scenario: &Scenario,
+    hosts: &[Option<HostMatcher>],
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+) -> Vec<ExpectationResult> {
+    // A timeout too long for an Instant never runs out
+    let deadline = Instant::now().checked_add(Duration::from_secs_f64(scenario.timeout));
+    let sampler = WindowSampler::start(DEFAULT_SAMPLE_INTERVAL);
+    let mut states: Vec<State> = scenario.expectations.iter().map(|_| State::WaitingForClick).collect();
+
+    loop {
+        let all_done = states.iter().all(|state| matches!(state, State::Done(_)));
+        let observing = states.iter().any(|state| matches!(state, State::Observing { .. }));
+        if all_done || (deadline.is_some_and(|deadline| Instant::now() >= deadline) && !observing) {
+            break;
+        }
+
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(Message::Click(click)) => {
+                for (index, expectation) in scenario.expectations.iter().enumerate() {
+                    if !matches!(states[index], State::WaitingForClick) || !expectation.matches_click(&click) {
+                        continue;
+                    }
+                    log::info!("Verify: '{}' triggered by click in PID {}", expectation.display_name(), click.pid);
+                    states[index] = State::Observing { started: Instant::now() };
+
+                    let pid = expectation.pid.unwrap_or(click.pid);
+                    let window = Duration::from_secs_f64(expectation.within);
+                    // The sample from before the click only covers the clicked process
+                    let baseline = if pid == click.pid { click.traffic_before.clone() } else { Vec::new() };
+                    let sender = sender.clone();
+                    sampler.watch(pid, Instant::now(), window, baseline, move |traffic| {
+                        let _ = sender.send(Message::Window(index, traffic));
+                    });
+                }
+            }
+            Ok(Message::Window(index, traffic)) => {
+                let expectation = &scenario.expectations[index];
+                let duration = match states[index] {
+                    State::Observing { started } => started.elapsed(),
+                    _ => Duration::ZERO,
+                };
+                log::debug!("Verify: traffic for '{}': {:?}", expectation.display_name(), traffic);
+                states[index] = State::Done(ExpectationResult {
+                    name: expectation.display_name(),
+                    failures: evaluate(expectation, hosts[index].as_ref(), &traffic),
+                    duration,
+                });
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    states
+        .into_iter()
+        .zip(&scenario.expectations)
+        .map(|(state, expectation)| match state {
+            State::Done(result) => result,
+            _ => ExpectationResult {
+                name: expectation.display_name(),
+                failures: vec![format!("no click on {} within {}s", expectation.click.describe(), scenario.timeout)],
+                duration: Duration::ZERO,
+            },
+        })
+        .collect()
+}
+
+// Print the outcome, write the JUnit report and stop the watcher's run loop
+fn finish(scenario: &Scenario, results: &[ExpectationResult], junit_path: &Path) {
+    let failed = results.iter().filter(|result| !result.failures.is_empty()).count();
+    for result in results {
+        if result.failures.is_empty() {
+            println!("✅ {}", result.name);
+        } else {
+            println!("❌ {}: {}", result.name, result.failures.join("; "));
+        }
+    }
+    println!("{} passed, {} failed", results.len() - failed, failed);
+
+    match fs::write(junit_path, junit_xml(&scenario.name, results)) {
+        Ok(()) => println!("JUnit report written to {}", junit_path.display()),
+        Err(e) => log::error!("Failed to write JUnit report {:?}: {}", junit_path, e),
+    }
+
+    EXIT_CODE.store(if failed == 0 { 0 } else { 1 }, Ordering::SeqCst);
+    shutdown::request_stop();
+}
+
+pub fn junit_xml(suite_name: &str, results: &[ExpectationResult]) -> String {
+    let failed = results.iter().filter(|result| !result.failures.is_empty()).count();
+    let total_time: f64 = results.iter().map(|result| result.duration.as_secs_f64()).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        xml_escape(suite_name),
+        results.len(),
+        failed,
+        total_time
+    ));
+    for result in results {
+        xml.push_str(&format!(
+            "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            xml_escape(suite_name),
+            xml_escape(&result.name),
+            result.duration.as_secs_f64()
+        ));
+        if result.failures.is_empty() {
+            xml.push_str("/>\n");
+        } else {
+            xml.push_str(&format!(
+                ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                xml_escape(&result.failures[0]),
+                xml_escape(&result.failures.join("\n"))
+            ));
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expectation(yaml: &str) -> Expectation {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn api() -> HostMatcher {
+        HostMatcher::new("api.example.com", None, HashSet::from(["93.184.216.34".parse().unwrap()]))
+    }
+
+    fn delta(remote: &str, bytes_in: u64, bytes_out: u64, is_new: bool) -> ConnectionDelta {
+        ConnectionDelta {
+            protocol: "tcp4".to_string(),
+            remote_host: remote.to_string(),
+            remote_port: Some(443),
+            hostname: None,
+            bytes_in,
+            bytes_out,
+            is_new,
+        }
+    }
+
+    fn traffic(connections: Vec<ConnectionDelta>) -> WindowTraffic {
+        WindowTraffic {
+            bytes_in: connections.iter().map(|c| c.bytes_in).sum(),
+            bytes_out: connections.iter().map(|c| c.bytes_out).sum(),
+            connections,
+            ..WindowTraffic::default()
+        }
+    }
+
+    #[test]
+    fn reused_connection_to_the_host_counts_as_contact() {
+        let expectation = expectation("click: {label: Send}\nhost: api.example.com\nmin_bytes_out: 100");
+        let window = traffic(vec![delta("93.184.216.34", 2000, 300, false)]);
+        assert!(evaluate(&expectation, Some(&api()), &window).is_empty());
+    }
+
+    #[test]
+    fn no_bytes_to_the_host_fails() {
+        let expectation = expectation("click: {label: Send}\nhost: api.example.com");
+        let window = traffic(vec![delta("1.1.1.1", 2000, 300, true)]);
+        assert_eq!(evaluate(&expectation, Some(&api()), &window), vec!["expected traffic to api.example.com, saw none".to_string()]);
+    }
+
+    #[test]
+    fn connection_counts_and_byte_limits_are_checked() {
+        let expectation = expectation("click: {label: Send}\nhost: api.example.com\nconnections: 1\nmax_bytes_in: 1000");
+        let window = traffic(vec![delta("93.184.216.34", 2000, 300, false)]);
+        assert_eq!(
+            evaluate(&expectation, Some(&api()), &window),
+            vec![
+                "expected 1 new connection(s) to api.example.com, saw 0".to_string(),
+                "expected at most 1000 bytes in to api.example.com, saw 2000".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn host_matcher_checks_the_port_when_given() {
+        let with_port = HostMatcher::new("api.example.com", Some(8443), api().addresses);
+        assert!(api().matches(&delta("93.184.216.34", 0, 0, true)));
+        assert!(!with_port.matches(&delta("93.184.216.34", 0, 0, true)));
+        assert!(api().matches(&delta("api.example.com", 0, 0, true)));
+    }
+
+    fn load(name: &str, yaml: &str) -> Result<Scenario, String> {
+        let path = std::env::temp_dir().join(format!("macos-watcher-verify-{}-{}.yaml", std::process::id(), name));
+        fs::write(&path, yaml).unwrap();
+        let loaded = load_scenario(&path);
+        fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn the_example_scenario_loads() {
+        let scenario = load_scenario(&Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/example-buttons.yaml")).unwrap();
+        assert_eq!((scenario.timeout, scenario.expectations.len()), (60.0, 2));
+        assert!(scenario.expectations.iter().all(|expectation| expectation.connections.is_none()));
+    }
+
+    #[test]
+    fn times_that_are_not_durations_are_rejected() {
+        let scenario = |timeout: &str, within: &str| {
+            format!("name: s\ntimeout: {}\nexpectations:\n  - name: send\n    click: {{label: Send}}\n    within: {}\n", timeout, within)
+        };
+        assert!(load("valid", &scenario("0", "0.5")).is_ok());
+        assert!(load("empty", "name: s\nexpectations: []\n").unwrap_err().ends_with("has no expectations"));
+        for (name, timeout) in [("negative-timeout", "-1"), ("nan-timeout", ".nan"), ("huge-timeout", "1e300")] {
+            assert!(load(name, &scenario(timeout, "2")).unwrap_err().ends_with("timeout must be a number of seconds"), "{}", name);
+        }
+        for (name, within) in [("negative-within", "-0.5"), ("nan-within", ".nan"), ("infinite-within", ".inf")] {
+            assert_eq!(load(name, &scenario("60", within)).unwrap_err(), "Expectation 'send': within must be a number of seconds", "{}", name);
+        }
+    }
+
+    #[test]
+    fn junit_report_escapes_and_counts_failures() {
+        let results = [
+            ExpectationResult { name: "login <ok>".to_string(), failures: Vec::new(), duration: Duration::from_millis(1500) },
+            ExpectationResult { name: "send".to_string(), failures: vec!["expected \"x\"".to_string()], duration: Duration::ZERO },
+        ];
+        let xml = junit_xml("smoke & more", &results);
+        assert!(xml.contains("<testsuite name=\"smoke &amp; more\" tests=\"2\" failures=\"1\" time=\"1.500\">"));
+        assert!(xml.contains("name=\"login &lt;ok&gt;\" time=\"1.500\"/>"));
+        assert!(xml.contains("<failure message=\"expected &quot;x&quot;\">"));
+    }
+}
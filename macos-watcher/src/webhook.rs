@@ -0,0 +1,579 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::events::{EventSink, WatcherEvent, EVENT_KINDS};
+use crate::session;
+use crate::utils::home_file;
+
+// Events are posted in batches of at most this many, or whatever arrived within FLUSH_INTERVAL of the first one
+const MAX_BATCH: usize = 20;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+// A batch is tried this often, waiting INITIAL_BACKOFF and then twice as
+// long after every failure, before it goes to the spool
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+// While the endpoint is down, the spool is retried this often
+const SPOOL_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Batches that could not be delivered wait here, one request body per line
+pub const DEFAULT_SPOOL_FILE: &str = "macos_watcher_webhook_spool.jsonl";
+
+// Button clicks, tap loss, startup and shutdown (with the session summary) and alerts
+pub const DEFAULT_EVENT_TYPES: &[&str] = &["click", "tap_disabled", "startup", "shutdown", "alert"];
+
+// Shape of the request body
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PayloadFormat {
+    // {"session_id": ..., "events": [<event with a "text" field>, ...]}
+    #[default]
+    Json,
+    // {"text": "<one line per event>"}, what Slack incoming webhooks (and
+    // Mattermost, Discord's /slack endpoint, ...) accept
+    Slack,
+}
+
+impl PayloadFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "json" => Ok(PayloadFormat::Json),
+            "slack" => Ok(PayloadFormat::Slack),
+            _ => Err(format!("Unknown webhook format '{}' (expected json or slack)", name)),
+        }
+    }
+}
+
+// The --webhook* options
+#[derive(Clone, Debug)]
+pub struct WebhookOptions {
+    // None turns the webhook off
+    pub url: Option<String>,
+    pub format: PayloadFormat,
+    // kind() of the events to post
+    pub event_types: Vec<String>,
+    // YAML map from event kind to a text template
+    pub templates_path: Option<PathBuf>,
+    pub spool_path: PathBuf,
+}
+
+impl Default for WebhookOptions {
+    fn default() -> Self {
+        WebhookOptions {
+            url: None,
+            format: PayloadFormat::default(),
+            event_types: DEFAULT_EVENT_TYPES.iter().map(|kind| kind.to_string()).collect(),
+            templates_path: None,
+            spool_path: home_file(DEFAULT_SPOOL_FILE),
+        }
+    }
+}
+
+// "click,alert" -> ["click", "alert"]
+pub fn parse_event_types(value: &str) -> Result<Vec<String>, String> {
+    let kinds: Vec<String> = value.split(',').map(str::trim).filter(|kind| !kind.is_empty()).map(str::to_string).collect();
+    if let Some(unknown) = kinds.iter().find(|kind| !EVENT_KINDS.contains(&kind.as_str())) {
+        return Err(format!("Unknown event type '{}' (expected some of {})", unknown, EVENT_KINDS.join(", ")));
+    }
+    if kinds.is_empty() {
+        return Err("--webhook-events needs at least one event type".to_string());
+    }
+    Ok(kinds)
+}
+
+pub fn load_templates(path: &Path) -> Result<HashMap<String, String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let templates: HashMap<String, String> =
+        serde_yaml::from_str(&contents).map_err(|e| format!("Invalid webhook templates {}: {}", path.display(), e))?;
+    if let Some(unknown) = templates.keys().find(|kind| !EVENT_KINDS.contains(&kind.as_str())) {
+        return Err(format!("{}: unknown event type '{}'", path.display(), unknown));
+    }
+    Ok(templates)
+}
+
+// Replace every "{{path}}" with the value at that dotted path of `context`,
+// e.g. "{{selector.label}}" or "{{summary.duration_secs}}". Strings are
+// inserted as they are, other values as JSON, missing ones as nothing.
+pub fn render_template(template: &str, context: &Value) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else { break };
+        rendered.push_str(&rest[..start]);
+        let path = rest[start + 2..start + 2 + end].trim();
+        match path.split('.').try_fold(context, |value, key| value.get(key)) {
+            Some(Value::String(text)) => rendered.push_str(text),
+            Some(Value::Null) | None => {}
+            Some(value) => rendered.push_str(&value.to_string()),
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+// The line an event gets without a template
+pub fn default_text(event: &WatcherEvent) -> String {
+    match event {
+        WatcherEvent::Click(click) => format!("🖱 {} in '{}' (PID={})", click.selector.describe(), click.app_name, click.pid),
+        WatcherEvent::Key(key) => format!("⌨️ KeyCode {} in '{}' (PID={})", key.keycode, key.app_name, key.pid),
+        WatcherEvent::NetworkSample(sample) => format!("📡 {} ↑ {} B ↓ {} B", sample.process, sample.delta_out, sample.delta_in),
+        WatcherEvent::Correlation(correlation) => format!(
+            "🔗 {} in '{}' → ↑ {} B ↓ {} B",
+            correlation.click.selector.describe(),
+            correlation.click.app_name,
+            correlation.traffic.bytes_out,
+            correlation.traffic.bytes_in
+        ),
+        WatcherEvent::Checkpoint(checkpoint) => format!("📍 Checkpoint: {}", checkpoint.label),
+        WatcherEvent::Alert(alert) => format!("🚨 [{}] {}: {}", alert.severity, alert.rule, alert.message),
+        WatcherEvent::TapDisabled(tap) => format!("⚠️ Event tap disabled ({}), input monitoring stopped", tap.reason),
+        WatcherEvent::Startup(startup) => format!("▶️ macos-watcher session {} started", startup.session_id),
+        WatcherEvent::Shutdown(shutdown) => format!("⏹ macos-watcher session ended\n```\n{}```", shutdown.summary.format_text()),
+    }
+}
+
+// The event as JSON with its session and text, which is also what templates see
+pub fn event_payload(event: &WatcherEvent, templates: &HashMap<String, String>) -> Value {
+    let mut payload = match serde_json::to_value(event) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    payload.insert("session_id".to_string(), Value::String(session::session_id().to_string()));
+    payload.insert("text".to_string(), Value::String(default_text(event)));
+    let mut payload = Value::Object(payload);
+    if let Some(template) = templates.get(event.kind()) {
+        payload["text"] = Value::String(render_template(template, &payload));
+    }
+    payload
+}
+
+// One request body for a batch of event payloads
+pub fn request_body(format: PayloadFormat, events: Vec<Value>) -> String {
+    let body = match format {
+        PayloadFormat::Json => json!({ "session_id": session::session_id(), "events": events }),
+        PayloadFormat::Slack => {
+            let lines: Vec<&str> = events.iter().filter_map(|event| event["text"].as_str()).collect();
+            json!({ "text": lines.join("\n") })
+        }
+    };
+    body.to_string()
+}
+
+enum PostError {
+    // The endpoint refused the body (4xx); sending it again will not help
+    Rejected(String),
+    Failed(String),
+}
+
+fn post(url: &str, body: &str) -> Result<(), PostError> {
+    match ureq::post(url).set("Content-Type", "application/json").timeout(REQUEST_TIMEOUT).send_string(body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, _)) if (400..500).contains(&status) && status != 408 && status != 429 => {
+            Err(PostError::Rejected(format!("HTTP {}", status)))
+        }
+        Err(e) => Err(PostError::Failed(e.to_string())),
+    }
+}
+
+// Request bodies waiting for the endpoint to come back, oldest first. Survives
+// restarts: whatever a previous run left behind is sent first.
+struct Spool {
+    path: PathBuf,
+}
+
+impl Spool {
+    fn load(&self) -> Vec<String> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => contents.lines().filter(|line| !line.is_empty()).map(str::to_string).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                log::warn!("Failed to read webhook spool {:?}: {}", self.path, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn append(&self, body: &str) {
+        let written = OpenOptions::new().create(true).append(true).open(&self.path).and_then(|mut file| writeln!(file, "{}", body));
+        if let Err(e) = written {
+            log::error!("Failed to spool webhook batch to {:?}, dropping it: {}", self.path, e);
+        }
+    }
+
+    // Keep only `remaining`; the file goes away once everything is delivered
+    fn replace(&self, remaining: &[String]) {
+        let result = if remaining.is_empty() {
+            fs::remove_file(&self.path).or_else(|e| if e.kind() == io::ErrorKind::NotFound { Ok(()) } else { Err(e) })
+        } else {
+            let temporary = self.path.with_extension("tmp");
+            fs::write(&temporary, remaining.join("\n") + "\n").and_then(|()| fs::rename(&temporary, &self.path))
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to update webhook spool {:?}: {}", self.path, e);
+        }
+    }
+}
+
+struct Delivery {
+    url: String,
+    format: PayloadFormat,
+    spool: Spool,
+    // Set while the endpoint is down: nothing is posted before then
+    retry_at: Option<Instant>,
+}
+
+impl Delivery {
+    // Post `body` unless the endpoint is known to be down or older batches
+    // are still waiting; those go first, so the receiver sees events in order
+    fn deliver(&mut self, body: String, attempts: u32) {
+        if self.retry_at.is_some() || !self.spool.load().is_empty() {
+            self.spool.append(&body);
+            return;
+        }
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=attempts {
+            match post(&self.url, &body) {
+                Ok(()) => {
+                    log::debug!("Posted webhook batch to {}", self.url);
+                    return;
+                }
+                Err(PostError::Rejected(e)) => {
+                    log::error!("Webhook {} rejected a batch, dropping it: {}", self.url, e);
+                    return;
+                }
+                Err(PostError::Failed(e)) => {
+                    log::warn!("Webhook {} failed (attempt {}/{}): {}", self.url, attempt, attempts, e);
+                    if attempt < attempts {
+                        thread::sleep(backoff);
+                        backoff *= 2;
+                    }
+                }
+            }
+        }
+        log::warn!("Spooling webhook batch to {:?} until {} is back", self.spool.path, self.url);
+        self.spool.append(&body);
+        self.retry_at = Some(Instant::now() + SPOOL_RETRY_INTERVAL);
+    }
+
+    // Send spooled batches in order until one fails
+    fn drain_spool(&mut self) {
+        if self.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+            return;
+        }
+        self.retry_at = None;
+        let spooled = self.spool.load();
+        if spooled.is_empty() {
+            return;
+        }
+        let mut sent = 0;
+        for body in &spooled {
+            match post(&self.url, body) {
+                Ok(()) => {}
+                Err(PostError::Rejected(e)) => log::error!("Webhook {} rejected a spooled batch, dropping it: {}", self.url, e),
+                Err(PostError::Failed(e)) => {
+                    log::debug!("Webhook {} still unavailable: {}", self.url, e);
+                    self.retry_at = Some(Instant::now() + SPOOL_RETRY_INTERVAL);
+                    break;
+                }
+            }
+            sent += 1;
+        }
+        if sent > 0 {
+            log::info!("Delivered {} spooled webhook batch(es) to {}", sent, self.url);
+            self.spool.replace(&spooled[sent..]);
+        }
+    }
+}
+
+// Batches payloads on a background thread so a slow endpoint never blocks the event tap
+fn run_delivery(mut delivery: Delivery, receiver: Receiver<Value>, flush_interval: Duration) {
+    let mut batch: Vec<Value> = Vec::new();
+    // Counted from the first event of the batch, so a steady stream of
+    // events cannot hold the batch back
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let wait = deadline.map_or(flush_interval, |deadline| deadline.saturating_duration_since(Instant::now()));
+        let disconnected = match receiver.recv_timeout(wait) {
+            Ok(payload) => {
+                batch.push(payload);
+                let deadline = *deadline.get_or_insert_with(|| Instant::now() + flush_interval);
+                if batch.len() < MAX_BATCH && Instant::now() < deadline {
+                    continue;
+                }
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+        deadline = None;
+
+        delivery.drain_spool();
+        if !batch.is_empty() {
+            let body = request_body(delivery.format, std::mem::take(&mut batch));
+            // No backoff on shutdown; the spool keeps what does not go through
+            delivery.deliver(body, if disconnected { 1 } else { MAX_ATTEMPTS });
+        }
+        if disconnected {
+            return;
+        }
+    }
+}
+
+// POSTs the selected events to an HTTP webhook, in batches
+pub struct WebhookSink {
+    // Taken on finish so the delivery thread sees the channel close
+    sender: Option<Sender<Value>>,
+    delivery: Option<JoinHandle<()>>,
+    event_types: Vec<String>,
+    templates: HashMap<String, String>,
+}
+
+impl WebhookSink {
+    pub fn start(url: &str, options: &WebhookOptions) -> Result<Self, String> {
+        let templates = match &options.templates_path {
+            Some(path) => load_templates(path)?,
+            None => HashMap::new(),
+        };
+        let delivery = Delivery {
+            url: url.to_string(),
+            format: options.format,
+            spool: Spool { path: options.spool_path.clone() },
+            retry_at: None,
+        };
+        let (sender, receiver) = mpsc::channel();
+        let delivery = thread::spawn(move || run_delivery(delivery, receiver, FLUSH_INTERVAL));
+        Ok(WebhookSink { sender: Some(sender), delivery: Some(delivery), event_types: options.event_types.clone(), templates })
+    }
+}
+
+impl EventSink for WebhookSink {
+    fn handle(&mut self, event: &WatcherEvent) {
+        if !self.event_types.iter().any(|kind| kind == event.kind()) {
+            return;
+        }
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(event_payload(event, &self.templates));
+        }
+    }
+
+    // Post (or spool) whatever is still batched before the process exits
+    fn finish(&mut self) {
+        drop(self.sender.take());
+        if let Some(delivery) = self.delivery.take() {
+            let _ = delivery.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{click, key};
+    use crate::otel::tests::http_receiver;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("macos-watcher-webhook-{}-{}", std::process::id(), name))
+    }
+
+    fn send_click() -> WatcherEvent {
+        WatcherEvent::Click(Box::new(click(0, 812, "Mail", "Send")))
+    }
+
+    fn options(name: &str) -> WebhookOptions {
+        let spool_path = temp_path(name);
+        let _ = fs::remove_file(&spool_path);
+        WebhookOptions { spool_path, ..WebhookOptions::default() }
+    }
+
+    // Post each event through a sink and wait for it to finish
+    fn post_events(url: &str, options: &WebhookOptions, events: &[WatcherEvent]) {
+        let mut sink = WebhookSink::start(url, options).unwrap();
+        for event in events {
+            sink.handle(event);
+        }
+        sink.finish();
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        assert_eq!(PayloadFormat::parse("slack"), Ok(PayloadFormat::Slack));
+        assert!(PayloadFormat::parse("teams").is_err());
+        assert_eq!(parse_event_types(" click, alert,").unwrap(), ["click", "alert"]);
+        assert!(parse_event_types("click,scroll").unwrap_err().contains("'scroll'"));
+        assert!(parse_event_types(" , ").is_err());
+    }
+
+    #[test]
+    fn templates_fill_in_dotted_paths() {
+        let context = json!({ "app_name": "Mail", "pid": 812, "selector": { "label": "Send", "identifier": null }, "ok": true });
+        assert_eq!(
+            render_template("{{ selector.label }} in {{app_name}} ({{pid}}, {{ok}}){{selector.identifier}}{{missing.path}}", &context),
+            "Send in Mail (812, true)"
+        );
+        assert_eq!(render_template("{{selector}}", &context), r#"{"identifier":null,"label":"Send"}"#);
+        assert_eq!(render_template("unclosed {{app_name", &context), "unclosed {{app_name");
+        assert_eq!(render_template("no placeholders", &context), "no placeholders");
+    }
+
+    #[test]
+    fn templates_are_loaded_per_event_type() {
+        let path = temp_path("templates.yaml");
+        fs::write(&path, "click: \"{{app_name}} clicked {{selector.label}}\"\nalert: \"{{message}}\"\n").unwrap();
+        let templates = load_templates(&path).unwrap();
+        fs::write(&path, "scroll: \"{{app_name}}\"\n").unwrap();
+        let unknown = load_templates(&path).unwrap_err();
+        fs::write(&path, "- click\n").unwrap();
+        let invalid = load_templates(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(templates["click"], "{{app_name}} clicked {{selector.label}}");
+        assert!(unknown.contains("unknown event type 'scroll'"), "{}", unknown);
+        assert!(invalid.starts_with("Invalid webhook templates"), "{}", invalid);
+        assert!(load_templates(&temp_path("missing.yaml")).unwrap_err().starts_with("Failed to read"));
+    }
+
+    #[test]
+    fn payloads_carry_the_event_session_and_text() {
+        let payload = event_payload(&send_click(), &HashMap::new());
+        assert_eq!(payload["type"], "click");
+        assert_eq!(payload["pid"], 812);
+        assert_eq!(payload["session_id"], session::session_id());
+        assert_eq!(payload["text"], "🖱 AXButton 'Send' in 'Mail' (PID=812)");
+
+        let templates = HashMap::from([("click".to_string(), "{{app_name}}: {{text}} [{{session_id}}]".to_string())]);
+        let payload = event_payload(&send_click(), &templates);
+        assert_eq!(payload["text"], format!("Mail: 🖱 AXButton 'Send' in 'Mail' (PID=812) [{}]", session::session_id()));
+        assert_eq!(event_payload(&key(0, 812, "Mail", 36), &templates)["text"], "⌨️ KeyCode 36 in 'Mail' (PID=812)");
+    }
+
+    #[test]
+    fn request_bodies_follow_the_format() {
+        let events = vec![json!({ "type": "click", "text": "first" }), json!({ "type": "alert", "text": "second" }), json!({ "type": "key" })];
+        let body: Value = serde_json::from_str(&request_body(PayloadFormat::Json, events.clone())).unwrap();
+        assert_eq!(body, json!({ "session_id": session::session_id(), "events": events }));
+        let body: Value = serde_json::from_str(&request_body(PayloadFormat::Slack, events)).unwrap();
+        assert_eq!(body, json!({ "text": "first\nsecond" }));
+    }
+
+    #[test]
+    fn selected_events_are_posted_in_one_batch() {
+        let (url, requests) = http_receiver(200);
+        let options = WebhookOptions { format: PayloadFormat::Slack, ..options("posted.jsonl") };
+        post_events(&url, &options, &[send_click(), key(10, 812, "Mail", 36), send_click()]);
+
+        let (request_line, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request_line, "POST / HTTP/1.1");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["text"], "🖱 AXButton 'Send' in 'Mail' (PID=812)\n🖱 AXButton 'Send' in 'Mail' (PID=812)");
+        assert!(requests.try_recv().is_err());
+        assert!(!options.spool_path.exists());
+    }
+
+    #[test]
+    fn batches_are_flushed_on_time_while_events_keep_arriving() {
+        let (url, requests) = http_receiver(200);
+        let delivery = Delivery { url, format: PayloadFormat::Json, spool: Spool { path: temp_path("steady.jsonl") }, retry_at: None };
+        let _ = fs::remove_file(&delivery.spool.path);
+        let (sender, receiver) = mpsc::channel();
+        let delivery = thread::spawn(move || run_delivery(delivery, receiver, Duration::from_millis(300)));
+
+        // One event every 50ms for 1.5s: never a quiet FLUSH_INTERVAL, and
+        // MAX_BATCH would only be reached after a second
+        let started = Instant::now();
+        let mut first_batch = None;
+        for n in 0..30 {
+            sender.send(json!({ "n": n })).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            if first_batch.is_none() {
+                first_batch = requests.try_recv().ok().map(|(_, body)| (started.elapsed(), body));
+            }
+        }
+        drop(sender);
+        delivery.join().unwrap();
+
+        let (elapsed, body) = first_batch.expect("no batch while events kept arriving");
+        assert!(elapsed < Duration::from_millis(900), "first batch after {:?}", elapsed);
+        let first: Value = serde_json::from_str(&body).unwrap();
+        let first = first["events"].as_array().unwrap().len();
+        assert!((2..MAX_BATCH).contains(&first), "{} events in the first batch", first);
+        let rest: usize = requests
+            .try_iter()
+            .map(|(_, body)| serde_json::from_str::<Value>(&body).unwrap()["events"].as_array().unwrap().len())
+            .sum();
+        assert_eq!(first + rest, 30);
+    }
+
+    #[test]
+    fn rejected_batches_are_dropped() {
+        let (url, requests) = http_receiver(400);
+        let options = options("rejected.jsonl");
+        post_events(&url, &options, &[send_click()]);
+
+        assert!(requests.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert!(!options.spool_path.exists());
+    }
+
+    #[test]
+    fn failed_batches_are_spooled_and_sent_first_next_time() {
+        let (down, failures) = http_receiver(503);
+        let options = options("spool.jsonl");
+        post_events(&down, &options, &[send_click()]);
+        assert!(failures.recv_timeout(Duration::from_secs(5)).is_ok());
+        let spooled = Spool { path: options.spool_path.clone() }.load();
+        assert_eq!(spooled.len(), 1);
+
+        let (up, requests) = http_receiver(200);
+        post_events(&up, &options, &[WatcherEvent::Click(Box::new(click(1000, 812, "Mail", "Archive")))]);
+        let (_, first) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        let (_, second) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(first, spooled[0]);
+        let second: Value = serde_json::from_str(&second).unwrap();
+        assert_eq!(second["events"][0]["selector"]["label"], "Archive");
+        assert!(!options.spool_path.exists());
+    }
+
+    #[test]
+    fn the_spool_keeps_what_was_not_delivered() {
+        let spool = Spool { path: temp_path("replace.jsonl") };
+        let _ = fs::remove_file(&spool.path);
+        assert!(spool.load().is_empty());
+        for body in ["one", "two", "three"] {
+            spool.append(body);
+        }
+        assert_eq!(spool.load(), ["one", "two", "three"]);
+        spool.replace(&["three".to_string()]);
+        assert_eq!(spool.load(), ["three"]);
+        spool.replace(&[]);
+        assert!(!spool.path.exists());
+        spool.replace(&[]);
+    }
+
+    #[test]
+    fn batches_wait_behind_the_spool_while_the_endpoint_is_down() {
+        let (url, requests) = http_receiver(200);
+        let mut delivery =
+            Delivery { url, format: PayloadFormat::Json, spool: Spool { path: temp_path("waiting.jsonl") }, retry_at: Some(Instant::now() + SPOOL_RETRY_INTERVAL) };
+        let _ = fs::remove_file(&delivery.spool.path);
+        delivery.deliver("{\"n\":1}".to_string(), 1);
+        delivery.drain_spool();
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+        delivery.deliver("{\"n\":2}".to_string(), 1);
+        assert_eq!(delivery.spool.load(), ["{\"n\":1}", "{\"n\":2}"]);
+
+        delivery.retry_at = Some(Instant::now());
+        delivery.drain_spool();
+        assert_eq!(requests.recv_timeout(Duration::from_secs(5)).unwrap().1, "{\"n\":1}");
+        assert_eq!(requests.recv_timeout(Duration::from_secs(5)).unwrap().1, "{\"n\":2}");
+        assert!(delivery.retry_at.is_none());
+        assert!(!delivery.spool.path.exists());
+    }
+}